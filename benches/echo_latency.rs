@@ -0,0 +1,100 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate coio;
+extern crate num_cpus;
+extern crate time;
+
+use std::io::{Read, Write};
+use std::net;
+use std::thread;
+use std::time::Duration;
+
+use coio::Scheduler;
+use coio::net::TcpListener;
+
+const NS_PER_US: u64 = 1_000;
+const REQUEST_COUNT: usize = 2_000;
+
+// Pause between two requests, long enough for all Processors to park
+const REQUEST_INTERVAL_US: u64 = 500;
+
+// Runs an echo server and sends it requests from a plain thread, one at a time and with
+// a pause in between. Every request thus has to wake a parked Processor through the event
+// loop, which makes the round trip times the latency of I/O readiness wakeups.
+// Returns the round trip times in ns, sorted.
+fn run_test(worker_count: usize) -> Vec<u64> {
+    Scheduler::new()
+        .with_workers(worker_count)
+        .run(|| {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+
+            let server = Scheduler::spawn(move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut buf = [0u8; 64];
+
+                loop {
+                    match stream.read(&mut buf) {
+                        Ok(0) | Err(..) => break,
+                        Ok(len) => stream.write_all(&buf[..len]).unwrap(),
+                    }
+                }
+            });
+
+            let client = thread::spawn(move || {
+                let mut stream = net::TcpStream::connect(addr).unwrap();
+                stream.set_nodelay(true).unwrap();
+
+                let mut buf = [0u8; 4];
+                let mut rtts = Vec::with_capacity(REQUEST_COUNT);
+
+                for _ in 0..REQUEST_COUNT {
+                    thread::sleep(Duration::new(0, (REQUEST_INTERVAL_US * NS_PER_US) as u32));
+
+                    let beg = time::precise_time_ns();
+                    stream.write_all(b"ping").unwrap();
+                    stream.read_exact(&mut buf).unwrap();
+                    rtts.push(time::precise_time_ns() - beg);
+                }
+
+                rtts
+            });
+
+            let mut rtts = Scheduler::spawn_blocking(move || client.join().unwrap());
+            server.join().unwrap();
+
+            rtts.sort();
+            rtts
+        })
+        .unwrap()
+}
+
+fn percentile(sorted: &[u64], p: usize) -> u64 {
+    sorted[(sorted.len() - 1) * p / 100]
+}
+
+// Run this test with
+//   cargo bench --bench echo_latency
+fn main() {
+    let mut worker_counts = vec![1, 2];
+
+    if num_cpus::get() > 2 {
+        worker_counts.push(num_cpus::get());
+    }
+
+    for &i in &worker_counts {
+        let rtts = run_test(i);
+
+        println!("{} Workers: p50 {} us, p99 {} us, max {} us",
+                 i,
+                 percentile(&rtts, 50) / NS_PER_US,
+                 percentile(&rtts, 99) / NS_PER_US,
+                 rtts[rtts.len() - 1] / NS_PER_US);
+    }
+}
@@ -0,0 +1,100 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate coio;
+extern crate num_cpus;
+extern crate time;
+
+use coio::Scheduler;
+use coio::sync::mpmc::{self, TrySendError};
+
+const NS_PER_MS: usize = 1_000_000;
+const MESSAGE_COUNT: usize = 1_000_000;
+const CAPACITY: usize = 16;
+
+#[inline]
+fn rdiv(a: usize, b: usize) -> usize {
+    (a + (b / 2)) / b
+}
+
+// Sends MESSAGE_COUNT messages from one coroutine per worker through a small buffer.
+// If `spin` is true the senders spin on try_send() and sched() instead of parking.
+fn run_test(worker_count: usize, spin: bool) -> usize {
+    Scheduler::new()
+        .with_workers(worker_count)
+        .run(move || {
+            let (tx, rx) = mpmc::channel(CAPACITY);
+            let per_sender = MESSAGE_COUNT / worker_count;
+
+            let beg = time::precise_time_ns();
+
+            let handles: Vec<_> = (0..worker_count)
+                                      .map(|_| {
+                                          let tx = tx.clone();
+
+                                          Scheduler::spawn(move || {
+                                              for i in 0..per_sender {
+                                                  if !spin {
+                                                      tx.send(i).unwrap();
+                                                      continue;
+                                                  }
+
+                                                  let mut t = i;
+
+                                                  while let Err(TrySendError::Full(t_)) =
+                                                            tx.try_send(t) {
+                                                      t = t_;
+                                                      Scheduler::sched();
+                                                  }
+                                              }
+                                          })
+                                      })
+                                      .collect();
+
+            drop(tx);
+
+            while let Ok(_) = rx.recv() {}
+
+            for h in handles {
+                h.join().unwrap();
+            }
+
+            let end = time::precise_time_ns();
+            (end - beg) as usize
+        })
+        .unwrap()
+}
+
+// Run this test with
+//   cargo bench --bench mpmc -- --csv
+// to get a parsable output.
+// The columns contain the worker count and the ns/message for parking and spinning senders.
+fn main() {
+    let csv = std::env::args().any(|arg| arg == "--csv");
+
+    for i in 1..(num_cpus::get() + 1) {
+        let parking = run_test(i, false);
+        let spinning = run_test(i, true);
+
+        if csv {
+            println!("{};{};{}",
+                     i,
+                     rdiv(parking, MESSAGE_COUNT),
+                     rdiv(spinning, MESSAGE_COUNT));
+        } else {
+            println!("{} Workers: {} messages in {} ms ({} ns/message) parking, {} ms ({} \
+                      ns/message) spinning",
+                     i,
+                     MESSAGE_COUNT,
+                     rdiv(parking, NS_PER_MS),
+                     rdiv(parking, MESSAGE_COUNT),
+                     rdiv(spinning, NS_PER_MS),
+                     rdiv(spinning, MESSAGE_COUNT));
+        }
+    }
+}
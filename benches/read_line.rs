@@ -0,0 +1,84 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate coio;
+extern crate time;
+
+use std::io::{BufRead, Write};
+
+use coio::Scheduler;
+use coio::io::{BufReader, BufWriter};
+use coio::net::{TcpListener, TcpStream};
+
+const NS_PER_MS: usize = 1_000_000;
+const LINE_COUNT: usize = 1_000_000;
+
+#[inline]
+fn rdiv(a: usize, b: usize) -> usize {
+    (a + (b / 2)) / b
+}
+
+// Streams short lines over a local TCP connection and reads them with read_line().
+// Most lines are served from the buffer, so this mostly measures the overhead of
+// the buffering compared to the occasional park on the socket.
+fn run_test(worker_count: usize) -> usize {
+    Scheduler::new()
+        .with_workers(worker_count)
+        .run(|| {
+            let acceptor = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = acceptor.local_addr().unwrap();
+
+            let writer_fut = Scheduler::spawn(move || {
+                let (stream, _) = acceptor.accept().unwrap();
+                let mut writer = BufWriter::new(stream);
+
+                for i in 0..LINE_COUNT {
+                    write!(writer, "GET /{} HTTP/1.1\r\n", i).unwrap();
+                }
+
+                writer.flush().unwrap();
+            });
+
+            let mut reader = BufReader::new(TcpStream::connect(addr).unwrap());
+            let mut line = String::new();
+            let beg = time::precise_time_ns();
+
+            for _ in 0..LINE_COUNT {
+                line.clear();
+                reader.read_line(&mut line).unwrap();
+            }
+
+            let end = time::precise_time_ns();
+
+            writer_fut.join().unwrap();
+            (end - beg) as usize
+        })
+        .unwrap()
+}
+
+// Run this test with
+//   cargo bench --bench read_line -- --csv
+// to get a parsable output.
+// The first column will contain the worker count and the second one the ns/line.
+fn main() {
+    let csv = std::env::args().any(|arg| arg == "--csv");
+
+    for i in 1..3 {
+        let duration = run_test(i);
+
+        if csv {
+            println!("{};{}", i, rdiv(duration, LINE_COUNT));
+        } else {
+            println!("{} Workers: {} lines in {} ms => {} ns/line",
+                     i,
+                     LINE_COUNT,
+                     rdiv(duration, NS_PER_MS),
+                     rdiv(duration, LINE_COUNT));
+        }
+    }
+}
@@ -0,0 +1,74 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate coio;
+extern crate num_cpus;
+extern crate time;
+
+use coio::{Options, Scheduler};
+
+const NS_PER_MS: usize = 1_000_000;
+const COROUTINE_COUNT: usize = 100_000;
+
+#[inline]
+fn rdiv(a: usize, b: usize) -> usize {
+    (a + (b / 2)) / b
+}
+
+// Spawns a large number of short lived coroutines from the main coroutine.
+// Since all of them are pushed into the local queue of a single Processor,
+// all the others are forced to steal them, which stresses the work stealing code.
+fn run_test(worker_count: usize) -> usize {
+    Scheduler::new()
+        .with_workers(worker_count)
+        .run(|| {
+            let mut opts = Options::new();
+            opts.stack_size(16 * 1024);
+
+            let beg = time::precise_time_ns();
+
+            let handles: Vec<_> = (0..COROUTINE_COUNT)
+                                      .map(|_| {
+                                          Scheduler::spawn_opts(|| {
+                                                                    Scheduler::sched();
+                                                                },
+                                                                opts.clone())
+                                      })
+                                      .collect();
+
+            for h in handles {
+                h.join().unwrap();
+            }
+
+            let end = time::precise_time_ns();
+            (end - beg) as usize
+        })
+        .unwrap()
+}
+
+// Run this test with
+//   cargo bench --bench spawn -- --csv
+// to get a parsable output.
+// The first column will contain the worker count and the second one the ns/coroutine.
+fn main() {
+    let csv = std::env::args().any(|arg| arg == "--csv");
+
+    for i in 1..(num_cpus::get() + 1) {
+        let duration = run_test(i);
+
+        if csv {
+            println!("{};{}", i, rdiv(duration, COROUTINE_COUNT));
+        } else {
+            println!("{} Workers: {} coroutines in {} ms => {} ns/coroutine",
+                     i,
+                     COROUTINE_COUNT,
+                     rdiv(duration, NS_PER_MS),
+                     rdiv(duration, COROUTINE_COUNT));
+        }
+    }
+}
@@ -0,0 +1,89 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate coio;
+extern crate time;
+
+use coio::Scheduler;
+use coio::sync::{mpmc, spsc};
+
+const NS_PER_MS: usize = 1_000_000;
+const MESSAGE_COUNT: usize = 1_000_000;
+const CAPACITY: usize = 16;
+
+#[inline]
+fn rdiv(a: usize, b: usize) -> usize {
+    (a + (b / 2)) / b
+}
+
+// Sends MESSAGE_COUNT messages from a producer to a consumer coroutine through a small buffer.
+// If `use_spsc` is true an spsc channel is used, otherwise an mpmc one.
+fn run_test(worker_count: usize, use_spsc: bool) -> usize {
+    Scheduler::new()
+        .with_workers(worker_count)
+        .run(move || {
+            let beg = time::precise_time_ns();
+
+            if use_spsc {
+                let (tx, rx) = spsc::channel(CAPACITY);
+
+                let h = Scheduler::spawn(move || {
+                    for i in 0..MESSAGE_COUNT {
+                        tx.send(i).unwrap();
+                    }
+                });
+
+                while let Ok(_) = rx.recv() {}
+                h.join().unwrap();
+            } else {
+                let (tx, rx) = mpmc::channel(CAPACITY);
+
+                let h = Scheduler::spawn(move || {
+                    for i in 0..MESSAGE_COUNT {
+                        tx.send(i).unwrap();
+                    }
+                });
+
+                while let Ok(_) = rx.recv() {}
+                h.join().unwrap();
+            }
+
+            let end = time::precise_time_ns();
+            (end - beg) as usize
+        })
+        .unwrap()
+}
+
+// Run this test with
+//   cargo bench --bench spsc -- --csv
+// to get a parsable output.
+// The columns contain the worker count and the ns/message for the spsc and mpmc channel.
+fn main() {
+    let csv = std::env::args().any(|arg| arg == "--csv");
+
+    for i in 1..3 {
+        let spsc = run_test(i, true);
+        let mpmc = run_test(i, false);
+
+        if csv {
+            println!("{};{};{}",
+                     i,
+                     rdiv(spsc, MESSAGE_COUNT),
+                     rdiv(mpmc, MESSAGE_COUNT));
+        } else {
+            println!("{} Workers: {} messages in {} ms ({} ns/message) spsc, {} ms ({} \
+                      ns/message) mpmc",
+                     i,
+                     MESSAGE_COUNT,
+                     rdiv(spsc, NS_PER_MS),
+                     rdiv(spsc, MESSAGE_COUNT),
+                     rdiv(mpmc, NS_PER_MS),
+                     rdiv(mpmc, MESSAGE_COUNT));
+        }
+    }
+}
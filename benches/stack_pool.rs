@@ -0,0 +1,58 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate coio;
+extern crate time;
+
+use coio::Scheduler;
+
+const NS_PER_MS: usize = 1_000_000;
+const COROUTINE_COUNT: usize = 1_000_000;
+const BATCH_SIZE: usize = 1_000;
+
+#[inline]
+fn rdiv(a: usize, b: usize) -> usize {
+    (a + (b / 2)) / b
+}
+
+// Spawns tiny coroutines in batches and waits for each batch to finish.
+// With pooling every batch after the first one reuses the stacks of the previous one,
+// while without it each spawn has to mmap() and mprotect() a fresh stack.
+fn run_test(stack_pool_limit: usize) -> usize {
+    Scheduler::new()
+        .with_stack_pool_limit(stack_pool_limit)
+        .run(|| {
+            let beg = time::precise_time_ns();
+
+            for _ in 0..(COROUTINE_COUNT / BATCH_SIZE) {
+                let handles: Vec<_> = (0..BATCH_SIZE).map(|i| Scheduler::spawn(move || i)).collect();
+
+                for h in handles {
+                    h.join().unwrap();
+                }
+            }
+
+            let end = time::precise_time_ns();
+            (end - beg) as usize
+        })
+        .unwrap()
+}
+
+// Run this test with
+//   cargo bench --bench stack_pool
+fn main() {
+    for &(name, limit) in &[("pooled", 2 * 1024 * 1024 * 1024), ("unpooled", 0)] {
+        let duration = run_test(limit);
+
+        println!("{}: {} coroutines in {} ms => {} ns/coroutine",
+                 name,
+                 COROUTINE_COUNT,
+                 rdiv(duration, NS_PER_MS),
+                 rdiv(duration, COROUTINE_COUNT));
+    }
+}
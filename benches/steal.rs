@@ -0,0 +1,162 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate coio;
+extern crate num_cpus;
+extern crate time;
+
+use coio::{Options, Scheduler, StealStrategy};
+
+const NS_PER_MS: usize = 1_000_000;
+const SPAWNER_COUNT: usize = 8;
+const COROUTINE_COUNT: usize = 100_000;
+const BURST_COUNT: usize = 1_000;
+const SPAWN_TREE_DEPTH: usize = 10;
+
+#[inline]
+fn rdiv(a: usize, b: usize) -> usize {
+    (a + (b / 2)) / b
+}
+
+fn busy_work(n: usize) -> usize {
+    (0..n).fold(0, |acc, x| acc.wrapping_mul(31).wrapping_add(x))
+}
+
+// Starts a couple of spawners which themselves spawn a very unevenly distributed
+// amount of coroutines: The first spawner spawns half of all coroutines, the second
+// one a quarter and so on. This leaves a few Processors with very long queues.
+fn run_test(worker_count: usize, strategy: StealStrategy) -> usize {
+    Scheduler::new()
+        .with_workers(worker_count)
+        .with_steal_strategy(strategy)
+        .run(|| {
+            let beg = time::precise_time_ns();
+
+            let spawners: Vec<_> = (0..SPAWNER_COUNT)
+                                       .map(|i| {
+                                           Scheduler::spawn(move || {
+                                               let mut opts = Options::new();
+                                               opts.stack_size(16 * 1024);
+
+                                               let count = COROUTINE_COUNT >> (i + 1);
+                                               let handles: Vec<_> = (0..count)
+                                                                         .map(|_| {
+                                                                             let f = || busy_work(1_000);
+                                                                             Scheduler::spawn_opts(f, opts.clone())
+                                                                         })
+                                                                         .collect();
+
+                                               for h in handles {
+                                                   h.join().unwrap();
+                                               }
+                                           })
+                                       })
+                                       .collect();
+
+            for h in spawners {
+                h.join().unwrap();
+            }
+
+            let end = time::precise_time_ns();
+            (end - beg) as usize
+        })
+        .unwrap()
+}
+
+// Spawns short bursts of coroutines, each one waited for before the next one is spawned.
+// Processors thus keep on running out of work and have to find the next burst quickly.
+fn run_bursty_test(worker_count: usize, steal_retries: usize) -> usize {
+    Scheduler::new()
+        .with_workers(worker_count)
+        .with_steal_retries(steal_retries)
+        .run(move || {
+            let beg = time::precise_time_ns();
+
+            let mut opts = Options::new();
+            opts.stack_size(16 * 1024);
+
+            for _ in 0..BURST_COUNT {
+                let handles: Vec<_> = (0..(worker_count * 4))
+                                          .map(|_| {
+                                              let f = || busy_work(10_000);
+                                              Scheduler::spawn_opts(f, opts.clone())
+                                          })
+                                          .collect();
+
+                for h in handles {
+                    h.join().unwrap();
+                }
+            }
+
+            let end = time::precise_time_ns();
+            (end - beg) as usize
+        })
+        .unwrap()
+}
+
+// Every coroutine spawns two children until `depth` is reached, so most of them are spawned by
+// stolen coroutines. Their children should stay on the Processor which stole their parent.
+fn run_spawn_tree_test(worker_count: usize, depth: usize) -> usize {
+    fn spawn_tree(depth: usize, opts: Options) -> usize {
+        if depth == 0 {
+            return busy_work(1_000);
+        }
+
+        let handles: Vec<_> = (0..2)
+                                  .map(|_| {
+                                      let child_opts = opts.clone();
+                                      let f = move || spawn_tree(depth - 1, child_opts);
+                                      Scheduler::spawn_opts(f, opts.clone())
+                                  })
+                                  .collect();
+
+        handles.into_iter().fold(0, |acc, h| acc.wrapping_add(h.join().unwrap()))
+    }
+
+    Scheduler::new()
+        .with_workers(worker_count)
+        .run(move || {
+            let beg = time::precise_time_ns();
+
+            let mut opts = Options::new();
+            opts.stack_size(16 * 1024);
+            spawn_tree(depth, opts);
+
+            let end = time::precise_time_ns();
+            (end - beg) as usize
+        })
+        .unwrap()
+}
+
+// Run this test with
+//   cargo bench --bench steal
+fn main() {
+    for i in 2..(num_cpus::get() + 1) {
+        for &(name, strategy) in &[("random", StealStrategy::Random),
+                                   ("longest", StealStrategy::Longest)] {
+            let duration = run_test(i, strategy);
+
+            println!("{} Workers, {}: {} ms",
+                     i,
+                     name,
+                     rdiv(duration, NS_PER_MS));
+        }
+
+        for &steal_retries in &[1, 4, 16] {
+            let duration = run_bursty_test(i, steal_retries);
+
+            println!("{} Workers, bursty, {} steal retries: {} ms",
+                     i,
+                     steal_retries,
+                     rdiv(duration, NS_PER_MS));
+        }
+
+        let duration = run_spawn_tree_test(i, SPAWN_TREE_DEPTH);
+        println!("{} Workers, spawn tree: {} ms", i, rdiv(duration, NS_PER_MS));
+    }
+}
@@ -0,0 +1,81 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate coio;
+extern crate time;
+
+use coio::Scheduler;
+use coio::net::UdpSocket;
+
+const NS_PER_MS: usize = 1_000_000;
+const ROUND_TRIPS: usize = 100_000;
+
+#[inline]
+fn rdiv(a: usize, b: usize) -> usize {
+    (a + (b / 2)) / b
+}
+
+// Bounces a single datagram between two connected sockets in two coroutines.
+// Every round trip parks both coroutines once, which measures the
+// latency of waking a coroutine on socket readiness.
+fn run_test(worker_count: usize) -> usize {
+    Scheduler::new()
+        .with_workers(worker_count)
+        .run(|| {
+            let ping = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let pong = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+            ping.connect(pong.local_addr().unwrap()).unwrap();
+            pong.connect(ping.local_addr().unwrap()).unwrap();
+
+            let pong_fut = Scheduler::spawn(move || {
+                let mut buf = [0u8; 64];
+
+                for _ in 0..ROUND_TRIPS {
+                    let len = pong.recv(&mut buf).unwrap();
+                    pong.send(&buf[..len]).unwrap();
+                }
+            });
+
+            let mut buf = [0u8; 64];
+            let beg = time::precise_time_ns();
+
+            for _ in 0..ROUND_TRIPS {
+                ping.send(b"ping").unwrap();
+                ping.recv(&mut buf).unwrap();
+            }
+
+            let end = time::precise_time_ns();
+
+            pong_fut.join().unwrap();
+            (end - beg) as usize
+        })
+        .unwrap()
+}
+
+// Run this test with
+//   cargo bench --bench udp -- --csv
+// to get a parsable output.
+// The first column will contain the worker count and the second one the ns/round trip.
+fn main() {
+    let csv = std::env::args().any(|arg| arg == "--csv");
+
+    for i in 1..3 {
+        let duration = run_test(i);
+
+        if csv {
+            println!("{};{}", i, rdiv(duration, ROUND_TRIPS));
+        } else {
+            println!("{} Workers: {} round trips in {} ms => {} ns/round trip",
+                     i,
+                     ROUND_TRIPS,
+                     rdiv(duration, NS_PER_MS),
+                     rdiv(duration, ROUND_TRIPS));
+        }
+    }
+}
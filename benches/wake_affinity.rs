@@ -0,0 +1,105 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate coio;
+extern crate num_cpus;
+extern crate time;
+
+use std::io::{Read, Write};
+
+use coio::{Options, Scheduler, WakeAffinity};
+use coio::net::{TcpListener, TcpStream};
+
+const NS_PER_MS: usize = 1_000_000;
+const CLIENT_COUNT: usize = 64;
+const REQUEST_COUNT: usize = 1_000;
+
+// Amount of per connection state touched by the server on every request
+const STATE_SIZE: usize = 64 * 1024;
+
+#[inline]
+fn rdiv(a: usize, b: usize) -> usize {
+    (a + (b / 2)) / b
+}
+
+// Runs a request/response server whose connection handlers each work on their own state.
+// Every request wakes the handler up through the event loop, after which it might be resumed
+// on a different Processor than the one whose caches still hold it's state.
+fn run_test(worker_count: usize, affinity: WakeAffinity) -> usize {
+    Scheduler::new()
+        .with_workers(worker_count)
+        .run(move || {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+
+            let server = Scheduler::spawn(move || {
+                for _ in 0..CLIENT_COUNT {
+                    let (mut stream, _) = listener.accept().unwrap();
+
+                    let mut opts = Options::new();
+                    opts.wake_affinity(affinity);
+
+                    Scheduler::spawn_opts(move || {
+                                              let mut state = vec![0u8; STATE_SIZE];
+                                              let mut buf = [0u8; 1];
+
+                                              while let Ok(1) = stream.read(&mut buf) {
+                                                  for b in state.iter_mut() {
+                                                      *b = b.wrapping_add(buf[0]);
+                                                  }
+
+                                                  buf[0] = state[STATE_SIZE / 2];
+                                                  stream.write_all(&buf).unwrap();
+                                              }
+                                          },
+                                          opts);
+                }
+            });
+
+            let beg = time::precise_time_ns();
+
+            let clients: Vec<_> = (0..CLIENT_COUNT)
+                                      .map(|_| {
+                                          Scheduler::spawn(move || {
+                                              let mut stream = TcpStream::connect(addr).unwrap();
+                                              let mut buf = [1u8; 1];
+
+                                              for _ in 0..REQUEST_COUNT {
+                                                  stream.write_all(&buf).unwrap();
+                                                  stream.read_exact(&mut buf).unwrap();
+                                              }
+                                          })
+                                      })
+                                      .collect();
+
+            for h in clients {
+                h.join().unwrap();
+            }
+
+            let end = time::precise_time_ns();
+            server.join().unwrap();
+            (end - beg) as usize
+        })
+        .unwrap()
+}
+
+// Run this test with
+//   cargo bench --bench wake_affinity
+fn main() {
+    for i in 2..(num_cpus::get() + 1) {
+        for &(name, affinity) in &[("any", WakeAffinity::Any),
+                                   ("last processor", WakeAffinity::LastProcessor)] {
+            let duration = run_test(i, affinity);
+
+            println!("{} Workers, {}: {} ms",
+                     i,
+                     name,
+                     rdiv(duration, NS_PER_MS));
+        }
+    }
+}
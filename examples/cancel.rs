@@ -0,0 +1,57 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate env_logger;
+
+extern crate coio;
+
+use std::io::{ErrorKind, Write};
+use std::time::Duration;
+
+use coio::{Builder, CancellationToken, Scheduler};
+use coio::net::TcpListener;
+
+fn main() {
+    env_logger::init().unwrap();
+
+    Scheduler::new()
+        .run(|| {
+            let token = CancellationToken::new();
+
+            let server = Builder::new()
+                             .name("Acceptor".to_owned())
+                             .cancel_token(token.clone())
+                             .spawn(|| {
+                                 let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+                                 println!("Listening on {:?}", listener.local_addr().unwrap());
+
+                                 // accept() fails with Interrupted as soon as the token is cancelled
+                                 loop {
+                                     match listener.accept() {
+                                         Ok((mut stream, addr)) => {
+                                             println!("Accepted {:?}", addr);
+                                             let _ = stream.write_all(b"Hello\n");
+                                         }
+                                         Err(ref err) if err.kind() == ErrorKind::Interrupted => {
+                                             println!("Cancelled, stop accepting");
+                                             break;
+                                         }
+                                         Err(err) => panic!("accept() failed: {:?}", err),
+                                     }
+                                 }
+                             });
+
+            coio::sleep(Duration::from_secs(1));
+
+            println!("Cancelling the acceptor");
+            token.cancel();
+
+            server.join().unwrap();
+        })
+        .unwrap();
+}
@@ -0,0 +1,43 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#[macro_use]
+extern crate coio;
+
+use std::cell::Cell;
+
+use coio::Scheduler;
+
+coroutine_local!(static REQUEST_ID: Cell<usize> = Cell::new(0));
+
+fn log(msg: &str) {
+    REQUEST_ID.with(|id| println!("[request #{}] {}", id.get(), msg));
+}
+
+fn handle_request(id: usize) {
+    REQUEST_ID.with(|r| r.set(id));
+
+    log("started");
+    Scheduler::sched();
+    log("finished");
+}
+
+fn main() {
+    Scheduler::new()
+        .with_workers(2)
+        .run(|| {
+            let handles: Vec<_> = (1..5)
+                                      .map(|id| Scheduler::spawn(move || handle_request(id)))
+                                      .collect();
+
+            for h in handles {
+                h.join().unwrap();
+            }
+        })
+        .unwrap();
+}
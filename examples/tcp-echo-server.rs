@@ -50,8 +50,8 @@ fn main() {
             for stream in server.incoming() {
                 use std::io::{Read, Write};
 
-                let (mut stream, addr) = stream.unwrap();
-                info!("Accept connection: {:?}", addr);
+                let mut stream = stream.unwrap();
+                info!("Accept connection: {:?}", stream.peer_addr().unwrap());
 
                 Scheduler::spawn(move || {
                     let mut buf = [0; 1024 * 16];
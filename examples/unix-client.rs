@@ -0,0 +1,52 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate clap;
+extern crate env_logger;
+
+extern crate coio;
+
+use std::io::{self, BufRead, Read, Write};
+
+use clap::{Arg, App};
+
+use coio::Scheduler;
+use coio::net::unix::UnixStream;
+
+fn main() {
+    env_logger::init().unwrap();
+
+    let matches = App::new("unix-client")
+                      .version(env!("CARGO_PKG_VERSION"))
+                      .arg(Arg::with_name("PATH")
+                               .short("p")
+                               .long("path")
+                               .takes_value(true)
+                               .required(true)
+                               .help("Connect to this socket path"))
+                      .get_matches();
+
+    let path = matches.value_of("PATH").unwrap().to_owned();
+
+    Scheduler::new()
+        .run(move || {
+            let mut stream = UnixStream::connect(&path).unwrap();
+
+            // Sends every line from stdin and prints the echoed reply
+            let stdin = io::stdin();
+            for line in stdin.lock().lines() {
+                let line = line.unwrap();
+                stream.write_all(line.as_bytes()).unwrap();
+
+                let mut buf = vec![0u8; line.len()];
+                stream.read_exact(&mut buf).unwrap();
+                println!("{}", String::from_utf8_lossy(&buf));
+            }
+        })
+        .unwrap();
+}
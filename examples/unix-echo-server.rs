@@ -0,0 +1,80 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate clap;
+#[macro_use]
+extern crate log;
+extern crate env_logger;
+
+extern crate coio;
+
+use std::fs;
+
+use clap::{Arg, App};
+
+use coio::Scheduler;
+use coio::net::unix::UnixListener;
+
+fn main() {
+    env_logger::init().unwrap();
+
+    let matches = App::new("unix-echo")
+                      .version(env!("CARGO_PKG_VERSION"))
+                      .arg(Arg::with_name("PATH")
+                               .short("p")
+                               .long("path")
+                               .takes_value(true)
+                               .required(true)
+                               .help("Listening on this socket path"))
+                      .arg(Arg::with_name("THREADS")
+                               .short("t")
+                               .long("threads")
+                               .takes_value(true)
+                               .help("Number of threads"))
+                      .get_matches();
+
+    let path = matches.value_of("PATH").unwrap().to_owned();
+
+    Scheduler::new()
+        .with_workers(matches.value_of("THREADS").unwrap_or("1").parse().unwrap())
+        .run(move || {
+            // Remove a stale socket file from a previous run
+            let _ = fs::remove_file(&path);
+            let server = UnixListener::bind(&path).unwrap();
+
+            info!("Listening on {:?}", path);
+
+            loop {
+                use std::io::{Read, Write};
+
+                let mut stream = server.accept().unwrap();
+                info!("Accept connection");
+
+                Scheduler::spawn(move || {
+                    let mut buf = [0; 1024 * 16];
+
+                    loop {
+                        match stream.read(&mut buf) {
+                            Ok(0) => {
+                                debug!("EOF received, going to close");
+                                break;
+                            }
+                            Ok(len) => {
+                                info!("Read {} bytes, echo back!", len);
+                                stream.write_all(&buf[0..len]).unwrap();
+                            }
+                            Err(err) => {
+                                panic!("Error occurs: {:?}", err);
+                            }
+                        }
+                    }
+                });
+            }
+        })
+        .unwrap();
+}
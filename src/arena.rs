@@ -0,0 +1,214 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Processor local bump allocator
+//!
+//! Every Processor owns an `Arena`, which is lent to coroutines by `Scheduler::with_arena()`.
+//! Allocating from it only bumps an offset into a chunk of memory and nothing is ever freed
+//! individually. Instead the whole arena is reset once the Processor's local queue ran empty
+//! and no coroutine is inside of `with_arena()` anymore, even if it's parked in there.
+//! Since everything allocated lives at most until the end of the `with_arena()` call,
+//! it's well suited for request scoped scratch memory.
+
+use std::cmp;
+use std::mem;
+use std::slice;
+use std::str;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use sync::spinlock::Spinlock;
+
+const CHUNK_SIZE: usize = 16 * 1024;
+
+struct State {
+    chunks: Vec<Vec<u8>>,
+    // Offset into the last chunk
+    pos: usize,
+}
+
+/// A bump allocator, see the module documentation
+///
+/// Only `Copy` types can be allocated, since their destructors would never be run.
+pub struct Arena {
+    state: Spinlock<State>,
+    borrows: AtomicUsize,
+}
+
+impl Arena {
+    /// Creates an empty arena, which allocates it's first chunk on demand
+    pub fn new() -> Arena {
+        Arena {
+            state: Spinlock::new(State {
+                chunks: Vec::new(),
+                pos: 0,
+            }),
+            borrows: AtomicUsize::new(0),
+        }
+    }
+
+    /// Moves `value` into the arena
+    pub fn alloc<T: Copy>(&self, value: T) -> &mut T {
+        unsafe {
+            let ptr = self.alloc_raw(mem::size_of::<T>(), mem::align_of::<T>()) as *mut T;
+            *ptr = value;
+            &mut *ptr
+        }
+    }
+
+    /// Copies `values` into the arena
+    pub fn alloc_slice<T: Copy>(&self, values: &[T]) -> &mut [T] {
+        unsafe {
+            let size = mem::size_of::<T>() * values.len();
+            let ptr = self.alloc_raw(size, mem::align_of::<T>()) as *mut T;
+            let dst = slice::from_raw_parts_mut(ptr, values.len());
+            dst.copy_from_slice(values);
+            dst
+        }
+    }
+
+    /// Copies `s` into the arena
+    pub fn alloc_str(&self, s: &str) -> &str {
+        let bytes = self.alloc_slice(s.as_bytes());
+        unsafe { str::from_utf8_unchecked(bytes) }
+    }
+
+    /// Returns the number of bytes of the chunks currently held by the arena
+    pub fn capacity(&self) -> usize {
+        self.state.lock().chunks.iter().fold(0, |n, chunk| n + chunk.len())
+    }
+
+    #[doc(hidden)]
+    pub fn borrow(&self) -> ArenaBorrow {
+        self.borrows.fetch_add(1, Ordering::Acquire);
+        ArenaBorrow(self)
+    }
+
+    /// Drops all allocations unless the arena is borrowed, keeping the last chunk for reuse
+    ///
+    /// # Safety
+    ///
+    /// All allocations made without holding a `borrow()` must be dead.
+    #[doc(hidden)]
+    pub unsafe fn reset_if_unused(&self) {
+        if self.borrows.load(Ordering::Acquire) != 0 {
+            return;
+        }
+
+        let mut state = self.state.lock();
+
+        // with_arena() might have been entered since the check above
+        if self.borrows.load(Ordering::Acquire) != 0 {
+            return;
+        }
+
+        if let Some(last) = state.chunks.pop() {
+            state.chunks.clear();
+            state.chunks.push(last);
+        }
+        state.pos = 0;
+    }
+
+    fn alloc_raw(&self, size: usize, align: usize) -> *mut u8 {
+        let mut state = self.state.lock();
+
+        let offset = match state.chunks.last() {
+            Some(chunk) => {
+                let base = chunk.as_ptr() as usize;
+                let start = (base + state.pos + align - 1) & !(align - 1);
+
+                if start + size <= base + chunk.len() {
+                    Some(start - base)
+                } else {
+                    None
+                }
+            }
+            None => None,
+        };
+
+        let offset = match offset {
+            Some(offset) => offset,
+            None => {
+                // Chunks are never resized, which keeps the earlier allocations in place
+                let chunk = vec![0u8; cmp::max(CHUNK_SIZE, size + align)];
+                let base = chunk.as_ptr() as usize;
+                state.chunks.push(chunk);
+                ((base + align - 1) & !(align - 1)) - base
+            }
+        };
+
+        state.pos = offset + size;
+
+        let chunk = state.chunks.last_mut().unwrap();
+        unsafe { chunk.as_mut_ptr().offset(offset as isize) }
+    }
+}
+
+impl Default for Arena {
+    fn default() -> Arena {
+        Arena::new()
+    }
+}
+
+unsafe impl Send for Arena {}
+unsafe impl Sync for Arena {}
+
+// Keeps the arena from being reset, see Scheduler::with_arena()
+#[doc(hidden)]
+pub struct ArenaBorrow<'a>(&'a Arena);
+
+impl<'a> Drop for ArenaBorrow<'a> {
+    fn drop(&mut self) {
+        self.0.borrows.fetch_sub(1, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::mem;
+
+    use super::*;
+
+    #[test]
+    fn arena_alloc() {
+        let arena = Arena::new();
+
+        let a = arena.alloc(1u8);
+        let b = arena.alloc(2u64);
+        let s = arena.alloc_str("hello");
+        let v = arena.alloc_slice(&[1u32, 2, 3]);
+
+        assert_eq!(*a, 1);
+        assert_eq!(*b, 2);
+        assert_eq!(s, "hello");
+        assert_eq!(&*v, &[1, 2, 3]);
+        assert_eq!(b as *mut u64 as usize % mem::align_of::<u64>(), 0);
+        assert_eq!(v.as_ptr() as usize % mem::align_of::<u32>(), 0);
+
+        // Larger than a chunk
+        let big = arena.alloc_slice(&[7u8; 64 * 1024][..]);
+        assert_eq!(big.len(), 64 * 1024);
+        assert_eq!(*a, 1);
+    }
+
+    #[test]
+    fn arena_reset() {
+        let arena = Arena::new();
+
+        {
+            let _borrow = arena.borrow();
+            arena.alloc_slice(&[0u8; 32 * 1024][..]);
+            arena.alloc(0u8);
+
+            unsafe { arena.reset_if_unused() };
+            assert!(arena.capacity() > 32 * 1024);
+        }
+
+        unsafe { arena.reset_if_unused() };
+        assert_eq!(arena.capacity(), CHUNK_SIZE);
+    }
+}
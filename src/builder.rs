@@ -0,0 +1,175 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Builder for the most common Scheduler options
+//!
+//! `Scheduler::builder()` gathers the options nearly every application sets in one place and
+//! `build()` hands out the configured `Scheduler`, on which the more specialized `with_*()`
+//! methods can still be chained before calling `run()`:
+//!
+//! ```ignore
+//! Scheduler::builder()
+//!     .workers(4)
+//!     .stack_size(64 * 1024)
+//!     .build()
+//!     .with_steal_strategy(StealStrategy::LeastLoaded)
+//!     .run(|| {});
+//! ```
+//!
+//! All options are fixed once `run()` was called, except for the number of workers:
+//! `Scheduler::add_processor()` and `Scheduler::remove_processor()` change it at runtime,
+//! up to the limit set by `max_workers()`. `Scheduler::new()` remains the shortcut for
+//! a Scheduler with the default options.
+
+use std::any::Any;
+use std::time::Duration;
+
+use park::Parker;
+use scheduler::{PanicContext, Scheduler};
+
+/// Configures a `Scheduler`, see the module documentation
+pub struct SchedulerBuilder {
+    scheduler: Scheduler,
+}
+
+impl SchedulerBuilder {
+    /// Starts out with the defaults of `Scheduler::new()`
+    pub fn new() -> SchedulerBuilder {
+        SchedulerBuilder { scheduler: Scheduler::new() }
+    }
+
+    /// Starts out with the defaults of `Scheduler::single_threaded()`
+    pub fn single_threaded() -> SchedulerBuilder {
+        SchedulerBuilder { scheduler: Scheduler::single_threaded() }
+    }
+
+    /// See `Scheduler::with_workers()`
+    pub fn workers(mut self, workers: usize) -> SchedulerBuilder {
+        self.scheduler = self.scheduler.with_workers(workers);
+        self
+    }
+
+    /// See `Scheduler::with_max_workers()`
+    pub fn max_workers(mut self, max: usize) -> SchedulerBuilder {
+        self.scheduler = self.scheduler.with_max_workers(max);
+        self
+    }
+
+    /// See `Scheduler::default_stack_size()`
+    pub fn stack_size(mut self, stack_size: usize) -> SchedulerBuilder {
+        self.scheduler = self.scheduler.default_stack_size(stack_size);
+        self
+    }
+
+    /// See `Scheduler::with_seed()`
+    pub fn seed(mut self, seed: u64) -> SchedulerBuilder {
+        self.scheduler = self.scheduler.with_seed(seed);
+        self
+    }
+
+    /// See `Scheduler::with_parker()`
+    pub fn parker<P>(mut self, parker: P) -> SchedulerBuilder
+        where P: Parker + 'static
+    {
+        self.scheduler = self.scheduler.with_parker(parker);
+        self
+    }
+
+    /// See `Scheduler::with_park_backoff()`
+    pub fn park_backoff(mut self, spins: u32, yields: u32, timeout: Duration) -> SchedulerBuilder {
+        self.scheduler = self.scheduler.with_park_backoff(spins, yields, timeout);
+        self
+    }
+
+    /// See `Scheduler::with_yield_budget()`
+    pub fn yield_budget(mut self, budget: usize) -> SchedulerBuilder {
+        self.scheduler = self.scheduler.with_yield_budget(budget);
+        self
+    }
+
+    /// See `Scheduler::with_blocking_threads()`
+    pub fn blocking_threads(mut self, threads: usize) -> SchedulerBuilder {
+        self.scheduler = self.scheduler.with_blocking_threads(threads);
+        self
+    }
+
+    /// See `Scheduler::with_panic_handler()`
+    pub fn panic_handler<F>(mut self, handler: F) -> SchedulerBuilder
+        where F: Fn(&PanicContext, &(Any + Send)) + Send + Sync + 'static
+    {
+        self.scheduler = self.scheduler.with_panic_handler(handler);
+        self
+    }
+
+    /// Returns the configured Scheduler
+    pub fn build(self) -> Scheduler {
+        self.scheduler
+    }
+}
+
+impl Default for SchedulerBuilder {
+    fn default() -> SchedulerBuilder {
+        SchedulerBuilder::new()
+    }
+}
+
+impl Scheduler {
+    /// Returns a builder for the most common options, see `SchedulerBuilder`
+    pub fn builder() -> SchedulerBuilder {
+        SchedulerBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use scheduler::Scheduler;
+    use super::*;
+
+    #[test]
+    fn test_builder() {
+        let panics = Arc::new(AtomicUsize::new(0));
+
+        let mut scheduler = {
+            let panics = panics.clone();
+
+            Scheduler::builder()
+                .workers(2)
+                .max_workers(3)
+                .stack_size(64 * 1024)
+                .seed(7)
+                .blocking_threads(1)
+                .panic_handler(move |_, _| {
+                    panics.fetch_add(1, Ordering::SeqCst);
+                })
+                .build()
+        };
+
+        assert_eq!(scheduler.seed(), Some(7));
+
+        scheduler.run(|| {
+                     assert_eq!(Scheduler::spawn_blocking(|| 42), 42);
+                     assert!(Scheduler::spawn(|| panic!()).join().is_err());
+                     assert!(Scheduler::instance().unwrap().add_processor().is_some());
+                 })
+                 .unwrap();
+
+        assert_eq!(panics.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_builder_single_threaded() {
+        SchedulerBuilder::single_threaded()
+            .seed(1)
+            .build()
+            .run(|| {})
+            .unwrap();
+    }
+}
@@ -0,0 +1,454 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Cooperative cancellation of coroutines
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::ptr::Shared;
+use std::sync::Arc;
+
+use coroutine::Handle;
+use join_handle::JoinHandle;
+use options::Options;
+use runtime::Processor;
+use scheduler::{ReadyHandle, Scheduler};
+use sync::condvar::{Waiter, WaiterState};
+use sync::spinlock::Spinlock;
+
+struct TokenState {
+    cancelled: bool,
+
+    // Waiters of parked coroutines which observe this token,
+    // together with the Scheduler they have to be rescheduled on.
+    waiters: Vec<(Shared<Waiter>, ReadyHandle)>,
+}
+
+/// A token to ask coroutines to stop at their next blocking operation
+///
+/// Coroutines are associated with a token using `Options::cancel_token()`.
+/// Once the token is cancelled `Scheduler::is_cancelled()` returns true for them,
+/// and all of their I/O operations which are parked or would have to park
+/// fail with a `Cancelled` error, see `is_cancelled_error()`.
+/// Clones of a token share the same state.
+#[derive(Clone)]
+pub struct CancellationToken {
+    inner: Arc<Spinlock<TokenState>>,
+}
+
+unsafe impl Send for CancellationToken {}
+unsafe impl Sync for CancellationToken {}
+
+impl CancellationToken {
+    /// Create a token which isn't cancelled yet.
+    pub fn new() -> CancellationToken {
+        CancellationToken {
+            inner: Arc::new(Spinlock::new(TokenState {
+                cancelled: false,
+                waiters: Vec::new(),
+            })),
+        }
+    }
+
+    /// Cancel the token and wake up all coroutines parked in an operation observing it.
+    ///
+    /// May be called from any coroutine or thread. Cancelling a token twice has no effect.
+    pub fn cancel(&self) {
+        let mut woken = Vec::new();
+
+        {
+            let mut state = self.inner.lock();
+
+            if state.cancelled {
+                return;
+            }
+
+            state.cancelled = true;
+
+            // The waiters unregister themselves while holding the lock, so they are all still
+            // alive here, but not anymore once it's released.
+            for (waiter, ready_handle) in state.waiters.drain(..) {
+                let waiter = unsafe { &**waiter };
+
+                if let Some(hdl) = waiter.notify(WaiterState::Cancelled) {
+                    woken.push((hdl, ready_handle));
+                }
+            }
+        }
+
+        // Readied outside of the lock, since a coroutine which can't be scheduled anymore is
+        // dropped and unwinding it unregisters it's waiter
+        for (hdl, ready_handle) in woken {
+            match Processor::current() {
+                Some(mut p) => {
+                    if let Some(hdl) = hdl.wake() {
+                        p.ready(hdl);
+                    }
+                }
+                None => ready_handle.ready(hdl),
+            }
+        }
+    }
+
+    /// Returns true if `cancel()` was called.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.lock().cancelled
+    }
+
+    // Notifies `waiter` on cancellation until `unregister()` is called.
+    // If the token is already cancelled the waiter is notified immediately.
+    #[doc(hidden)]
+    pub fn register(&self, waiter: &Waiter, scheduler: &Scheduler) {
+        let mut state = self.inner.lock();
+
+        if state.cancelled {
+            let hdl = waiter.notify(WaiterState::Cancelled);
+            debug_assert!(hdl.is_none());
+            return;
+        }
+
+        let waiter = unsafe { Shared::new(waiter as *const _ as *mut _) };
+        state.waiters.push((waiter, scheduler.ready_handle()));
+    }
+
+    #[doc(hidden)]
+    pub fn unregister(&self, waiter: &Waiter) {
+        let ptr = waiter as *const Waiter;
+        self.inner.lock().waiters.retain(|&(w, _)| *w as *const Waiter != ptr);
+    }
+}
+
+impl Default for CancellationToken {
+    fn default() -> CancellationToken {
+        CancellationToken::new()
+    }
+}
+
+impl fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CancellationToken {{ cancelled: {} }}", self.is_cancelled())
+    }
+}
+
+struct GroupState {
+    // Number of members which haven't finished yet
+    count: usize,
+    // IDs of the members which started running and haven't finished yet
+    ids: Vec<u64>,
+}
+
+/// A set of related coroutines which can be cancelled all at once
+///
+/// Members are spawned with `spawn()` and share a `CancellationToken`, which `cancel_all()`
+/// cancels. Finished members leave the group on their own, even if they panicked.
+/// Coroutines spawned into an already cancelled group start out cancelled.
+/// Clones of a group share the same members.
+#[derive(Clone)]
+pub struct CoroutineGroup {
+    token: CancellationToken,
+    state: Arc<Spinlock<GroupState>>,
+}
+
+// Removes the current coroutine from it's group once it finishes
+struct GroupGuard {
+    state: Arc<Spinlock<GroupState>>,
+    id: Option<u64>,
+}
+
+impl Drop for GroupGuard {
+    fn drop(&mut self) {
+        let mut state = self.state.lock();
+        state.count -= 1;
+
+        if let Some(id) = self.id {
+            state.ids.retain(|&x| x != id);
+        }
+    }
+}
+
+impl CoroutineGroup {
+    /// Create an empty group
+    pub fn new() -> CoroutineGroup {
+        CoroutineGroup {
+            token: CancellationToken::new(),
+            state: Arc::new(Spinlock::new(GroupState {
+                count: 0,
+                ids: Vec::new(),
+            })),
+        }
+    }
+
+    /// Spawn a new member with the Scheduler's default options
+    pub fn spawn<F, T>(&self, f: F) -> JoinHandle<T>
+        where F: FnOnce() -> T + Send + 'static,
+              T: Send + 'static
+    {
+        let opts = Scheduler::instance().unwrap().default_spawn_options();
+        self.spawn_opts(f, opts)
+    }
+
+    /// Spawn a new member with options
+    ///
+    /// The cancellation token in `opts` is replaced by the one of the group.
+    pub fn spawn_opts<F, T>(&self, f: F, mut opts: Options) -> JoinHandle<T>
+        where F: FnOnce() -> T + Send + 'static,
+              T: Send + 'static
+    {
+        opts.cancel_token(self.token.clone());
+        self.state.lock().count += 1;
+
+        let state = self.state.clone();
+
+        Scheduler::spawn_opts(move || {
+                                  let id = Scheduler::current_id();
+                                  let _guard = GroupGuard {
+                                      state: state,
+                                      id: id,
+                                  };
+
+                                  if let Some(id) = id {
+                                      _guard.state.lock().ids.push(id);
+                                  }
+
+                                  f()
+                              },
+                              opts)
+    }
+
+    /// Cancel all current and future members, see `CancellationToken::cancel()`
+    pub fn cancel_all(&self) {
+        self.token.cancel();
+    }
+
+    /// Returns true if `cancel_all()` was called.
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Returns the number of members which haven't finished yet
+    pub fn len(&self) -> usize {
+        self.state.lock().count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the IDs (see `Scheduler::current_id()`) of the members which are running
+    ///
+    /// Members which were spawned, but didn't start running yet, are not included.
+    pub fn ids(&self) -> Vec<u64> {
+        self.state.lock().ids.clone()
+    }
+}
+
+impl Default for CoroutineGroup {
+    fn default() -> CoroutineGroup {
+        CoroutineGroup::new()
+    }
+}
+
+impl fmt::Debug for CoroutineGroup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "CoroutineGroup {{ len: {}, cancelled: {} }}",
+               self.len(),
+               self.is_cancelled())
+    }
+}
+
+/// The error of I/O operations failing because the `CancellationToken` was cancelled
+///
+/// It's wrapped in an `io::Error` of `ErrorKind::Other`. `ErrorKind::Interrupted` is retried
+/// by `read_exact()`, `write_all()` and the like, which would never give up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.description().fmt(f)
+    }
+}
+
+impl Error for Cancelled {
+    fn description(&self) -> &str {
+        "operation cancelled"
+    }
+}
+
+/// Returns true if `err` was caused by a cancelled `CancellationToken`
+pub fn is_cancelled_error(err: &io::Error) -> bool {
+    err.get_ref().map_or(false, |err| err.is::<Cancelled>())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Write;
+    use std::time::Duration;
+
+    use net::{TcpListener, TcpStream};
+    use options::Options;
+    use scheduler::Scheduler;
+
+    #[test]
+    fn cancel_parked_accept() {
+        Scheduler::new()
+            .run(|| {
+                let token = CancellationToken::new();
+                let mut opts = Options::new();
+                opts.cancel_token(token.clone());
+
+                let hdl = Scheduler::spawn_opts(|| {
+                                                    let listener = TcpListener::bind("127.0.0.1:0")
+                                                                       .unwrap();
+
+                                                    assert!(!Scheduler::is_cancelled());
+                                                    let err = listener.accept().unwrap_err();
+                                                    assert!(Scheduler::is_cancelled());
+                                                    is_cancelled_error(&err)
+                                                },
+                                                opts);
+
+                ::sleep(Duration::from_millis(100));
+                token.cancel();
+
+                assert!(hdl.join().unwrap());
+            })
+            .unwrap();
+    }
+
+    // The waiter of a coroutine force unwound while parked is removed from the token
+    #[test]
+    fn cancel_after_force_unwind() {
+        let token = CancellationToken::new();
+
+        {
+            let token = token.clone();
+
+            Scheduler::new()
+                .run(move || {
+                    let mut opts = Options::new();
+                    opts.cancel_token(token);
+
+                    Scheduler::spawn_opts(|| {
+                                              let listener = TcpListener::bind("127.0.0.1:0")
+                                                                 .unwrap();
+                                              let _ = listener.accept();
+                                          },
+                                          opts);
+
+                    ::sleep(Duration::from_millis(100));
+
+                    let timeout = Duration::from_millis(10);
+                    Scheduler::instance().unwrap().shutdown_with_timeout(timeout);
+                })
+                .unwrap();
+        }
+
+        assert!(token.inner.lock().waiters.is_empty());
+        token.cancel();
+    }
+
+    #[test]
+    fn cancel_before_wait() {
+        Scheduler::new()
+            .run(|| {
+                let token = CancellationToken::new();
+                token.cancel();
+
+                let mut opts = Options::new();
+                opts.cancel_token(token);
+
+                let hdl = Scheduler::spawn_opts(|| {
+                                                    let listener = TcpListener::bind("127.0.0.1:0")
+                                                                       .unwrap();
+                                                    is_cancelled_error(&listener.accept()
+                                                                                .unwrap_err())
+                                                },
+                                                opts);
+
+                assert!(hdl.join().unwrap());
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn cancel_group() {
+        Scheduler::new()
+            .with_workers(2)
+            .run(|| {
+                let group = CoroutineGroup::new();
+                let listener = ::std::sync::Arc::new(TcpListener::bind("127.0.0.1:0").unwrap());
+
+                let handles: Vec<_> = (0..4)
+                                          .map(|_| {
+                                              let listener = listener.clone();
+                                              let f = move || {
+                                                  is_cancelled_error(&listener.accept()
+                                                                              .unwrap_err())
+                                              };
+                                              group.spawn(f)
+                                          })
+                                          .collect();
+
+                // A member which finishes on it's own leaves the group
+                group.spawn(|| {}).join().unwrap();
+
+                ::sleep(Duration::from_millis(100));
+                assert_eq!(group.len(), 4);
+                assert_eq!(group.ids().len(), 4);
+
+                group.cancel_all();
+
+                for h in handles {
+                    assert!(h.join().unwrap());
+                }
+
+                assert!(group.is_empty());
+                assert!(group.ids().is_empty());
+
+                // Members spawned after the cancellation start out cancelled
+                let h = group.spawn(|| Scheduler::is_cancelled());
+                assert!(h.join().unwrap());
+            })
+            .unwrap();
+    }
+
+    // write_all() retries ErrorKind::Interrupted, so it would never return with it
+    #[test]
+    fn cancel_write_all() {
+        Scheduler::new()
+            .run(|| {
+                let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+                let addr = listener.local_addr().unwrap();
+
+                let token = CancellationToken::new();
+                let mut opts = Options::new();
+                opts.cancel_token(token.clone());
+
+                let hdl = Scheduler::spawn_opts(move || {
+                                                    let mut stream = TcpStream::connect(addr)
+                                                                         .unwrap();
+                                                    token.cancel();
+
+                                                    // The peer never reads, so this has to park
+                                                    let buf = vec![0u8; 16 * 1024 * 1024];
+                                                    is_cancelled_error(&stream.write_all(&buf)
+                                                                              .unwrap_err())
+                                                },
+                                                opts);
+
+                let (_peer, _) = listener.accept().unwrap();
+                assert!(hdl.join().unwrap());
+            })
+            .unwrap();
+    }
+}
@@ -6,18 +6,29 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use std::any::Any;
 use std::boxed::FnBox;
+use std::cmp;
+use std::collections::HashMap;
 use std::fmt;
 use std::mem;
 use std::ops::{Deref, DerefMut};
 use std::panic;
 use std::ptr::{self, Shared};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+use std::time::Instant;
 
 use context::{Context, Transfer};
 
+use cancel::CancellationToken;
 use runtime::processor::Processor;
 use runtime::stack_pool::{Stack, StackPool};
-use options::Options;
+use options::{Options, Priority, WakeAffinity};
+use scheduler::Scheduler;
+
+// Source of the IDs of all coroutines, starting at 1
+static NEXT_COROUTINE_ID: AtomicUsize = ATOMIC_USIZE_INIT;
 
 extern "C" fn coroutine_entry(t: Transfer) -> ! {
     // Take over the data from Coroutine::spawn_opts
@@ -28,13 +39,31 @@ extern "C" fn coroutine_entry(t: Transfer) -> ! {
 
     let mut coro = Coroutine {
         context: None,
+        id: NEXT_COROUTINE_ID.fetch_add(1, Ordering::Relaxed) as u64 + 1,
         name: None,
         state: State::Suspended,
+        priority: AtomicUsize::new(Priority::Normal as usize),
+        held_locks: AtomicUsize::new(0),
+        base_priority: AtomicUsize::new(Priority::Normal as usize),
+        locals: None,
+        deadline: None,
+        deadline_expired: false,
+        pinned_to: None,
+        fifo: false,
+        no_steal: false,
+        wake_affinity: WakeAffinity::Any,
+        last_processor: None,
+        cancel_token: None,
+        depth: 0,
+        budget: 0,
+        blocking_allowed: AtomicUsize::new(0),
+        wake_state: Arc::new(AtomicUsize::new(WAKE_PARKED)),
 
         prev: None,
         next: None,
 
         stack: Some(stack),
+        stack_painted: false,
     };
 
     {
@@ -52,6 +81,12 @@ extern "C" fn coroutine_entry(t: Transfer) -> ! {
             callback();
             trace!("{:?}: finished", coro);
         }));
+
+        // Coroutine locals are dropped while we are still running on the coroutine's stack.
+        // This way their destructors may still use coio's APIs.
+        let _ = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            coro.locals = None;
+        }));
     }
 
     coro.state = State::Finished;
@@ -73,6 +108,9 @@ extern "C" fn coroutine_exit(mut t: Transfer) -> Transfer {
 
     let stack = coro.stack.take();
 
+    // WakeHandles created for it's last park may outlive the struct
+    coro.set_wake_state(WAKE_FINISHED);
+
     trace!("{:?}: dropping struct", coro);
     unsafe { ptr::drop_in_place(coro) };
 
@@ -109,20 +147,56 @@ pub enum State {
     Suspended,
     Running,
     Parked,
+    Yielded,
     Finished,
     Dropping,
 }
 
+// The states of Coroutine::wake_state. New coroutines count as parked until they're first resumed.
+// The remaining bits count the parks, which tells WakeHandles of earlier ones apart.
+const WAKE_RUNNING: usize = 0;
+const WAKE_PARKED: usize = 1;
+const WAKE_SCHEDULED: usize = 2;
+const WAKE_FINISHED: usize = 3;
+const WAKE_STATE_MASK: usize = 3;
+const WAKE_GENERATION: usize = 4;
+
 /// Coroutine is nothing more than a context and a stack
 pub struct Coroutine {
     context: Option<Context>,
+    id: u64,
     name: Option<String>,
     state: State,
+    // Can be changed by other threads, see the priority inheritance of sync::Mutex
+    priority: AtomicUsize,
+    // Number of sync::Mutexes held and the priority before acquiring the first of them
+    held_locks: AtomicUsize,
+    base_priority: AtomicUsize,
+    locals: Option<HashMap<usize, Box<Any>>>,
+    deadline: Option<Instant>,
+    // Set once a wait timed out because the deadline passed, see Scheduler::with_deadline()
+    deadline_expired: bool,
+    pinned_to: Option<usize>,
+    fifo: bool,
+    no_steal: bool,
+    wake_affinity: WakeAffinity,
+    last_processor: Option<usize>,
+    cancel_token: Option<CancellationToken>,
+    // Number of coroutines it was spawned from, see Scheduler::current_depth()
+    depth: usize,
+    // Operations left until it's suspended, see Scheduler::consume_budget()
+    budget: usize,
+    // Nesting depth of Scheduler::allow_blocking(), read by the watchdog thread
+    blocking_allowed: AtomicUsize,
+    // Makes sure only the first of multiple wakers queues it, see try_wake().
+    // Shared with the WakeHandles, which may outlive the coroutine.
+    wake_state: Arc<AtomicUsize>,
 
     prev: Option<Shared<Coroutine>>,
     next: Option<Handle>,
 
     stack: Option<Stack>,
+    stack_painted: bool,
 }
 
 unsafe impl Send for Coroutine {}
@@ -133,26 +207,40 @@ impl Coroutine {
         trace!("Coroutine: spawning {:?}", opts);
 
         let data = InitData {
-            stack: StackPool::raw_allocate(opts.stack_size),
+            stack: StackPool::raw_allocate(opts.stack_size, opts.guard_page),
             callback: f,
         };
 
-        Coroutine::create_coroutine(data, opts)
+        let depth = Scheduler::current_depth().map_or(0, |depth| depth + 1);
+        Coroutine::create_coroutine(data, opts, depth)
     }
 
+    /// Like `spawn_opts()`, but allocates the stack from `pool`
+    ///
+    /// Callers are holding a `ProcessorHandle` for the pool and thus have to pass the
+    /// `depth` of the new coroutine, see `ProcessorHandle::spawn_depth()`.
     #[inline]
-    pub fn spawn_opts_with_pool(f: Box<FnBox()>, opts: Options, pool: &mut StackPool) -> Handle {
+    pub fn spawn_opts_with_pool(f: Box<FnBox()>,
+                                opts: Options,
+                                depth: usize,
+                                pool: &mut StackPool)
+                                -> Handle {
         trace!("Coroutine: spawning {:?}", opts);
 
         let data = InitData {
-            stack: pool.allocate(opts.stack_size),
+            stack: pool.allocate(opts.stack_size, opts.guard_page),
             callback: f,
         };
 
-        Coroutine::create_coroutine(data, opts)
+        Coroutine::create_coroutine(data, opts, depth)
     }
 
-    fn create_coroutine(data: InitData, opts: Options) -> Handle {
+    fn create_coroutine(mut data: InitData, opts: Options, depth: usize) -> Handle {
+        if opts.track_stack_usage {
+            // The stack has to be painted before the Context writes it's initial frame on it
+            unsafe { data.stack.paint() };
+        }
+
         let context = Context::new(&data.stack, coroutine_entry);
 
         // Give him the initialization data
@@ -167,6 +255,15 @@ impl Coroutine {
             coro_ref.set_name(name);
         }
 
+        coro_ref.set_priority(opts.priority);
+        coro_ref.pinned_to = opts.pinned_to;
+        coro_ref.fifo = opts.fifo;
+        coro_ref.no_steal = opts.no_steal;
+        coro_ref.wake_affinity = opts.wake_affinity;
+        coro_ref.cancel_token = opts.cancel_token;
+        coro_ref.stack_painted = opts.track_stack_usage;
+        coro_ref.depth = depth;
+
         ::global_work_count_add();
 
         // Done!
@@ -178,16 +275,255 @@ impl Coroutine {
         self.state
     }
 
+    /// Returns the number of bytes of the stack used by the coroutine
+    ///
+    /// This is the peak usage if it was spawned with `Options::track_stack_usage()`,
+    /// otherwise only the current one. It must only be called on the coroutine itself.
+    pub fn stack_usage(&self) -> usize {
+        let stack = self.stack.as_ref().expect("Coroutine without a stack");
+
+        // NOTE: Stacks grow downwards on all supported platforms, see Stack::high_water_mark()
+        let marker = 0u8;
+        let current = stack.top() as usize - &marker as *const u8 as usize;
+
+        if self.stack_painted {
+            cmp::max(current, stack.high_water_mark())
+        } else {
+            current
+        }
+    }
+
+    /// Unique ID of the coroutine, assigned when it is spawned
+    ///
+    /// It stays the same if the coroutine is moved to another Processor.
+    #[inline]
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
     #[inline]
     pub fn name(&self) -> Option<&str> {
         self.name.as_ref().map(String::as_str)
     }
 
+    /// Name identifying the coroutine in logs and panic messages, e.g. `Coroutine#42(worker)`
+    ///
+    /// It's made up of the ID and the name of the coroutine, if it has one.
+    pub fn debug_name(&self) -> String {
+        format!("{:?}", self)
+    }
+
     #[inline]
     pub fn set_name(&mut self, name: String) {
         self.name = Some(name);
     }
 
+    #[inline]
+    pub fn priority(&self) -> Priority {
+        match self.priority.load(Ordering::Relaxed) {
+            0 => Priority::High,
+            1 => Priority::Normal,
+            _ => Priority::Low,
+        }
+    }
+
+    /// Changes the priority used the next time the coroutine is queued
+    ///
+    /// This method *is* thread safe.
+    #[inline]
+    pub fn set_priority(&self, priority: Priority) {
+        self.priority.store(priority as usize, Ordering::Relaxed);
+    }
+
+    /// Called by `sync::Mutex` once the coroutine acquired it
+    #[inline]
+    pub fn lock_acquired(&self) {
+        if self.held_locks.fetch_add(1, Ordering::Relaxed) == 0 {
+            self.base_priority.store(self.priority.load(Ordering::Relaxed), Ordering::Relaxed);
+        }
+    }
+
+    /// Called by `sync::Mutex` once the coroutine released it
+    ///
+    /// The priority is raised by coroutines waiting for any of the Mutexes it holds.
+    /// It gets back the one it had before acquiring the first of them
+    /// only once it released all of them, so that no boost ends too early.
+    #[inline]
+    pub fn lock_released(&self) {
+        if self.held_locks.fetch_sub(1, Ordering::Relaxed) == 1 {
+            self.priority.store(self.base_priority.load(Ordering::Relaxed), Ordering::Relaxed);
+        }
+    }
+
+    /// Number of coroutines between this one and the root of it's spawn tree
+    ///
+    /// Coroutines which weren't spawned by another coroutine have a depth of 0.
+    #[inline]
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Refills the yield budget, which is done every time the coroutine is resumed
+    #[inline]
+    pub fn reset_budget(&mut self, budget: usize) {
+        self.budget = budget;
+    }
+
+    /// Counts an operation against the yield budget and returns true once it's used up
+    #[inline]
+    pub fn consume_budget(&mut self) -> bool {
+        if self.budget > 1 {
+            self.budget -= 1;
+            false
+        } else {
+            true
+        }
+    }
+
+    /// Marks the coroutine as blocking intentionally, see `Scheduler::allow_blocking()`
+    #[inline]
+    pub fn enter_blocking(&self) {
+        self.blocking_allowed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn leave_blocking(&self) {
+        self.blocking_allowed.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Returns true while the coroutine is inside of `Scheduler::allow_blocking()`
+    ///
+    /// This method *is* thread safe.
+    #[inline]
+    pub fn is_blocking_allowed(&self) -> bool {
+        self.blocking_allowed.load(Ordering::Relaxed) != 0
+    }
+
+    // Replaces the state of wake_state, keeping the generation
+    #[inline]
+    fn set_wake_state(&self, wake_state: usize) {
+        let generation = self.wake_state.load(Ordering::Relaxed) & !WAKE_STATE_MASK;
+        self.wake_state.store(generation | wake_state, Ordering::Release);
+    }
+
+    /// Called by the Processor before it resumes the coroutine
+    #[inline]
+    pub fn set_running(&self) {
+        self.set_wake_state(WAKE_RUNNING);
+    }
+
+    /// Called by the Processor once the coroutine parked, before it's Handle is handed out
+    ///
+    /// Every park starts a new generation, see `WakeHandle`.
+    #[inline]
+    pub fn set_parked(&self) {
+        let generation = self.wake_state.load(Ordering::Relaxed) & !WAKE_STATE_MASK;
+        let generation = generation.wrapping_add(WAKE_GENERATION);
+        self.wake_state.store(generation | WAKE_PARKED, Ordering::Release);
+    }
+
+    /// Moves a parked coroutine over to scheduled and returns true if it was parked
+    ///
+    /// Fails if a waker got to the coroutine first, see `Handle::wake()`.
+    #[inline]
+    pub fn try_wake(&self) -> bool {
+        let parked = self.wake_state.load(Ordering::Acquire);
+
+        if parked & WAKE_STATE_MASK != WAKE_PARKED {
+            return false;
+        }
+
+        let scheduled = parked & !WAKE_STATE_MASK | WAKE_SCHEDULED;
+        self.wake_state.compare_and_swap(parked, scheduled, Ordering::AcqRel) == parked
+    }
+
+    /// ID of the Processor this coroutine is pinned to
+    #[inline]
+    pub fn pinned_to(&self) -> Option<usize> {
+        self.pinned_to
+    }
+
+    /// Unpins the coroutine, e.g. because the Processor it was pinned to was removed
+    #[inline]
+    pub fn unpin(&mut self) {
+        self.pinned_to = None;
+    }
+
+    /// ID of the Processor a coroutine woken up by I/O or a timer should be queued on
+    ///
+    /// This is the Processor it is pinned to or, depending on `Options::wake_affinity()`,
+    /// the one it last ran on.
+    #[inline]
+    pub fn wake_processor(&self) -> Option<usize> {
+        if self.pinned_to.is_some() {
+            return self.pinned_to;
+        }
+
+        match self.wake_affinity {
+            WakeAffinity::LastProcessor => self.last_processor,
+            WakeAffinity::Any => None,
+        }
+    }
+
+    #[inline]
+    pub fn set_last_processor(&mut self, processor_id: usize) {
+        self.last_processor = Some(processor_id);
+    }
+
+    /// Pins the coroutine to the given Processor if it may not be stolen from it
+    ///
+    /// Does nothing unless it was spawned with `Options::no_steal()` and isn't pinned yet.
+    #[inline]
+    pub fn bind_to_processor(&mut self, processor_id: usize) {
+        if self.no_steal && self.pinned_to.is_none() {
+            self.pinned_to = Some(processor_id);
+        }
+    }
+
+    /// Returns true if the coroutine must never skip the queue, see `Options::fifo()`
+    #[inline]
+    pub fn is_fifo(&self) -> bool {
+        self.fifo
+    }
+
+    /// Token set by `Options::cancel_token()`
+    #[inline]
+    pub fn cancel_token(&self) -> Option<&CancellationToken> {
+        self.cancel_token.as_ref()
+    }
+
+    /// Storage for `coio::local::LocalKey`
+    #[inline]
+    pub fn locals_mut(&mut self) -> &mut HashMap<usize, Box<Any>> {
+        if self.locals.is_none() {
+            self.locals = Some(HashMap::new());
+        }
+
+        self.locals.as_mut().unwrap()
+    }
+
+    /// Deadline set by `Scheduler::with_timeout()`
+    #[inline]
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    #[inline]
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    /// Whether a wait timed out because the deadline passed
+    #[inline]
+    pub fn deadline_expired(&self) -> bool {
+        self.deadline_expired
+    }
+
+    #[inline]
+    pub fn set_deadline_expired(&mut self, expired: bool) {
+        self.deadline_expired = expired;
+    }
+
     #[doc(hidden)]
     #[inline]
     fn take_context(&mut self) -> Context {
@@ -231,13 +567,41 @@ impl Coroutine {
 
         data
     }
+
+    /// Yields the Coroutine back to whoever resumed it, passing `value` along
+    ///
+    /// The value stays in a slot on this Coroutine's stack until it is taken out
+    /// by `resume_with_yield()` on the other side.
+    #[doc(hidden)]
+    pub fn yield_value<T>(&mut self, value: T) {
+        let mut slot = Some(value);
+        self.yield_with(State::Yielded, &mut slot as *mut Option<T> as usize);
+    }
+
+    /// Resume the Coroutine and take the value passed to `yield_value()`
+    ///
+    /// Returns None if the Coroutine finished instead of yielding a value.
+    ///
+    /// # Safety
+    ///
+    /// `T` must be the exact type the Coroutine is passing to `yield_value()`.
+    #[doc(hidden)]
+    pub unsafe fn resume_with_yield<T>(&mut self) -> Option<T> {
+        let data = self.resume(0);
+
+        if self.state == State::Yielded {
+            (&mut *(data as *mut Option<T>)).take()
+        } else {
+            None
+        }
+    }
 }
 
 impl fmt::Debug for Coroutine {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self.name {
-            Some(ref name) => write!(f, "Coroutine({})", name),
-            None => write!(f, "Coroutine({:p})", self),
+            Some(ref name) => write!(f, "Coroutine#{}({})", self.id, name),
+            None => write!(f, "Coroutine#{}", self.id),
         }
     }
 }
@@ -270,6 +634,74 @@ impl Handle {
         assert!(coro != 0 as *mut _, "must be a non-zero pointer");
         Handle(&mut *coro)
     }
+
+    /// Returns the Handle if this waker is the first one to wake the coroutine
+    ///
+    /// Every entry point queueing parked coroutines goes through this exactly once.
+    /// If another waker got to the coroutine first, this Handle is merely an alias of the one
+    /// which was queued already and it's forgotten instead of being dropped.
+    /// Wakers which might come late, after the coroutine was resumed already,
+    /// must hold a `WakeHandle` instead.
+    #[doc(hidden)]
+    pub fn wake(self) -> Option<Handle> {
+        if self.try_wake() {
+            Some(self)
+        } else {
+            trace!("{:?}: ignoring duplicate wakeup", self);
+            mem::forget(self);
+            None
+        }
+    }
+
+    /// Turns the Handle of a parked coroutine into a `WakeHandle` for racing wakers
+    #[doc(hidden)]
+    pub fn into_wake_handle(self) -> WakeHandle {
+        let state = self.wake_state.clone();
+        let generation = state.load(Ordering::Acquire);
+
+        debug_assert!(generation & WAKE_STATE_MASK == WAKE_PARKED,
+                      "only parked coroutines can be woken up");
+
+        WakeHandle {
+            coro: self.into_raw(),
+            state: state,
+            generation: generation & !WAKE_STATE_MASK,
+        }
+    }
+}
+
+/// Wakes up a parked coroutine, which multiple wakers race for, e.g. a timer and an I/O event
+///
+/// Every waker gets a clone of it. The first one to call `wake()` gets back the Handle,
+/// the others get None, just like the ones coming late: The state is shared with the coroutine
+/// and outlives it, so that wakers never touch the coroutine once it was resumed or finished.
+/// If all of them are dropped without calling `wake()` the coroutine is leaked.
+#[doc(hidden)]
+#[derive(Clone)]
+pub struct WakeHandle {
+    coro: *mut Coroutine,
+    state: Arc<AtomicUsize>,
+    generation: usize,
+}
+
+unsafe impl Send for WakeHandle {}
+
+impl WakeHandle {
+    /// Returns the Handle if the coroutine is still in the park this was created for
+    /// and no other waker got to it first
+    pub fn wake(self) -> Option<Handle> {
+        // Starting the next generation invalidates all other WakeHandles,
+        // while the coroutine still counts as parked for Scheduler::ready()
+        let parked = self.generation | WAKE_PARKED;
+        let next = self.generation.wrapping_add(WAKE_GENERATION) | WAKE_PARKED;
+
+        if self.state.compare_and_swap(parked, next, Ordering::AcqRel) == parked {
+            Some(unsafe { Handle::from_raw(self.coro) })
+        } else {
+            trace!("Coroutine: ignoring stale wakeup");
+            None
+        }
+    }
 }
 
 unsafe impl Send for Handle {}
@@ -622,9 +1054,11 @@ mod test {
     use std::mem;
     use std::sync::{Arc, Mutex};
     use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
 
-    use options::Options;
+    use options::{Options, WakeAffinity};
     use scheduler::Scheduler;
+    use sync::mpsc;
     use super::*;
 
     #[test]
@@ -634,6 +1068,25 @@ mod test {
         assert_eq!(mem::size_of::<Option<Handle>>(), size);
     }
 
+    #[test]
+    fn coroutine_debug_name() {
+        Scheduler::new()
+            .run(|| {
+                let mut opts = Options::new();
+                opts.name("worker".to_owned());
+
+                let h = Scheduler::spawn_opts(|| {
+                    let mut p = Processor::current_required();
+                    let coro = p.current().unwrap();
+                    (coro.id(), coro.debug_name())
+                }, opts);
+
+                let (id, name) = h.join().unwrap();
+                assert_eq!(name, format!("Coroutine#{}(worker)", id));
+            })
+            .unwrap();
+    }
+
     #[test]
     fn coroutine_unwinds_on_drop() {
         let shared_usize = Arc::new(AtomicUsize::new(0));
@@ -694,4 +1147,75 @@ mod test {
         };
         let _ = Coroutine::spawn_opts(Box::new(f), opts);
     }
+
+    #[test]
+    fn coroutine_unwinds_on_shutdown() {
+        struct DropCheck(Arc<AtomicUsize>);
+
+        impl Drop for DropCheck {
+            fn drop(&mut self) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let dropped = Arc::new(AtomicUsize::new(0));
+
+        {
+            let dropped = dropped.clone();
+
+            Scheduler::new()
+                .run(move || {
+                    let (tx, rx) = mpsc::channel::<()>();
+
+                    // Parked on the channel until the shutdown
+                    let check = DropCheck(dropped.clone());
+                    Scheduler::spawn(move || {
+                        let _check = check;
+                        let _ = rx.recv();
+                    });
+
+                    // Keeps the Sender alive until it's force unwound during the shutdown,
+                    // which readies the receiving coroutine above while the Processor is
+                    // already dropping it's coroutines.
+                    let check = DropCheck(dropped.clone());
+                    Scheduler::spawn(move || {
+                        let _check = check;
+                        let _tx = tx;
+                        ::sleep(Duration::from_secs(3600));
+                    });
+
+                    Scheduler::sched();
+                })
+                .unwrap();
+        }
+
+        assert_eq!(dropped.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn coroutine_wake_processor() {
+        Scheduler::new()
+            .run(|| {
+                let spawn = |affinity, pinned_to| {
+                    let mut opts = Options::new();
+                    opts.wake_affinity(affinity);
+
+                    if let Some(id) = pinned_to {
+                        opts.pinned_to(id);
+                    }
+
+                    let mut coro = Coroutine::spawn_opts(Box::new(|| {}), opts);
+                    coro.set_last_processor(3);
+                    coro.wake_processor()
+                };
+
+                assert_eq!(spawn(WakeAffinity::Any, None), None);
+                assert_eq!(spawn(WakeAffinity::LastProcessor, None), Some(3));
+
+                // Pinned coroutines always return to their Processor
+                assert_eq!(spawn(WakeAffinity::Any, Some(1)), Some(1));
+                assert_eq!(spawn(WakeAffinity::LastProcessor, Some(1)), Some(1));
+            })
+            .unwrap();
+    }
 }
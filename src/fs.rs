@@ -0,0 +1,232 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! File I/O which doesn't block the Processor
+//!
+//! Regular files are always "ready" for epoll and friends, which is why a read or write
+//! might still block for a long time, e.g. on a slow disk. All operations are thus run
+//! on the blocking thread pool (see `Scheduler::spawn_blocking()`), while the calling
+//! coroutine is parked and the Processor keeps on running other coroutines.
+
+use std::boxed::FnBox;
+use std::fmt;
+use std::fs::{self, Metadata, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::Path;
+
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use scheduler::Scheduler;
+
+// The amount of data read_to_end() reads with a single call to the thread pool
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// A file whose operations are run on the blocking thread pool
+///
+/// Each call to `read()`, `write()` etc. is a single operation on the thread pool and
+/// parks the calling coroutine until it has finished. The provided methods of `Read` and
+/// `Write`, like `read_to_end()` and `write_all()`, loop over those calls.
+///
+/// # Ordering
+///
+/// Since a coroutine waits for each operation, all operations of the same coroutine are
+/// performed in program order. Operations of different coroutines on clones of the same
+/// `File` (see `try_clone()`) share the file's cursor and are performed in an unspecified order.
+/// In particular the chunks written by concurrent `write_all()` calls might be interleaved.
+/// Synchronize them, e.g. with a `coio::sync::Mutex`, or open the file in append mode and
+/// write each record with a single `write()`, if their order matters.
+pub struct File {
+    inner: fs::File,
+}
+
+impl File {
+    /// Opens a file in read-only mode, see `std::fs::File::open()`
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<File> {
+        let path = path.as_ref();
+        File::wrap(run_blocking(move || fs::File::open(path)))
+    }
+
+    /// Opens a file in write-only mode, creating or truncating it, see `std::fs::File::create()`
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<File> {
+        let path = path.as_ref();
+        File::wrap(run_blocking(move || fs::File::create(path)))
+    }
+
+    /// Opens a file with the given options, see `std::fs::OpenOptions::open()`
+    pub fn open_with<P: AsRef<Path>>(path: P, options: &OpenOptions) -> io::Result<File> {
+        let path = path.as_ref();
+        File::wrap(run_blocking(move || options.open(path)))
+    }
+
+    /// Wraps a file opened with `std::fs`
+    pub fn from_std(file: fs::File) -> File {
+        File { inner: file }
+    }
+
+    /// Unwraps the underlying `std::fs::File`
+    pub fn into_std(self) -> fs::File {
+        self.inner
+    }
+
+    /// Queries the metadata of the file
+    pub fn metadata(&self) -> io::Result<Metadata> {
+        let inner = &self.inner;
+        run_blocking(move || inner.metadata())
+    }
+
+    /// Truncates or extends the file to `size` bytes
+    pub fn set_len(&self, size: u64) -> io::Result<()> {
+        let inner = &self.inner;
+        run_blocking(move || inner.set_len(size))
+    }
+
+    /// Flushes all data and metadata to the disk
+    pub fn sync_all(&self) -> io::Result<()> {
+        let inner = &self.inner;
+        run_blocking(move || inner.sync_all())
+    }
+
+    /// Flushes all data, but not necessarily the metadata, to the disk
+    pub fn sync_data(&self) -> io::Result<()> {
+        let inner = &self.inner;
+        run_blocking(move || inner.sync_data())
+    }
+
+    /// Creates a new handle for the same file, which shares the cursor with this one
+    pub fn try_clone(&self) -> io::Result<File> {
+        let inner = &self.inner;
+        File::wrap(run_blocking(move || inner.try_clone()))
+    }
+
+    fn wrap(file: io::Result<fs::File>) -> io::Result<File> {
+        file.map(File::from_std)
+    }
+}
+
+impl Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let inner = &mut self.inner;
+        run_blocking(move || inner.read(buf))
+    }
+
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        let start = buf.len();
+
+        loop {
+            let len = buf.len();
+            buf.resize(len + READ_CHUNK_SIZE, 0);
+
+            let ret = {
+                let inner = &mut self.inner;
+                let chunk = &mut buf[len..];
+                run_blocking(move || inner.read(chunk))
+            };
+
+            match ret {
+                Ok(0) => {
+                    buf.truncate(len);
+                    return Ok(len - start);
+                }
+                Ok(n) => buf.truncate(len + n),
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => buf.truncate(len),
+                Err(err) => {
+                    buf.truncate(len);
+                    return Err(err);
+                }
+            }
+        }
+    }
+}
+
+impl Write for File {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let inner = &mut self.inner;
+        run_blocking(move || inner.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let inner = &mut self.inner;
+        run_blocking(move || inner.flush())
+    }
+}
+
+impl Seek for File {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let inner = &mut self.inner;
+        run_blocking(move || inner.seek(pos))
+    }
+}
+
+impl fmt::Debug for File {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "File({:?})", self.inner)
+    }
+}
+
+#[cfg(unix)]
+impl AsRawFd for File {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+// Runs `f`, which may borrow from the current stack, on the blocking thread pool
+fn run_blocking<'a, F, T>(f: F) -> T
+    where F: FnOnce() -> T + Send + 'a,
+          T: Send + 'static
+{
+    let f: Box<FnBox() -> T + Send + 'a> = Box::new(f);
+
+    // Safe since spawn_blocking() doesn't return before `f` has finished
+    let f: Box<FnBox() -> T + Send + 'static> = unsafe { mem::transmute(f) };
+
+    Scheduler::spawn_blocking(move || f())
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+    use std::io::{Read, Seek, SeekFrom, Write};
+
+    use scheduler::Scheduler;
+    use super::File;
+
+    #[test]
+    fn test_file_read_write() {
+        let path = env::temp_dir().join("coio-test-file-read-write");
+        let cloned = path.clone();
+
+        Scheduler::new()
+            .run(move || {
+                let data: Vec<u8> = (0..200_000).map(|i| i as u8).collect();
+
+                {
+                    let mut file = File::create(&cloned).unwrap();
+                    file.write_all(&data).unwrap();
+                    file.sync_all().unwrap();
+                    assert_eq!(file.metadata().unwrap().len(), data.len() as u64);
+                }
+
+                let mut file = File::open(&cloned).unwrap();
+                let mut buf = Vec::new();
+                assert_eq!(file.read_to_end(&mut buf).unwrap(), data.len());
+                assert!(buf == data);
+
+                file.seek(SeekFrom::Start(10)).unwrap();
+                let mut buf = [0u8; 4];
+                file.read_exact(&mut buf).unwrap();
+                assert_eq!(&buf, &data[10..14]);
+            })
+            .unwrap();
+
+        fs::remove_file(path).unwrap();
+    }
+}
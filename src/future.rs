@@ -0,0 +1,279 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Compatibility with the `futures` crate
+//!
+//! This module is only available with the `futures` feature enabled.
+//!
+//! `CoioFuture` allows futures executors to poll the result of a coroutine, while
+//! `Scheduler::await_future()` drives a future to completion on a coroutine. Neither of them
+//! is an executor on it's own: A future awaited by a coroutine is polled by that coroutine,
+//! which is readied again once the future's task is unparked, no matter on which thread.
+
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::thread;
+
+use futures::{Async, Future, Poll};
+use futures::executor::{self, Unpark};
+use futures::task::{self, Task};
+
+use coroutine::{ForceUnwind, Handle};
+use scheduler::{ReadyHandle, Scheduler, SchedulerHandle};
+use sync::spinlock::Spinlock;
+
+struct FutureState<T> {
+    result: Option<thread::Result<T>>,
+    task: Option<Task>,
+}
+
+/// A `Future` resolving to the result of a coroutine
+///
+/// A panic of the coroutine resolves it with the panic's payload as the error.
+pub struct CoioFuture<T> {
+    state: Arc<Spinlock<FutureState<T>>>,
+}
+
+impl<T: Send + 'static> CoioFuture<T> {
+    /// Spawns `f` as a new coroutine on the current Scheduler
+    ///
+    /// Outside of a coroutine use `spawn_with()` instead.
+    pub fn spawn<F>(f: F) -> CoioFuture<T>
+        where F: FnOnce() -> T + Send + 'static
+    {
+        let state = CoioFuture::new_state();
+
+        {
+            let state = state.clone();
+            Scheduler::spawn(move || run_coroutine(state, f));
+        }
+
+        CoioFuture { state: state }
+    }
+
+    /// Spawns `f` as a new coroutine through `handle`, e.g. from a thread running an executor
+    ///
+    /// Fails and returns `f` if the Scheduler isn't running, see `SchedulerHandle::spawn()`.
+    pub fn spawn_with<F>(handle: &SchedulerHandle, f: F) -> Result<CoioFuture<T>, F>
+        where F: FnOnce() -> T + Send + 'static
+    {
+        let state = CoioFuture::new_state();
+
+        // SchedulerHandle::spawn() only returns the closure wrapping `f`
+        let slot = Arc::new(Spinlock::new(Some(f)));
+
+        let ret = {
+            let state = state.clone();
+            let slot = slot.clone();
+
+            handle.spawn(move || {
+                let f = slot.lock().take().unwrap();
+                run_coroutine(state, f)
+            })
+        };
+
+        match ret {
+            Ok(_) => Ok(CoioFuture { state: state }),
+            Err(job) => {
+                drop(job);
+                let f = slot.lock().take().unwrap();
+                Err(f)
+            }
+        }
+    }
+
+    fn new_state() -> Arc<Spinlock<FutureState<T>>> {
+        Arc::new(Spinlock::new(FutureState {
+            result: None,
+            task: None,
+        }))
+    }
+}
+
+impl<T> Future for CoioFuture<T> {
+    type Item = T;
+    type Error = Box<Any + Send>;
+
+    fn poll(&mut self) -> Poll<T, Box<Any + Send>> {
+        let mut state = self.state.lock();
+
+        match state.result.take() {
+            Some(Ok(value)) => Ok(Async::Ready(value)),
+            Some(Err(err)) => Err(err),
+            None => {
+                state.task = Some(task::park());
+                Ok(Async::NotReady)
+            }
+        }
+    }
+}
+
+fn run_coroutine<F, T>(state: Arc<Spinlock<FutureState<T>>>, f: F)
+    where F: FnOnce() -> T
+{
+    let ret = match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Err(err) => {
+            // The coroutine is dropped by the Scheduler shutting down and must keep on unwinding
+            if err.is::<ForceUnwind>() {
+                complete(&state, Err(Box::new("coroutine was dropped")));
+                panic::resume_unwind(err);
+            }
+
+            Err(err)
+        }
+        ret => ret,
+    };
+
+    complete(&state, ret);
+}
+
+fn complete<T>(state: &Spinlock<FutureState<T>>, ret: thread::Result<T>) {
+    let task = {
+        let mut state = state.lock();
+        state.result = Some(ret);
+        state.task.take()
+    };
+
+    if let Some(task) = task {
+        task.unpark();
+    }
+}
+
+struct UnparkState {
+    notified: bool,
+    coro: Option<Handle>,
+}
+
+// Readies the coroutine awaiting a future, see Scheduler::await_future()
+struct CoroutineUnpark {
+    state: Spinlock<UnparkState>,
+    ready: ReadyHandle,
+}
+
+impl Unpark for CoroutineUnpark {
+    fn unpark(&self) {
+        let hdl = {
+            let mut state = self.state.lock();
+
+            match state.coro.take() {
+                Some(hdl) => hdl,
+                None => {
+                    // The coroutine is still polling, so it has to poll once more
+                    state.notified = true;
+                    return;
+                }
+            }
+        };
+
+        self.ready.ready(hdl);
+    }
+}
+
+impl Scheduler {
+    /// Drives `fut` to completion, parking the current coroutine while it isn't ready
+    ///
+    /// The future is polled on the coroutine itself and whoever unparks it's task, e.g. a reactor
+    /// running on another thread, readies the coroutine through the Scheduler again.
+    /// Outside of a coroutine the current thread is blocked instead.
+    pub fn await_future<F: Future>(fut: F) -> Result<F::Item, F::Error> {
+        let scheduler = match Scheduler::instance() {
+            Some(scheduler) if ::in_coroutine() => scheduler,
+            _ => return executor::spawn(fut).wait_future(),
+        };
+
+        let unpark = Arc::new(CoroutineUnpark {
+            state: Spinlock::new(UnparkState {
+                notified: false,
+                coro: None,
+            }),
+            ready: scheduler.ready_handle(),
+        });
+
+        let mut spawn = executor::spawn(fut);
+
+        loop {
+            if let Async::Ready(value) = try!(spawn.poll_future(unpark.clone())) {
+                return Ok(value);
+            }
+
+            Scheduler::park_with(|p, coro| {
+                let mut state = unpark.state.lock();
+
+                if state.notified {
+                    state.notified = false;
+                    drop(state);
+                    p.ready(coro);
+                } else {
+                    state.coro = Some(coro);
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+    use std::time::Duration;
+
+    use futures;
+    use futures::executor;
+
+    use scheduler::Scheduler;
+    use super::*;
+
+    #[test]
+    fn test_await_coio_future() {
+        Scheduler::new()
+            .with_workers(2)
+            .run(|| {
+                let fut = CoioFuture::spawn(|| {
+                    ::sleep_ms(10);
+                    42
+                });
+
+                assert_eq!(Scheduler::await_future(fut).unwrap(), 42);
+                assert!(Scheduler::await_future(CoioFuture::spawn(|| panic!())).is_err());
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_await_foreign_future() {
+        Scheduler::new()
+            .run(|| {
+                let (tx, rx) = futures::oneshot();
+
+                thread::spawn(move || {
+                    thread::sleep(Duration::from_millis(10));
+                    tx.complete(5);
+                });
+
+                assert_eq!(Scheduler::await_future(rx).unwrap(), 5);
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_poll_from_executor() {
+        Scheduler::new()
+            .with_workers(2)
+            .run(|| {
+                let handle = Scheduler::instance().unwrap().handle();
+
+                let t = thread::spawn(move || {
+                    let fut = CoioFuture::spawn_with(&handle, || 6 * 7).ok().unwrap();
+                    executor::spawn(fut).wait_future().ok().unwrap()
+                });
+
+                assert_eq!(Scheduler::spawn_blocking(move || t.join().unwrap()), 42);
+            })
+            .unwrap();
+    }
+}
@@ -0,0 +1,184 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Generators yielding values back to whoever resumes them
+
+use std::cell::Cell;
+use std::marker::PhantomData;
+use std::ptr;
+use std::rc::Rc;
+
+use coroutine::{Coroutine, Handle};
+use options::Options;
+use runtime::processor::Processor;
+
+/// A coroutine which is resumed directly by its owner instead of the Scheduler
+///
+/// Every call to `resume()` runs the generator until it passes the next value to
+/// `Yielder::yield_value()` or returns. Dropping an unfinished generator unwinds its stack.
+///
+/// The body runs on the thread of its owner and thus must not block on any of
+/// coio's primitives (sleeping, I/O, channels, ...), since these would park the owner.
+/// Neither the body nor the values have to be `Send`, so a generator is bound to the
+/// thread it was created on.
+pub struct Generator<T: 'static> {
+    coro: Handle,
+    // Makes it !Send and !Sync, since the Handle alone would be Send
+    _marker: PhantomData<*const T>,
+}
+
+impl<T: 'static> Generator<T> {
+    /// Create a new generator with the default options
+    pub fn new<F>(f: F) -> Generator<T>
+        where F: FnOnce(&Yielder<T>) + 'static
+    {
+        Generator::new_opts(f, Options::new())
+    }
+
+    /// Create a new generator with options
+    pub fn new_opts<F>(f: F, opts: Options) -> Generator<T>
+        where F: FnOnce(&Yielder<T>) + 'static
+    {
+        // The Coroutine doesn't exist before it's spawned, so the body is
+        // told about it through this slot before it is resumed for the first time.
+        let slot = Rc::new(Cell::new(ptr::null_mut()));
+
+        let callback = {
+            let slot = slot.clone();
+
+            move || {
+                let yielder = Yielder {
+                    coro: slot.get(),
+                    _marker: PhantomData,
+                };
+
+                f(&yielder)
+            }
+        };
+
+        let mut coro = match Processor::current() {
+            Some(mut p) => {
+                let depth = p.spawn_depth();
+                Coroutine::spawn_opts_with_pool(Box::new(callback), opts, depth, p.stack_pool())
+            }
+            None => Coroutine::spawn_opts(Box::new(callback), opts),
+        };
+
+        slot.set(&mut *coro as *mut Coroutine);
+
+        Generator {
+            coro: coro,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Run the generator until it yields the next value
+    ///
+    /// Returns None once the generator has finished.
+    pub fn resume(&mut self) -> Option<T> {
+        if self.coro.is_finished() {
+            return None;
+        }
+
+        unsafe { self.coro.resume_with_yield() }
+    }
+
+    /// Check if the generator has finished
+    pub fn is_finished(&self) -> bool {
+        self.coro.is_finished()
+    }
+}
+
+impl<T: 'static> Iterator for Generator<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.resume()
+    }
+}
+
+/// Passed into the body of a `Generator` to yield values back to its owner
+pub struct Yielder<T> {
+    coro: *mut Coroutine,
+    _marker: PhantomData<T>,
+}
+
+impl<T> Yielder<T> {
+    /// Suspend the generator and hand `value` over to the caller of `Generator::resume()`
+    pub fn yield_value(&self, value: T) {
+        let coro = unsafe { &mut *self.coro };
+        coro.yield_value(value);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use scheduler::Scheduler;
+    use super::*;
+
+    #[test]
+    fn test_generator_basic() {
+        Scheduler::new()
+            .run(|| {
+                let fib = Generator::new(|y| {
+                    let (mut a, mut b) = (0, 1);
+
+                    for _ in 0..10 {
+                        y.yield_value(a);
+
+                        let c = a + b;
+                        a = b;
+                        b = c;
+                    }
+                });
+
+                let values: Vec<u32> = fib.collect();
+                assert_eq!(values, vec![0, 1, 1, 2, 3, 5, 8, 13, 21, 34]);
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_generator_unwinds_on_drop() {
+        struct Flag(Arc<AtomicBool>);
+
+        impl Drop for Flag {
+            fn drop(&mut self) {
+                self.0.store(true, Ordering::SeqCst);
+            }
+        }
+
+        Scheduler::new()
+            .run(|| {
+                let dropped = Arc::new(AtomicBool::new(false));
+
+                let mut gen = {
+                    let flag = Flag(dropped.clone());
+
+                    Generator::new(move |y| {
+                        let _flag = flag;
+
+                        loop {
+                            y.yield_value(());
+                        }
+                    })
+                };
+
+                assert_eq!(gen.resume(), Some(()));
+                assert_eq!(gen.resume(), Some(()));
+                assert!(!dropped.load(Ordering::SeqCst));
+
+                drop(gen);
+                assert!(dropped.load(Ordering::SeqCst));
+            })
+            .unwrap();
+    }
+}
@@ -0,0 +1,288 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Buffered I/O for coroutines
+//!
+//! `BufReader` and `BufWriter` wrap any reader or writer, but are meant for the sockets in
+//! `coio::net`: The underlying `read()` and `write()` are only called once the buffer has run
+//! empty or full respectively, which parks the calling coroutine (and not the Processor)
+//! until the socket is ready. Everything else is served from the buffer without parking.
+//!
+//! Unlike `std::io::BufWriter` the `BufWriter` doesn't flush on drop while the current
+//! coroutine is unwinding, since flushing might park it. This is the case if the coroutine
+//! panicked or is forcefully unwound during the shutdown of the Scheduler.
+
+use std::cmp;
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+use std::thread;
+
+const DEFAULT_BUF_SIZE: usize = 8 * 1024;
+
+/// Adds buffering to a reader, see the module documentation
+///
+/// Together with the provided methods of `BufRead`, like `read_line()` and `read_until()`,
+/// this is well suited for line based protocols.
+pub struct BufReader<R> {
+    inner: R,
+    buf: Box<[u8]>,
+    pos: usize,
+    cap: usize,
+}
+
+impl<R: Read> BufReader<R> {
+    /// Creates a new `BufReader` with a default buffer capacity of 8KiB
+    pub fn new(inner: R) -> BufReader<R> {
+        BufReader::with_capacity(DEFAULT_BUF_SIZE, inner)
+    }
+
+    /// Creates a new `BufReader` with the given buffer capacity
+    pub fn with_capacity(cap: usize, inner: R) -> BufReader<R> {
+        assert!(cap > 0, "BufReader capacity must not be zero");
+
+        BufReader {
+            inner: inner,
+            buf: vec![0; cap].into_boxed_slice(),
+            pos: 0,
+            cap: 0,
+        }
+    }
+}
+
+impl<R> BufReader<R> {
+    /// Gets a reference to the underlying reader
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Gets a mutable reference to the underlying reader
+    ///
+    /// Reading directly from it skips the buffered data.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Returns the data which was read from the underlying reader, but not consumed yet
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf[self.pos..self.cap]
+    }
+
+    /// Unwraps this `BufReader`, returning the underlying reader
+    ///
+    /// Any buffered data is lost, so check `buffer()` before calling this.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for BufReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Bypass the buffer for large reads if it's empty anyways
+        if self.pos == self.cap && buf.len() >= self.buf.len() {
+            return self.inner.read(buf);
+        }
+
+        let n = {
+            let mut rem = try!(self.fill_buf());
+            try!(rem.read(buf))
+        };
+
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: Read> BufRead for BufReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        // Only read from the underlying reader (and thus possibly park)
+        // if all of the buffered data has been consumed
+        if self.pos >= self.cap {
+            self.cap = try!(self.inner.read(&mut self.buf));
+            self.pos = 0;
+        }
+
+        Ok(&self.buf[self.pos..self.cap])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = cmp::min(self.pos + amt, self.cap);
+    }
+}
+
+impl<R: fmt::Debug> fmt::Debug for BufReader<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BufReader")
+         .field("reader", &self.inner)
+         .field("buffer", &format_args!("{}/{}", self.cap - self.pos, self.buf.len()))
+         .finish()
+    }
+}
+
+/// Adds buffering to a writer, see the module documentation
+///
+/// The buffer is written out once it's full, on `flush()` and on drop.
+pub struct BufWriter<W: Write> {
+    inner: Option<W>,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> BufWriter<W> {
+    /// Creates a new `BufWriter` with a default buffer capacity of 8KiB
+    pub fn new(inner: W) -> BufWriter<W> {
+        BufWriter::with_capacity(DEFAULT_BUF_SIZE, inner)
+    }
+
+    /// Creates a new `BufWriter` with the given buffer capacity
+    pub fn with_capacity(cap: usize, inner: W) -> BufWriter<W> {
+        BufWriter {
+            inner: Some(inner),
+            buf: Vec::with_capacity(cap),
+        }
+    }
+
+    /// Gets a reference to the underlying writer
+    pub fn get_ref(&self) -> &W {
+        self.inner.as_ref().unwrap()
+    }
+
+    /// Gets a mutable reference to the underlying writer
+    ///
+    /// Writing directly to it bypasses the buffered data.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.inner.as_mut().unwrap()
+    }
+
+    /// Returns the data which was not written to the underlying writer yet
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// Writes out the buffer and returns the underlying writer
+    ///
+    /// If writing out the buffer fails, the error is returned together with this `BufWriter`.
+    pub fn into_inner(mut self) -> Result<W, (io::Error, BufWriter<W>)> {
+        match self.flush_buf() {
+            Ok(()) => Ok(self.inner.take().unwrap()),
+            Err(err) => Err((err, self)),
+        }
+    }
+
+    fn flush_buf(&mut self) -> io::Result<()> {
+        let mut written = 0;
+        let mut ret = Ok(());
+
+        {
+            let inner = self.inner.as_mut().unwrap();
+
+            while written < self.buf.len() {
+                match inner.write(&self.buf[written..]) {
+                    Ok(0) => {
+                        ret = Err(io::Error::new(io::ErrorKind::WriteZero,
+                                                 "failed to write the buffered data"));
+                        break;
+                    }
+                    Ok(n) => written += n,
+                    Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+                    Err(err) => {
+                        ret = Err(err);
+                        break;
+                    }
+                }
+            }
+        }
+
+        // Keep the data which wasn't written, so that a later flush can retry it
+        self.buf.drain(..written);
+        ret
+    }
+}
+
+impl<W: Write> Write for BufWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.buf.len() + buf.len() > self.buf.capacity() {
+            try!(self.flush_buf());
+        }
+
+        // Bypass the buffer for large writes, since it's empty now anyways
+        if buf.len() >= self.buf.capacity() {
+            self.get_mut().write(buf)
+        } else {
+            self.buf.write(buf)
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        try!(self.flush_buf());
+        self.get_mut().flush()
+    }
+}
+
+impl<W: Write + fmt::Debug> fmt::Debug for BufWriter<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BufWriter")
+         .field("writer", self.inner.as_ref().unwrap())
+         .field("buffer", &format_args!("{}/{}", self.buf.len(), self.buf.capacity()))
+         .finish()
+    }
+}
+
+impl<W: Write> Drop for BufWriter<W> {
+    fn drop(&mut self) {
+        // Flushing might park the coroutine, which must not happen while it's unwinding
+        if self.inner.is_some() && !thread::panicking() {
+            let _ = self.flush_buf();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufRead, Read, Write};
+
+    use net::{TcpListener, TcpStream};
+    use scheduler::Scheduler;
+    use super::{BufReader, BufWriter};
+
+    #[test]
+    fn test_buf_read_line() {
+        Scheduler::new()
+            .run(|| {
+                let acceptor = TcpListener::bind("127.0.0.1:0").unwrap();
+                let addr = acceptor.local_addr().unwrap();
+
+                let listen_fut = Scheduler::spawn(move || {
+                    let (stream, _) = acceptor.accept().unwrap();
+
+                    let mut writer = BufWriter::with_capacity(16, stream);
+                    for i in 0..100 {
+                        write!(writer, "line {}\n", i).unwrap();
+                    }
+
+                    // The rest is written out on drop
+                    writer.write_all(b"no newline").unwrap();
+                });
+
+                let stream = TcpStream::connect(addr).unwrap();
+                let mut reader = BufReader::with_capacity(7, stream);
+                let mut line = String::new();
+
+                for i in 0..100 {
+                    line.clear();
+                    reader.read_line(&mut line).unwrap();
+                    assert_eq!(line, format!("line {}\n", i));
+                }
+
+                listen_fut.join().unwrap();
+
+                let mut rest = String::new();
+                reader.read_to_string(&mut rest).unwrap();
+                assert_eq!(rest, "no newline");
+            })
+            .unwrap();
+    }
+}
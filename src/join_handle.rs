@@ -6,63 +6,38 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::cell::UnsafeCell;
-use std::sync::Arc;
 use std::thread;
 
-use sync::mono_barrier::MonoBarrier;
-
-struct JoinHandleInner<T> {
-    barrier: MonoBarrier,
-    data: UnsafeCell<Option<thread::Result<T>>>,
-}
-
-unsafe impl<T: Send> Send for JoinHandleInner<T> {}
-unsafe impl<T> Sync for JoinHandleInner<T> {}
-
-impl<T> JoinHandleInner<T> {
-    fn new() -> JoinHandleInner<T> {
-        JoinHandleInner {
-            barrier: MonoBarrier::new(),
-            data: UnsafeCell::new(None),
-        }
-    }
-}
+use sync::oneshot;
 
 pub struct JoinHandleSender<T> {
-    inner: Arc<JoinHandleInner<T>>,
+    tx: oneshot::Sender<thread::Result<T>>,
 }
 
 impl<T> JoinHandleSender<T> {
     pub fn push(self, result: thread::Result<T>) {
-        let data = unsafe { &mut *self.inner.data.get() };
-        *data = Some(result);
-        self.inner.barrier.notify();
+        // Nobody is interested in the result if the JoinHandle was dropped
+        let _ = self.tx.send(result);
     }
 }
 
 pub struct JoinHandleReceiver<T> {
-    inner: Arc<JoinHandleInner<T>>,
-    received: bool,
+    rx: oneshot::Receiver<thread::Result<T>>,
 }
 
 impl<T> JoinHandleReceiver<T> {
-    pub fn pop(mut self) -> thread::Result<T> {
-        self.inner.barrier.wait().unwrap();
-        let data = unsafe { &mut *self.inner.data.get() };
-        self.received = true;
-        data.take().unwrap()
+    pub fn pop(self) -> thread::Result<T> {
+        match self.rx.recv() {
+            Ok(result) => result,
+            // The coroutine was dropped before it finished, e.g. during shutdown
+            Err(canceled) => Err(Box::new(canceled)),
+        }
     }
 }
 
 pub fn handle_pair<T>() -> (JoinHandleSender<T>, JoinHandleReceiver<T>) {
-    let inner = Arc::new(JoinHandleInner::new());
-    let sender = JoinHandleSender { inner: inner.clone() };
-    let receiver = JoinHandleReceiver {
-        inner: inner,
-        received: false,
-    };
-    (sender, receiver)
+    let (tx, rx) = oneshot::channel();
+    (JoinHandleSender { tx: tx }, JoinHandleReceiver { rx: rx })
 }
 
 #[cfg(test)]
@@ -70,6 +45,7 @@ mod test {
     use super::*;
 
     use scheduler::Scheduler;
+    use sync::oneshot;
 
     #[test]
     fn test_join_handle_basic() {
@@ -131,4 +107,13 @@ mod test {
             })
             .unwrap();
     }
+
+    #[test]
+    fn test_join_handle_canceled() {
+        let (tx, rx) = handle_pair::<()>();
+        drop(tx);
+
+        let err = rx.pop().unwrap_err();
+        assert!(err.downcast_ref::<oneshot::Canceled>().is_some());
+    }
 }
@@ -15,6 +15,7 @@
     optin_builtin_traits,
     reflect_marker,
     shared,
+    specialization,
 )]
 
 #[macro_use]
@@ -27,27 +28,64 @@ extern crate linked_hash_map;
 extern crate mio;
 extern crate rand;
 extern crate slab;
-extern crate time;
+extern crate time as time_crate;
+
+#[cfg(feature = "futures")]
+extern crate futures;
+#[cfg(feature = "tls")]
+extern crate rustls;
 
 #[cfg(test)]
 extern crate env_logger;
 
+#[macro_use]
+pub mod local;
+#[macro_use]
+pub mod select;
+
+pub mod arena;
+pub mod builder;
+pub mod cancel;
+pub mod fs;
+#[cfg(feature = "futures")]
+pub mod future;
+pub mod generator;
+pub mod io;
 pub mod join_handle;
 pub mod net;
 pub mod options;
+pub mod park;
 pub mod promise;
 pub mod scheduler;
+pub mod scope;
+#[cfg(unix)]
+pub mod signal;
+pub mod spawner;
+pub mod supervisor;
 pub mod sync;
+pub mod time;
 
-pub use options::Options;
+pub use arena::Arena;
+pub use builder::SchedulerBuilder;
+pub use cancel::{Cancelled, CancellationToken, CoroutineGroup};
+pub use generator::Generator;
+pub use options::{Options, Priority, WakeAffinity};
+pub use park::Parker;
 pub use promise::Promise;
-pub use scheduler::{Scheduler, JoinHandle};
+pub use scheduler::{Scheduler, AdaptiveParking, ExternalDispatch, JoinHandle, Metrics,
+                    PanicContext, ProcessorMetrics, ReadyStates, ReadyType, SchedulerEvent,
+                    SchedulerHandle, SchedulerObserver, RandomStealPolicy, StealCandidate,
+                    StealPolicy, StealStrategy, TimeoutError, STEAL_RATE_ONE};
+pub use spawner::BoundedSpawner;
+pub use supervisor::RestartPolicy;
 
 mod coroutine;
 mod runtime;
 
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+use runtime::Processor;
 
 #[cfg(debug_assertions)]
 use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
@@ -76,6 +114,54 @@ pub fn sched() {
     Scheduler::sched()
 }
 
+/// Yield the current coroutine, the counterpart of `std::thread::yield_now()`
+///
+/// The coroutine is put at the end of the local queue, so that the other coroutines queued
+/// on the Processor run first. Outside of a coroutine it's safe to call and yields the thread.
+///
+/// ```
+/// use std::sync::Arc;
+/// use std::sync::atomic::{AtomicBool, Ordering};
+///
+/// coio::Scheduler::new()
+///     .run(|| {
+///         let done = Arc::new(AtomicBool::new(false));
+///         let cloned = done.clone();
+///         let h = coio::spawn(move || cloned.store(true, Ordering::SeqCst));
+///
+///         // Lets the spawned coroutine run
+///         coio::yield_now();
+///         assert!(done.load(Ordering::SeqCst));
+///         h.join().unwrap();
+///     })
+///     .unwrap();
+/// ```
+#[inline]
+pub fn yield_now() {
+    Scheduler::sched()
+}
+
+/// Run `f`, which is known to block the current thread, see `Scheduler::allow_blocking()`
+#[inline]
+pub fn allow_blocking<F, T>(f: F) -> T
+    where F: FnOnce() -> T
+{
+    Scheduler::allow_blocking(f)
+}
+
+/// Returns true if the caller is running inside of a coroutine
+///
+/// Libraries can use this to decide between the parking primitives of coio
+/// and the blocking ones of std. It's false on threads other than the Processors
+/// and on a Processor which isn't resuming a coroutine right now, e.g. in an idle handler.
+#[inline]
+pub fn in_coroutine() -> bool {
+    match Processor::current() {
+        Some(p) => p.is_running_coroutine(),
+        None => false,
+    }
+}
+
 /// Put the current coroutine to sleep for the specific amount of time
 #[inline]
 pub fn sleep_ms(ms: u64) {
@@ -91,6 +177,22 @@ pub fn sleep(dur: Duration) {
     }
 }
 
+/// Put the current coroutine to sleep until `deadline`
+///
+/// Returns immediately if the deadline already passed.
+pub fn sleep_until(deadline: Instant) {
+    match Scheduler::instance() {
+        Some(s) => s.sleep_until(deadline),
+        None => {
+            let now = Instant::now();
+
+            if deadline > now {
+                thread::sleep(deadline.duration_since(now));
+            }
+        }
+    }
+}
+
 /// Coroutine configuration. Provides detailed control over
 /// the properties and behavior of new coroutines.
 pub struct Builder {
@@ -112,6 +214,27 @@ impl Builder {
         self
     }
 
+    /// Always enqueues the new coroutine at the tail of the queue when it becomes ready.
+    #[inline]
+    pub fn fifo(mut self, enabled: bool) -> Builder {
+        self.opts.fifo = enabled;
+        self
+    }
+
+    /// Enables or disables the guard page below the stack of the new coroutine.
+    #[inline]
+    pub fn guard_page(mut self, enabled: bool) -> Builder {
+        self.opts.guard_page = enabled;
+        self
+    }
+
+    /// Tracks the peak stack usage of the new coroutine.
+    #[inline]
+    pub fn track_stack_usage(mut self, enabled: bool) -> Builder {
+        self.opts.track_stack_usage = enabled;
+        self
+    }
+
     /// Names the coroutine-to-be. Currently the name
     // is used for identification only in panic messages.
     #[inline]
@@ -120,6 +243,27 @@ impl Builder {
         self
     }
 
+    /// Sets the scheduling priority of the new coroutine.
+    #[inline]
+    pub fn priority(mut self, priority: Priority) -> Builder {
+        self.opts.priority = priority;
+        self
+    }
+
+    /// Pins the new coroutine to the Processor with the given ID.
+    #[inline]
+    pub fn pinned_to(mut self, processor_id: usize) -> Builder {
+        self.opts.pinned_to = Some(processor_id);
+        self
+    }
+
+    /// Associates the new coroutine with a CancellationToken.
+    #[inline]
+    pub fn cancel_token(mut self, token: CancellationToken) -> Builder {
+        self.opts.cancel_token = Some(token);
+        self
+    }
+
     /// Spawn a new coroutine
     #[inline]
     pub fn spawn<F, T>(self, f: F) -> JoinHandle<T>
@@ -178,4 +322,24 @@ mod test {
             })
             .unwrap();
     }
+
+    #[test]
+    fn test_yield_now_outside_coroutine() {
+        yield_now();
+    }
+
+    #[test]
+    fn test_in_coroutine() {
+        assert!(!in_coroutine());
+
+        Scheduler::new()
+            .run(|| {
+                assert!(in_coroutine());
+                assert!(spawn(|| in_coroutine()).join().unwrap());
+                assert!(!::std::thread::spawn(|| in_coroutine()).join().unwrap());
+            })
+            .unwrap();
+
+        assert!(!in_coroutine());
+    }
 }
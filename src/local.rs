@@ -0,0 +1,118 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Coroutine local storage
+//!
+//! Works just like `thread_local!`, but the values are bound to the currently running coroutine
+//! instead of the thread. They survive `sched()`, parking and migration to other Processors
+//! and are dropped as soon as the coroutine finishes.
+
+use std::any::Any;
+use std::collections::HashMap;
+
+use runtime::Processor;
+
+/// Declare a new coroutine local storage key of type `coio::local::LocalKey`.
+///
+/// ```ignore
+/// coroutine_local!(static REQUEST_ID: Cell<usize> = Cell::new(0));
+/// ```
+#[macro_export]
+macro_rules! coroutine_local {
+    (static $name:ident: $t:ty = $init:expr) => (
+        static $name: $crate::local::LocalKey<$t> = {
+            fn __init() -> $t { $init }
+            $crate::local::LocalKey { __init: __init }
+        };
+    );
+    (pub static $name:ident: $t:ty = $init:expr) => (
+        pub static $name: $crate::local::LocalKey<$t> = {
+            fn __init() -> $t { $init }
+            $crate::local::LocalKey { __init: __init }
+        };
+    );
+}
+
+/// A key for coroutine local data, created by the `coroutine_local!` macro
+pub struct LocalKey<T: 'static> {
+    #[doc(hidden)]
+    pub __init: fn() -> T,
+}
+
+impl<T: Any> LocalKey<T> {
+    /// Acquire a reference to the value of this key in the current coroutine.
+    ///
+    /// The value is lazily initialized the first time it is accessed by a coroutine.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a coroutine.
+    pub fn with<F, R>(&'static self, f: F) -> R
+        where F: FnOnce(&T) -> R
+    {
+        let key = self as *const _ as usize;
+
+        // The map belongs to the coroutine, so the ProcessorHandle is dropped right away.
+        // This allows `__init` to access other coroutine locals in turn.
+        let locals = {
+            let mut p = Processor::current()
+                            .expect("cannot access a coroutine local outside of a coroutine");
+            let coro = p.current().expect("cannot access a coroutine local outside of a coroutine");
+            coro.locals_mut() as *mut HashMap<usize, Box<Any>>
+        };
+
+        if !unsafe { &*locals }.contains_key(&key) {
+            let value = Box::new((self.__init)());
+            unsafe { &mut *locals }.insert(key, value);
+        }
+
+        // The boxed value is never moved or removed until the coroutine finishes.
+        // Taking a pointer to it allows `f` to access other coroutine locals.
+        let value = unsafe { &*locals }
+                        .get(&key)
+                        .and_then(|v| v.downcast_ref::<T>())
+                        .unwrap() as *const T;
+
+        f(unsafe { &*value })
+    }
+}
+
+unsafe impl<T: 'static> Sync for LocalKey<T> {}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+
+    use scheduler::Scheduler;
+
+    coroutine_local!(static COUNTER: Cell<usize> = Cell::new(0));
+
+    #[test]
+    fn coroutine_local_basic() {
+        Scheduler::new()
+            .with_workers(2)
+            .run(|| {
+                let handles: Vec<_> = (0..10)
+                                          .map(|i| {
+                                              Scheduler::spawn(move || {
+                                                  COUNTER.with(|c| c.set(i));
+                                                  Scheduler::sched();
+                                                  COUNTER.with(|c| c.get())
+                                              })
+                                          })
+                                          .collect();
+
+                for (i, h) in handles.into_iter().enumerate() {
+                    assert_eq!(h.join().unwrap(), i);
+                }
+
+                COUNTER.with(|c| assert_eq!(c.get(), 0));
+            })
+            .unwrap();
+    }
+}
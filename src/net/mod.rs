@@ -8,9 +8,13 @@
 
 //! Asynchronous network library
 
+pub mod pool;
 pub mod tcp;
 pub mod udp;
 
+#[cfg(feature = "tls")]
+pub mod tls;
+
 #[cfg(unix)]
 pub mod unix;
 
@@ -20,35 +24,79 @@ pub use self::udp::UdpSocket;
 #[cfg(unix)]
 pub use self::unix::{UnixListener, UnixStream, UnixSocket};
 
+use std::boxed::FnBox;
 use std::cell::UnsafeCell;
 use std::fmt::Debug;
 use std::io::{self, Read, Write};
-use std::net::{SocketAddr, ToSocketAddrs};
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, ToSocketAddrs};
 use std::ops::{Deref, DerefMut};
 use std::time::Duration;
 
 #[cfg(unix)]
 use std::os::unix::io::{AsRawFd, RawFd};
 
+#[cfg(unix)]
+use libc;
+
 use mio::{Evented, EventSet, Token};
 
 use scheduler::{ReadyStates, ReadyType, Scheduler};
+use select::{Select, SelectOp};
 use sync::spinlock::Spinlock;
 
+#[doc(hidden)]
 #[cfg(unix)]
-fn make_timeout() -> io::Error {
-    use libc;
+pub fn make_timeout() -> io::Error {
     io::Error::from_raw_os_error(libc::ETIMEDOUT)
 }
 
+#[doc(hidden)]
 #[cfg(windows)]
-fn make_timeout() -> io::Error {
+pub fn make_timeout() -> io::Error {
     const WSAETIMEDOUT: i32 = 10060;
     io::Error::from_raw_os_error(WSAETIMEDOUT)
 }
 
-#[derive(Debug)]
 #[doc(hidden)]
+#[cfg(unix)]
+pub fn make_would_block() -> io::Error {
+    io::Error::from_raw_os_error(libc::EWOULDBLOCK)
+}
+
+#[doc(hidden)]
+#[cfg(windows)]
+pub fn make_would_block() -> io::Error {
+    const WSAEWOULDBLOCK: i32 = 10035;
+    io::Error::from_raw_os_error(WSAEWOULDBLOCK)
+}
+
+// Timeouts of zero are rejected, just like std does
+fn check_timeout(dur: Option<Duration>) -> io::Result<()> {
+    match dur {
+        Some(dur) if dur == Duration::new(0, 0) => {
+            Err(io::Error::new(io::ErrorKind::InvalidInput,
+                               "cannot set a 0 duration timeout"))
+        }
+        _ => Ok(()),
+    }
+}
+
+// Sources which are still connecting report NotConnected, which is the same as WouldBlock to us
+fn would_block<T>(ret: io::Result<T>) -> io::Result<T> {
+    match ret {
+        Err(ref err) if err.kind() == io::ErrorKind::NotConnected => {
+            Err(make_would_block())
+        }
+        ret => ret,
+    }
+}
+
+/// Wraps any non-blocking mio `Evented` source, parking the coroutine if it would block
+///
+/// The source is registered with the Scheduler (see `Scheduler::register()`) on creation
+/// and deregistered when dropped. All of the types in this module are based on it.
+#[derive(Debug)]
 pub struct GenericEvented<E: Evented + Debug> {
     inner: UnsafeCell<E>,
     token: Token,
@@ -58,7 +106,7 @@ pub struct GenericEvented<E: Evented + Debug> {
 }
 
 impl<E: Evented + Debug> GenericEvented<E> {
-    #[doc(hidden)]
+    /// Registers `inner` for the events in `interest`
     pub fn new(inner: E, interest: EventSet) -> io::Result<GenericEvented<E>> {
         let scheduler = try!(Scheduler::instance_or_err());
         let (token, ready_states) = try!(scheduler.register(&inner, interest));
@@ -105,8 +153,15 @@ impl<E: Evented + Debug> DerefMut for GenericEvented<E> {
 }
 
 impl<'a, E: Evented + Debug + Read + 'a> GenericEvented<E> {
+    /// Sets the timeout for blocking reads, just like `std::net::TcpStream::set_read_timeout()`.
+    ///
+    /// If a read didn't receive any data within the timeout it fails with `ErrorKind::TimedOut`.
+    /// Since data is only ever consumed by reads returning `Ok`, no data is lost
+    /// by a timeout and the next read will continue where the last successful one stopped.
+    /// Passing a zero `Duration` is an error.
     #[inline]
     pub fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        try!(check_timeout(dur));
         *self.read_timeout.lock() = dur;
         Ok(())
     }
@@ -116,6 +171,27 @@ impl<'a, E: Evented + Debug + Read + 'a> GenericEvented<E> {
         Ok(*self.read_timeout.lock())
     }
 
+    /// Returns a `select!` operation which completes as soon as data was read into `buf`
+    ///
+    /// It's result is the same as the one of `read()`.
+    pub fn select_read<'b>(&'b self, buf: &'b mut [u8]) -> ReadOp<'b, E> {
+        ReadOp {
+            evented: self,
+            buf: buf,
+            result: None,
+        }
+    }
+
+    /// Attempts to read data into `buf` without parking the current coroutine
+    ///
+    /// Fails with `ErrorKind::WouldBlock` if no data is available right now.
+    /// The read timeout is ignored, since this never waits.
+    pub fn try_read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let ret = would_block(self.get_inner_mut().read(buf));
+        trace!("GenericEvented({:?}): try_read() => {:?}", self.token, ret);
+        ret
+    }
+
     fn read_inner(&self, buf: &mut [u8]) -> io::Result<usize> {
         let mut sync_guard = SyncGuard::new();
 
@@ -140,21 +216,20 @@ impl<'a, E: Evented + Debug + Read + 'a> GenericEvented<E> {
             trace!("GenericEvented({:?}): wait(Readable)", self.token);
             sync_guard.disarm();
 
-            match *self.read_timeout.lock() {
-                None => self.ready_states.wait(ReadyType::Readable),
-                Some(t) => {
-                    if self.ready_states.wait_timeout(ReadyType::Readable, t) {
-                        return Err(make_timeout());
-                    }
-                }
-            }
+            let timeout = *self.read_timeout.lock();
+            try!(self.ready_states.wait_timeout_opt(ReadyType::Readable, timeout));
         }
     }
 }
 
 impl<'a, E: Evented + Debug + Write + 'a> GenericEvented<E> {
+    /// Sets the timeout for blocking writes, just like `std::net::TcpStream::set_write_timeout()`.
+    ///
+    /// If a write couldn't send any data within the timeout it fails with `ErrorKind::TimedOut`.
+    /// Passing a zero `Duration` is an error.
     #[inline]
     pub fn set_write_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        try!(check_timeout(dur));
         *self.write_timeout.lock() = dur;
         Ok(())
     }
@@ -164,6 +239,16 @@ impl<'a, E: Evented + Debug + Write + 'a> GenericEvented<E> {
         Ok(*self.write_timeout.lock())
     }
 
+    /// Attempts to write the data in `buf` without parking the current coroutine
+    ///
+    /// Fails with `ErrorKind::WouldBlock` if the data can't be sent right now.
+    /// The write timeout is ignored, since this never waits.
+    pub fn try_write(&self, buf: &[u8]) -> io::Result<usize> {
+        let ret = would_block(self.get_inner_mut().write(buf));
+        trace!("GenericEvented({:?}): try_write() => {:?}", self.token, ret);
+        ret
+    }
+
     fn write_inner(&self, buf: &[u8]) -> io::Result<usize> {
         let mut sync_guard = SyncGuard::new();
 
@@ -188,14 +273,8 @@ impl<'a, E: Evented + Debug + Write + 'a> GenericEvented<E> {
             trace!("GenericEvented({:?}): wait(Writable)", self.token);
             sync_guard.disarm();
 
-            match *self.write_timeout.lock() {
-                None => self.ready_states.wait(ReadyType::Writable),
-                Some(t) => {
-                    if self.ready_states.wait_timeout(ReadyType::Writable, t) {
-                        return Err(make_timeout());
-                    }
-                }
-            }
+            let timeout = *self.write_timeout.lock();
+            try!(self.ready_states.wait_timeout_opt(ReadyType::Writable, timeout));
         }
     }
 
@@ -223,14 +302,8 @@ impl<'a, E: Evented + Debug + Write + 'a> GenericEvented<E> {
             trace!("GenericEvented({:?}): wait(Writable)", self.token);
             sync_guard.disarm();
 
-            match *self.write_timeout.lock() {
-                None => self.ready_states.wait(ReadyType::Writable),
-                Some(t) => {
-                    if self.ready_states.wait_timeout(ReadyType::Writable, t) {
-                        return Err(make_timeout());
-                    }
-                }
-            }
+            let timeout = *self.write_timeout.lock();
+            try!(self.ready_states.wait_timeout_opt(ReadyType::Writable, timeout));
         }
     }
 }
@@ -280,9 +353,52 @@ impl<'a, E: Evented + Debug + Write + 'a> Write for &'a GenericEvented<E> {
     }
 }
 
+/// Created by `GenericEvented::select_read()`
+pub struct ReadOp<'a, E: Evented + Debug + 'a> {
+    evented: &'a GenericEvented<E>,
+    buf: &'a mut [u8],
+    result: Option<io::Result<usize>>,
+}
+
+impl<'a, E: Evented + Debug + Read + 'a> ReadOp<'a, E> {
+    pub fn take(&mut self) -> io::Result<usize> {
+        self.result.take().expect("ReadOp didn't complete yet")
+    }
+}
+
+impl<'a, E: Evented + Debug + Read + 'a> SelectOp for ReadOp<'a, E> {
+    fn try_complete(&mut self) -> bool {
+        match self.evented.get_inner_mut().read(self.buf) {
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock ||
+                            err.kind() == io::ErrorKind::NotConnected => {
+                trace!("GenericEvented({:?}): select_read() => WouldBlock",
+                       self.evented.token);
+                return false;
+            }
+            ret => self.result = Some(ret),
+        }
+
+        true
+    }
+
+    fn is_completed(&self) -> bool {
+        self.result.is_some()
+    }
+
+    fn register(&mut self, select: &Select) {
+        self.evented.ready_states.register_select(ReadyType::Readable, select);
+    }
+
+    fn unregister(&mut self, select: &Select) {
+        self.evented.ready_states.unregister_select(ReadyType::Readable, select);
+    }
+}
+
 unsafe impl<E: Evented + Debug> Send for GenericEvented<E> {}
 unsafe impl<E: Evented + Debug> Sync for GenericEvented<E> {}
 
+// Counts an operation which completed without parking against the yield budget of the
+// current coroutine when dropped, see Scheduler::consume_budget()
 struct SyncGuard(bool);
 
 impl SyncGuard {
@@ -300,19 +416,146 @@ impl SyncGuard {
 impl Drop for SyncGuard {
     fn drop(&mut self) {
         if self.0 {
-            Scheduler::sched();
+            Scheduler::consume_budget();
         }
     }
 }
 
 
+/// Resolves `host` to all of it's addresses, using `port` as their port
+///
+/// The lookup via `getaddrinfo()` blocks, which is why it's run on the blocking thread pool
+/// (see `Scheduler::spawn_blocking()`) while the current coroutine is parked.
+/// IP addresses are returned right away. All addresses are returned in the order
+/// they were resolved in, so that callers can try IPv4 and IPv6 ones concurrently.
+pub fn resolve(host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+    resolve_addrs(&(host, port))
+}
+
+// Resolves `addr` inline if that can't block (see ResolveInline) and on the blocking thread pool
+// otherwise.
+fn resolve_addrs<A: ToSocketAddrs + Sync>(addr: &A) -> io::Result<Vec<SocketAddr>> {
+    if let Some(addrs) = addr.resolve_inline() {
+        return addrs;
+    }
+
+    let f: Box<FnBox() -> io::Result<Vec<SocketAddr>> + Send> =
+        Box::new(move || addr.to_socket_addrs().map(|addrs| addrs.collect()));
+
+    // Safe since spawn_blocking() doesn't return before `f` has finished
+    let f: Box<FnBox() -> io::Result<Vec<SocketAddr>> + Send + 'static> = unsafe {
+        mem::transmute(f)
+    };
+
+    Scheduler::spawn_blocking(move || f())
+}
+
+// Addresses for which `to_socket_addrs()` doesn't need to do a DNS lookup
+//
+// `resolve_inline()` returns None if resolving `self` might block.
+trait ResolveInline {
+    fn resolve_inline(&self) -> Option<io::Result<Vec<SocketAddr>>>;
+}
+
+impl<A: ToSocketAddrs + ?Sized> ResolveInline for A {
+    default fn resolve_inline(&self) -> Option<io::Result<Vec<SocketAddr>>> {
+        None
+    }
+}
+
+macro_rules! resolve_inline_ip {
+    ($($t:ty),*) => {
+        $(
+            impl ResolveInline for $t {
+                fn resolve_inline(&self) -> Option<io::Result<Vec<SocketAddr>>> {
+                    Some(self.to_socket_addrs().map(|addrs| addrs.collect()))
+                }
+            }
+        )*
+    }
+}
+
+resolve_inline_ip!(SocketAddr,
+                   SocketAddrV4,
+                   SocketAddrV6,
+                   (IpAddr, u16),
+                   (Ipv4Addr, u16),
+                   (Ipv6Addr, u16));
+
+impl<'a> ResolveInline for &'a [SocketAddr] {
+    fn resolve_inline(&self) -> Option<io::Result<Vec<SocketAddr>>> {
+        Some(Ok(self.to_vec()))
+    }
+}
+
+impl ResolveInline for str {
+    fn resolve_inline(&self) -> Option<io::Result<Vec<SocketAddr>>> {
+        self.parse::<SocketAddr>().ok().map(|addr| Ok(vec![addr]))
+    }
+}
+
+impl<'a> ResolveInline for &'a str {
+    fn resolve_inline(&self) -> Option<io::Result<Vec<SocketAddr>>> {
+        ResolveInline::resolve_inline(*self)
+    }
+}
+
+impl ResolveInline for String {
+    fn resolve_inline(&self) -> Option<io::Result<Vec<SocketAddr>>> {
+        ResolveInline::resolve_inline(&self[..])
+    }
+}
+
+impl<'a> ResolveInline for (&'a str, u16) {
+    fn resolve_inline(&self) -> Option<io::Result<Vec<SocketAddr>>> {
+        self.0.parse::<IpAddr>().ok().map(|ip| Ok(vec![SocketAddr::new(ip, self.1)]))
+    }
+}
+
+#[cfg(unix)]
+fn socket_addr_to_raw(addr: &SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+
+    let len = match *addr {
+        SocketAddr::V4(ref a) => {
+            let raw = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in) };
+            let ip = a.ip().octets();
+
+            raw.sin_family = libc::AF_INET as libc::sa_family_t;
+            raw.sin_port = a.port().to_be();
+            raw.sin_addr.s_addr = (((ip[0] as u32) << 24) | ((ip[1] as u32) << 16) |
+                                   ((ip[2] as u32) << 8) | (ip[3] as u32))
+                                      .to_be();
+            mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(ref a) => {
+            let raw = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6) };
+
+            raw.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            raw.sin6_port = a.port().to_be();
+            raw.sin6_flowinfo = a.flowinfo();
+            raw.sin6_scope_id = a.scope_id();
+            for (i, seg) in a.ip().segments().iter().enumerate() {
+                raw.sin6_addr.s6_addr[i * 2] = (seg >> 8) as u8;
+                raw.sin6_addr.s6_addr[i * 2 + 1] = *seg as u8;
+            }
+            mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+
+    (storage, len as libc::socklen_t)
+}
+
 // Credit goes to std::net::each_addr
-fn each_addr<A: ToSocketAddrs, F, T>(addr: A, mut f: F) -> io::Result<T>
+//
+// The addresses are resolved with resolve_addrs(), so that DNS lookups don't block the Processor
+// while IP addresses are used right away.
+fn each_addr<A: ToSocketAddrs + Sync, F, T>(addr: A, mut f: F) -> io::Result<T>
     where F: FnMut(&SocketAddr) -> io::Result<T>
 {
     let mut last_err = None;
 
-    for addr in try!(addr.to_socket_addrs()) {
+    for addr in try!(resolve_addrs(&addr)) {
         match f(&addr) {
             Ok(l) => return Ok(l),
             Err(e) => last_err = Some(e),
@@ -324,3 +567,27 @@ fn each_addr<A: ToSocketAddrs, F, T>(addr: A, mut f: F) -> io::Result<T>
                        "could not resolve to any addresses")
     }))
 }
+
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    use super::ResolveInline;
+
+    #[test]
+    fn test_resolve_inline() {
+        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
+
+        assert_eq!(addr.resolve_inline().unwrap().unwrap(), vec![addr]);
+        assert_eq!((&[addr][..]).resolve_inline().unwrap().unwrap(), vec![addr]);
+        assert_eq!("127.0.0.1:8080".resolve_inline().unwrap().unwrap(), vec![addr]);
+        assert_eq!(("127.0.0.1", 8080).resolve_inline().unwrap().unwrap(), vec![addr]);
+    }
+
+    #[test]
+    fn test_resolve_inline_hostname() {
+        assert!("localhost:8080".resolve_inline().is_none());
+        assert!(("localhost", 8080).resolve_inline().is_none());
+        assert!("localhost:8080".to_owned().resolve_inline().is_none());
+    }
+}
@@ -0,0 +1,291 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Pool of TCP connections
+
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Weak};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+
+#[cfg(unix)]
+use libc;
+
+use options::Options;
+use runtime::Processor;
+use scheduler::Scheduler;
+use select;
+use sync::mpmc;
+
+use super::{make_timeout, resolve_addrs, TcpStream};
+
+// Each slot is the permission to hold one connection, optionally with an idle one.
+// The channel thus never contains more than `size` slots and sending a slot back never blocks.
+type Slot = Option<(TcpStream, Instant)>;
+
+struct PoolShared {
+    sender: mpmc::Sender<Slot>,
+    receiver: mpmc::Receiver<Slot>,
+    reaper_started: AtomicBool,
+}
+
+/// A pool of up to `size` TCP connections to the same address
+///
+/// `get()` hands out one of the idle connections or opens a new one if none is idle.
+/// If all of the connections are in use it parks the coroutine until one is returned,
+/// which happens as soon as the `PooledConnection` is dropped. The maximum time to wait
+/// can be limited with `with_max_wait()`, after which `get()` fails with `ErrorKind::TimedOut`.
+///
+/// Idle connections are closed once they've been idle for longer than the idle timeout,
+/// or if the peer closed them. This is checked whenever one of them is about to be handed out
+/// and periodically by a coroutine spawned on the first call to `get()`,
+/// which exits shortly after the pool and all of it's clones were dropped.
+/// Clones share the same connections.
+#[derive(Clone)]
+pub struct ConnectionPool {
+    addrs: Vec<SocketAddr>,
+    size: usize,
+    idle_timeout: Duration,
+    connect_timeout: Option<Duration>,
+    max_wait: Option<Duration>,
+    shared: Arc<PoolShared>,
+}
+
+impl ConnectionPool {
+    /// Creates a pool of up to `size` connections to `addr`
+    ///
+    /// `addr` is resolved right away and new connections try the addresses in order.
+    /// The idle timeout defaults to 60s, connecting and waiting for a connection
+    /// have no timeout by default.
+    pub fn new<A: ToSocketAddrs + Sync>(addr: A, size: usize) -> io::Result<ConnectionPool> {
+        assert!(size >= 1, "Must allow at least one connection");
+
+        let addrs = try!(resolve_addrs(&addr));
+        let (sender, receiver) = mpmc::channel(size);
+
+        for _ in 0..size {
+            sender.try_send(None).unwrap();
+        }
+
+        Ok(ConnectionPool {
+            addrs: addrs,
+            size: size,
+            idle_timeout: Duration::from_secs(60),
+            connect_timeout: None,
+            max_wait: None,
+            shared: Arc::new(PoolShared {
+                sender: sender,
+                receiver: receiver,
+                reaper_started: AtomicBool::new(false),
+            }),
+        })
+    }
+
+    /// Set the time after which idle connections are closed
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> ConnectionPool {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Set the timeout for opening new connections, see `TcpStream::connect_timeout()`
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> ConnectionPool {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Set the maximum time `get()` waits for a connection while all of them are in use
+    pub fn with_max_wait(mut self, timeout: Duration) -> ConnectionPool {
+        self.max_wait = Some(timeout);
+        self
+    }
+
+    /// Returns the maximum number of connections
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns an idle connection or opens a new one, waiting while all of them are in use
+    pub fn get(&self) -> io::Result<PooledConnection> {
+        self.start_reaper();
+
+        let slot = match self.max_wait {
+            Some(dur) => {
+                select! {
+                    slot = self.shared.receiver.select_recv() => slot,
+                    _ = select::timeout(dur) => return Err(make_timeout()),
+                }
+            }
+            None => self.shared.receiver.recv(),
+        };
+
+        // The slot is given back on drop, even if opening a new connection below fails
+        let mut conn = PooledConnection {
+            stream: None,
+            sender: self.shared.sender.clone(),
+        };
+
+        if let Some((stream, since)) = slot.expect("ConnectionPool lost it's Sender") {
+            if is_usable(&stream, since, self.idle_timeout) {
+                conn.stream = Some(stream);
+                return Ok(conn);
+            }
+
+            trace!("ConnectionPool: closing {:?}", stream);
+        }
+
+        conn.stream = Some(try!(self.connect()));
+        Ok(conn)
+    }
+
+    fn connect(&self) -> io::Result<TcpStream> {
+        let deadline = self.connect_timeout.map(|timeout| Instant::now() + timeout);
+        let mut last_err = None;
+
+        for addr in &self.addrs {
+            match TcpStream::connect_deadline(addr, deadline) {
+                Ok(stream) => return Ok(stream),
+                Err(err) => last_err = Some(err),
+            }
+        }
+
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput,
+                           "could not resolve to any addresses")
+        }))
+    }
+
+    fn start_reaper(&self) {
+        if Processor::current().is_none() ||
+           self.shared.reaper_started.swap(true, Ordering::Relaxed) {
+            return;
+        }
+
+        let shared = Arc::downgrade(&self.shared);
+        let size = self.size;
+        let idle_timeout = self.idle_timeout;
+
+        let mut opts = Options::new();
+        opts.stack_size(32 * 1024);
+        opts.name("<ConnectionPool reaper>".to_owned());
+
+        Scheduler::spawn_opts(move || reap(shared, size, idle_timeout), opts);
+    }
+}
+
+impl fmt::Debug for ConnectionPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "ConnectionPool {{ addrs: {:?}, size: {} }}",
+               self.addrs,
+               self.size)
+    }
+}
+
+// Periodically closes expired idle connections until the pool is dropped.
+// Each slot is taken out of the channel once and sent back.
+fn reap(shared: Weak<PoolShared>, size: usize, idle_timeout: Duration) {
+    loop {
+        ::sleep(idle_timeout);
+
+        let shared = match shared.upgrade() {
+            Some(shared) => shared,
+            None => break,
+        };
+
+        for _ in 0..size {
+            let slot = match shared.receiver.try_recv() {
+                Ok(slot) => slot,
+                Err(..) => break,
+            };
+
+            let keep = match slot {
+                Some((ref stream, since)) => is_usable(stream, since, idle_timeout),
+                None => true,
+            };
+
+            let _ = shared.sender.try_send(if keep {
+                slot
+            } else {
+                None
+            });
+        }
+    }
+}
+
+/// A connection handed out by `ConnectionPool::get()`
+///
+/// It derefs to the `TcpStream` and is returned to the pool when dropped.
+/// Connections in an unknown state, e.g. after an I/O error, should be dropped
+/// with `discard()` instead, so that they aren't reused.
+pub struct PooledConnection {
+    stream: Option<TcpStream>,
+    sender: mpmc::Sender<Slot>,
+}
+
+impl PooledConnection {
+    /// Closes the connection instead of returning it to the pool
+    pub fn discard(mut self) {
+        self.stream.take();
+    }
+}
+
+impl Deref for PooledConnection {
+    type Target = TcpStream;
+
+    fn deref(&self) -> &TcpStream {
+        self.stream.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledConnection {
+    fn deref_mut(&mut self) -> &mut TcpStream {
+        self.stream.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledConnection {
+    fn drop(&mut self) {
+        let slot = self.stream.take().map(|stream| (stream, Instant::now()));
+        let _ = self.sender.try_send(slot);
+    }
+}
+
+impl fmt::Debug for PooledConnection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PooledConnection({:?})", self.stream)
+    }
+}
+
+fn is_usable(stream: &TcpStream, since: Instant, idle_timeout: Duration) -> bool {
+    since.elapsed() < idle_timeout && is_open(stream)
+}
+
+// Returns false if the peer closed the connection or sent unexpected data
+#[cfg(unix)]
+fn is_open(stream: &TcpStream) -> bool {
+    let mut buf = [0u8; 1];
+    let ret = unsafe {
+        libc::recv(stream.as_raw_fd(),
+                   buf.as_mut_ptr() as *mut libc::c_void,
+                   buf.len(),
+                   libc::MSG_PEEK | libc::MSG_DONTWAIT)
+    };
+
+    ret < 0 && io::Error::last_os_error().kind() == io::ErrorKind::WouldBlock
+}
+
+#[cfg(not(unix))]
+fn is_open(_: &TcpStream) -> bool {
+    true
+}
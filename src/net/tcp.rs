@@ -10,18 +10,42 @@
 
 pub use mio::tcp::Shutdown;
 
+use std::fs::File;
 use std::io;
 use std::iter::Iterator;
 use std::net::{SocketAddr, ToSocketAddrs};
+use std::time::{Duration, Instant};
 
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+use std::cmp;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+use std::io::{Read, Seek, SeekFrom, Write};
 #[cfg(unix)]
-use std::os::unix::io::{FromRawFd, RawFd};
+use std::mem;
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+
+#[cfg(unix)]
+use libc;
 
 use mio::EventSet;
 use mio::tcp::{TcpListener as MioTcpListener, TcpStream as MioTcpStream};
 
-use scheduler::ReadyType;
-use super::{each_addr, make_timeout, GenericEvented, SyncGuard};
+use cancel::CancellationToken;
+use options::Options;
+use scheduler::{ReadyType, Scheduler};
+use select;
+use sync::mpsc;
+use super::{each_addr, make_timeout, resolve, GenericEvented, SyncGuard};
+#[cfg(unix)]
+use super::socket_addr_to_raw;
+
+// Head start of each connection attempt in connect_happy_eyeballs(), as recommended by RFC 8305
+const CONNECTION_ATTEMPT_DELAY_MS: u64 = 250;
+
+// Maximum number of buffers readv() and writev() accept, which is 1024 on Linux and the BSDs
+#[cfg(unix)]
+const IOV_MAX: usize = 1024;
 
 macro_rules! create_tcp_listener {
     ($inner:expr) => (TcpListener::new($inner, EventSet::readable()));
@@ -34,13 +58,34 @@ macro_rules! create_tcp_stream {
 pub type TcpListener = GenericEvented<MioTcpListener>;
 
 impl TcpListener {
-    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<TcpListener> {
+    pub fn bind<A: ToSocketAddrs + Sync>(addr: A) -> io::Result<TcpListener> {
         each_addr(addr, |addr| {
             let inner = try!(MioTcpListener::bind(addr));
             create_tcp_listener!(inner)
         })
     }
 
+    /// Like `bind()`, but sets `SO_REUSEPORT` so that multiple listeners can share the address
+    ///
+    /// Binding one listener per Processor, e.g. with `Scheduler::spawn_on_each_processor()`,
+    /// lets the kernel distribute the incoming connections among them without a shared
+    /// accept queue. Only Linux (3.9 and later) balances the connections though. Other
+    /// Unix systems allow binding the listeners, but usually hand all connections to
+    /// one of them. On all other platforms this is the same as `bind()`,
+    /// and binding the address a second time fails.
+    #[cfg(unix)]
+    pub fn bind_reuseport<A: ToSocketAddrs + Sync>(addr: A) -> io::Result<TcpListener> {
+        each_addr(addr, |addr| {
+            let inner = try!(bind_reuseport(addr));
+            create_tcp_listener!(inner)
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn bind_reuseport<A: ToSocketAddrs + Sync>(addr: A) -> io::Result<TcpListener> {
+        TcpListener::bind(addr)
+    }
+
     pub fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
         let mut sync_guard = SyncGuard::new();
 
@@ -62,14 +107,8 @@ impl TcpListener {
             trace!("TcpListener({:?}): wait(Readable)", self.token);
             sync_guard.disarm();
 
-            match *self.read_timeout.lock() {
-                None => self.ready_states.wait(ReadyType::Readable),
-                Some(t) => {
-                    if self.ready_states.wait_timeout(ReadyType::Readable, t) {
-                        return Err(make_timeout());
-                    }
-                }
-            }
+            let timeout = *self.read_timeout.lock();
+            try!(self.ready_states.wait_timeout_opt(ReadyType::Readable, timeout));
         }
     }
 
@@ -78,8 +117,17 @@ impl TcpListener {
         create_tcp_listener!(inner)
     }
 
+    /// Returns an iterator over the connections accepted by this listener
+    ///
+    /// Each call to `next()` parks the coroutine until a connection arrives, like `accept()`.
+    /// The iteration ends once the `CancellationToken` of the coroutine is cancelled,
+    /// or after an error signaling that the listener itself is unusable, e.g. since it
+    /// has been shut down. All other errors only concern the connection being accepted.
     pub fn incoming(&self) -> Incoming {
-        Incoming(self)
+        Incoming {
+            listener: self,
+            finished: false,
+        }
     }
 }
 
@@ -92,30 +140,605 @@ impl FromRawFd for TcpListener {
 }
 
 
-pub struct Incoming<'a>(&'a TcpListener);
+#[cfg(unix)]
+fn cvt(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+#[cfg(unix)]
+fn setsockopt<T>(fd: RawFd, level: libc::c_int, opt: libc::c_int, val: T) -> io::Result<()> {
+    unsafe {
+        try!(cvt(libc::setsockopt(fd,
+                                  level,
+                                  opt,
+                                  &val as *const T as *const libc::c_void,
+                                  mem::size_of::<T>() as libc::socklen_t)));
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn getsockopt<T: Copy>(fd: RawFd, level: libc::c_int, opt: libc::c_int) -> io::Result<T> {
+    unsafe {
+        let mut val: T = mem::zeroed();
+        let mut len = mem::size_of::<T>() as libc::socklen_t;
+
+        try!(cvt(libc::getsockopt(fd,
+                                  level,
+                                  opt,
+                                  &mut val as *mut T as *mut libc::c_void,
+                                  &mut len)));
+
+        Ok(val)
+    }
+}
+
+#[cfg(unix)]
+fn bind_reuseport(addr: &SocketAddr) -> io::Result<MioTcpListener> {
+    let family = match *addr {
+        SocketAddr::V4(..) => libc::AF_INET,
+        SocketAddr::V6(..) => libc::AF_INET6,
+    };
+
+    let fd = try!(cvt(unsafe { libc::socket(family, libc::SOCK_STREAM, 0) }));
+
+    // Closes the socket if any of the following calls fails
+    let listener = unsafe { MioTcpListener::from_raw_fd(fd) };
+
+    unsafe {
+        let flags = try!(cvt(libc::fcntl(fd, libc::F_GETFL)));
+        try!(cvt(libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK)));
+        try!(cvt(libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC)));
+
+        for &opt in &[libc::SO_REUSEADDR, libc::SO_REUSEPORT] {
+            try!(setsockopt(fd, libc::SOL_SOCKET, opt, 1 as libc::c_int));
+        }
+
+        let (storage, len) = socket_addr_to_raw(addr);
+        try!(cvt(libc::bind(fd, &storage as *const _ as *const libc::sockaddr, len)));
+        try!(cvt(libc::listen(fd, 1024)));
+    }
+
+    Ok(listener)
+}
+
+/// Iterator over the connections of a `TcpListener`, see `TcpListener::incoming()`
+pub struct Incoming<'a> {
+    listener: &'a TcpListener,
+    finished: bool,
+}
 
 impl<'a> Iterator for Incoming<'a> {
-    type Item = io::Result<(TcpStream, SocketAddr)>;
+    type Item = io::Result<TcpStream>;
+
+    fn next(&mut self) -> Option<io::Result<TcpStream>> {
+        if self.finished {
+            return None;
+        }
+
+        match self.listener.accept() {
+            Ok((stream, _)) => Some(Ok(stream)),
+            Err(..) if Scheduler::is_cancelled() => {
+                trace!("TcpListener({:?}): incoming() => cancelled", self.listener.token);
+                self.finished = true;
+                None
+            }
+            Err(err) => {
+                self.finished = is_listener_error(&err);
+                Some(Err(err))
+            }
+        }
+    }
+}
 
-    fn next(&mut self) -> Option<io::Result<(TcpStream, SocketAddr)>> {
-        Some(self.0.accept())
+#[cfg(unix)]
+fn is_listener_error(err: &io::Error) -> bool {
+    match err.raw_os_error() {
+        Some(libc::EBADF) | Some(libc::EINVAL) | Some(libc::ENOTSOCK) => true,
+        _ => false,
     }
 }
 
+#[cfg(not(unix))]
+fn is_listener_error(_: &io::Error) -> bool {
+    false
+}
+
 pub type TcpStream = GenericEvented<MioTcpStream>;
 
 impl TcpStream {
-    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<TcpStream> {
+    pub fn connect<A: ToSocketAddrs + Sync>(addr: A) -> io::Result<TcpStream> {
         each_addr(addr, |addr| {
             let inner = try!(MioTcpStream::connect(addr));
             create_tcp_stream!(inner)
         })
     }
 
+    /// Opens a TCP connection and waits until it is established, but at most for `timeout`.
+    ///
+    /// If `addr` resolves to multiple addresses they are tried in order, but `timeout`
+    /// is the deadline for all of these attempts together, not per address.
+    /// If an attempt fails with an error other than a timeout the next address is tried with
+    /// the remaining time. Once the deadline passes the pending attempt is aborted,
+    /// the remaining addresses are skipped and `ErrorKind::TimedOut` is returned.
+    pub fn connect_timeout<A>(addr: A, timeout: Duration) -> io::Result<TcpStream>
+        where A: ToSocketAddrs + Sync
+    {
+        let deadline = Instant::now() + timeout;
+
+        each_addr(addr, |addr| {
+            if Instant::now() >= deadline {
+                return Err(make_timeout());
+            }
+
+            TcpStream::connect_deadline(addr, Some(deadline))
+        })
+    }
+
+    /// Opens a TCP connection to `host`, racing the connection attempts to it's addresses.
+    ///
+    /// The addresses are resolved with `net::resolve()` and tried alternating between IPv6 and
+    /// IPv4, starting with the family of the first one. Each attempt gets a head start
+    /// of 250ms before the next one is started in parallel, unless it fails earlier.
+    /// The first established connection is returned and all other pending attempts are
+    /// cancelled. Connections succeeding after that are closed right away.
+    /// If all of the attempts fail, the error of the last failed one is returned.
+    ///
+    /// This must be called inside of a coroutine.
+    pub fn connect_happy_eyeballs(host: &str, port: u16) -> io::Result<TcpStream> {
+        let mut addrs = interleave_families(try!(resolve(host, port))).into_iter();
+        let mut next = addrs.next();
+
+        let token = CancellationToken::new();
+        let (tx, rx) = mpsc::channel();
+        let mut pending = 0;
+        let mut last_err = None;
+
+        loop {
+            if let Some(addr) = next.take() {
+                let tx = tx.clone();
+                let mut opts = Options::new();
+                opts.cancel_token(token.clone());
+
+                Scheduler::spawn_opts(move || {
+                                          let ret = TcpStream::connect_deadline(&addr, None);
+                                          let _ = tx.send(ret);
+                                      },
+                                      opts);
+
+                pending += 1;
+                next = addrs.next();
+            }
+
+            if pending == 0 {
+                break;
+            }
+
+            // Wait until one of the attempts finished or the head start of the last one passed
+            let ret = if next.is_some() {
+                select! {
+                    ret = rx.select_recv() => ret.ok(),
+                    _ = select::timeout(Duration::from_millis(CONNECTION_ATTEMPT_DELAY_MS)) => None,
+                }
+            } else {
+                rx.recv().ok()
+            };
+
+            match ret {
+                Some(Ok(stream)) => {
+                    token.cancel();
+                    return Ok(stream);
+                }
+                Some(Err(err)) => {
+                    // The next attempt is started right away
+                    pending -= 1;
+                    last_err = Some(err);
+                }
+                None => {}
+            }
+        }
+
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput,
+                           "could not resolve to any addresses")
+        }))
+    }
+
+    #[doc(hidden)]
+    pub fn connect_deadline(addr: &SocketAddr, deadline: Option<Instant>) -> io::Result<TcpStream> {
+        let inner = try!(MioTcpStream::connect(addr));
+        let stream = try!(create_tcp_stream!(inner));
+
+        loop {
+            // Reports the errors of failed connection attempts, e.g. ECONNREFUSED
+            try!(stream.get_inner().take_socket_error());
+
+            if stream.get_inner().peer_addr().is_ok() {
+                trace!("TcpStream({:?}): connect() => Ok(..)", stream.token);
+                return Ok(stream);
+            }
+
+            let timeout = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+
+                    if now >= deadline {
+                        break;
+                    }
+
+                    Some(deadline.duration_since(now))
+                }
+                None => None,
+            };
+
+            // The socket becomes writable as soon as the attempt either succeeded or failed
+            trace!("TcpStream({:?}): wait(Writable)", stream.token);
+            try!(stream.ready_states.wait_timeout_opt(ReadyType::Writable, timeout));
+        }
+
+        // Dropping the stream deregisters and closes it, which aborts the connection attempt
+        trace!("TcpStream({:?}): connect() => TimedOut", stream.token);
+        Err(make_timeout())
+    }
+
     pub fn try_clone(&self) -> io::Result<TcpStream> {
         let inner = try!(self.get_inner().try_clone());
         create_tcp_stream!(inner)
     }
+
+    /// Sets the value of the `TCP_NODELAY` option, which disables Nagle's algorithm if `true`.
+    pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        self.get_inner().set_nodelay(nodelay)
+    }
+
+    /// Enables TCP keepalive with the given idle time before the first probe, or disables it.
+    ///
+    /// The idle time is rounded up to whole seconds.
+    pub fn set_keepalive(&self, keepalive: Option<Duration>) -> io::Result<()> {
+        let secs = keepalive.map(|dur| {
+            let secs = dur.as_secs() + if dur.subsec_nanos() > 0 { 1 } else { 0 };
+            secs as u32
+        });
+
+        self.get_inner().set_keepalive(secs)
+    }
+
+    /// Sends up to `len` bytes of `file`, starting at `offset`, using `sendfile(2)` if possible
+    ///
+    /// On Linux the data is copied by the kernel without ever passing through user space.
+    /// Elsewhere it's read into a buffer and written out, which moves the file's cursor.
+    /// The coroutine is parked whenever the socket is full, at most for the timeout set by
+    /// `set_write_timeout()`. Returns the number of bytes sent, which is less than `len` if the
+    /// end of the file was reached. If an error occurs after some data was sent already,
+    /// that amount is returned instead and the error is left for the next call to report,
+    /// so that the caller can continue the transfer from `offset` plus the returned amount.
+    pub fn send_file(&self, file: &File, offset: u64, len: usize) -> io::Result<usize> {
+        let mut sent = 0;
+
+        while sent < len {
+            match self.send_file_chunk(file, offset + sent as u64, len - sent) {
+                Ok(0) => break,
+                Ok(n) => sent += n,
+                Err(err) => {
+                    if sent == 0 {
+                        return Err(err);
+                    }
+                    break;
+                }
+            }
+        }
+
+        trace!("TcpStream({:?}): send_file() => Ok({})", self.token, sent);
+        Ok(sent)
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn send_file_chunk(&self, file: &File, offset: u64, len: usize) -> io::Result<usize> {
+        let mut sync_guard = SyncGuard::new();
+
+        loop {
+            let mut off = offset as libc::off_t;
+            let ret = unsafe { libc::sendfile(self.as_raw_fd(), file.as_raw_fd(), &mut off, len) };
+
+            if ret >= 0 {
+                return Ok(ret as usize);
+            }
+
+            let err = io::Error::last_os_error();
+
+            match err.kind() {
+                io::ErrorKind::WouldBlock => {
+                    trace!("TcpStream({:?}): sendfile() => WouldBlock", self.token);
+                }
+                io::ErrorKind::Interrupted => continue,
+                _ => {
+                    trace!("TcpStream({:?}): sendfile() => Err(..)", self.token);
+                    return Err(err);
+                }
+            }
+
+            trace!("TcpStream({:?}): wait(Writable)", self.token);
+            sync_guard.disarm();
+
+            let timeout = *self.write_timeout.lock();
+            try!(self.ready_states.wait_timeout_opt(ReadyType::Writable, timeout));
+        }
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    fn send_file_chunk(&self, file: &File, offset: u64, len: usize) -> io::Result<usize> {
+        let mut buf = [0u8; 16 * 1024];
+        let mut file = file;
+
+        try!(file.seek(SeekFrom::Start(offset)));
+        let n = try!(file.read(&mut buf[..cmp::min(len, buf.len())]));
+
+        let mut stream = self;
+        let mut written = 0;
+
+        while written < n {
+            match stream.write(&buf[written..n]) {
+                Ok(0) => {
+                    return Err(io::Error::new(io::ErrorKind::WriteZero,
+                                              "failed to write the file data"));
+                }
+                Ok(m) => written += m,
+                Err(err) => {
+                    if written == 0 {
+                        return Err(err);
+                    }
+                    break;
+                }
+            }
+        }
+
+        Ok(written)
+    }
+}
+
+// Alternates between IPv6 and IPv4 addresses, starting with the family of the first one
+fn interleave_families(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let first_is_v6 = match addrs.first() {
+        Some(&SocketAddr::V6(..)) => true,
+        _ => false,
+    };
+
+    let (preferred, other): (Vec<_>, Vec<_>) = addrs.into_iter().partition(|addr| {
+        match *addr {
+            SocketAddr::V6(..) => first_is_v6,
+            SocketAddr::V4(..) => !first_is_v6,
+        }
+    });
+
+    let mut result = Vec::with_capacity(preferred.len() + other.len());
+    let mut preferred = preferred.into_iter();
+    let mut other = other.into_iter();
+
+    loop {
+        match (preferred.next(), other.next()) {
+            (None, None) => break,
+            (a, b) => {
+                result.extend(a);
+                result.extend(b);
+            }
+        }
+    }
+
+    result
+}
+
+#[cfg(unix)]
+impl TcpStream {
+    /// Returns the value of the `TCP_NODELAY` option, see `set_nodelay()`.
+    pub fn nodelay(&self) -> io::Result<bool> {
+        let val: libc::c_int = try!(getsockopt(self.as_raw_fd(),
+                                               libc::IPPROTO_TCP,
+                                               libc::TCP_NODELAY));
+        Ok(val != 0)
+    }
+
+    /// Sets the value of the `SO_LINGER` option.
+    ///
+    /// With `Some` closing the socket waits for unsent data to be transmitted, at most for the
+    /// given duration rounded up to whole seconds. A zero duration resets the connection
+    /// on close instead.
+    pub fn set_linger(&self, linger: Option<Duration>) -> io::Result<()> {
+        let secs = linger.map_or(0, |dur| {
+            dur.as_secs() + if dur.subsec_nanos() > 0 { 1 } else { 0 }
+        });
+
+        let val = libc::linger {
+            l_onoff: linger.is_some() as libc::c_int,
+            l_linger: secs as libc::c_int,
+        };
+
+        setsockopt(self.as_raw_fd(), libc::SOL_SOCKET, libc::SO_LINGER, val)
+    }
+
+    /// Returns the value of the `SO_LINGER` option, see `set_linger()`.
+    pub fn linger(&self) -> io::Result<Option<Duration>> {
+        let val: libc::linger = try!(getsockopt(self.as_raw_fd(),
+                                                libc::SOL_SOCKET,
+                                                libc::SO_LINGER));
+
+        if val.l_onoff == 0 {
+            Ok(None)
+        } else {
+            Ok(Some(Duration::from_secs(val.l_linger as u64)))
+        }
+    }
+
+    /// Receives data like `read()`, but without removing it from the socket's receive queue.
+    ///
+    /// A subsequent `read()` or `peek()` returns the same data again, possibly followed by
+    /// more data which arrived in the meantime. Just like `read()` it waits for data to arrive,
+    /// at most for the timeout set by `set_read_timeout()`.
+    pub fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut sync_guard = SyncGuard::new();
+
+        loop {
+            let ret = unsafe {
+                libc::recv(self.as_raw_fd(),
+                           buf.as_mut_ptr() as *mut libc::c_void,
+                           buf.len(),
+                           libc::MSG_PEEK)
+            };
+
+            if ret >= 0 {
+                trace!("TcpStream({:?}): peek() => Ok({})", self.token, ret);
+                return Ok(ret as usize);
+            }
+
+            let err = io::Error::last_os_error();
+
+            match err.kind() {
+                io::ErrorKind::WouldBlock => {
+                    trace!("TcpStream({:?}): peek() => WouldBlock", self.token);
+                }
+                io::ErrorKind::Interrupted => continue,
+                _ => {
+                    trace!("TcpStream({:?}): peek() => Err(..)", self.token);
+                    return Err(err);
+                }
+            }
+
+            trace!("TcpStream({:?}): wait(Readable)", self.token);
+            sync_guard.disarm();
+
+            let timeout = *self.read_timeout.lock();
+            try!(self.ready_states.wait_timeout_opt(ReadyType::Readable, timeout));
+        }
+    }
+
+    /// Like `read()`, but scatters the data into multiple buffers using a single `readv` call.
+    ///
+    /// Only the first 1024 buffers are filled, since that's the most `readv` accepts.
+    pub fn read_vectored(&self, bufs: &mut [&mut [u8]]) -> io::Result<usize> {
+        let iovecs: Vec<libc::iovec> = bufs.iter_mut()
+                                           .take(IOV_MAX)
+                                           .map(|buf| {
+                                               libc::iovec {
+                                                   iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+                                                   iov_len: buf.len(),
+                                               }
+                                           })
+                                           .collect();
+
+        let mut sync_guard = SyncGuard::new();
+
+        loop {
+            let ret = unsafe {
+                libc::readv(self.as_raw_fd(), iovecs.as_ptr(), iovecs.len() as libc::c_int)
+            };
+
+            if ret >= 0 {
+                trace!("TcpStream({:?}): readv() => Ok({})", self.token, ret);
+                return Ok(ret as usize);
+            }
+
+            let err = io::Error::last_os_error();
+
+            match err.kind() {
+                io::ErrorKind::WouldBlock => {
+                    trace!("TcpStream({:?}): readv() => WouldBlock", self.token);
+                }
+                io::ErrorKind::Interrupted => continue,
+                _ => {
+                    trace!("TcpStream({:?}): readv() => Err(..)", self.token);
+                    return Err(err);
+                }
+            }
+
+            trace!("TcpStream({:?}): wait(Readable)", self.token);
+            sync_guard.disarm();
+
+            let timeout = *self.read_timeout.lock();
+            try!(self.ready_states.wait_timeout_opt(ReadyType::Readable, timeout));
+        }
+    }
+
+    /// Like `write()`, but gathers the data from multiple buffers using a single `writev` call.
+    ///
+    /// Just like `write()` this might write only parts of the data.
+    /// Use `write_all_vectored()` to write all of it. Only the first 1024 buffers are written,
+    /// since that's the most `writev` accepts.
+    pub fn write_vectored(&self, bufs: &[&[u8]]) -> io::Result<usize> {
+        let iovecs: Vec<libc::iovec> = bufs.iter()
+                                           .take(IOV_MAX)
+                                           .map(|buf| {
+                                               libc::iovec {
+                                                   iov_base: buf.as_ptr() as *mut libc::c_void,
+                                                   iov_len: buf.len(),
+                                               }
+                                           })
+                                           .collect();
+
+        let mut sync_guard = SyncGuard::new();
+
+        loop {
+            let ret = unsafe {
+                libc::writev(self.as_raw_fd(), iovecs.as_ptr(), iovecs.len() as libc::c_int)
+            };
+
+            if ret >= 0 {
+                trace!("TcpStream({:?}): writev() => Ok({})", self.token, ret);
+                return Ok(ret as usize);
+            }
+
+            let err = io::Error::last_os_error();
+
+            match err.kind() {
+                io::ErrorKind::WouldBlock => {
+                    trace!("TcpStream({:?}): writev() => WouldBlock", self.token);
+                }
+                io::ErrorKind::Interrupted => continue,
+                _ => {
+                    trace!("TcpStream({:?}): writev() => Err(..)", self.token);
+                    return Err(err);
+                }
+            }
+
+            trace!("TcpStream({:?}): wait(Writable)", self.token);
+            sync_guard.disarm();
+
+            let timeout = *self.write_timeout.lock();
+            try!(self.ready_states.wait_timeout_opt(ReadyType::Writable, timeout));
+        }
+    }
+
+    /// Writes all buffers in order, retrying after short writes.
+    pub fn write_all_vectored(&self, bufs: &[&[u8]]) -> io::Result<()> {
+        let mut bufs: Vec<&[u8]> = bufs.iter().cloned().filter(|buf| !buf.is_empty()).collect();
+        let mut idx = 0;
+
+        while idx < bufs.len() {
+            let mut n = try!(self.write_vectored(&bufs[idx..]));
+
+            if n == 0 {
+                return Err(io::Error::new(io::ErrorKind::WriteZero,
+                                          "failed to write whole buffer"));
+            }
+
+            // Skip the buffers which were written completely...
+            while idx < bufs.len() && n >= bufs[idx].len() {
+                n -= bufs[idx].len();
+                idx += 1;
+            }
+
+            // ...and advance into the one which was written partially
+            if n > 0 {
+                let buf = bufs[idx];
+                bufs[idx] = &buf[n..];
+            }
+        }
+
+        Ok(())
+    }
 }
 
 #[cfg(unix)]
@@ -0,0 +1,228 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! TLS over coio sockets using rustls
+//!
+//! This module is only available with the `tls` feature enabled.
+//!
+//! `TlsStream` drives a rustls `Session` over any parking reader and writer, usually a
+//! `TcpStream`. It implements `Read` and `Write` itself, so that code written against a plain
+//! socket works over TLS as well. Reads and writes park the calling coroutine whenever the
+//! underlying socket does, and records rustls wants to send while reading (e.g. during the
+//! handshake) are written out before the coroutine waits for more data.
+//!
+//! ```no_run
+//! # extern crate coio;
+//! # extern crate rustls;
+//! use std::io::{Read, Write};
+//! use std::sync::Arc;
+//!
+//! use coio::net::TcpStream;
+//! use coio::net::tls::TlsStream;
+//!
+//! # fn main() {
+//! coio::Scheduler::new()
+//!     .run(|| {
+//!         let config = Arc::new(rustls::ClientConfig::new());
+//!         let stream = TcpStream::connect("example.com:443").unwrap();
+//!         let mut tls = TlsStream::connect(&config, "example.com", stream).unwrap();
+//!
+//!         tls.write_all(b"GET / HTTP/1.0\r\nHost: example.com\r\n\r\n").unwrap();
+//!
+//!         let mut response = Vec::new();
+//!         tls.read_to_end(&mut response).unwrap();
+//!     })
+//!     .unwrap();
+//! # }
+//! ```
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+use rustls::{ClientConfig, ClientSession, ServerConfig, ServerSession, Session, TLSError};
+
+/// A TLS client stream, see `TlsStream::connect()`
+pub type ClientTlsStream<T> = TlsStream<ClientSession, T>;
+
+/// A TLS server stream, see `TlsStream::accept()`
+pub type ServerTlsStream<T> = TlsStream<ServerSession, T>;
+
+/// A rustls `Session` running over the stream `T`, see the module documentation
+pub struct TlsStream<S: Session, T: Read + Write> {
+    session: S,
+    stream: T,
+    eof: bool,
+}
+
+impl<T: Read + Write> TlsStream<ClientSession, T> {
+    /// Connects to the server `hostname` over `stream` and completes the handshake
+    ///
+    /// The certificate of the server is verified against `hostname`.
+    pub fn connect(config: &Arc<ClientConfig>,
+                   hostname: &str,
+                   stream: T)
+                   -> io::Result<ClientTlsStream<T>> {
+        let mut tls = TlsStream::new(ClientSession::new(config, hostname), stream);
+        try!(tls.handshake());
+        Ok(tls)
+    }
+}
+
+impl<T: Read + Write> TlsStream<ServerSession, T> {
+    /// Accepts a client connected over `stream` and completes the handshake
+    pub fn accept(config: &Arc<ServerConfig>, stream: T) -> io::Result<ServerTlsStream<T>> {
+        let mut tls = TlsStream::new(ServerSession::new(config), stream);
+        try!(tls.handshake());
+        Ok(tls)
+    }
+}
+
+impl<S: Session, T: Read + Write> TlsStream<S, T> {
+    /// Wraps an already configured `session`
+    ///
+    /// The handshake isn't done until it's needed by the first read or write,
+    /// or until `handshake()` is called explicitly.
+    pub fn new(session: S, stream: T) -> TlsStream<S, T> {
+        TlsStream {
+            session: session,
+            stream: stream,
+            eof: false,
+        }
+    }
+
+    /// Parks the current coroutine until the handshake has completed
+    ///
+    /// Returns immediately if it's already done.
+    pub fn handshake(&mut self) -> io::Result<()> {
+        while self.session.is_handshaking() {
+            try!(self.write_tls());
+
+            if self.session.is_handshaking() && self.session.wants_read() {
+                if try!(self.read_tls()) == 0 {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                              "connection closed during the TLS handshake"));
+                }
+            }
+        }
+
+        // The final handshake messages might still be buffered
+        self.write_tls()
+    }
+
+    /// Sends a close_notify alert to the peer, telling it that nothing will be sent anymore
+    pub fn shutdown(&mut self) -> io::Result<()> {
+        self.session.send_close_notify();
+        self.write_tls()
+    }
+
+    /// Gets a reference to the rustls session
+    pub fn get_session(&self) -> &S {
+        &self.session
+    }
+
+    /// Gets a mutable reference to the rustls session
+    pub fn get_session_mut(&mut self) -> &mut S {
+        &mut self.session
+    }
+
+    /// Gets a reference to the underlying stream
+    pub fn get_ref(&self) -> &T {
+        &self.stream
+    }
+
+    /// Gets a mutable reference to the underlying stream
+    ///
+    /// Reading from or writing to it directly corrupts the TLS connection.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.stream
+    }
+
+    /// Unwraps this `TlsStream`, returning the session and the underlying stream
+    pub fn into_inner(self) -> (S, T) {
+        (self.session, self.stream)
+    }
+
+    // Writes out all the records rustls has queued up, parking if the socket is full
+    fn write_tls(&mut self) -> io::Result<()> {
+        while self.session.wants_write() {
+            if try!(self.session.write_tls(&mut self.stream)) == 0 {
+                return Err(io::Error::new(io::ErrorKind::WriteZero,
+                                          "failed to write the TLS records"));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Reads and processes the next records, parking until some arrive
+    //
+    // Returns the number of bytes read from the socket, which is 0 on EOF.
+    fn read_tls(&mut self) -> io::Result<usize> {
+        let n = try!(self.session.read_tls(&mut self.stream));
+
+        if n == 0 {
+            self.eof = true;
+        } else {
+            try!(self.session.process_new_packets().map_err(tls_error));
+        }
+
+        Ok(n)
+    }
+}
+
+impl<S: Session, T: Read + Write> Read for TlsStream<S, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            // Renegotiation and the handshake make rustls send records while we read,
+            // which the peer might wait for before sending us any data
+            try!(self.write_tls());
+
+            let n = try!(self.session.read(buf));
+
+            if n > 0 || self.eof {
+                return Ok(n);
+            }
+
+            try!(self.read_tls());
+        }
+    }
+}
+
+impl<S: Session, T: Read + Write> Write for TlsStream<S, T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        try!(self.handshake());
+
+        let n = try!(self.session.write(buf));
+        try!(self.write_tls());
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        try!(self.session.flush());
+        try!(self.write_tls());
+        self.stream.flush()
+    }
+}
+
+impl<S: Session, T: Read + Write + fmt::Debug> fmt::Debug for TlsStream<S, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TlsStream")
+         .field("stream", &self.stream)
+         .field("handshaking", &self.session.is_handshaking())
+         .finish()
+    }
+}
+
+fn tls_error(err: TLSError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("TLS error: {:?}", err))
+}
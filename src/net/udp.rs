@@ -12,13 +12,18 @@ use std::io;
 use std::net::{SocketAddr, ToSocketAddrs};
 
 #[cfg(unix)]
-use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+
+#[cfg(unix)]
+use libc;
 
 use mio::EventSet;
 use mio::udp::UdpSocket as MioUdpSocket;
 
 use scheduler::ReadyType;
-use super::{each_addr, make_timeout, GenericEvented, SyncGuard};
+use super::{each_addr, make_timeout, make_would_block, GenericEvented, SyncGuard};
+#[cfg(unix)]
+use super::socket_addr_to_raw;
 
 macro_rules! create_udp_socket {
     ($inner:expr) => (UdpSocket::new($inner, EventSet::readable() | EventSet::writable()));
@@ -39,7 +44,7 @@ impl UdpSocket {
         create_udp_socket!(inner)
     }
 
-    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<UdpSocket> {
+    pub fn bind<A: ToSocketAddrs + Sync>(addr: A) -> io::Result<UdpSocket> {
         each_addr(addr, |addr| {
             let sock = try!(match *addr {
                 SocketAddr::V4(..) => Self::v4(),
@@ -57,6 +62,10 @@ impl UdpSocket {
         create_udp_socket!(inner)
     }
 
+    /// Receives a single datagram, parking the coroutine until one arrives.
+    ///
+    /// If the datagram is larger than `buf` the excess bytes are discarded
+    /// and `buf.len()` is returned, just like `std::net::UdpSocket::recv_from()`.
     pub fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
         let mut sync_guard = SyncGuard::new();
 
@@ -78,14 +87,28 @@ impl UdpSocket {
             trace!("UdpSocket({:?}): wait(Readable)", self.token);
             sync_guard.disarm();
 
-            match *self.read_timeout.lock() {
-                None => self.ready_states.wait(ReadyType::Readable),
-                Some(t) => {
-                    if self.ready_states.wait_timeout(ReadyType::Readable, t) {
-                        return Err(make_timeout());
-                    }
-                }
-            }
+            let timeout = *self.read_timeout.lock();
+            try!(self.ready_states.wait_timeout_opt(ReadyType::Readable, timeout));
+        }
+    }
+
+    /// Receives a single datagram without parking the current coroutine
+    ///
+    /// Fails with `ErrorKind::WouldBlock` if no datagram is available right now.
+    pub fn try_recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        match try!(self.get_inner_mut().recv_from(buf)) {
+            Some(t) => Ok(t),
+            None => Err(make_would_block()),
+        }
+    }
+
+    /// Sends a single datagram without parking the current coroutine
+    ///
+    /// Fails with `ErrorKind::WouldBlock` if it can't be sent right now.
+    pub fn try_send_to(&self, buf: &[u8], target: &SocketAddr) -> io::Result<usize> {
+        match try!(self.get_inner_mut().send_to(buf, target)) {
+            Some(len) => Ok(len),
+            None => Err(make_would_block()),
         }
     }
 
@@ -110,14 +133,112 @@ impl UdpSocket {
             trace!("UdpSocket({:?}): wait(Writable)", self.token);
             sync_guard.disarm();
 
-            match *self.read_timeout.lock() {
-                None => self.ready_states.wait(ReadyType::Writable),
-                Some(t) => {
-                    if self.ready_states.wait_timeout(ReadyType::Writable, t) {
-                        return Err(make_timeout());
-                    }
+            let timeout = *self.write_timeout.lock();
+            try!(self.ready_states.wait_timeout_opt(ReadyType::Writable, timeout));
+        }
+    }
+}
+
+#[cfg(unix)]
+impl UdpSocket {
+    /// Connects the socket to a remote address, which is then used by `send()` and `recv()`.
+    ///
+    /// Datagrams from any other address will be filtered out by the kernel.
+    pub fn connect<A: ToSocketAddrs + Sync>(&self, addr: A) -> io::Result<()> {
+        each_addr(addr, |addr| {
+            let (storage, len) = socket_addr_to_raw(addr);
+
+            let ret = unsafe {
+                libc::connect(self.as_raw_fd(),
+                              &storage as *const _ as *const libc::sockaddr,
+                              len)
+            };
+
+            if ret == 0 {
+                trace!("UdpSocket({:?}): connect() => Ok(())", self.token);
+                Ok(())
+            } else {
+                trace!("UdpSocket({:?}): connect() => Err(..)", self.token);
+                Err(io::Error::last_os_error())
+            }
+        })
+    }
+
+    /// Receives a single datagram from the connected address.
+    ///
+    /// Has the same truncation semantics as `recv_from()`.
+    pub fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut sync_guard = SyncGuard::new();
+
+        loop {
+            let ret = unsafe {
+                libc::recv(self.as_raw_fd(),
+                           buf.as_mut_ptr() as *mut libc::c_void,
+                           buf.len(),
+                           0)
+            };
+
+            if ret >= 0 {
+                trace!("UdpSocket({:?}): recv() => Ok({})", self.token, ret);
+                return Ok(ret as usize);
+            }
+
+            let err = io::Error::last_os_error();
+
+            match err.kind() {
+                io::ErrorKind::WouldBlock => {
+                    trace!("UdpSocket({:?}): recv() => WouldBlock", self.token);
+                }
+                io::ErrorKind::Interrupted => continue,
+                _ => {
+                    trace!("UdpSocket({:?}): recv() => Err(..)", self.token);
+                    return Err(err);
                 }
             }
+
+            trace!("UdpSocket({:?}): wait(Readable)", self.token);
+            sync_guard.disarm();
+
+            let timeout = *self.read_timeout.lock();
+            try!(self.ready_states.wait_timeout_opt(ReadyType::Readable, timeout));
+        }
+    }
+
+    /// Sends a single datagram to the connected address.
+    pub fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        let mut sync_guard = SyncGuard::new();
+
+        loop {
+            let ret = unsafe {
+                libc::send(self.as_raw_fd(),
+                           buf.as_ptr() as *const libc::c_void,
+                           buf.len(),
+                           0)
+            };
+
+            if ret >= 0 {
+                trace!("UdpSocket({:?}): send() => Ok({})", self.token, ret);
+                return Ok(ret as usize);
+            }
+
+            let err = io::Error::last_os_error();
+
+            match err.kind() {
+                io::ErrorKind::WouldBlock => {
+                    trace!("UdpSocket({:?}): send() => WouldBlock", self.token);
+                }
+                io::ErrorKind::Interrupted => continue,
+                _ => {
+                    trace!("UdpSocket({:?}): send() => Err(..)", self.token);
+                    return Err(err);
+                }
+            }
+
+            trace!("UdpSocket({:?}): wait(Writable)", self.token);
+            sync_guard.disarm();
+
+            let timeout = *self.write_timeout.lock();
+            try!(self.ready_states.wait_timeout_opt(ReadyType::Writable, timeout));
         }
     }
 }
@@ -8,9 +8,14 @@
 
 //! Unix domain socket
 
+use std::cmp;
 use std::io;
-use std::os::unix::io::{FromRawFd, RawFd};
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::path::Path;
+use std::ptr;
+
+use libc;
 
 use mio::EventSet;
 use mio::unix::PipeReader as MioPipeReader;
@@ -107,14 +112,8 @@ impl UnixListener {
             trace!("UnixListener({:?}): wait(Readable)", self.token);
             sync_guard.disarm();
 
-            match *self.read_timeout.lock() {
-                None => self.ready_states.wait(ReadyType::Readable),
-                Some(t) => {
-                    if self.ready_states.wait_timeout(ReadyType::Readable, t) {
-                        return Err(make_timeout());
-                    }
-                }
-            }
+            let timeout = *self.read_timeout.lock();
+            try!(self.ready_states.wait_timeout_opt(ReadyType::Readable, timeout));
         }
     }
 
@@ -143,6 +142,199 @@ impl UnixStream {
         let inner = try!(self.get_inner().try_clone());
         create_unix_stream!(inner)
     }
+
+    /// Sends the data in `buf` together with the file descriptor `fd` (via `SCM_RIGHTS`).
+    ///
+    /// The descriptor is duplicated into the receiving process and stays open in this one.
+    /// `buf` must not be empty, since the descriptor is attached to the data.
+    pub fn send_fd(&self, buf: &[u8], fd: RawFd) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "cannot send a file descriptor without data"));
+        }
+
+        let mut iov = libc::iovec {
+            iov_base: buf.as_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        };
+
+        let mut cmsg_buf = CmsgBuffer::new();
+        unsafe {
+            let cmsg = cmsg_buf.as_mut_ptr() as *mut libc::cmsghdr;
+            (*cmsg).cmsg_len = cmsg_len() as _;
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            ptr::write(cmsg_data(cmsg), fd);
+        }
+
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = cmsg_buf.as_mut_ptr();
+        msg.msg_controllen = cmsg_space() as _;
+
+        let mut sync_guard = SyncGuard::new();
+
+        loop {
+            let ret = unsafe { libc::sendmsg(self.as_raw_fd(), &msg, 0) };
+
+            if ret >= 0 {
+                trace!("UnixStream({:?}): sendmsg() => Ok({})", self.token, ret);
+                return Ok(ret as usize);
+            }
+
+            let err = io::Error::last_os_error();
+
+            match err.kind() {
+                io::ErrorKind::WouldBlock => {
+                    trace!("UnixStream({:?}): sendmsg() => WouldBlock", self.token);
+                }
+                io::ErrorKind::Interrupted => continue,
+                _ => {
+                    trace!("UnixStream({:?}): sendmsg() => Err(..)", self.token);
+                    return Err(err);
+                }
+            }
+
+            trace!("UnixStream({:?}): wait(Writable)", self.token);
+            sync_guard.disarm();
+
+            let timeout = *self.write_timeout.lock();
+            try!(self.ready_states.wait_timeout_opt(ReadyType::Writable, timeout));
+        }
+    }
+
+    /// Receives data into `buf` together with a file descriptor sent by `send_fd()`.
+    ///
+    /// Returns the number of bytes read and the received descriptor, if any.
+    /// The caller takes ownership of the descriptor and is responsible for closing it.
+    /// If the peer sent multiple descriptors at once, all but the first one are closed.
+    /// Fails with `ErrorKind::InvalidData` if the kernel had to drop descriptors, since they
+    /// didn't fit into the control message buffer. The data read by that call is lost.
+    pub fn recv_fd(&self, buf: &mut [u8]) -> io::Result<(usize, Option<RawFd>)> {
+        let mut iov = libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        };
+
+        let mut cmsg_buf = CmsgBuffer::new();
+
+        let mut sync_guard = SyncGuard::new();
+
+        loop {
+            let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            msg.msg_control = cmsg_buf.as_mut_ptr();
+            msg.msg_controllen = mem::size_of::<CmsgBuffer>() as _;
+
+            let ret = unsafe { libc::recvmsg(self.as_raw_fd(), &mut msg, RECV_FLAGS) };
+
+            if ret >= 0 {
+                trace!("UnixStream({:?}): recvmsg() => Ok({})", self.token, ret);
+
+                let mut fds = unsafe { received_fds(&msg) }.into_iter();
+
+                if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+                    for fd in fds {
+                        unsafe { libc::close(fd) };
+                    }
+
+                    return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                              "too many file descriptors received"));
+                }
+
+                // Nobody else would ever close the ones after the first
+                let fd = fds.next();
+                for fd in fds {
+                    unsafe { libc::close(fd) };
+                }
+
+                return Ok((ret as usize, fd));
+            }
+
+            let err = io::Error::last_os_error();
+
+            match err.kind() {
+                io::ErrorKind::WouldBlock => {
+                    trace!("UnixStream({:?}): recvmsg() => WouldBlock", self.token);
+                }
+                io::ErrorKind::Interrupted => continue,
+                _ => {
+                    trace!("UnixStream({:?}): recvmsg() => Err(..)", self.token);
+                    return Err(err);
+                }
+            }
+
+            trace!("UnixStream({:?}): wait(Readable)", self.token);
+            sync_guard.disarm();
+
+            let timeout = *self.read_timeout.lock();
+            try!(self.ready_states.wait_timeout_opt(ReadyType::Readable, timeout));
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+const RECV_FLAGS: libc::c_int = libc::MSG_CMSG_CLOEXEC;
+
+#[cfg(not(target_os = "linux"))]
+const RECV_FLAGS: libc::c_int = 0;
+
+// Equivalents of the CMSG_* macros for a control message carrying exactly one fd
+fn cmsg_align(len: usize) -> usize {
+    let align = mem::size_of::<usize>();
+    (len + align - 1) & !(align - 1)
+}
+
+fn cmsg_len() -> usize {
+    cmsg_align(mem::size_of::<libc::cmsghdr>()) + mem::size_of::<RawFd>()
+}
+
+fn cmsg_space() -> usize {
+    cmsg_align(mem::size_of::<libc::cmsghdr>()) + cmsg_align(mem::size_of::<RawFd>())
+}
+
+unsafe fn cmsg_data(cmsg: *const libc::cmsghdr) -> *mut RawFd {
+    (cmsg as *mut u8).offset(cmsg_align(mem::size_of::<libc::cmsghdr>()) as isize) as *mut RawFd
+}
+
+// Descriptors of the SCM_RIGHTS control message received by recvmsg() into `msg`
+unsafe fn received_fds(msg: &libc::msghdr) -> Vec<RawFd> {
+    let header = cmsg_align(mem::size_of::<libc::cmsghdr>());
+    let mut fds = Vec::new();
+
+    if (msg.msg_controllen as usize) < header {
+        return fds;
+    }
+
+    let cmsg = msg.msg_control as *const libc::cmsghdr;
+    if (*cmsg).cmsg_level != libc::SOL_SOCKET || (*cmsg).cmsg_type != libc::SCM_RIGHTS {
+        return fds;
+    }
+
+    let len = cmp::min((*cmsg).cmsg_len as usize, msg.msg_controllen as usize);
+    let count = len.saturating_sub(header) / mem::size_of::<RawFd>();
+
+    for i in 0..count {
+        fds.push(ptr::read(cmsg_data(cmsg).offset(i as isize)));
+    }
+
+    fds
+}
+
+// Properly aligned storage for a single control message
+struct CmsgBuffer([usize; 8]);
+
+impl CmsgBuffer {
+    fn new() -> CmsgBuffer {
+        debug_assert!(cmsg_space() <= mem::size_of::<CmsgBuffer>());
+        CmsgBuffer([0; 8])
+    }
+
+    fn as_mut_ptr(&mut self) -> *mut libc::c_void {
+        self.0.as_mut_ptr() as *mut libc::c_void
+    }
 }
 
 impl FromRawFd for UnixStream {
@@ -10,11 +10,55 @@
 
 use std::default::Default;
 
+use cancel::CancellationToken;
+
+/// Scheduling priority of a coroutine
+///
+/// Processors always resume coroutines with a higher priority first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    High,
+    Normal,
+    Low,
+}
+
+impl Default for Priority {
+    fn default() -> Priority {
+        Priority::Normal
+    }
+}
+
+/// Determines which Processor a coroutine woken up by I/O or a timer is queued on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WakeAffinity {
+    /// Queue it globally, so that the first Processor looking for work resumes it
+    Any,
+    /// Queue it on the Processor it last ran on
+    ///
+    /// The caches of that Processor's CPU likely still hold the data the coroutine worked on.
+    /// Other Processors may still steal it as usual if that Processor is busy.
+    LastProcessor,
+}
+
+impl Default for WakeAffinity {
+    fn default() -> WakeAffinity {
+        WakeAffinity::Any
+    }
+}
+
 /// Coroutine options
 #[derive(Debug, Clone)]
 pub struct Options {
     pub stack_size: usize,
     pub name: Option<String>,
+    pub priority: Priority,
+    pub pinned_to: Option<usize>,
+    pub guard_page: bool,
+    pub fifo: bool,
+    pub no_steal: bool,
+    pub wake_affinity: WakeAffinity,
+    pub track_stack_usage: bool,
+    pub cancel_token: Option<CancellationToken>,
 }
 
 /// Default coroutine stack size, 128KB
@@ -25,9 +69,18 @@ impl Options {
         Options {
             stack_size: DEFAULT_STACK,
             name: None,
+            priority: Priority::Normal,
+            pinned_to: None,
+            guard_page: true,
+            fifo: false,
+            no_steal: false,
+            wake_affinity: WakeAffinity::Any,
+            track_stack_usage: false,
+            cancel_token: None,
         }
     }
 
+    /// Set the size of the coroutine's stack in bytes, `DEFAULT_STACK` by default
     pub fn stack_size(&mut self, size: usize) -> &mut Options {
         self.stack_size = size;
         self
@@ -37,6 +90,80 @@ impl Options {
         self.name = Some(name);
         self
     }
+
+    pub fn priority(&mut self, priority: Priority) -> &mut Options {
+        self.priority = priority;
+        self
+    }
+
+    /// Pin the coroutine to the Processor with the given ID
+    ///
+    /// A pinned coroutine is never stolen by other Processors, which is useful if it
+    /// relies on thread locals or the CPU affinity of a thread. Pinned coroutines
+    /// are resumed in the order they became ready, ignoring their priority.
+    pub fn pinned_to(&mut self, processor_id: usize) -> &mut Options {
+        self.pinned_to = Some(processor_id);
+        self
+    }
+
+    /// Always enqueue the coroutine at the tail of the queue when it becomes ready
+    ///
+    /// By default a coroutine woken up by the Processor itself, e.g. because of
+    /// an I/O event or from within a `park_with()` callback, takes the place of the
+    /// coroutine which is resumed next. FIFO coroutines are resumed in the order
+    /// they became ready instead, which makes the latency more predictable.
+    pub fn fifo(&mut self, enabled: bool) -> &mut Options {
+        self.fifo = enabled;
+        self
+    }
+
+    /// Keep the coroutine on the Processor which first queues or resumes it
+    ///
+    /// Such a coroutine is never stolen by other Processors once it's bound to one,
+    /// which is useful if it relies on thread locals but doesn't care which thread it started
+    /// on. From then on it behaves just like a coroutine pinned to that Processor.
+    pub fn no_steal(&mut self, enabled: bool) -> &mut Options {
+        self.no_steal = enabled;
+        self
+    }
+
+    /// Set which Processor the coroutine is queued on when woken up by I/O or a timer
+    ///
+    /// Defaults to `WakeAffinity::Any`. Coroutines pinned to a Processor always return to it.
+    pub fn wake_affinity(&mut self, affinity: WakeAffinity) -> &mut Options {
+        self.wake_affinity = affinity;
+        self
+    }
+
+    /// Enable or disable the guard page below the coroutine's stack, enabled by default
+    ///
+    /// With a guard page a stack overflow reliably faults with SIGSEGV, instead of
+    /// silently corrupting adjacent memory. Disabling it saves a page and a `mprotect` call
+    /// for every stack that isn't taken from the stack pool.
+    pub fn guard_page(&mut self, enabled: bool) -> &mut Options {
+        self.guard_page = enabled;
+        self
+    }
+
+    /// Track the peak stack usage of the coroutine, disabled by default
+    ///
+    /// The stack is filled with a byte pattern when the coroutine is spawned, which allows
+    /// `Scheduler::current_stack_usage()` to report the maximum number of bytes used so far
+    /// instead of only the current usage. Use this to find the right `stack_size()`.
+    /// Since the entire stack is written it's expensive for large stacks.
+    pub fn track_stack_usage(&mut self, enabled: bool) -> &mut Options {
+        self.track_stack_usage = enabled;
+        self
+    }
+
+    /// Associate the coroutine with a CancellationToken
+    ///
+    /// After the token was cancelled `Scheduler::is_cancelled()` returns true
+    /// and I/O operations of the coroutine fail with `cancel::Cancelled`.
+    pub fn cancel_token(&mut self, token: CancellationToken) -> &mut Options {
+        self.cancel_token = Some(token);
+        self
+    }
 }
 
 impl Default for Options {
@@ -0,0 +1,306 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Parking of idle Processors
+//!
+//! A Processor which ran out of work, even after backing off (see
+//! `Scheduler::with_park_backoff()`), blocks it's thread through the `Parker` of the Scheduler
+//! until coroutines are readied for it. `CondvarParker` is the portable default.
+//! On Linux `EventFdParker` blocks in poll(2) on an eventfd instead, which doesn't need a lock
+//! shared by all Processors and can be polled along with other file descriptors.
+//!
+//! NOTE: The Processors don't poll for I/O themselves, that's done by the Scheduler's event
+//! loop thread, which readies the coroutines waiting for it. A parked Processor is thus only
+//! woken up once a coroutine was readied, no matter which `Parker` is used.
+
+#[cfg(target_os = "linux")]
+use std::cmp;
+#[cfg(target_os = "linux")]
+use std::io;
+#[cfg(target_os = "linux")]
+use std::os::unix::io::{AsRawFd, RawFd};
+#[cfg(target_os = "linux")]
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+#[cfg(target_os = "linux")]
+use libc;
+
+/// Blocks the threads of idle Processors, see the module documentation
+///
+/// Implementations may wake up threads spuriously, but must never lose a wakeup.
+pub trait Parker: Send + Sync {
+    /// Blocks the calling thread until it's unparked or the optional `timeout` has passed
+    ///
+    /// `before_wait` is called right before blocking and the thread must not block at all if it
+    /// returns false. It's used to check for work a last time. An `unpark()`, `unpark_all()` or
+    /// `close()` happening after it was called must wake the thread or keep it from blocking.
+    /// It's not called once the parker is closed.
+    fn park(&self, timeout: Option<Duration>, before_wait: &mut FnMut() -> bool);
+
+    /// Wakes up at most `n` parked threads
+    fn unpark(&self, n: usize);
+
+    /// Wakes up all parked threads
+    fn unpark_all(&self);
+
+    /// Wakes up all parked threads and keeps them from blocking in `park()` from now on
+    fn close(&self);
+}
+
+/// Parks the threads on a `Condvar`, the default `Parker`
+pub struct CondvarParker {
+    closed: Mutex<bool>,
+    condvar: Condvar,
+}
+
+impl CondvarParker {
+    pub fn new() -> CondvarParker {
+        CondvarParker {
+            closed: Mutex::new(false),
+            condvar: Condvar::new(),
+        }
+    }
+}
+
+impl Default for CondvarParker {
+    fn default() -> CondvarParker {
+        CondvarParker::new()
+    }
+}
+
+impl Parker for CondvarParker {
+    fn park(&self, timeout: Option<Duration>, before_wait: &mut FnMut() -> bool) {
+        // Unparking acquires the lock as well, so it can't slip in between
+        // the call to before_wait() and the wait
+        let closed = self.closed.lock().unwrap();
+
+        if *closed || !before_wait() {
+            return;
+        }
+
+        match timeout {
+            Some(dur) => {
+                let _ = self.condvar.wait_timeout(closed, dur);
+            }
+            None => {
+                let _ = self.condvar.wait(closed);
+            }
+        }
+    }
+
+    fn unpark(&self, n: usize) {
+        let _guard = self.closed.lock().unwrap();
+
+        for _ in 0..n {
+            self.condvar.notify_one();
+        }
+    }
+
+    fn unpark_all(&self) {
+        let _guard = self.closed.lock().unwrap();
+        self.condvar.notify_all();
+    }
+
+    fn close(&self) {
+        *self.closed.lock().unwrap() = true;
+        self.condvar.notify_all();
+    }
+}
+
+/// Parks the threads in poll(2) on an eventfd in semaphore mode
+///
+/// Unparking only writes the number of wakeups to the eventfd, without taking a lock.
+/// Each wakeup is consumed by one of the threads polling it, while the others might return
+/// spuriously. The eventfd is readable as long as there are pending wakeups, which allows
+/// waiting for it along with other file descriptors through `as_raw_fd()`.
+#[cfg(target_os = "linux")]
+pub struct EventFdParker {
+    fd: RawFd,
+    // Number of threads in park(), which bounds the number of pending wakeups
+    waiting: AtomicUsize,
+    closed: AtomicBool,
+}
+
+#[cfg(target_os = "linux")]
+impl EventFdParker {
+    pub fn new() -> io::Result<EventFdParker> {
+        let flags = libc::EFD_CLOEXEC | libc::EFD_NONBLOCK | libc::EFD_SEMAPHORE;
+        let fd = unsafe { libc::eventfd(0, flags) };
+
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(EventFdParker {
+            fd: fd,
+            waiting: AtomicUsize::new(0),
+            closed: AtomicBool::new(false),
+        })
+    }
+
+    // Adds `n` pending wakeups
+    fn notify(&self, n: usize) {
+        let value = n as u64;
+        let buf = &value as *const u64 as *const libc::c_void;
+
+        // This can only fail if the counter overflows, in which case plenty of wakeups are pending
+        let _ = unsafe { libc::write(self.fd, buf, 8) };
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Parker for EventFdParker {
+    fn park(&self, timeout: Option<Duration>, before_wait: &mut FnMut() -> bool) {
+        // Registering as waiting before calling before_wait() makes unparkers racing with it
+        // add a wakeup, which the poll below will see
+        self.waiting.fetch_add(1, Ordering::SeqCst);
+
+        if !self.closed.load(Ordering::SeqCst) && before_wait() {
+            let timeout = match timeout {
+                Some(dur) => {
+                    // Rounded up, since waking up too early only makes the Processor park again
+                    let ms = dur.as_secs() * 1_000 +
+                             (dur.subsec_nanos() as u64 + 999_999) / 1_000_000;
+                    cmp::min(ms, libc::c_int::max_value() as u64) as libc::c_int
+                }
+                None => -1,
+            };
+
+            let mut pfd = libc::pollfd {
+                fd: self.fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+
+            if unsafe { libc::poll(&mut pfd, 1, timeout) } > 0 {
+                // Consumes one wakeup, unless another thread was faster, which is just as well
+                let mut value = 0u64;
+                let buf = &mut value as *mut u64 as *mut libc::c_void;
+                let _ = unsafe { libc::read(self.fd, buf, 8) };
+            }
+        }
+
+        self.waiting.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    fn unpark(&self, n: usize) {
+        let n = cmp::min(n, self.waiting.load(Ordering::SeqCst));
+
+        if n > 0 {
+            self.notify(n);
+        }
+    }
+
+    fn unpark_all(&self) {
+        let n = self.waiting.load(Ordering::SeqCst);
+
+        if n > 0 {
+            self.notify(n);
+        }
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.unpark_all();
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl AsRawFd for EventFdParker {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for EventFdParker {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    fn check_parker<P: Parker + 'static>(parker: P) {
+        let parker = Arc::new(parker);
+
+        // Must not block if before_wait() returns false
+        parker.park(None, &mut || false);
+
+        let start = Instant::now();
+        parker.park(Some(Duration::from_millis(20)), &mut || true);
+        assert!(start.elapsed() >= Duration::from_millis(10));
+
+        let checked = Arc::new(AtomicBool::new(false));
+
+        let t = {
+            let parker = parker.clone();
+            let checked = checked.clone();
+
+            thread::spawn(move || {
+                parker.park(None,
+                            &mut || {
+                                checked.store(true, Ordering::SeqCst);
+                                true
+                            })
+            })
+        };
+
+        // An unpark after before_wait() was called must not be lost
+        while !checked.load(Ordering::SeqCst) {
+            thread::yield_now();
+        }
+
+        parker.unpark(1);
+        t.join().unwrap();
+
+        parker.close();
+        parker.park(None, &mut || panic!("must not be called once the parker is closed"));
+    }
+
+    #[test]
+    fn test_condvar_parker() {
+        check_parker(CondvarParker::new());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_eventfd_parker() {
+        use scheduler::Scheduler;
+
+        check_parker(EventFdParker::new().unwrap());
+
+        Scheduler::new()
+            .with_workers(4)
+            .with_parker(EventFdParker::new().unwrap())
+            .run(|| {
+                let mut handles = Vec::new();
+
+                for i in 0..100 {
+                    handles.push(Scheduler::spawn(move || {
+                        ::sleep_ms(1);
+                        i
+                    }));
+                }
+
+                for (i, h) in handles.into_iter().enumerate() {
+                    assert_eq!(h.join().unwrap(), i);
+                }
+            })
+            .unwrap();
+    }
+}
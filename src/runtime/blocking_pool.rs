@@ -0,0 +1,94 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Thread pool for blocking operations, see `Scheduler::spawn_blocking()`
+
+use std::boxed::FnBox;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, Builder};
+
+pub type Job = Box<FnBox() + Send>;
+
+pub struct BlockingPool {
+    sender: Option<Mutex<Sender<Job>>>,
+    threads: Vec<thread::JoinHandle<()>>,
+    // Set by discard_pending()
+    discard: Arc<AtomicBool>,
+}
+
+impl BlockingPool {
+    /// Spawns `size` threads waiting for jobs
+    pub fn new(size: usize) -> BlockingPool {
+        let (tx, rx) = mpsc::channel();
+        let rx = Arc::new(Mutex::new(rx));
+        let discard = Arc::new(AtomicBool::new(false));
+
+        let threads = (0..size)
+                          .map(|id| {
+                              let rx = rx.clone();
+                              let discard = discard.clone();
+
+                              Builder::new()
+                                  .name(format!("Blocking#{}", id))
+                                  .spawn(move || BlockingPool::worker(rx, discard))
+                                  .unwrap()
+                          })
+                          .collect();
+
+        BlockingPool {
+            sender: Some(Mutex::new(tx)),
+            threads: threads,
+            discard: discard,
+        }
+    }
+
+    fn worker(rx: Arc<Mutex<Receiver<Job>>>, discard: Arc<AtomicBool>) {
+        loop {
+            // The lock is released before running the job
+            let job = rx.lock().unwrap().recv();
+
+            match job {
+                Ok(job) => {
+                    if discard.load(Ordering::SeqCst) {
+                        drop(job);
+                    } else {
+                        job();
+                    }
+                }
+                Err(..) => break,
+            }
+        }
+    }
+
+    /// Drops the jobs which haven't started running yet instead of running them
+    ///
+    /// Jobs which are already running are still run to completion.
+    pub fn discard_pending(&self) {
+        self.discard.store(true, Ordering::SeqCst);
+    }
+
+    /// Runs the job on one of the threads as soon as one of them is idle
+    pub fn execute(&self, job: Job) {
+        if let Some(ref sender) = self.sender {
+            sender.lock().unwrap().send(job).unwrap();
+        }
+    }
+}
+
+impl Drop for BlockingPool {
+    fn drop(&mut self) {
+        // Closing the channel makes the threads exit after finishing their current job
+        self.sender.take();
+
+        for t in self.threads.drain(..) {
+            let _ = t.join();
+        }
+    }
+}
@@ -8,6 +8,8 @@
 
 pub use self::processor::Processor;
 
+pub mod blocking_pool;
 pub mod processor;
 pub mod stack_pool;
 pub mod timer;
+pub mod watchdog;
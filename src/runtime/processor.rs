@@ -9,39 +9,141 @@
 //! Processing unit of a thread
 
 use std::boxed::FnBox;
-use std::cell::UnsafeCell;
+use std::cell::{Cell, UnsafeCell};
+use std::cmp;
 use std::fmt;
 use std::mem;
 use std::ops::{Deref, DerefMut};
+use std::panic;
 use std::ptr;
 use std::sync::{Arc, Barrier, Weak};
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::mpsc::{self, Receiver, Sender, SendError};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, SendError, TryRecvError};
 use std::thread::{self, Builder};
+use std::time::{Duration, Instant};
 
-use rand::{self, Rng};
+use rand::{self, Rng, SeedableRng, XorShiftRng};
 
-use coroutine::{Coroutine, State, Handle};
-use options::Options;
+use arena::Arena;
+use coroutine::{Coroutine, State, Handle, HandleList};
+use options::{Options, Priority};
 use runtime::stack_pool::StackPool;
-use scheduler::Scheduler;
+use scheduler::{AdaptiveParking, Scheduler, SchedulerEvent, StealCandidate, StealPolicy,
+                StealStrategy, STEAL_RATE_ONE};
+use sync::spinlock::{self, Spinlock};
 
 pub const QUEUE_SIZE: usize = 256;
 
-thread_local!(static PROCESSOR: UnsafeCell<Option<Processor>> = UnsafeCell::new(None));
+// The number of cpu_relax() calls of the first spin before parking, doubled with every other spin
+const PARK_SPIN_BASE: usize = 1 << 4;
+
+// The number of schedule() iterations after which the global queue is checked even if the
+// local queue isn't empty. Prime, like Go's, to avoid coinciding with patterns in the workload.
+const GLOBAL_QUEUE_INTERVAL: usize = 61;
+
+thread_local!(static PROCESSOR: ProcessorSlot = ProcessorSlot::new());
+
+// The Processor of the current thread, see Processor::current()
+//
+// It's set once by the Processor's thread before it starts scheduling and never changes
+// afterwards, so the `&'static mut` references derived from `ptr` never dangle. They are only
+// unique as long as no two `ProcessorHandle`s are alive at once though, which debug builds
+// check with `borrowed`: acquiring a second handle panics instead of aliasing the first one.
+struct ProcessorSlot {
+    processor: UnsafeCell<Option<Processor>>,
+    // Points into `processor` once it's set
+    ptr: Cell<*mut Processor>,
+    // Set while a ProcessorHandle is alive, only maintained in debug builds
+    borrowed: Cell<bool>,
+}
+
+impl ProcessorSlot {
+    fn new() -> ProcessorSlot {
+        ProcessorSlot {
+            processor: UnsafeCell::new(None),
+            ptr: Cell::new(ptr::null_mut()),
+            borrowed: Cell::new(false),
+        }
+    }
+
+    fn set(&self, processor: Processor) {
+        assert!(self.ptr.get().is_null(),
+                "the Processor of a thread can only be set once");
+
+        unsafe {
+            let slot = &mut *self.processor.get();
+            *slot = Some(processor);
+            self.ptr.set(slot.as_mut().unwrap());
+        }
+    }
+
+    #[inline]
+    fn get(&self) -> Option<&'static mut Processor> {
+        let ptr = self.ptr.get();
+
+        if ptr.is_null() {
+            None
+        } else {
+            Some(unsafe { &mut *ptr })
+        }
+    }
+
+    #[inline]
+    fn acquire(&self) -> Option<ProcessorHandle> {
+        let processor = match self.get() {
+            Some(p) => p,
+            None => return None,
+        };
+
+        if cfg!(debug_assertions) {
+            assert!(!self.borrowed.get(),
+                    "acquired a ProcessorHandle while another one is alive");
+            self.borrowed.set(true);
+        }
+
+        Some(ProcessorHandle(processor))
+    }
+
+    #[inline]
+    fn is_borrowed(&self) -> bool {
+        self.borrowed.get()
+    }
+
+    #[inline]
+    fn release(&self) {
+        self.borrowed.set(false);
+    }
+
+    // Reads the ID through the raw pointer, without acquiring a handle
+    #[inline]
+    fn id(&self) -> Option<usize> {
+        let ptr = self.ptr.get();
+
+        if ptr.is_null() {
+            None
+        } else {
+            Some(unsafe { (*ptr).id() })
+        }
+    }
+}
 
 type BlockWithCallback<'a> = &'a mut FnMut(&mut Processor, Handle);
 
 #[derive(Clone)]
 pub struct ProcMessageSender {
     inner: Sender<ProcMessage>,
-    _processor: Processor,
+    processor: Processor,
 }
 
 impl ProcMessageSender {
     pub fn send(&self, proc_msg: ProcMessage) -> Result<(), SendError<ProcMessage>> {
-        try!(self.inner.send(proc_msg));
-        Ok(())
+        // Incremented before sending, so that the Processor doesn't park with messages pending
+        self.processor.pending_message_count.fetch_add(1, Ordering::Release);
+
+        self.inner.send(proc_msg).map_err(|err| {
+            self.processor.pending_message_count.fetch_sub(1, Ordering::Relaxed);
+            err
+        })
     }
 }
 
@@ -62,10 +164,22 @@ pub struct Machine {
 /// running on the previous Processor. The same thing is true for `sched()`.
 /// In both cases one is forced to acquire a new `ProcessorHandle`.
 ///
+/// Only a single handle may be alive on a thread at a time, which debug builds assert.
+/// Drop it before calling anything which might acquire another one.
+///
 /// Related issue: https://github.com/zonyitoo/coio-rs/issues/26
 pub struct ProcessorHandle(&'static mut Processor);
 
 impl ProcessorHandle {
+    // Gives up the handle before the current coroutine is suspended,
+    // so that the coroutines resumed in the meantime can acquire their own ones
+    #[inline]
+    fn into_inner(self) -> &'static mut Processor {
+        let processor = &mut *self.0 as *mut Processor;
+        drop(self);
+        unsafe { &mut *processor }
+    }
+
     #[inline]
     pub fn id(&self) -> usize {
         self.0.id()
@@ -73,7 +187,17 @@ impl ProcessorHandle {
 
     #[inline]
     pub fn sched(self) {
-        self.0.sched()
+        self.into_inner().sched()
+    }
+
+    #[inline]
+    pub fn is_running_coroutine(&self) -> bool {
+        self.0.is_running_coroutine()
+    }
+
+    #[inline]
+    pub fn arena(&self) -> &Arena {
+        self.0.arena()
     }
 
     #[inline]
@@ -82,7 +206,7 @@ impl ProcessorHandle {
     }
 
     #[inline]
-    pub fn scheduler(&self) -> &Scheduler {
+    pub fn scheduler(&self) -> &'static Scheduler {
         self.0.scheduler()
     }
 
@@ -96,17 +220,82 @@ impl ProcessorHandle {
         self.0.current_coroutine()
     }
 
+    /// Returns the depth of the coroutines spawned by the current one,
+    /// see `Scheduler::current_depth()`
+    #[inline]
+    pub fn spawn_depth(&mut self) -> usize {
+        if !self.0.is_running_coroutine() {
+            return 0;
+        }
+
+        self.0.current_coroutine().map_or(0, |coro| coro.depth() + 1)
+    }
+
     #[inline]
     pub fn spawn_opts<F: FnOnce() + Send + 'static>(&mut self, f: F, opts: Options) {
         self.spawn_opts_imp(Box::new(f), opts)
     }
 
     pub fn spawn_opts_imp(&mut self, f: Box<FnBox()>, opts: Options) {
-        let new_coro = Coroutine::spawn_opts_with_pool(f, opts, self.stack_pool());
+        if let Some(id) = opts.pinned_to {
+            assert!(id < self.scheduler().get_machines().len(),
+                    "cannot pin a coroutine to the non-existent Processor#{}",
+                    id);
+        }
+
+        let depth = self.spawn_depth();
+        let new_coro = Coroutine::spawn_opts_with_pool(f, opts, depth, self.stack_pool());
+        self.0.observe(&new_coro, SchedulerEvent::Spawn);
         self.ready(new_coro);
         self.scheduler().unpark_processor_maybe(1);
     }
 
+    #[inline]
+    pub fn spawn_batch<I, F>(&mut self, iter: I, opts: Options)
+        where I: IntoIterator<Item = F>,
+              F: FnOnce() + Send + 'static
+    {
+        let fs = iter.into_iter().map(|f| Box::new(f) as Box<FnBox()>).collect();
+        self.spawn_batch_imp(fs, opts)
+    }
+
+    /// Spawns a coroutine for each of the closures, all of them queued at once
+    ///
+    /// In contrast to `spawn_opts()` none of them takes the place of the coroutine
+    /// resumed next. Coroutines pinned to a different Processor are sent there
+    /// in a single `ProcMessage::ReadyBatch`.
+    pub fn spawn_batch_imp(&mut self, fs: Vec<Box<FnBox()>>, opts: Options) {
+        if let Some(id) = opts.pinned_to {
+            assert!(id < self.scheduler().get_machines().len(),
+                    "cannot pin a coroutine to the non-existent Processor#{}",
+                    id);
+        }
+
+        let count = fs.len();
+        let depth = self.spawn_depth();
+        let mut coros = Vec::with_capacity(count);
+
+        for f in fs {
+            let new_coro = Coroutine::spawn_opts_with_pool(f,
+                                                           opts.clone(),
+                                                           depth,
+                                                           self.stack_pool());
+            self.0.observe(&new_coro, SchedulerEvent::Spawn);
+            coros.push(new_coro);
+        }
+
+        match opts.pinned_to {
+            Some(id) if id != self.id() => self.scheduler().ready_pinned_batch(id, coros),
+            _ => {
+                for coro in coros {
+                    self.0.queue_push_back(coro);
+                }
+            }
+        }
+
+        self.scheduler().unpark_processor_maybe(count);
+    }
+
     /// Obtains the currently running coroutine after setting it's state to Parked.
     ///
     /// # Safety
@@ -119,7 +308,7 @@ impl ProcessorHandle {
     pub fn park_with<'scope, F>(self, f: F)
         where F: FnOnce(&mut Processor, Handle) + 'scope
     {
-        let processor = self.0;
+        let processor = self.into_inner();
 
         debug_assert!(processor.current_coro.is_some(), "Coroutine is missing");
 
@@ -148,6 +337,29 @@ impl ProcessorHandle {
     pub fn stack_pool(&mut self) -> &mut StackPool {
         &mut self.0.stack_pool
     }
+
+    /// Drops the coroutines queued on this Processor, if it's shutting down
+    ///
+    /// Returns the number of coroutines dropped. Coroutines dropped during the shutdown
+    /// use this to unwind the ones which borrow from their stack first, see `scope()`.
+    #[doc(hidden)]
+    pub fn drop_queued_coroutines(self) -> usize {
+        // Unwinding the coroutines acquires handles in turn
+        let processor = self.into_inner();
+
+        if !processor.shutdown_received {
+            return 0;
+        }
+
+        processor.drop_queued_coroutines()
+    }
+}
+
+#[cfg(debug_assertions)]
+impl Drop for ProcessorHandle {
+    fn drop(&mut self) {
+        PROCESSOR.with(|slot| slot.release());
+    }
 }
 
 impl Eq for ProcessorHandle {}
@@ -194,6 +406,65 @@ type TakeCoroutineCallback<'a> = &'a mut FnMut(&mut Processor, Handle);
 //   runqput() and runqget() for pushing and shifting Handles
 //   runqgrab() and runqsteal() for stealing Handles
 
+struct ResumeState {
+    coro: *const Coroutine,
+    start: Instant,
+    reported: bool,
+}
+
+// A locked list of Handles, whose length can be read without taking the lock
+//
+// The length is only updated while the lock is held, but may be outdated once it's read.
+struct LockedQueue {
+    list: Spinlock<HandleList>,
+    len: AtomicUsize,
+}
+
+impl LockedQueue {
+    fn new() -> LockedQueue {
+        LockedQueue {
+            list: Spinlock::new(HandleList::new()),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn push_back(&self, hdl: Handle) {
+        let mut list = self.list.lock();
+        list.push_back(hdl);
+        self.len.store(list.len(), Ordering::Relaxed);
+    }
+
+    fn pop_front(&self) -> Option<Handle> {
+        // Skips the lock when there's nothing to pop, e.g. while stealing
+        if self.is_empty() {
+            return None;
+        }
+
+        let mut list = self.list.lock();
+        let hdl = list.pop_front();
+        self.len.store(list.len(), Ordering::Relaxed);
+        hdl
+    }
+
+    fn remove(&self, coro: *const Coroutine) -> Option<Handle> {
+        let mut list = self.list.lock();
+        let pos = list.iter().position(|hdl: &Handle| &**hdl as *const Coroutine == coro);
+        let hdl = pos.and_then(|pos| list.remove(pos));
+        self.len.store(list.len(), Ordering::Relaxed);
+        hdl
+    }
+}
+
 /// Processing unit of a thread
 pub struct ProcessorInner {
     id: usize,
@@ -201,8 +472,9 @@ pub struct ProcessorInner {
     weak_self: WeakProcessor,
     scheduler: *mut Scheduler,
 
+    // NOTE: The Processor holds no Sender itself, so that the channel disconnects
+    //       once the Scheduler dropped all of them, see ProcessorError::Disconnected.
     chan_receiver: Receiver<ProcMessage>,
-    chan_sender: Sender<ProcMessage>,
 
     /// The backing of the SPMC ring buffer forming the execution queue for the Processor
     ///
@@ -230,64 +502,98 @@ pub struct ProcessorInner {
     /// but might be read by foreign ones.
     queue_tail: AtomicUsize,
 
+    /// Local queues for coroutines with a priority other than `Priority::Normal`
+    ///
+    /// In contrast to the ring buffer above these are simple locked lists, since they are
+    /// expected to be short. Both can be stolen from by other Processors as well.
+    high_queue: LockedQueue,
+    low_queue: LockedQueue,
+
+    /// Local queue for coroutines pinned to this Processor
+    ///
+    /// It is never stolen from. Whenever a pinned coroutine becomes ready on a different thread
+    /// it is sent back via `ProcMessage::Ready` and moved into this queue.
+    pinned_queue: LockedQueue,
+
+    // Alternates between the pinned queue and the ring buffer in queue_pop_front(),
+    // so that neither of them can starve the other one.
+    pinned_turn: bool,
+
+    // Number of messages sent through chan_sender which haven't been received yet
+    pending_message_count: AtomicUsize,
+
+    // Set once all Processors acknowledged ProcMessage::Shutdown,
+    // so that schedule() only drops the remaining coroutines if it's restarted after a panic.
+    shutdown_received: bool,
+
+    /// Number of schedule() iterations, used to check the global queue once in a while
+    schedule_tick: usize,
+
+    /// Number of coroutines this Processor has stolen from other Processors
+    steal_count: AtomicUsize,
+
+    /// Whether the Processor is currently parked in `Scheduler::park_processor()`
+    parked: AtomicBool,
+
+    /// Whether the Processor was removed by `Scheduler::remove_processor()`
+    retired: AtomicBool,
+
+    /// Moving average of successful steal attempts, see `AdaptiveParking`
+    steal_rate: AtomicUsize,
+
+    /// The coroutine currently being resumed and when it was resumed, see `watchdog_check()`
+    ///
+    /// It's only maintained if the Scheduler has a watchdog.
+    resume_state: Spinlock<ResumeState>,
+
     // NOTE: current_coro is ONLY to be used by resume() and park_with().
     current_coro: Option<Handle>,
+
+    /// Whether a coroutine is being resumed right now
+    ///
+    /// current_coro can't tell this, since it's used for the next coroutine in between.
+    running: bool,
+
+    arena: Arena,
     rand_order: RandomProcessorOrder,
-    rng: rand::XorShiftRng,
+    rng: XorShiftRng,
+    // Reused by policy_steal() to avoid an allocation per attempt
+    steal_candidates: Vec<StealCandidate>,
 
     stack_pool: StackPool,
 }
 
 impl Processor {
     /// Spawns a new thread and runs a new Processor on it.
+    ///
+    /// The `initial` coroutine, if any, is pushed into the local queue before scheduling begins.
     pub fn spawn(sched: *mut Scheduler,
                  processor_id: usize,
                  barrier: Arc<Barrier>,
-                 max_stack_memory_limit: usize)
+                 max_stack_memory_limit: usize,
+                 initial: Option<Handle>)
                  -> Machine {
         let (tx, rx) = mpsc::channel();
+        let p = Processor::new(sched, processor_id, max_stack_memory_limit, rx);
 
-        let mut p = Processor(Arc::new(UnsafeCell::new(ProcessorInner {
-            id: processor_id,
-
-            weak_self: unsafe { mem::zeroed() },
-            scheduler: sched,
-
-            chan_receiver: rx,
-            chan_sender: tx,
-
-            queue_head: AtomicUsize::new(0),
-            queue_tail: AtomicUsize::new(0),
-            queue: unsafe { mem::zeroed() },
-
-            current_coro: None,
-            rand_order: RandomProcessorOrder::new(),
-            rng: rand::weak_rng(),
-
-            stack_pool: StackPool::new(Some(max_stack_memory_limit / 2),
-                                       Some(max_stack_memory_limit)),
-        })));
-
-        {
-            let weak_self = WeakProcessor(Arc::downgrade(&p.0));
-            let inner = p.deref_mut();
-            mem::forget(mem::replace(&mut inner.weak_self, weak_self));
-        }
-
-        let processor_handle = p.handle();
+        let processor_handle = ProcMessageSender {
+            inner: tx,
+            processor: p.clone(),
+        };
         let processor = p.clone();
         let thread_handle = {
             Builder::new()
                 .name(format!("Processor#{}", processor_id))
                 .stack_size(32 * 1024)
                 .spawn(move || {
-                    PROCESSOR.with(|proc_opt| unsafe {
-                        let proc_opt = &mut *proc_opt.get();
-                        *proc_opt = Some(p.clone());
-                    });
+                    PROCESSOR.with(|slot| slot.set(p.clone()));
+
+                    if let Some(hdl) = initial {
+                        p.queue_push_back(hdl);
+                    }
 
                     barrier.wait();
-                    p.schedule();
+                    p.run();
                 })
                 .unwrap()
         };
@@ -299,17 +605,83 @@ impl Processor {
         }
     }
 
+    fn new(sched: *mut Scheduler,
+           processor_id: usize,
+           max_stack_memory_limit: usize,
+           chan_receiver: Receiver<ProcMessage>)
+           -> Processor {
+        let mut p = Processor(Arc::new(UnsafeCell::new(ProcessorInner {
+            id: processor_id,
+
+            // Points to the Processor as soon as it's Arc exists, see below
+            weak_self: WeakProcessor(Weak::new()),
+            scheduler: sched,
+
+            chan_receiver: chan_receiver,
+
+            queue_head: AtomicUsize::new(0),
+            queue_tail: AtomicUsize::new(0),
+            queue: unsafe { mem::zeroed() },
+
+            high_queue: LockedQueue::new(),
+            low_queue: LockedQueue::new(),
+            pinned_queue: LockedQueue::new(),
+            pinned_turn: false,
+
+            pending_message_count: AtomicUsize::new(0),
+            shutdown_received: false,
+            schedule_tick: 0,
+
+            steal_count: AtomicUsize::new(0),
+            parked: AtomicBool::new(false),
+            retired: AtomicBool::new(false),
+            steal_rate: AtomicUsize::new(STEAL_RATE_ONE),
+
+            resume_state: Spinlock::new(ResumeState {
+                coro: ptr::null(),
+                start: Instant::now(),
+                reported: false,
+            }),
+
+            current_coro: None,
+            running: false,
+            arena: Arena::new(),
+            rand_order: RandomProcessorOrder::new(),
+            rng: processor_rng(unsafe { (*sched).seed() }, processor_id),
+            steal_candidates: Vec::new(),
+
+            stack_pool: StackPool::new(Some(max_stack_memory_limit / 2),
+                                       Some(max_stack_memory_limit)),
+        })));
+
+        p.weak_self = WeakProcessor(Arc::downgrade(&p.0));
+        p
+    }
+
     /// Get the thread local processor.
     ///
     /// # Safety
     ///
     /// This method *is* thread safe.
+    ///
+    /// # Panics
+    ///
+    /// Debug builds panic if another `ProcessorHandle` of this thread is still alive.
     // NOTE:
     //   This is required to be inline(never) due to
     //   https://github.com/rust-lang/rust/commit/12c5fc5877f708e8e4df05bf834261f5237ac437
     #[inline(never)]
     pub fn current() -> Option<ProcessorHandle> {
-        PROCESSOR.with(|proc_opt| unsafe { (&mut *proc_opt.get()) }).as_mut().map(ProcessorHandle)
+        PROCESSOR.with(|slot| slot.acquire())
+    }
+
+    /// Like `current()`, but returns None instead of panicking if another handle is alive
+    ///
+    /// For code which may run while a handle is alive, like the panic hook.
+    #[doc(hidden)]
+    #[inline(never)]
+    pub fn try_current() -> Option<ProcessorHandle> {
+        PROCESSOR.with(|slot| if slot.is_borrowed() { None } else { slot.acquire() })
     }
 
     #[inline(never)]
@@ -338,6 +710,27 @@ impl Processor {
         self.current_coro.as_mut()
     }
 
+    /// Returns true if this is called from within a coroutine running on this Processor.
+    ///
+    /// # Safety
+    ///
+    /// This method *is not* thread safe.
+    #[inline]
+    pub fn is_running_coroutine(&self) -> bool {
+        self.thread_assert();
+        self.running
+    }
+
+    /// Returns the arena lent out by `Scheduler::with_arena()`.
+    ///
+    /// # Safety
+    ///
+    /// This method *is* thread safe.
+    #[inline]
+    pub fn arena(&self) -> &Arena {
+        &self.arena
+    }
+
     /// Returns a reference to a weak self reference.
     ///
     /// # Safety
@@ -360,15 +753,24 @@ impl Processor {
 
     /// Returns the handle through which messages can be sent to this instance.
     pub fn handle(&self) -> ProcMessageSender {
-        ProcMessageSender {
-            inner: self.chan_sender.clone(),
-            _processor: self.clone(),
-        }
+        self.scheduler().get_machines()[self.id].processor_handle.clone()
     }
 
-    /// Enqueue a coroutine to be resumed as soon as possible (making it the head of the queue)
-    pub fn ready(&mut self, coro: Handle) {
-        if self.current_coro.is_none() {
+    /// Enqueue a coroutine to be resumed
+    ///
+    /// If no coroutine is running, i.e. if it's called by the Processor itself, the coroutine
+    /// will be resumed next (making it the head of the queue), unless it was spawned with
+    /// `Options::fifo()`. Otherwise it's pushed to the tail of the queue.
+    pub fn ready(&mut self, mut coro: Handle) {
+        coro.bind_to_processor(self.id);
+
+        if let Some(id) = coro.pinned_to() {
+            if id != self.id {
+                return self.scheduler().ready_pinned(id, coro);
+            }
+        }
+
+        if self.current_coro.is_none() && !coro.is_fifo() {
             self.current_coro = Some(coro);
         } else {
             self.queue_push_back(coro);
@@ -387,14 +789,21 @@ impl Processor {
         }
     }
 
+    // Reports an event to the SchedulerObserver, if there is one
+    #[inline]
+    fn observe(&self, coro: &Coroutine, event: SchedulerEvent) {
+        if let Some(observer) = self.scheduler().observer() {
+            observer.on_event(coro.id(), self.id, event);
+        }
+    }
+
     // Helper method to ensure that private, thread unsafe methods are never
     // somehow called from foreign threads through public methods.
     #[cfg(debug_assertions)]
     fn thread_assert(&self) {
-        if let Some(p) = Processor::current() {
-            if p.id() == self.id {
-                return;
-            }
+        // Called from within the methods of handles, so it mustn't acquire another one
+        if PROCESSOR.with(|slot| slot.id()) == Some(self.id) {
+            return;
         }
 
         panic!("called a thread unsafe method from a foreign thread");
@@ -404,54 +813,223 @@ impl Processor {
     #[inline(always)]
     fn thread_assert(&self) {}
 
-    fn queue_empty(&self) -> bool {
-        self.queue_head.load(Ordering::Relaxed) == self.queue_tail.load(Ordering::Relaxed)
-    }
-
-    fn queue_pop_front(&mut self) -> Option<Handle> {
-        self.thread_assert();
-
-        loop {
-            let h = self.queue_head.load(Ordering::Acquire);
-            let t = self.queue_tail.load(Ordering::Relaxed);
+    /// Returns the number of coroutines waiting in the local queues.
+    ///
+    /// # Safety
+    ///
+    /// This method *is* thread safe, but the result might be outdated by the time it's returned.
+    pub fn queue_len(&self) -> usize {
+        let h = self.queue_head.load(Ordering::Relaxed);
+        let t = self.queue_tail.load(Ordering::Relaxed);
 
-            if t == h {
-                return None;
-            }
+        t.wrapping_sub(h) + self.high_queue.len() + self.low_queue.len() + self.pinned_queue.len()
+    }
 
-            let coro = unsafe { *self.queue.get_unchecked(h % QUEUE_SIZE) };
+    /// Returns the ID, name and running time of the currently resumed coroutine,
+    /// if it's been running for longer than `threshold` without yielding.
+    ///
+    /// Each resume of a coroutine is only reported once.
+    ///
+    /// # Safety
+    ///
+    /// This method *is* thread safe.
+    pub fn watchdog_check(&self, threshold: Duration) -> Option<(u64, Option<String>, Duration)> {
+        let mut state = self.resume_state.lock();
 
-            if self.queue_head.compare_and_swap(h, h.wrapping_add(1), Ordering::Release) == h {
-                let hdl = Some(unsafe { Handle::from_raw(coro) });
-                trace!("{:?}: popped {:?} from local queue", self, hdl);
-                return hdl;
-            }
+        if state.coro.is_null() || state.reported {
+            return None;
         }
-    }
 
-    fn queue_push_back(&mut self, hdl: Handle) {
-        self.thread_assert();
-        trace!("{:?}: pushing {:?} to local queue", self, hdl);
-
-        let coro = hdl.into_raw();
+        let elapsed = state.start.elapsed();
 
-        loop {
-            let h = self.queue_head.load(Ordering::Acquire);
-            let t = self.queue_tail.load(Ordering::Relaxed);
+        if elapsed < threshold {
+            return None;
+        }
 
-            if t.wrapping_sub(h) < QUEUE_SIZE {
-                unsafe { *self.queue.get_unchecked_mut(t % QUEUE_SIZE) = coro };
-                self.queue_tail.store(t.wrapping_add(1), Ordering::Release);
-                return;
-            }
+        // The coroutine can't be dropped while it's set in the resume_state,
+        // since resume() needs to acquire the lock to reset it first.
+        let coro = unsafe { &*state.coro };
 
-            if self.queue_push_back_slow(coro, h, t) {
-                return;
-            }
+        // It's checked again later on, in case it keeps on blocking after allow_blocking()
+        if coro.is_blocking_allowed() {
+            return None;
         }
+
+        state.reported = true;
+        Some((coro.id(), coro.name().map(str::to_owned), elapsed))
     }
 
-    /// Only to be called by queue_push_back()
+    /// Returns the cumulative number of coroutines stolen from other Processors.
+    ///
+    /// # Safety
+    ///
+    /// This method *is* thread safe.
+    #[inline]
+    pub fn steal_count(&self) -> usize {
+        self.steal_count.load(Ordering::Relaxed)
+    }
+
+    /// Returns the moving average of successful steal attempts out of `STEAL_RATE_ONE`.
+    ///
+    /// # Safety
+    ///
+    /// This method *is* thread safe.
+    #[inline]
+    pub fn steal_rate(&self) -> usize {
+        self.steal_rate.load(Ordering::Relaxed)
+    }
+
+    /// Removes `coro` from the local low priority queue, if it's queued there.
+    ///
+    /// # Safety
+    ///
+    /// This method *is* thread safe.
+    pub fn take_low_priority(&self, coro: *const Coroutine) -> Option<Handle> {
+        self.low_queue.remove(coro)
+    }
+
+    /// Returns true if the Processor is parked and waiting for work.
+    ///
+    /// # Safety
+    ///
+    /// This method *is* thread safe.
+    #[inline]
+    pub fn is_parked(&self) -> bool {
+        self.parked.load(Ordering::Relaxed)
+    }
+
+    /// Returns true if the Processor was removed, see `Scheduler::remove_processor()`.
+    ///
+    /// # Safety
+    ///
+    /// This method *is* thread safe.
+    #[inline]
+    pub fn is_retired(&self) -> bool {
+        self.retired.load(Ordering::Relaxed)
+    }
+
+    /// Marks the Processor as removed, so that it's skipped by the dispatch of new coroutines.
+    ///
+    /// It only stops scheduling once it receives a `ProcMessage::Retire`.
+    ///
+    /// # Safety
+    ///
+    /// This method *is* thread safe.
+    #[inline]
+    pub fn set_retired(&self) {
+        self.retired.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns the approximate number of coroutines queued on this Processor.
+    ///
+    /// # Safety
+    ///
+    /// This method *is* thread safe.
+    pub fn load(&self) -> usize {
+        // NOTE: An inconsistent head and tail might make the length overflow
+        cmp::min(self.queue_ring_len(), QUEUE_SIZE) +
+        self.pending_message_count.load(Ordering::Relaxed)
+    }
+
+    /// Returns the approximate number of coroutines in the local ring buffer.
+    ///
+    /// This is the part of the queue which can be stolen by `queue_steal()`.
+    #[inline]
+    fn queue_ring_len(&self) -> usize {
+        let h = self.queue_head.load(Ordering::Relaxed);
+        let t = self.queue_tail.load(Ordering::Relaxed);
+        t.wrapping_sub(h)
+    }
+
+    fn queue_empty(&self) -> bool {
+        self.queue_head.load(Ordering::Relaxed) == self.queue_tail.load(Ordering::Relaxed) &&
+        self.high_queue.is_empty() && self.low_queue.is_empty() && self.pinned_queue.is_empty()
+    }
+
+    fn queue_pop_front(&mut self) -> Option<Handle> {
+        self.thread_assert();
+
+        if let Some(hdl) = self.high_queue.pop_front() {
+            trace!("{:?}: popped {:?} from local high priority queue", self, hdl);
+            return Some(hdl);
+        }
+
+        self.pinned_turn = !self.pinned_turn;
+
+        if self.pinned_turn {
+            if let Some(hdl) = self.pinned_queue.pop_front() {
+                trace!("{:?}: popped {:?} from local pinned queue", self, hdl);
+                return Some(hdl);
+            }
+        }
+
+        loop {
+            let h = self.queue_head.load(Ordering::Acquire);
+            let t = self.queue_tail.load(Ordering::Relaxed);
+
+            if t == h {
+                break;
+            }
+
+            let coro = unsafe { *self.queue.get_unchecked(h % QUEUE_SIZE) };
+
+            if self.queue_head.compare_and_swap(h, h.wrapping_add(1), Ordering::Release) == h {
+                let hdl = Some(unsafe { Handle::from_raw(coro) });
+                trace!("{:?}: popped {:?} from local queue", self, hdl);
+                return hdl;
+            }
+        }
+
+        if let Some(hdl) = self.pinned_queue.pop_front() {
+            trace!("{:?}: popped {:?} from local pinned queue", self, hdl);
+            return Some(hdl);
+        }
+
+        let hdl = self.low_queue.pop_front();
+
+        if hdl.is_some() {
+            trace!("{:?}: popped {:?} from local low priority queue", self, hdl);
+        }
+
+        hdl
+    }
+
+    fn queue_push_back(&mut self, mut hdl: Handle) {
+        self.thread_assert();
+        trace!("{:?}: pushing {:?} to local queue", self, hdl);
+
+        // Coroutines which may not be stolen must stay out of the ring buffer from now on
+        hdl.bind_to_processor(self.id);
+
+        if hdl.pinned_to().is_some() {
+            return self.pinned_queue.push_back(hdl);
+        }
+
+        match hdl.priority() {
+            Priority::High => return self.high_queue.push_back(hdl),
+            Priority::Low => return self.low_queue.push_back(hdl),
+            Priority::Normal => {}
+        }
+
+        let coro = hdl.into_raw();
+
+        loop {
+            let h = self.queue_head.load(Ordering::Acquire);
+            let t = self.queue_tail.load(Ordering::Relaxed);
+
+            if t.wrapping_sub(h) < QUEUE_SIZE {
+                unsafe { *self.queue.get_unchecked_mut(t % QUEUE_SIZE) = coro };
+                self.queue_tail.store(t.wrapping_add(1), Ordering::Release);
+                return;
+            }
+
+            if self.queue_push_back_slow(coro, h, t) {
+                return;
+            }
+        }
+    }
+
+    /// Only to be called by queue_push_back()
     #[cold]
     fn queue_push_back_slow(&mut self, coro: *mut Coroutine, h: usize, t: usize) -> bool {
         let mut batch: [*mut Coroutine; QUEUE_SIZE / 2 + 1] = unsafe { mem::uninitialized() };
@@ -529,6 +1107,14 @@ impl Processor {
         }
 
         trace!("{:?}: stole {} Coroutines from {:?}", self, n, from);
+        self.steal_count.fetch_add(n, Ordering::Relaxed);
+
+        if self.scheduler().observer().is_some() {
+            for i in 0..n {
+                let coro = unsafe { &**self.queue.get_unchecked(t.wrapping_add(i) % QUEUE_SIZE) };
+                self.observe(coro, SchedulerEvent::Steal);
+            }
+        }
 
         let n = n - 1;
         let coro = unsafe { *self.queue.get_unchecked(t.wrapping_add(n) % QUEUE_SIZE) };
@@ -618,10 +1204,76 @@ impl Processor {
     }
 
     fn fetch_foreign_coroutines(&mut self) -> Option<Handle> {
-        // Randomly steal from neighbors
+        // There are no neighbors to steal from and the ones in the local queues
+        // were already checked, so skip the random order below
+        if self.scheduler().is_single_threaded() {
+            return self.global_queue_get_batch();
+        }
+
+        let machines = self.scheduler().get_machines();
+
+        // Processors might have been added in the meantime, see Scheduler::add_processor()
+        if self.rand_order.count != machines.len() {
+            self.rand_order.reset(machines.len());
+        }
+
+        // Prefer stealing coroutines with a high priority
         {
-            let machines = self.scheduler().get_machines();
+            let rnd = self.rng.gen();
+
+            for x in self.rand_order.iter(rnd) {
+                let hdl = machines[x].processor.high_queue.pop_front();
+
+                if hdl.is_some() {
+                    trace!("{:?}: stole {:?} from {:?}", self, hdl, machines[x].processor);
+                    self.steal_count.fetch_add(1, Ordering::Relaxed);
+
+                    if let Some(ref coro) = hdl {
+                        self.observe(coro, SchedulerEvent::Steal);
+                    }
+
+                    return hdl;
+                }
+            }
+        }
+
+        // A StealPolicy replaces the built-in strategies below
+        let policy = self.scheduler().steal_policy();
+
+        if let Some(policy) = policy {
+            let hdl = self.policy_steal(policy, machines);
+
+            if hdl.is_some() {
+                return hdl;
+            }
+        }
 
+        // Steal from the neighbor with the longest queue
+        if policy.is_none() && self.scheduler().steal_strategy() == StealStrategy::Longest {
+            let mut victim = None;
+            let mut victim_len = 0;
+
+            for (x, machine) in machines.iter().enumerate() {
+                let len = machine.processor.queue_ring_len();
+
+                // NOTE: An inconsistent head and tail might make the length overflow
+                if len > victim_len && len <= QUEUE_SIZE {
+                    victim = Some(x);
+                    victim_len = len;
+                }
+            }
+
+            if let Some(x) = victim {
+                let hdl = self.queue_steal(&mut machines[x].processor);
+
+                if hdl.is_some() {
+                    return hdl;
+                }
+            }
+        }
+
+        // Randomly steal from neighbors
+        if policy.is_none() {
             for _ in 0..4 {
                 let rnd = self.rng.gen();
 
@@ -644,27 +1296,182 @@ impl Processor {
             }
         }
 
+        // Steal coroutines with a low priority last
+        {
+            let rnd = self.rng.gen();
+
+            for x in self.rand_order.iter(rnd) {
+                let hdl = machines[x].processor.low_queue.pop_front();
+
+                if hdl.is_some() {
+                    trace!("{:?}: stole {:?} from {:?}", self, hdl, machines[x].processor);
+                    self.steal_count.fetch_add(1, Ordering::Relaxed);
+
+                    if let Some(ref coro) = hdl {
+                        self.observe(coro, SchedulerEvent::Steal);
+                    }
+
+                    return hdl;
+                }
+            }
+        }
+
         None
     }
 
-    fn schedule(&mut self) {
+    // Steals from the Processors picked by the Scheduler's StealPolicy
+    fn policy_steal(&mut self, policy: &StealPolicy, machines: &mut [Machine]) -> Option<Handle> {
+        // Taken out of self, since it's borrowed along with the rest of the Processor below
+        let mut candidates = mem::replace(&mut self.steal_candidates, Vec::new());
+        candidates.clear();
+
+        for machine in machines.iter() {
+            candidates.push(StealCandidate {
+                processor_id: machine.processor.id,
+                // NOTE: An inconsistent head and tail might make the length overflow
+                queue_len: cmp::min(machine.processor.queue_ring_len(), QUEUE_SIZE),
+            });
+        }
+
+        let mut attempt = 0;
+        let mut hdl = None;
+
+        while let Some(x) = policy.next_victim(self.id, &candidates, &mut self.rng, attempt) {
+            if x >= machines.len() {
+                break;
+            }
+
+            attempt += 1;
+
+            let before = self.steal_count.load(Ordering::Relaxed);
+            hdl = self.queue_steal(&mut machines[x].processor);
+            let stolen = self.steal_count.load(Ordering::Relaxed) - before;
+
+            policy.on_steal(self.id, machines[x].processor.id, stolen);
+
+            if hdl.is_some() {
+                break;
+            }
+        }
+
+        self.steal_candidates = candidates;
+        hdl
+    }
+
+    // Runs schedule() and restarts it whenever the Processor itself panics, e.g. in an observer.
+    // A panic would otherwise kill the thread and the coroutines in it's queues,
+    // which stay in place and are either resumed or stolen as usual after the restart,
+    // while the Scheduler would wait for the dead Processor forever during the shutdown.
+    fn run(&mut self) {
+        loop {
+            let ret = {
+                let this = &mut *self;
+                panic::catch_unwind(panic::AssertUnwindSafe(move || this.schedule()))
+            };
+
+            match ret {
+                Ok(Ok(())) => break,
+                Ok(Err(err)) => {
+                    error!("{:?}: {}, stopping", self, err);
+                    self.scheduler().processor_failed(self.id, err);
+                    break;
+                }
+                Err(err) => {
+                    error!("{:?}: panicked, restarting", self);
+
+                    self.resume_state.lock().coro = ptr::null();
+
+                    if let Some(coro) = self.current_coro.take() {
+                        if !coro.is_finished() {
+                            self.queue_push_back(coro);
+                        }
+                    }
+
+                    self.scheduler().processor_panicked(self.id, err);
+                }
+            }
+        }
+    }
+
+    fn schedule(&mut self) -> Result<(), ProcessorError> {
         self.thread_assert();
         trace!("{:?}: local scheduler begin", self);
 
         let machine_len = self.scheduler().get_machines().len();
         let scheduler = self.scheduler();
         let mut run_next = None;
+        let mut backoff = 0;
 
         self.rand_order.reset(machine_len);
 
-        loop {
-            if let Ok(ProcMessage::Shutdown(barrier)) = self.chan_receiver.try_recv() {
+        while !self.shutdown_received {
+            let mut shutdown: Option<Arc<ShutdownBarrier>> = None;
+
+            loop {
+                let msg = match self.chan_receiver.try_recv() {
+                    Ok(msg) => msg,
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        return self.schedule_disconnected(run_next);
+                    }
+                };
+
+                self.pending_message_count.fetch_sub(1, Ordering::Relaxed);
+
+                match msg {
+                    ProcMessage::Shutdown(barrier) => {
+                        shutdown = Some(barrier);
+                        break;
+                    }
+                    ProcMessage::Retire => return self.schedule_retired(run_next),
+                    ProcMessage::Ready(hdl) => {
+                        trace!("{:?}: got pinned {:?}", self, hdl);
+                        self.queue_push_back(hdl);
+                    }
+                    ProcMessage::ReadyBatch(hdls) => {
+                        trace!("{:?}: got {} pinned coroutines", self, hdls.len());
+
+                        for hdl in hdls {
+                            self.queue_push_back(hdl);
+                        }
+                    }
+                }
+            }
+
+            if let Some(shutdown) = shutdown {
                 trace!("{:?}: got shutdown signal", self);
-                barrier.wait();
-                break;
+                shutdown.quiesce();
+                self.shutdown_received = true;
+
+                // NOTE:
+                //   All other Processors stopped scheduling as well at this point. Until the
+                //   drain is over, they only touch the queues of others through Processor::ready()
+                //   and the message channels, which is why it's done in rounds.
+                trace!("{:?}: dropping queued coroutines", self);
+                shutdown.drain(|| {
+                    let dropped = if run_next.take().is_some() { 1 } else { 0 };
+                    dropped + self.drop_queued_coroutines()
+                });
+
+                trace!("{:?}: local scheduler end", self);
+                return Ok(());
             }
 
-            // TODO: Ensure that coroutines from foreign queues are fetched once in a while.
+            // Coroutines which keep the local queue busy, e.g. by yielding or waking each other
+            // up, would otherwise starve the global queue, which is only checked once the local
+            // queue runs empty. global_queue_get_batch() moves at most half of the local queue's
+            // capacity, so the local queue isn't starved in turn.
+            self.schedule_tick = self.schedule_tick.wrapping_add(1);
+
+            if self.schedule_tick % GLOBAL_QUEUE_INTERVAL == 0 {
+                if let Some(hdl) = self.global_queue_get_batch() {
+                    if let Some(prev) = run_next.take() {
+                        self.queue_push_back(prev);
+                    }
+
+                    run_next = Some(hdl);
+                }
+            }
 
             // Run tasks in local queue
             if run_next.is_none() {
@@ -672,65 +1479,300 @@ impl Processor {
             }
 
             if run_next.is_none() {
+                // Coroutines can only allocate from it within Scheduler::with_arena()
+                unsafe { self.arena.reset_if_unused() };
+
                 scheduler.inc_spinning();
                 run_next = self.fetch_foreign_coroutines();
+
+                for _ in 1..scheduler.steal_retries() {
+                    let pending = self.pending_message_count.load(Ordering::Acquire);
+
+                    if run_next.is_some() || pending > 0 {
+                        break;
+                    }
+
+                    thread::yield_now();
+                    run_next = self.fetch_foreign_coroutines();
+                }
+
                 scheduler.dec_spinning();
+
+                // Only the first attempt after the local queue ran empty is sampled,
+                // since the ones during the backoff below would mostly be failures.
+                if let (0, Some(params)) = (backoff, scheduler.adaptive_parking()) {
+                    let rate = self.steal_rate.load(Ordering::Relaxed);
+                    let rate = update_steal_rate(rate, run_next.is_some(), params);
+                    self.steal_rate.store(rate, Ordering::Relaxed);
+                }
+
+                // The stolen coroutine goes through the local queue like every other one,
+                // behind the rest of the batch stolen along with it.
+                if let Some(hdl) = run_next.take() {
+                    self.queue_push_back(hdl);
+                    backoff = 0;
+                    continue;
+                }
             }
 
             if let Some(hdl) = run_next {
                 run_next = self.resume(hdl);
+                backoff = 0;
+                continue;
+            }
+
+            // Back off for a while before parking, since new work will often arrive shortly
+            // under bursty load. Pinned coroutines and the shutdown signal are still checked
+            // at the beginning of each iteration.
+            let (mut spins, yields, timeout) = scheduler.park_backoff();
+
+            if let Some(params) = scheduler.adaptive_parking() {
+                spins = adapt_spins(spins, self.steal_rate.load(Ordering::Relaxed), params);
+            }
+
+            if backoff < spins {
+                for _ in 0..(PARK_SPIN_BASE << backoff) {
+                    spinlock::cpu_relax();
+                }
+                backoff += 1;
+            } else if backoff < spins + yields {
+                thread::yield_now();
+                backoff += 1;
             } else {
+                let timeout = if backoff == spins + yields && timeout != Duration::from_millis(0) {
+                    backoff += 1;
+                    Some(timeout)
+                } else {
+                    backoff = 0;
+                    None
+                };
+
+                if let Some(handler) = scheduler.idle_handler() {
+                    handler(self);
+
+                    // Coroutines readied by the handler end up in current_coro or the queues
+                    run_next = self.current_coro.take();
+
+                    if run_next.is_some() || !self.queue_empty() {
+                        backoff = 0;
+                        continue;
+                    }
+                }
+
+                // There's no need to poll for I/O here: The event loop pushes the coroutines
+                // readied by it into the global queue or sends them to their Processor,
+                // both of which unpark the Processors (see benches/echo_latency.rs).
                 trace!("{:?}: parking", self);
-                scheduler.park_processor(|| {
+                self.parked.store(true, Ordering::Relaxed);
+                scheduler.park_processor(timeout, || {
                     run_next = self.fetch_foreign_coroutines();
-                    run_next.is_none()
+                    run_next.is_none() && self.pending_message_count.load(Ordering::Acquire) == 0
                 });
+                self.parked.store(false, Ordering::Relaxed);
                 trace!("{:?}: unparked", self);
             }
         }
 
         // NOTE:
-        //   A Barrier is sent inside the ProcMessage::Shutdown and
-        //   awaited as soon as the Shutdown message is received.
-        //   This means that this point in schedule() outside of the main loop above is only
-        //   reached when all other Processors have fully acknowledged the Shutdown message too.
-        //   We can thus safely access any local members without synchronization.
-
-        trace!("{:?}: dropping run_next", self);
+        //   This point is only reached if schedule() was restarted after a panic during the
+        //   drain above. The other Processors are still waiting for this one to finish it's
+        //   round, so the remaining coroutines are dropped without taking part in more rounds.
         drop(run_next);
+        while self.drop_queued_coroutines() > 0 {}
+
+        trace!("{:?}: local scheduler end", self);
+        Ok(())
+    }
+
+    /// Leaves schedule() after the channel disconnected without a `ProcMessage::Shutdown`.
+    ///
+    /// The Scheduler can't reach the Processor anymore, so it stops without waiting for the
+    /// others. Since those might still be running, the local coroutines are handed over to them.
+    #[cold]
+    fn schedule_disconnected(&mut self, run_next: Option<Handle>) -> Result<(), ProcessorError> {
+        let scheduler = self.scheduler();
+
+        if let Some(hdl) = run_next {
+            scheduler.push_global_queue(hdl);
+        }
+
+        // NOTE: queue_pop_front() might skip the pinned queue once
+        while !self.queue_empty() {
+            if let Some(hdl) = self.queue_pop_front() {
+                scheduler.push_global_queue(hdl);
+            }
+        }
+
+        trace!("{:?}: local scheduler end (disconnected)", self);
+        Err(ProcessorError::Disconnected)
+    }
+
+    /// Leaves schedule() after the Processor was removed by `Scheduler::remove_processor()`.
+    ///
+    /// The local coroutines are unpinned and handed over to the other Processors. Threads which
+    /// haven't seen the Processor retire yet might still send coroutines to it, which is why
+    /// it keeps forwarding them until the Scheduler shuts down, instead of exiting right away.
+    #[cold]
+    fn schedule_retired(&mut self, run_next: Option<Handle>) -> Result<(), ProcessorError> {
+        trace!("{:?}: retiring", self);
+
+        if let Some(hdl) = run_next {
+            self.migrate(hdl);
+        }
+
+        // NOTE: queue_pop_front() might skip the pinned queue once
+        while !self.queue_empty() {
+            if let Some(hdl) = self.queue_pop_front() {
+                self.migrate(hdl);
+            }
+        }
+
+        loop {
+            let msg = match self.chan_receiver.recv() {
+                Ok(msg) => msg,
+                Err(..) => break,
+            };
+
+            self.pending_message_count.fetch_sub(1, Ordering::Relaxed);
+
+            match msg {
+                ProcMessage::Shutdown(shutdown) => {
+                    shutdown.quiesce();
+                    self.shutdown_received = true;
+
+                    // The queues are empty, but coroutines might still be sent to this Processor
+                    shutdown.drain(|| self.drop_queued_coroutines());
+                    break;
+                }
+                ProcMessage::Retire => {}
+                ProcMessage::Ready(hdl) => self.migrate(hdl),
+                ProcMessage::ReadyBatch(hdls) => {
+                    for hdl in hdls {
+                        self.migrate(hdl);
+                    }
+                }
+            }
+        }
+
+        trace!("{:?}: local scheduler end (retired)", self);
+        Ok(())
+    }
+
+    // Hands a coroutine of a retired Processor over to the others
+    fn migrate(&self, mut hdl: Handle) {
+        // It would only be sent back to this Processor otherwise
+        hdl.unpin();
+        self.scheduler().push_global_queue(hdl);
+    }
+
+    /// Drops all coroutines in the local and global queues and returns their number.
+    fn drop_queued_coroutines(&mut self) -> usize {
+        let mut dropped = 0;
+
+        // Processor::ready() puts coroutines readied during the shutdown into current_coro
+        if let Some(coro) = self.current_coro.take() {
+            drop(coro);
+            dropped += 1;
+        }
 
         trace!("{:?}: dropping local coroutines", self);
         while self.queue_head.load(Ordering::Relaxed) != self.queue_tail.load(Ordering::Relaxed) {
             // pop from tail of local queue
             let t = self.queue_tail.fetch_sub(1, Ordering::Relaxed) - 1;
             let _coro = unsafe { Handle::from_raw(*self.queue.get_unchecked(t % QUEUE_SIZE)) };
+            dropped += 1;
         }
 
-        trace!("{:?}: local scheduler end", self);
+        // The Handles are dropped outside of the locks, since unwinding them runs arbitrary code.
+        // They are popped one by one, so that the coroutines being unwound in the meantime
+        // find the remaining ones, see ProcessorHandle::drop_queued_coroutines().
+        for queue in &[&self.high_queue, &self.low_queue, &self.pinned_queue] {
+            loop {
+                let coro = queue.pop_front();
+
+                match coro {
+                    Some(coro) => drop(coro),
+                    None => break,
+                }
+
+                dropped += 1;
+            }
+        }
+
+        trace!("{:?}: dropping pinned coroutines sent by other Processors", self);
+        while let Ok(msg) = self.chan_receiver.try_recv() {
+            self.pending_message_count.fetch_sub(1, Ordering::Relaxed);
+
+            dropped += match msg {
+                ProcMessage::Ready(..) => 1,
+                ProcMessage::ReadyBatch(ref hdls) => hdls.len(),
+                _ => 0,
+            };
+        }
+
+        trace!("{:?}: dropping global coroutines", self);
+        loop {
+            let coro = self.scheduler().get_global_queue().pop_front();
+
+            match coro {
+                Some(coro) => drop(coro),
+                None => break,
+            }
+
+            dropped += 1;
+        }
+
+        dropped
     }
 
-    fn resume(&mut self, coro: Handle) -> Option<Handle> {
+    fn resume(&mut self, mut coro: Handle) -> Option<Handle> {
         self.thread_assert();
 
         assert!(coro.is_finished() == false,
                 "Cannot resume a finished coroutine");
 
+        coro.set_last_processor(self.id);
+        coro.bind_to_processor(self.id);
+        coro.reset_budget(self.scheduler().yield_budget());
+        coro.set_running();
+
         trace!("{:?}: resuming {:?}", self, coro);
+        self.observe(&coro, SchedulerEvent::Resume);
+
+        let watchdog = self.scheduler().has_watchdog();
+
+        if watchdog {
+            let mut state = self.resume_state.lock();
+            state.coro = &*coro;
+            state.start = Instant::now();
+            state.reported = false;
+        }
+
         let data = {
             self.current_coro = Some(coro);
+            self.running = true;
 
-            if let Some(ref mut c) = self.current_coro {
+            let data = if let Some(ref mut c) = self.current_coro {
                 c.resume(0)
             } else {
                 0
-            }
+            };
+
+            self.running = false;
+            data
         };
 
+        if watchdog {
+            self.resume_state.lock().coro = ptr::null();
+        }
+
         let mut hdl = None;
         if let Some(coro) = self.current_coro.take() {
             trace!("{:?}: yielded with {:?}", &coro, coro.state());
             match coro.state() {
                 State::Suspended => {
+                    self.observe(&coro, SchedulerEvent::Yield);
+
                     // If the currently suspended coroutine is the only local one
                     // we want to ensure that it's not immediately resumed.
                     // Thus we fetch foreign coroutines first and then put the
@@ -742,6 +1784,9 @@ impl Processor {
                     self.queue_push_back(coro);
                 }
                 State::Parked => {
+                    self.observe(&coro, SchedulerEvent::Park);
+                    coro.set_parked();
+
                     assert!(data != 0, "Coroutine parked with data == 0");
                     // Take out the data carrier
                     let carrier = unsafe {
@@ -759,6 +1804,7 @@ impl Processor {
                 }
                 State::Finished => {
                     trace!("{:?}: finished", coro);
+                    self.observe(&coro, SchedulerEvent::Finish);
                 }
                 s => {
                     panic!("Coroutine yielded with invalid state {:?}", s);
@@ -766,6 +1812,15 @@ impl Processor {
             }
         }
 
+        // Coroutines readied from within a park_with() callback took the
+        // place of current_coro and are thus supposed to be resumed next.
+        if let Some(coro) = self.current_coro.take() {
+            match hdl {
+                None => hdl = Some(coro),
+                Some(_) => self.queue_push_back(coro),
+            }
+        }
+
         hdl
     }
 }
@@ -831,9 +1886,118 @@ impl WeakProcessor {
     }
 }
 
+/// Reasons for a Processor to stop scheduling before it received a `ProcMessage::Shutdown`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessorError {
+    /// All senders of the Processor's message channel were dropped
+    Disconnected,
+}
+
+impl fmt::Display for ProcessorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ProcessorError::Disconnected => "message channel disconnected".fmt(f),
+        }
+    }
+}
+
 pub enum ProcMessage {
     /// Ask the processor to shutdown, which will going to force unwind all pending coroutines.
-    Shutdown(Arc<Barrier>),
+    Shutdown(Arc<ShutdownBarrier>),
+    /// A coroutine pinned to the processor, or preferring it (see `Options::wake_affinity()`),
+    /// became ready on a different thread.
+    Ready(Handle),
+    /// Multiple coroutines pinned to the processor were spawned on a different thread.
+    ReadyBatch(Vec<Handle>),
+    /// Ask the processor to hand it's coroutines over to the other ones and stop scheduling,
+    /// see `Scheduler::remove_processor()`.
+    Retire,
+}
+
+/// Synchronizes the Processors while they shut down, see `ProcMessage::Shutdown`
+///
+/// The shutdown happens in two phases: First every Processor stops scheduling and waits for all
+/// others to do the same (`quiesce()`). From then on no coroutines are stolen or sent between
+/// Processors anymore, except for those readied while dropping the queued coroutines: Dropping
+/// one force unwinds it and the destructors on it's stack might wake up others, e.g. by
+/// dropping the Sender of a channel they are waiting on. The queues are thus drained in rounds
+/// (`drain()`), until a round passed in which none of the Processors dropped a coroutine.
+/// Only then no coroutine is left in any queue or message channel.
+pub struct ShutdownBarrier {
+    barrier: Barrier,
+    dropped: AtomicUsize,
+}
+
+impl ShutdownBarrier {
+    /// Creates a barrier, which all `parties` have to take part in
+    pub fn new(parties: usize) -> ShutdownBarrier {
+        ShutdownBarrier {
+            barrier: Barrier::new(parties),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    /// Blocks until all parties stopped scheduling
+    pub fn quiesce(&self) {
+        self.barrier.wait();
+    }
+
+    /// Calls `drop_queued` once per round until no party dropped any coroutines during a round
+    ///
+    /// `drop_queued` returns the number of coroutines it dropped.
+    pub fn drain<F>(&self, mut drop_queued: F)
+        where F: FnMut() -> usize
+    {
+        let mut total = 0;
+
+        loop {
+            self.dropped.fetch_add(drop_queued(), Ordering::SeqCst);
+            self.barrier.wait();
+
+            // Nobody adds to the count of the next round before all parties read it,
+            // so that all of them agree on whether to continue.
+            let dropped = self.dropped.load(Ordering::SeqCst);
+            self.barrier.wait();
+
+            if dropped == total {
+                break;
+            }
+
+            total = dropped;
+        }
+    }
+
+    /// Returns the number of coroutines dropped during the drain
+    pub fn dropped_count(&self) -> usize {
+        self.dropped.load(Ordering::SeqCst)
+    }
+}
+
+// Returns the RNG of the Processor with the given ID, which is seeded randomly by default
+fn processor_rng(seed: Option<u64>, processor_id: usize) -> XorShiftRng {
+    let seed = match seed {
+        Some(seed) => seed.wrapping_add(processor_id as u64),
+        None => return rand::weak_rng(),
+    };
+
+    // SplitMix64 spreads similar seeds over the whole state, which must not be all zeros
+    let mut state = seed;
+    let mut next = || {
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    };
+
+    let (a, b) = (next(), next());
+    let mut words = [a as u32, (a >> 32) as u32, b as u32, (b >> 32) as u32];
+
+    if words == [0; 4] {
+        words[0] = 1;
+    }
+
+    XorShiftRng::from_seed(words)
 }
 
 // The following idea stems from Go:
@@ -914,45 +2078,237 @@ impl ExactSizeIterator for RandomProcessorIter {
     }
 }
 
+// Adds an attempt to the exponential moving average of successful steal attempts
+fn update_steal_rate(rate: usize, success: bool, params: AdaptiveParking) -> usize {
+    let sample = if success { STEAL_RATE_ONE } else { 0 };
+    rate - (rate >> params.smoothing) + (sample >> params.smoothing)
+}
+
+// Reduces the spins of a Processor below the hot threshold proportionally to it's steal rate
+fn adapt_spins(spins: u32, rate: usize, params: AdaptiveParking) -> u32 {
+    if rate >= params.hot_threshold {
+        spins
+    } else {
+        (spins as usize * rate / params.hot_threshold) as u32
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::ops::Deref;
-    use std::sync::{Arc, Mutex};
+    use std::sync::{mpsc, Arc, Barrier, Mutex};
     use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    use rand::Rng;
+
+    use coroutine::Coroutine;
+    use options::{Options, Priority};
+    use rand::XorShiftRng;
+
+    use scheduler::{AdaptiveParking, Scheduler, StealCandidate, StealPolicy, StealStrategy,
+                    STEAL_RATE_ONE};
+    use super::{adapt_spins, processor_rng, update_steal_rate, Processor, RandomProcessorOrder,
+                PROCESSOR};
 
-    use options::Options;
-    use scheduler::Scheduler;
-    use super::RandomProcessorOrder;
+    #[test]
+    fn processor_new_and_drop() {
+        let mut scheduler = Scheduler::new();
+        let (_tx, rx) = mpsc::channel();
+
+        let p = Processor::new(&mut scheduler, 0, 1024 * 1024, rx);
+        let weak = p.weak_self().clone();
+        assert_eq!(weak.upgrade().map(|p| p.id()), Some(0));
+
+        drop(p);
+        assert!(weak.upgrade().is_none());
+    }
 
-    // Scheduler::spawn() must push the new coroutine at the head of the runqueue.
-    // Thus if we spawn a number of coroutines they will be executed in reverse order.
-    // This test will make sure that this is the case.
+    #[test]
+    fn processor_current() {
+        assert!(Processor::current().is_none());
+
+        Scheduler::new()
+            .with_workers(2)
+            .run(|| {
+                let id = Processor::current().unwrap().id();
+                assert_eq!(Processor::current_required().id(), id);
+
+                // Acquiring a second handle while the first one is alive panics in debug builds
+                let h = Scheduler::spawn(|| {
+                    let _p = Processor::current_required();
+                    Processor::current_required().id()
+                });
+                assert_eq!(h.join().is_err(), cfg!(debug_assertions));
+
+                // The first handle was released while unwinding
+                let h = Scheduler::spawn(|| Processor::current_required().id());
+                assert!(h.join().is_ok());
+
+                let p = Processor::current_required();
+                assert_eq!(Processor::try_current().is_none(), cfg!(debug_assertions));
+                drop(p);
+                assert!(Processor::try_current().is_some());
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn processor_slot_set_once() {
+        let t = thread::spawn(|| {
+            let mut scheduler = Scheduler::new();
+            let (_tx1, rx1) = mpsc::channel();
+            let (_tx2, rx2) = mpsc::channel();
+
+            PROCESSOR.with(|slot| {
+                slot.set(Processor::new(&mut scheduler, 0, 1024 * 1024, rx1));
+                assert_eq!(slot.get().map(|p| p.id()), Some(0));
+
+                slot.set(Processor::new(&mut scheduler, 1, 1024 * 1024, rx2));
+            });
+        });
+
+        assert!(t.join().is_err());
+    }
+
+    // Scheduler::spawn() called from a running coroutine pushes the new coroutine at the
+    // tail of the runqueue. Thus they will be executed in the order they were spawned,
+    // after the spawning coroutine yields. This test will make sure that this is the case.
     #[test]
     fn processor_sched_order() {
+        Scheduler::new().run(sched_order).unwrap();
+        Scheduler::single_threaded().run(sched_order).unwrap();
+    }
+
+    fn sched_order() {
+        let results = Arc::new(Mutex::new(Vec::with_capacity(4)));
+        let expected = vec![0, 1, 2, 3];
+
+        for i in 1..4 {
+            let results = results.clone();
+
+            Scheduler::spawn(move || {
+                let mut results = results.lock().unwrap();
+                results.push(i);
+            });
+        }
+
+        {
+            let mut results = results.lock().unwrap();
+            results.push(0);
+        }
+
+        Scheduler::sched();
+
+        let results = results.lock().unwrap();
+        assert_eq!(results.deref(), &expected);
+    }
+
+    // Interleaved coroutines must always run in exactly the same order in single threaded mode.
+    #[test]
+    fn processor_sched_order_single_threaded() {
+        fn interleave() -> Vec<(usize, usize)> {
+            let results = Arc::new(Mutex::new(Vec::new()));
+            let mut handles = Vec::new();
+
+            for i in 0..4 {
+                let results = results.clone();
+
+                handles.push(Scheduler::spawn(move || {
+                    for round in 0..3 {
+                        results.lock().unwrap().push((i, round));
+                        Scheduler::sched();
+                    }
+                }));
+            }
+
+            for h in handles {
+                h.join().unwrap();
+            }
+
+            let results = results.lock().unwrap();
+            results.clone()
+        }
+
+        let mut expected = Vec::new();
+        for round in 0..3 {
+            for i in 0..4 {
+                expected.push((i, round));
+            }
+        }
+
+        for _ in 0..10 {
+            assert_eq!(Scheduler::single_threaded().run(interleave).unwrap(), expected);
+        }
+    }
+
+    // A coroutine readied by the Processor itself (here inside a park_with() callback) is
+    // resumed next, skipping the queue. With Options::fifo() it gets to the tail instead.
+    #[test]
+    fn processor_sched_order_fifo() {
+        fn run(fifo: bool) -> Vec<usize> {
+            Scheduler::new()
+                .run(move || {
+                    let results = Arc::new(Mutex::new(Vec::with_capacity(2)));
+
+                    let mut opts = Options::new();
+                    opts.fifo(fifo);
+
+                    let cloned = results.clone();
+                    let hdl = Scheduler::spawn_opts(move || {
+                                                        let queued = cloned.clone();
+                                                        Scheduler::spawn(move || {
+                                                            queued.lock().unwrap().push(1);
+                                                        });
+
+                                                        Scheduler::park_with(|p, coro| {
+                                                            p.ready(coro)
+                                                        });
+                                                        cloned.lock().unwrap().push(0);
+                                                    },
+                                                    opts);
+
+                    hdl.join().unwrap();
+                    Scheduler::sched();
+
+                    let results = results.lock().unwrap();
+                    results.clone()
+                })
+                .unwrap()
+        }
+
+        assert_eq!(run(false), vec![0, 1]);
+        assert_eq!(run(true), vec![1, 0]);
+    }
+
+    // Coroutines with a higher priority must be resumed first,
+    // independent of the order in which they were spawned.
+    #[test]
+    fn processor_sched_priority() {
         Scheduler::new()
             .run(|| {
-                //
-                let results = Arc::new(Mutex::new(Vec::with_capacity(4)));
-                let expected = vec![0, 1, 2, 3];
+                let results = Arc::new(Mutex::new(Vec::with_capacity(3)));
+                let priorities = [Priority::Low, Priority::Normal, Priority::High];
+                let mut handles = Vec::new();
 
-                for i in 1..4 {
+                for &priority in priorities.iter() {
                     let results = results.clone();
+                    let mut opts = Options::new();
+                    opts.priority(priority);
 
-                    Scheduler::spawn(move || {
-                        let mut results = results.lock().unwrap();
-                        results.push(i);
-                    });
+                    let f = move || {
+                        results.lock().unwrap().push(priority);
+                    };
+                    handles.push(Scheduler::spawn_opts(f, opts));
                 }
 
-                {
-                    let mut results = results.lock().unwrap();
-                    results.push(0);
+                for h in handles {
+                    h.join().unwrap();
                 }
 
-                Scheduler::sched();
-
                 let results = results.lock().unwrap();
-                assert_eq!(results.deref(), &expected);
+                assert_eq!(results.deref(),
+                           &vec![Priority::High, Priority::Normal, Priority::Low]);
             })
             .unwrap();
     }
@@ -996,4 +2352,238 @@ mod test {
         assert_eq!(order.iter(4).collect::<Vec<usize>>(), vec![4, 0, 1, 2, 3]);
         assert_eq!(order.iter(5).collect::<Vec<usize>>(), vec![0, 2, 4, 1, 3]);
     }
+
+    // A pinned coroutine must always be resumed on the Processor it's pinned to,
+    // no matter on which Processor it was spawned or woken up.
+    #[test]
+    fn processor_pinned() {
+        Scheduler::new()
+            .with_workers(4)
+            .run(|| {
+                let mut handles = Vec::new();
+
+                for id in 0..4 {
+                    let mut opts = Options::new();
+                    opts.pinned_to(id);
+
+                    let f = move || {
+                        for i in 0..100 {
+                            assert_eq!(Processor::current().unwrap().id(), id);
+
+                            if i % 10 == 0 {
+                                ::sleep_ms(1);
+                            } else {
+                                Scheduler::sched();
+                            }
+                        }
+                    };
+                    handles.push(Scheduler::spawn_opts(f, opts));
+                }
+
+                for h in handles {
+                    h.join().unwrap();
+                }
+            })
+            .unwrap();
+    }
+
+    // A no_steal coroutine must stay on the Processor it was bound to,
+    // even though the other ones are idle and steal whatever they can.
+    #[test]
+    fn processor_no_steal() {
+        Scheduler::new()
+            .with_workers(4)
+            .run(|| {
+                let mut handles = Vec::new();
+
+                for _ in 0..16 {
+                    let mut opts = Options::new();
+                    opts.no_steal(true);
+
+                    let f = move || {
+                        let id = Processor::current().unwrap().id();
+
+                        for i in 0..100 {
+                            if i % 10 == 0 {
+                                ::sleep_ms(1);
+                            } else {
+                                Scheduler::sched();
+                            }
+
+                            assert_eq!(Processor::current().unwrap().id(), id);
+                        }
+                    };
+                    handles.push(Scheduler::spawn_opts(f, opts));
+                }
+
+                for h in handles {
+                    h.join().unwrap();
+                }
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn processor_steal_longest() {
+        Scheduler::new()
+            .with_workers(4)
+            .with_steal_strategy(StealStrategy::Longest)
+            .run(|| {
+                let counter = Arc::new(AtomicUsize::new(0));
+
+                let handles: Vec<_> = (0..1000)
+                                          .map(|_| {
+                                              let counter = counter.clone();
+                                              Scheduler::spawn(move || {
+                                                  Scheduler::sched();
+                                                  counter.fetch_add(1, Ordering::SeqCst);
+                                              })
+                                          })
+                                          .collect();
+
+                for h in handles {
+                    h.join().unwrap();
+                }
+
+                assert_eq!(counter.load(Ordering::SeqCst), 1000);
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn processor_steal_policy() {
+        // Always steals from the Processor with the longest queue and counts the results
+        struct Recorder(Arc<Mutex<(usize, usize)>>);
+
+        impl StealPolicy for Recorder {
+            fn next_victim(&self,
+                           _thief: usize,
+                           candidates: &[StealCandidate],
+                           _rng: &mut XorShiftRng,
+                           attempt: usize)
+                           -> Option<usize> {
+                if attempt > 0 {
+                    return None;
+                }
+
+                (0..candidates.len()).max_by_key(|&x| candidates[x].queue_len)
+            }
+
+            fn on_steal(&self, _thief: usize, _victim: usize, stolen: usize) {
+                let mut results = self.0.lock().unwrap();
+
+                if stolen > 0 {
+                    results.0 += 1;
+                } else {
+                    results.1 += 1;
+                }
+            }
+        }
+
+        let results = Arc::new(Mutex::new((0, 0)));
+
+        Scheduler::new()
+            .with_workers(4)
+            .with_steal_policy(Recorder(results.clone()))
+            .run(|| {
+                let handles: Vec<_> = (0..1000)
+                                          .map(|_| Scheduler::spawn(|| Scheduler::sched()))
+                                          .collect();
+
+                for h in handles {
+                    h.join().unwrap();
+                }
+            })
+            .unwrap();
+
+        // The main coroutine spawned all of them on Processor#0
+        assert!(results.lock().unwrap().0 > 0);
+    }
+
+    // A Processor whose message channel disconnected must stop on it's own instead of
+    // panicking, handing it's coroutines over to the other Processors.
+    #[test]
+    fn processor_disconnected() {
+        let mut scheduler = Scheduler::new();
+        let barrier = Arc::new(Barrier::new(2));
+        let coro = Coroutine::spawn_opts(Box::new(|| {}), Options::new());
+        let machine = Processor::spawn(&mut scheduler, 0, barrier.clone(), 1024 * 1024, Some(coro));
+
+        drop(machine.processor_handle);
+        barrier.wait();
+
+        assert!(machine.thread_handle.join().is_ok());
+        assert_eq!(scheduler.global_queue_size(), 1);
+    }
+
+    #[test]
+    fn processor_rng_seed() {
+        fn numbers(seed: Option<u64>, processor_id: usize) -> Vec<u32> {
+            let mut rng = processor_rng(seed, processor_id);
+            (0..8).map(|_| rng.gen()).collect()
+        }
+
+        // Equal seeds and IDs yield the same sequence, while each Processor gets it's own one
+        assert_eq!(numbers(Some(42), 0), numbers(Some(42), 0));
+        assert!(numbers(Some(42), 0) != numbers(Some(42), 1));
+        assert!(numbers(Some(42), 0) != numbers(Some(43), 0));
+    }
+
+    // Coroutines in the global queue must run eventually, even if the local queue never empties.
+    #[test]
+    fn processor_global_queue_fairness() {
+        let mut scheduler = Scheduler::single_threaded();
+        let handle = scheduler.handle();
+
+        scheduler.run(move || {
+                     let flag = Arc::new(AtomicUsize::new(0));
+
+                     {
+                         let flag = flag.clone();
+                         let f = move || { flag.store(1, Ordering::SeqCst); };
+
+                         // Coroutines spawned through a SchedulerHandle go into the global queue
+                         handle.spawn(f).ok().unwrap();
+                     }
+
+                     for _ in 0..10 * super::GLOBAL_QUEUE_INTERVAL {
+                         if flag.load(Ordering::SeqCst) == 1 {
+                             break;
+                         }
+
+                         Scheduler::sched();
+                     }
+
+                     assert_eq!(flag.load(Ordering::SeqCst), 1);
+                 })
+                 .unwrap();
+    }
+
+    #[test]
+    fn processor_adaptive_parking() {
+        let params = AdaptiveParking::default();
+        let mut rate = STEAL_RATE_ONE;
+
+        // Successes keep a hot Processor hot
+        rate = update_steal_rate(rate, true, params);
+        assert_eq!(rate, STEAL_RATE_ONE);
+        assert_eq!(adapt_spins(6, rate, params), 6);
+
+        // Repeated failures stop the spinning
+        for _ in 0..100 {
+            rate = update_steal_rate(rate, false, params);
+        }
+        assert!(rate < params.hot_threshold);
+        assert_eq!(adapt_spins(6, rate, params), 0);
+
+        // ...and successes make it spin again
+        for _ in 0..100 {
+            rate = update_steal_rate(rate, true, params);
+        }
+        assert!(rate >= params.hot_threshold);
+        assert_eq!(adapt_spins(6, rate, params), 6);
+
+        let half = AdaptiveParking { hot_threshold: STEAL_RATE_ONE, ..params };
+        assert_eq!(adapt_spins(6, STEAL_RATE_ONE / 2, half), 3);
+    }
 }
@@ -8,43 +8,99 @@
 
 //! Stack pool
 
+use std::mem;
 use std::ops::{Deref, DerefMut};
+use std::ptr;
 
 use linked_hash_map::LinkedHashMap;
 
-use context::stack::ProtectedFixedSizeStack;
+use context::stack::{self, FixedSizeStack, ProtectedFixedSizeStack};
+
+// Byte pattern the stacks are filled with by Stack::paint()
+const STACK_PAINT: u8 = 0x5a;
+
+enum StackImpl {
+    // Followed by a guard page, so that overflows fault with SIGSEGV
+    Protected(ProtectedFixedSizeStack),
+    Unprotected(FixedSizeStack),
+}
 
 /// Stack representation
 pub struct Stack {
-    inner: ProtectedFixedSizeStack,
+    inner: StackImpl,
     size: usize,
 }
 
 impl Stack {
-    fn new(s: ProtectedFixedSizeStack, size: usize) -> Stack {
+    fn new(s: StackImpl, size: usize) -> Stack {
         Stack {
             inner: s,
             size: size,
         }
     }
+
+    /// Returns true if the stack is protected by a guard page
+    #[inline]
+    pub fn is_protected(&self) -> bool {
+        match self.inner {
+            StackImpl::Protected(..) => true,
+            StackImpl::Unprotected(..) => false,
+        }
+    }
+
+    /// Fills the whole stack with a byte pattern, see `high_water_mark()`
+    ///
+    /// # Safety
+    ///
+    /// The stack must not be in use yet, since it's contents are overwritten.
+    pub unsafe fn paint(&mut self) {
+        let bottom = self.bottom() as *mut u8;
+        ptr::write_bytes(bottom, STACK_PAINT, self.len());
+    }
+
+    /// Returns the maximum number of bytes of the stack used since `paint()` was called
+    ///
+    /// NOTE: All platforms supported by `context` grow their stacks downwards, from `top()`
+    /// to `bottom()`. The unused part of a stack thus begins at it's bottom and ends at the
+    /// first word which was overwritten. The result is rounded to the size of a word and
+    /// might be slightly too low if a word happened to be written with the pattern itself.
+    pub fn high_water_mark(&self) -> usize {
+        let pattern = usize::max_value() / 0xff * STACK_PAINT as usize;
+        let bottom = self.bottom() as usize;
+        let top = self.top() as usize;
+        let mut addr = bottom;
+
+        while addr < top && unsafe { *(addr as *const usize) } == pattern {
+            addr += mem::size_of::<usize>();
+        }
+
+        top - addr
+    }
 }
 
 impl Deref for Stack {
-    type Target = ProtectedFixedSizeStack;
-    fn deref(&self) -> &ProtectedFixedSizeStack {
-        &self.inner
+    type Target = stack::Stack;
+    fn deref(&self) -> &stack::Stack {
+        match self.inner {
+            StackImpl::Protected(ref s) => s,
+            StackImpl::Unprotected(ref s) => s,
+        }
     }
 }
 
 impl DerefMut for Stack {
-    fn deref_mut(&mut self) -> &mut ProtectedFixedSizeStack {
-        &mut self.inner
+    fn deref_mut(&mut self) -> &mut stack::Stack {
+        match self.inner {
+            StackImpl::Protected(ref mut s) => s,
+            StackImpl::Unprotected(ref mut s) => s,
+        }
     }
 }
 
 /// Stackpool
 pub struct StackPool {
-    inner: LinkedHashMap<usize, Vec<Stack>>,
+    // Stacks are cached by their size and whether they are protected by a guard page
+    inner: LinkedHashMap<(usize, bool), Vec<Stack>>,
 
     total_size: usize,
     higher_water_mark: Option<usize>,
@@ -66,16 +122,23 @@ impl StackPool {
         }
     }
 
-    /// Allocate stack by directly creation
-    pub fn raw_allocate(size: usize) -> Stack {
-        trace!("allocating {} bytes from raw", size);
-        Stack::new(ProtectedFixedSizeStack::new(size).expect("failed to acquire stack"),
-                   size)
+    /// Allocate stack by directly creation, with a guard page if `protected` is true
+    pub fn raw_allocate(size: usize, protected: bool) -> Stack {
+        trace!("allocating {} bytes from raw, protected: {}", size, protected);
+
+        let inner = if protected {
+            StackImpl::Protected(ProtectedFixedSizeStack::new(size)
+                                     .expect("failed to acquire stack"))
+        } else {
+            StackImpl::Unprotected(FixedSizeStack::new(size).expect("failed to acquire stack"))
+        };
+
+        Stack::new(inner, size)
     }
 
     /// Create a stack from pool, create if we don't have stack in pool
-    pub fn allocate(&mut self, size: usize) -> Stack {
-        let stack = match self.inner.get_refresh(&size) {
+    pub fn allocate(&mut self, size: usize, protected: bool) -> Stack {
+        let stack = match self.inner.get_refresh(&(size, protected)) {
             Some(cached) => {
                 match cached.pop() {
                     Some(stack) => {
@@ -83,10 +146,10 @@ impl StackPool {
                         self.total_size -= size;
                         stack
                     }
-                    None => StackPool::raw_allocate(size),
+                    None => StackPool::raw_allocate(size, protected),
                 }
             }
-            None => StackPool::raw_allocate(size),
+            None => StackPool::raw_allocate(size, protected),
         };
 
         self.try_shrink();
@@ -101,10 +164,11 @@ impl StackPool {
     /// Deallocate stack into pool
     pub fn deallocate(&mut self, stack: Stack) {
         let size = stack.size;
+        let key = (size, stack.is_protected());
 
-        let raw_inner: *mut LinkedHashMap<usize, Vec<Stack>> = &mut self.inner;
+        let raw_inner: *mut LinkedHashMap<(usize, bool), Vec<Stack>> = &mut self.inner;
 
-        match self.inner.get_refresh(&size) {
+        match self.inner.get_refresh(&key) {
             Some(cached) => {
                 cached.push(stack);
             }
@@ -113,7 +177,7 @@ impl StackPool {
 
                 // FIXME: Very annonying that LinkedHashMap doesn't provide .entry API
                 // Issue: https://github.com/contain-rs/linked-hash-map/issues/5
-                unsafe { &mut *raw_inner }.insert(size, cached);
+                unsafe { &mut *raw_inner }.insert(key, cached);
             }
         }
 
@@ -145,14 +209,16 @@ impl StackPool {
 
         'outer: while self.total_size > lower_bound {
             match self.inner.pop_back() {
-                Some((size, mut cached)) => {
+                Some((key, mut cached)) => {
+                    let size = key.0;
+
                     while let Some(..) = cached.pop() {
                         self.total_size -= size;
 
                         if self.total_size <= lower_bound {
                             // We still have some stacks inside, put it back
                             if !cached.is_empty() {
-                                self.inner.insert(size, cached);
+                                self.inner.insert(key, cached);
                             }
 
                             break 'outer;
@@ -183,16 +249,16 @@ mod test {
     #[test]
     fn stack_pool_basic() {
         let mut pool = StackPool::new(None, None);
-        let stack = pool.allocate(1024);
+        let stack = pool.allocate(1024, true);
         pool.deallocate(stack);
     }
 
     #[test]
     fn stack_pool_strink() {
         let mut pool = StackPool::new(Some(1024), Some(2048));
-        let stack1 = pool.allocate(1024);
-        let stack2 = pool.allocate(1024);
-        let stack3 = pool.allocate(1024);
+        let stack1 = pool.allocate(1024, true);
+        let stack2 = pool.allocate(1024, true);
+        let stack3 = pool.allocate(1024, true);
 
         assert_eq!(pool.total_size(), 0);
 
@@ -209,9 +275,9 @@ mod test {
     #[test]
     fn stack_pool_strink_without_lwm() {
         let mut pool = StackPool::new(None, Some(2048));
-        let stack1 = pool.allocate(1024);
-        let stack2 = pool.allocate(1024);
-        let stack3 = pool.allocate(1024);
+        let stack1 = pool.allocate(1024, true);
+        let stack2 = pool.allocate(1024, true);
+        let stack3 = pool.allocate(1024, true);
 
         assert_eq!(pool.total_size(), 0);
 
@@ -224,4 +290,26 @@ mod test {
         pool.deallocate(stack3);
         assert_eq!(pool.total_size(), 2048);
     }
+
+    #[test]
+    fn stack_pool_protection() {
+        let mut pool = StackPool::new(None, None);
+        let stack = pool.allocate(1024, false);
+        assert!(!stack.is_protected());
+        pool.deallocate(stack);
+
+        // Unprotected stacks are never handed out if a guard page was requested
+        let stack = pool.allocate(1024, true);
+        assert!(stack.is_protected());
+        assert_eq!(pool.total_size(), 1024);
+        pool.deallocate(stack);
+    }
+
+    #[test]
+    fn stack_pool_disabled() {
+        let mut pool = StackPool::new(Some(0), Some(0));
+        let stack = pool.allocate(1024, true);
+        pool.deallocate(stack);
+        assert_eq!(pool.total_size(), 0);
+    }
 }
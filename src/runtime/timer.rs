@@ -1,6 +1,7 @@
 use std::usize;
 use std::cmp::max;
-use time::precise_time_ns;
+use std::mem;
+use time_crate::precise_time_ns;
 
 const EMPTY: usize = usize::MAX;
 const NS_PER_MS: u64 = 1_000_000;
@@ -66,6 +67,11 @@ impl<T> Timer<T> {
         self.entries.count()
     }
 
+    #[inline]
+    pub fn tick_ms(&self) -> u64 {
+        self.tick_ms
+    }
+
     // Number of ms remaining until the next tick
     pub fn next_tick_in_ms(&self) -> Option<u64> {
         if self.entries.count() == 0 {
@@ -132,6 +138,28 @@ impl<T> Timer<T> {
         true
     }
 
+    // Removes all pending timeouts and returns their tokens
+    pub fn drain(&mut self) -> Vec<T> {
+        let mut tokens = Vec::with_capacity(self.entries.count());
+
+        for slot in 0..self.wheel.len() {
+            let mut curr = mem::replace(&mut self.wheel[slot], EMPTY);
+
+            while curr != EMPTY {
+                let next = self.entries[curr].links.next;
+
+                if let Some(e) = self.entries.remove(curr) {
+                    tokens.push(e.token);
+                }
+
+                curr = next;
+            }
+        }
+
+        self.next = EMPTY;
+        tokens
+    }
+
     fn insert(&mut self, token: T, tick: u64) -> Timeout {
         // Get the slot for the requested tick
         let slot = (tick & self.mask) as usize;
@@ -443,6 +471,23 @@ mod test {
         assert_eq!(0, t.count());
     }
 
+    #[test]
+    pub fn test_drain() {
+        let mut t = timer();
+
+        t.timeout_at_ms("a", 100);
+        t.timeout_at_ms("b", 100);
+        t.timeout_at_ms("c", 100 + TICK * SLOTS as u64);
+
+        let mut rcv = t.drain();
+        rcv.sort();
+        assert_eq!(rcv, ["a", "b", "c"]);
+        assert_eq!(t.count(), 0);
+
+        let tick = t.ms_to_tick(100 + TICK * SLOTS as u64);
+        assert_eq!(None, t.tick_to(tick));
+    }
+
     const TICK: u64 = 100;
     const SLOTS: usize = 16;
 
@@ -0,0 +1,81 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Monitor thread detecting coroutines which block a Processor, see `Scheduler::with_watchdog()`
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, Builder};
+use std::time::Duration;
+
+use scheduler::Scheduler;
+
+pub struct Watchdog {
+    shutdown: Arc<(Mutex<bool>, Condvar)>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Watchdog {
+    /// Spawns a thread checking the Processors of `scheduler` every quarter of `threshold`
+    ///
+    /// The Scheduler must outlive the Watchdog and all of it's Machines need to be spawned.
+    pub fn spawn(scheduler: &Scheduler, threshold: Duration) -> Watchdog {
+        let shutdown = Arc::new((Mutex::new(false), Condvar::new()));
+        let interval = threshold / 4;
+        let sched = scheduler as *const Scheduler as usize;
+
+        let thread = {
+            let shutdown = shutdown.clone();
+
+            Builder::new()
+                .name("Watchdog".to_owned())
+                .spawn(move || {
+                    let scheduler = unsafe { &*(sched as *const Scheduler) };
+                    Watchdog::monitor(scheduler, threshold, interval, &shutdown)
+                })
+                .unwrap()
+        };
+
+        Watchdog {
+            shutdown: shutdown,
+            thread: Some(thread),
+        }
+    }
+
+    fn monitor(scheduler: &'static Scheduler,
+               threshold: Duration,
+               interval: Duration,
+               shutdown: &(Mutex<bool>, Condvar)) {
+        let &(ref lock, ref condvar) = shutdown;
+        let mut is_shutdown = lock.lock().unwrap();
+
+        while !*is_shutdown {
+            is_shutdown = condvar.wait_timeout(is_shutdown, interval).unwrap().0;
+
+            for m in scheduler.get_machines().iter() {
+                if let Some((id, name, elapsed)) = m.processor.watchdog_check(threshold) {
+                    let name = name.as_ref().map(String::as_str);
+                    scheduler.watchdog_notify(m.processor.id(), id, name, elapsed);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        {
+            let &(ref lock, ref condvar) = &*self.shutdown;
+            *lock.lock().unwrap() = true;
+            condvar.notify_one();
+        }
+
+        if let Some(t) = self.thread.take() {
+            let _ = t.join();
+        }
+    }
+}
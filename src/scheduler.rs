@@ -8,26 +8,41 @@
 
 //! Global coroutine scheduler
 
+use std::any::Any;
+use std::boxed::FnBox;
 use std::cell::UnsafeCell;
-use std::fmt::Debug;
+use std::cmp;
+use std::error::Error;
+use std::fmt::{self, Debug};
 use std::io::{self, Write};
 use std::mem;
 use std::panic;
-use std::ptr::Shared;
-use std::sync::{Arc, Barrier, Condvar, Mutex, MutexGuard};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::ptr::{self, Shared};
+use std::slice;
+use std::sync::{Arc, Barrier, Mutex, MutexGuard, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::SendError;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use mio::{Evented, EventLoop, EventLoopConfig, EventSet, Handler, NotifyError, PollOpt, Sender,
           Token};
+use rand::{Rng, XorShiftRng};
 use slab::Slab;
 
-use coroutine::{Coroutine, Handle, HandleList};
-use join_handle::{self, JoinHandleReceiver};
+use arena::Arena;
+use cancel::Cancelled;
+use coroutine::{Coroutine, ForceUnwind, Handle, HandleList};
+use join_handle::{self, JoinHandleReceiver, JoinHandleSender};
 use options::Options;
-use runtime::processor::{self, Machine, Processor, ProcMessage};
+use park::{CondvarParker, Parker};
+use runtime::blocking_pool::BlockingPool;
+use runtime::watchdog::Watchdog;
+use runtime::processor::{self, Machine, Processor, ProcessorError, ProcMessage, ShutdownBarrier};
 use runtime::timer::{Timer, Timeout};
+use scope::{self, Scope};
+use select::{self, Select};
+use supervisor::{self, RestartPolicy};
 use sync::condvar::{Condvar as CoroCondvar, Waiter, WaiterState};
 use sync::spinlock::Spinlock;
 
@@ -40,11 +55,357 @@ unsafe impl<T: Send> Send for JoinHandle<T> {}
 
 impl<T> JoinHandle<T> {
     /// Await completion of the coroutine and return it's result.
+    ///
+    /// The error holds a `sync::oneshot::Canceled` if the coroutine was dropped before it
+    /// finished, e.g. because it was still queued when the Scheduler shut down.
     pub fn join(self) -> thread::Result<T> {
         self.result.pop()
     }
 }
 
+/// A handle to spawn coroutines on a Scheduler from threads outside of it
+///
+/// It's obtained with `Scheduler::handle()`, usually before calling `run()`, and can be cloned
+/// and sent to other threads. This allows to bridge coio with event sources running
+/// on their own threads. Coroutines spawned through it are distributed among the Processors
+/// according to `Scheduler::with_external_dispatch()`.
+pub struct SchedulerHandle {
+    shared: Arc<HandleShared>,
+}
+
+// Shared between a Scheduler and all of it's SchedulerHandles
+struct HandleShared {
+    // Address of the running Scheduler or 0 if it isn't running (anymore)
+    scheduler: RwLock<usize>,
+    // Number of existing SchedulerHandles, see Scheduler::run_until_idle()
+    count: AtomicUsize,
+}
+
+impl HandleShared {
+    fn handle(shared: &Arc<HandleShared>) -> SchedulerHandle {
+        shared.count.fetch_add(1, Ordering::SeqCst);
+        SchedulerHandle { shared: shared.clone() }
+    }
+}
+
+impl SchedulerHandle {
+    /// Spawn a new coroutine with the Scheduler's default options
+    ///
+    /// Fails and returns `f` if the Scheduler isn't running, i.e. before `run()` was called
+    /// and after the main coroutine finished, or if it's shutting down gracefully.
+    /// The returned `JoinHandle` can be joined on any thread.
+    pub fn spawn<F, T>(&self, f: F) -> Result<JoinHandle<T>, F>
+        where F: FnOnce() -> T + Send + 'static,
+              T: Send + 'static
+    {
+        // The lock prevents the Scheduler from shutting down while the coroutine is queued
+        let guard = self.shared.scheduler.read().unwrap();
+
+        if *guard == 0 {
+            return Err(f);
+        }
+
+        let scheduler = unsafe { &*(*guard as *const Scheduler) };
+
+        if scheduler.is_draining() {
+            return Err(f);
+        }
+
+        let (tx, rx) = join_handle::handle_pair();
+        let opts = scheduler.default_spawn_options.clone();
+
+        scheduler.running_coroutine_count.fetch_add(1, Ordering::Relaxed);
+        let coro = Coroutine::spawn_opts(Scheduler::wrap_coroutine(f, tx), opts);
+        scheduler.dispatch(coro);
+
+        Ok(JoinHandle { result: rx })
+    }
+
+    /// Spawn a coroutine running a clone of `f` on each of the Processors
+    ///
+    /// See `Scheduler::spawn_on_each_processor()`. Just like `spawn()` it fails and returns `f`
+    /// if the Scheduler isn't running or is shutting down gracefully.
+    pub fn spawn_on_each_processor<F, T>(&self, f: F) -> Result<Vec<JoinHandle<T>>, F>
+        where F: Fn() -> T + Clone + Send + 'static,
+              T: Send + 'static
+    {
+        // The lock prevents the Scheduler from shutting down while the coroutines are queued
+        let guard = self.shared.scheduler.read().unwrap();
+
+        if *guard == 0 {
+            return Err(f);
+        }
+
+        let scheduler = unsafe { &*(*guard as *const Scheduler) };
+
+        if scheduler.is_draining() {
+            return Err(f);
+        }
+
+        Ok(scheduler.spawn_on_each_processor_imp(f))
+    }
+
+    /// Returns true if the Scheduler is running and coroutines can be spawned
+    pub fn is_running(&self) -> bool {
+        *self.shared.scheduler.read().unwrap() != 0
+    }
+}
+
+impl Clone for SchedulerHandle {
+    fn clone(&self) -> SchedulerHandle {
+        HandleShared::handle(&self.shared)
+    }
+}
+
+impl Drop for SchedulerHandle {
+    fn drop(&mut self) {
+        if self.shared.count.fetch_sub(1, Ordering::SeqCst) != 1 {
+            return;
+        }
+
+        let guard = self.shared.scheduler.read().unwrap();
+
+        if *guard != 0 {
+            let scheduler = unsafe { &*(*guard as *const Scheduler) };
+            scheduler.shutdown_if_idle();
+        }
+    }
+}
+
+/// Readies parked coroutines of a Scheduler from any thread, see `Scheduler::ready_handle()`
+///
+/// Unlike `SchedulerHandle` it doesn't keep `run_until_idle()` from finishing.
+#[doc(hidden)]
+#[derive(Clone)]
+pub struct ReadyHandle {
+    shared: Arc<HandleShared>,
+}
+
+impl ReadyHandle {
+    /// Queues `hdl` like `dispatch_external()`, or drops it if the Scheduler isn't running
+    pub fn ready(&self, hdl: Handle) {
+        let hdl = match hdl.wake() {
+            Some(hdl) => hdl,
+            None => return,
+        };
+
+        let dropped = {
+            let guard = self.shared.scheduler.read().unwrap();
+
+            if *guard == 0 {
+                Some(hdl)
+            } else {
+                let scheduler = unsafe { &*(*guard as *const Scheduler) };
+                scheduler.dispatch(hdl);
+                None
+            }
+        };
+
+        // Dropping it unwinds the coroutine, which must not happen while holding the lock
+        drop(dropped);
+    }
+}
+
+impl fmt::Debug for SchedulerHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SchedulerHandle {{ running: {} }}", self.is_running())
+    }
+}
+
+/// The error returned by `Scheduler::with_timeout()` if the operation didn't finish in time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeoutError;
+
+impl fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.description())
+    }
+}
+
+impl Error for TimeoutError {
+    fn description(&self) -> &str {
+        "operation timed out"
+    }
+}
+
+/// A snapshot of the runtime state of a running Scheduler, see `Scheduler::metrics()`
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    /// Number of coroutines spawned through the Scheduler which haven't finished yet
+    pub coroutine_count: usize,
+    /// Number of Processors waiting for work
+    pub parked_processor_count: usize,
+    /// Number of coroutines waiting in the global queue
+    pub global_queue_len: usize,
+    /// Metrics of every Processor, ordered by their ID
+    pub processors: Vec<ProcessorMetrics>,
+}
+
+impl Metrics {
+    /// Cumulative number of coroutines stolen by all Processors
+    pub fn steal_count(&self) -> usize {
+        self.processors.iter().fold(0, |acc, p| acc + p.steal_count)
+    }
+}
+
+/// A snapshot of the runtime state of a single Processor
+#[derive(Debug, Clone, Copy)]
+pub struct ProcessorMetrics {
+    pub id: usize,
+    /// Number of coroutines waiting in the local queues
+    pub queue_len: usize,
+    /// Cumulative number of coroutines stolen from other Processors
+    pub steal_count: usize,
+    /// Moving average of the fraction of steal attempts which succeeded, between 0 and 1
+    ///
+    /// Only maintained with adaptive parking (see `Scheduler::with_adaptive_parking()`).
+    pub steal_rate: f64,
+}
+
+/// Parameters of the adaptive parking, see `Scheduler::with_adaptive_parking()`
+///
+/// Each Processor maintains an exponential moving average of how many of the attempts to
+/// steal coroutines, made whenever it's local queue ran empty, succeeded. Rates are given
+/// out of `STEAL_RATE_ONE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdaptiveParking {
+    /// Newer attempts have a weight of `1 / 2^smoothing` in the average
+    pub smoothing: u32,
+    /// Processors with a steal rate at or above this spin as configured by `with_park_backoff()`
+    ///
+    /// Below it the number of spins is reduced proportionally to the steal rate,
+    /// down to no spins at all for a Processor which never succeeds.
+    pub hot_threshold: usize,
+}
+
+/// The steal rate of one, i.e. of a Processor all of whose steal attempts succeed
+pub const STEAL_RATE_ONE: usize = 1024;
+
+// Fixed point representation of a load of 1.0, see Scheduler::load()
+const LOAD_ONE: usize = 1 << 20;
+
+// Threshold of the watchdog set by with_debug_watchdog(), see Scheduler::allow_blocking()
+const DEBUG_WATCHDOG_THRESHOLD_MS: u64 = 100;
+
+impl Default for AdaptiveParking {
+    fn default() -> AdaptiveParking {
+        AdaptiveParking {
+            smoothing: 3,
+            hot_threshold: STEAL_RATE_ONE / 4,
+        }
+    }
+}
+
+// Restores the previous deadline of the current coroutine, even if the operation panics
+struct DeadlineGuard {
+    deadline: Instant,
+    prev: Option<Instant>,
+    prev_expired: bool,
+}
+
+impl DeadlineGuard {
+    fn new(deadline: Instant) -> DeadlineGuard {
+        DeadlineGuard {
+            deadline: deadline,
+            prev: replace_current_deadline(Some(deadline)),
+            prev_expired: replace_deadline_expired(false),
+        }
+    }
+
+    // Whether a wait timed out because of this deadline so far
+    fn expired(&self) -> bool {
+        let mut p = match Processor::current() {
+            Some(p) => p,
+            None => return false,
+        };
+
+        let expired = p.current().map_or(false, |coro| coro.deadline_expired());
+        expired
+    }
+}
+
+impl Drop for DeadlineGuard {
+    fn drop(&mut self) {
+        replace_current_deadline(self.prev);
+
+        // A nested deadline, which was capped to the previous one, expired just as well
+        let expired = replace_deadline_expired(false);
+        let capped = self.prev == Some(self.deadline);
+        replace_deadline_expired(self.prev_expired || (expired && capped));
+    }
+}
+
+// The handler of with_watchdog()
+fn log_blocking(processor_id: usize, id: u64, name: Option<&str>, elapsed: Duration) {
+    warn!("Coroutine#{}({}) has been blocking Processor#{} for {:?}",
+          id,
+          name.unwrap_or("<unnamed>"),
+          processor_id,
+          elapsed);
+}
+
+fn make_cancelled() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, Cancelled)
+}
+
+fn replace_current_deadline(deadline: Option<Instant>) -> Option<Instant> {
+    let mut p = match Processor::current() {
+        Some(p) => p,
+        None => return None,
+    };
+
+    let prev = match p.current() {
+        Some(coro) => {
+            let prev = coro.deadline();
+            coro.set_deadline(deadline);
+            prev
+        }
+        None => None,
+    };
+    prev
+}
+
+fn replace_deadline_expired(expired: bool) -> bool {
+    let mut p = match Processor::current() {
+        Some(p) => p,
+        None => return false,
+    };
+
+    let prev = match p.current() {
+        Some(coro) => {
+            let prev = coro.deadline_expired();
+            coro.set_deadline_expired(expired);
+            prev
+        }
+        None => false,
+    };
+    prev
+}
+
+fn duration_to_us(dur: Duration) -> usize {
+    (dur.as_secs() * 1_000_000 + dur.subsec_nanos() as u64 / 1_000) as usize
+}
+
+// Shortens `dur` to the time remaining until the deadline of the current coroutine
+fn cap_to_deadline(dur: Option<Duration>) -> Option<Duration> {
+    let deadline = match Scheduler::current_deadline() {
+        Some(deadline) => deadline,
+        None => return dur,
+    };
+
+    let now = Instant::now();
+    let remaining = if deadline > now {
+        deadline.duration_since(now)
+    } else {
+        Duration::new(0, 0)
+    };
+
+    match dur {
+        Some(dur) if dur < remaining => Some(dur),
+        _ => Some(remaining),
+    }
+}
+
 
 type RegisterCallback<'a> = &'a mut FnMut(&mut EventLoop<Scheduler>, Token, ReadyStates) -> bool;
 type DeregisterCallback<'a> = &'a mut FnMut(&mut EventLoop<Scheduler>);
@@ -94,9 +455,9 @@ pub enum Message {
 unsafe impl Send for Message {}
 
 
-#[doc(hidden)]
+/// The kind of readiness a coroutine waits for with `ReadyStates`
 #[repr(usize)]
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ReadyType {
     Readable = 0,
     Writable,
@@ -111,9 +472,17 @@ impl Into<EventSet> for ReadyType {
 #[derive(Debug)]
 struct ReadyStatesInner {
     condvars: [CoroCondvar; 2],
+    selects: [Spinlock<Vec<Select>>; 2],
 }
 
-#[doc(hidden)]
+/// Readiness of an I/O source registered with `Scheduler::register()`
+///
+/// Sources are registered edge triggered: A coroutine waiting for readiness is woken up
+/// whenever the source *becomes* ready. One must thus only wait after an operation
+/// on the source failed with `ErrorKind::WouldBlock`, or no wakeup might ever arrive.
+///
+/// Once the source is deregistered all waiting coroutines are woken up and all further
+/// waits return immediately, since no more events will arrive for it.
 #[derive(Clone, Debug)]
 pub struct ReadyStates {
     inner: Arc<ReadyStatesInner>,
@@ -122,30 +491,96 @@ pub struct ReadyStates {
 impl ReadyStates {
     #[inline]
     fn new() -> ReadyStates {
-        let stats = ReadyStatesInner { condvars: [CoroCondvar::new(), CoroCondvar::new()] };
+        let stats = ReadyStatesInner {
+            condvars: [CoroCondvar::new(), CoroCondvar::new()],
+            selects: [Spinlock::new(Vec::new()), Spinlock::new(Vec::new())],
+        };
 
         ReadyStates { inner: Arc::new(stats) }
     }
 
+    /// Parks the current coroutine until the source becomes ready for `ready_type`
     pub fn wait(&self, ready_type: ReadyType) {
         let condvar = &self.inner.condvars[ready_type as usize];
         condvar.wait();
     }
 
-    // Returns true on timeout
+    /// Like `wait()`, but returns true if the source didn't become ready within `dur`
     pub fn wait_timeout(&self, ready_type: ReadyType, dur: Duration) -> bool {
         let condvar = &self.inner.condvars[ready_type as usize];
         condvar.wait_timeout(dur).is_err()
     }
 
+    /// Like `wait_timeout()`, but without a timeout if `dur` is `None`
+    ///
+    /// The wait never outlasts the deadline of the current coroutine, see `current_deadline()`.
+    /// Fails with `ErrorKind::TimedOut` on timeout, with `cancel::Cancelled` if the
+    /// coroutine was cancelled and with `ErrorKind::NotConnected` if the source was deregistered.
+    pub fn wait_timeout_opt(&self, ready_type: ReadyType, dur: Option<Duration>) -> io::Result<()> {
+        let dur = cap_to_deadline(dur);
+
+        if dur == Some(Duration::new(0, 0)) {
+            Scheduler::note_timeout();
+            return Err(::net::make_timeout());
+        }
+
+        let condvar = &self.inner.condvars[ready_type as usize];
+
+        match condvar.wait_timeout_opt(dur) {
+            WaiterState::Timeout => {
+                Scheduler::note_timeout();
+                Err(::net::make_timeout())
+            }
+            WaiterState::Cancelled => Err(make_cancelled()),
+            _ if condvar.is_closed() => {
+                Err(io::Error::new(io::ErrorKind::NotConnected, "source was deregistered"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns true if the source was deregistered
+    pub fn is_deregistered(&self) -> bool {
+        self.inner.condvars[0].is_closed()
+    }
+
+    // Notifies `select` about every `ready_type` event until unregister_select() is called
+    #[doc(hidden)]
+    pub fn register_select(&self, ready_type: ReadyType, select: &Select) {
+        self.inner.selects[ready_type as usize].lock().push(select.clone());
+    }
+
+    #[doc(hidden)]
+    pub fn unregister_select(&self, ready_type: ReadyType, select: &Select) {
+        select::remove(&mut self.inner.selects[ready_type as usize].lock(), select);
+    }
+
     #[inline]
     fn notify(&self, event_set: EventSet, handles: &mut HandleList) {
         if event_set.contains(EventSet::readable()) {
-            self.inner.condvars[ReadyType::Readable as usize].notify_one(handles);
+            self.notify_type(ReadyType::Readable, handles);
         }
 
         if event_set.contains(EventSet::writable()) {
-            self.inner.condvars[ReadyType::Writable as usize].notify_one(handles);
+            self.notify_type(ReadyType::Writable, handles);
+        }
+    }
+
+    #[inline]
+    fn notify_type(&self, ready_type: ReadyType, handles: &mut HandleList) {
+        self.inner.condvars[ready_type as usize].notify_one(handles);
+
+        let selects = self.inner.selects[ready_type as usize].lock();
+        select::notify_all(&selects, |hdl| handles.push_back(hdl));
+    }
+
+    // Wakes up everyone waiting for the source, since it was deregistered
+    fn close(&self, handles: &mut HandleList) {
+        for ready_type in &[ReadyType::Readable, ReadyType::Writable] {
+            self.inner.condvars[*ready_type as usize].close(handles);
+
+            let selects = self.inner.selects[*ready_type as usize].lock();
+            select::notify_all(&selects, |hdl| handles.push_back(hdl));
         }
     }
 }
@@ -153,13 +588,187 @@ impl ReadyStates {
 enum TimerWaitType {
     Handle(Handle),
     Waiter(Shared<Waiter>),
+    Select(Select),
+}
+
+type PanicHandler = Fn(&PanicContext, &(Any + Send)) + Send + Sync;
+
+/// Describes the coroutine which panicked, see `Scheduler::with_panic_handler()`
+///
+/// It's displayed like `Coroutine#42(worker) on Processor#1`.
+#[derive(Debug, Clone, Copy)]
+pub struct PanicContext<'a> {
+    id: u64,
+    name: Option<&'a str>,
+    processor_id: usize,
+}
+
+impl<'a> PanicContext<'a> {
+    /// The ID of the coroutine, see `Scheduler::current_id()`
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// The name of the coroutine, as set by `Options::name()`
+    pub fn name(&self) -> Option<&'a str> {
+        self.name
+    }
+
+    /// The ID of the Processor the coroutine panicked on
+    pub fn processor_id(&self) -> usize {
+        self.processor_id
+    }
+}
+
+impl<'a> fmt::Display for PanicContext<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(match self.name {
+            Some(name) => write!(f, "Coroutine#{}({})", self.id, name),
+            None => write!(f, "Coroutine#{}", self.id),
+        });
+
+        write!(f, " on Processor#{}", self.processor_id)
+    }
+}
+type WatchdogHandler = Fn(usize, u64, Option<&str>, Duration) + Send + Sync;
+type IdleHandler = Fn(&mut Processor) + Send + Sync;
+
+/// Events in the lifetime of a coroutine reported to a `SchedulerObserver`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SchedulerEvent {
+    /// The coroutine was spawned
+    Spawn,
+    /// The coroutine is going to be resumed
+    Resume,
+    /// The coroutine yielded by calling `Scheduler::sched()`
+    Yield,
+    /// The coroutine was parked, e.g. waiting for I/O or a lock
+    Park,
+    /// The coroutine was stolen from another Processor
+    Steal,
+    /// The coroutine finished
+    Finish,
+}
+
+/// Receives the events of all coroutines of a Scheduler, e.g. for profiling
+///
+/// `on_event()` is called synchronously on the Processor's thread each time a coroutine is
+/// spawned, resumed, yields, parks, is stolen or finishes. It's passed the coroutine's ID
+/// (see `Scheduler::current_id()`), the ID of the Processor and the event.
+/// Since it's called very frequently it should return quickly. It mustn't call into the
+/// Scheduler either, since it may run while the Processor is borrowed, e.g. by a spawn.
+pub trait SchedulerObserver: Send + Sync {
+    fn on_event(&self, coroutine_id: u64, processor_id: usize, event: SchedulerEvent);
+}
+
+/// Determines how coroutines readied outside of the Processors are distributed
+///
+/// This affects coroutines spawned through a `SchedulerHandle` and ones whose
+/// `spawn_blocking()` closure finished. Coroutines pinned to a Processor always go there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExternalDispatch {
+    /// Put them into the global queue, from where the first idle Processor takes them
+    Global,
+    /// Hand them to the Processors in turn, preferring parked ones
+    RoundRobin,
+    /// Hand them to the Processor with the fewest queued coroutines, preferring parked ones
+    LeastLoaded,
+}
+
+/// Determines which Processor an idle Processor steals coroutines from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StealStrategy {
+    /// Try all other Processors in a random order and steal from the first non-empty one
+    Random,
+    /// Steal from the Processor with the longest queue first
+    ///
+    /// This reduces the imbalance between Processors under skewed workloads, at the cost of
+    /// having to read the queue length of all Processors on every steal attempt.
+    Longest,
+}
+
+/// A Processor an idle Processor may steal coroutines from, see `StealPolicy`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StealCandidate {
+    /// The ID of the Processor
+    pub processor_id: usize,
+    /// The number of coroutines in it's local queue, which can be stolen
+    pub queue_len: usize,
+}
+
+/// Picks the Processors an idle Processor steals coroutines from
+///
+/// It replaces the built-in `StealStrategy` once set with `Scheduler::with_steal_policy()`.
+/// Coroutines with a high priority are still stolen first and the global queue is checked
+/// once the policy gave up. Both methods are called on the idle Processor's thread, `thief`
+/// being it's ID, and should return quickly.
+pub trait StealPolicy: Send + Sync {
+    /// Returns the index into `candidates` of the Processor to steal from next
+    ///
+    /// `candidates` holds all Processors, including the idle one, along with the length of their
+    /// queues at the time the idle Processor started stealing. `attempt` counts the victims
+    /// tried since then, starting at 0. Returning `None` gives up, as do indices out of range.
+    fn next_victim(&self,
+                   thief: usize,
+                   candidates: &[StealCandidate],
+                   rng: &mut XorShiftRng,
+                   attempt: usize)
+                   -> Option<usize>;
+
+    /// Called after each attempt with the number of coroutines stolen, which is 0 if it failed
+    fn on_steal(&self, thief: usize, victim: usize, stolen: usize) {
+        let _ = (thief, victim, stolen);
+    }
+}
+
+/// Tries random Processors, like `StealStrategy::Random`
+///
+/// Since the default doesn't go through a `StealPolicy`, this is only useful as the fallback
+/// of custom policies.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RandomStealPolicy;
+
+impl StealPolicy for RandomStealPolicy {
+    fn next_victim(&self,
+                   _thief: usize,
+                   candidates: &[StealCandidate],
+                   rng: &mut XorShiftRng,
+                   attempt: usize)
+                   -> Option<usize> {
+        // As many attempts as the 4 rounds over all Processors of StealStrategy::Random
+        if candidates.is_empty() || attempt >= 4 * candidates.len() {
+            None
+        } else {
+            Some(rng.gen_range(0, candidates.len()))
+        }
+    }
 }
 
 /// Coroutine scheduler
 pub struct Scheduler {
     default_spawn_options: Options,
     expected_worker_count: usize,
+    max_worker_count: usize,
     maximum_stack_memory_limit: usize,
+    steal_strategy: StealStrategy,
+    steal_policy: Option<Box<StealPolicy>>,
+    external_dispatch: ExternalDispatch,
+    next_dispatch: AtomicUsize,
+    single_threaded: bool,
+    seed: Option<u64>,
+    max_spawn_depth: Option<usize>,
+    yield_budget: usize,
+    steal_retries: usize,
+    park_backoff: (u32, u32, Duration),
+    adaptive_parking: Option<AdaptiveParking>,
+
+    // See load(): The smoothed load is stored as a fraction of LOAD_ONE and
+    // load_updated holds the microseconds since load_epoch of the last update.
+    load_high_water: usize,
+    load_window: Duration,
+    load_epoch: Instant,
+    load_smoothed: AtomicUsize,
+    load_updated: AtomicUsize,
 
     // Mio event loop handler
     event_loop_sender: Option<Sender<Message>>,
@@ -168,129 +777,648 @@ pub struct Scheduler {
 
     // NOTE:
     // This member is _used_ concurrently, but still deliberately used without any kind of locks.
-    // The reason for this is that during runtime of the Scheduler the vector of Machines will
+    // The reason for this is that during runtime of the Scheduler the Machines in this vector
     // never change and thus it's contents are constant as long as any Processor is running.
+    // Processors steal from each other through it, instead of keeping a list of neighbors, and
+    // since all of them are spawned before any starts scheduling (see the Barrier in run()),
+    // none of them ever sees an incomplete set.
+    // add_processor() appends Machines without reallocating the vector, since it's capacity is
+    // reserved upfront, and only publishes them through machine_count, which is the length
+    // everybody else uses. Removed Processors are kept, see remove_processor().
     machines: UnsafeCell<Vec<Machine>>,
+    machine_count: AtomicUsize,
+    // Set once the Processors are shutting down, after which machines may not change anymore
+    machines_closed: Mutex<bool>,
 
-    idle_processor_condvar: Condvar,
+    parker: Box<Parker>,
     idle_processor_count: AtomicUsize,
-    idle_processor_mutex: Mutex<bool>,
     spinning_processor_count: AtomicUsize,
 
     global_queue_size: AtomicUsize,
     global_queue: Mutex<HandleList>,
     io_handler_queue: HandleList,
+
+    // Number of coroutines spawned through Scheduler::spawn_opts() which haven't finished yet
+    running_coroutine_count: AtomicUsize,
+    draining: AtomicBool,
+    // Set by shutdown_with_timeout(), see force_killed_coroutines()
+    shutdown_deadline: Mutex<Option<Instant>>,
+    force_killing: AtomicBool,
+    force_killed: Mutex<Vec<u64>>,
+    // See dropped_coroutine_count()
+    dropped_coroutine_count: AtomicUsize,
+
+    blocking_thread_count: usize,
+    blocking_pool: Option<BlockingPool>,
+
+    panic_handler: Option<Box<PanicHandler>>,
+    observer: Option<Box<SchedulerObserver>>,
+    idle_handler: Option<Box<IdleHandler>>,
+    watchdog: Option<(Duration, Box<WatchdogHandler>)>,
+
+    restart_processors: bool,
+    processor_panic: Mutex<Option<Box<Any + Send>>>,
+
+    run_until_idle: bool,
+    handle_shared: Arc<HandleShared>,
 }
 
 impl Scheduler {
     /// Create a scheduler with default configurations
+    ///
+    /// The options are set through the `with_*()` methods or `builder()` and are fixed once
+    /// `run()` was called, see the `builder` module.
     pub fn new() -> Scheduler {
         Scheduler {
             default_spawn_options: Options::default(),
             expected_worker_count: 1,
+            max_worker_count: 0,
             maximum_stack_memory_limit: 2 * 1024 * 1024 * 1024, // 2GB
+            steal_strategy: StealStrategy::Random,
+            steal_policy: None,
+            external_dispatch: ExternalDispatch::Global,
+            next_dispatch: AtomicUsize::new(0),
+            single_threaded: false,
+            seed: None,
+            max_spawn_depth: None,
+            yield_budget: 1,
+            steal_retries: 1,
+            park_backoff: (6, 2, Duration::from_millis(1)),
+            adaptive_parking: None,
+
+            load_high_water: 128,
+            load_window: Duration::from_millis(100),
+            load_epoch: Instant::now(),
+            load_smoothed: AtomicUsize::new(0),
+            load_updated: AtomicUsize::new(0),
 
             event_loop_sender: None,
             slab: Slab::new(1024),
             timer: Spinlock::new(Timer::new(100, 1_024, 65_536)),
 
             machines: UnsafeCell::new(Vec::new()),
+            machine_count: AtomicUsize::new(0),
+            machines_closed: Mutex::new(true),
 
-            idle_processor_condvar: Condvar::new(),
+            parker: Box::new(CondvarParker::new()),
             idle_processor_count: AtomicUsize::new(0),
-            idle_processor_mutex: Mutex::new(false),
             spinning_processor_count: AtomicUsize::new(0),
 
             global_queue_size: AtomicUsize::new(0),
             global_queue: Mutex::new(HandleList::new()),
             io_handler_queue: HandleList::new(),
+
+            running_coroutine_count: AtomicUsize::new(0),
+            draining: AtomicBool::new(false),
+            shutdown_deadline: Mutex::new(None),
+            force_killing: AtomicBool::new(false),
+            force_killed: Mutex::new(Vec::new()),
+            dropped_coroutine_count: AtomicUsize::new(0),
+
+            blocking_thread_count: 4,
+            blocking_pool: None,
+
+            panic_handler: None,
+            observer: None,
+            idle_handler: None,
+            watchdog: None,
+
+            restart_processors: false,
+            processor_panic: Mutex::new(None),
+
+            run_until_idle: false,
+            handle_shared: Arc::new(HandleShared {
+                scheduler: RwLock::new(0),
+                count: AtomicUsize::new(0),
+            }),
         }
     }
 
-    /// Set the number of workers
-    pub fn with_workers(mut self, workers: usize) -> Scheduler {
-        assert!(workers >= 1, "Must have at least one worker");
-        self.expected_worker_count = workers;
+    /// Create a scheduler running all coroutines deterministically on a single Processor
+    ///
+    /// Coroutines are resumed strictly in the order they were queued, which makes
+    /// the execution order reproducible, e.g. for tests. It can't be combined with
+    /// more than one worker. Coroutines woken up by I/O or timers are still handed over
+    /// by the event loop and might thus be resumed in a different order on every run.
+    pub fn single_threaded() -> Scheduler {
+        let mut sched = Scheduler::new();
+        sched.single_threaded = true;
+        sched
+    }
+
+    #[doc(hidden)]
+    #[inline]
+    pub fn is_single_threaded(&self) -> bool {
+        self.single_threaded
+    }
+
+    /// Returns a handle to spawn coroutines from threads outside of the Scheduler
+    pub fn handle(&self) -> SchedulerHandle {
+        HandleShared::handle(&self.handle_shared)
+    }
+
+    #[doc(hidden)]
+    pub fn ready_handle(&self) -> ReadyHandle {
+        ReadyHandle { shared: self.handle_shared.clone() }
+    }
+
+    /// Set a handler which is called whenever a spawned coroutine panics
+    ///
+    /// The handler receives the ID and name of the coroutine together with the ID of it's
+    /// Processor, as well as the panic's payload. It is called on the panicked coroutine right
+    /// before the payload is passed on to the `JoinHandle`. The Processor keeps on running
+    /// other coroutines. Without a handler a line naming the coroutine and it's Processor
+    /// is printed to stderr instead, right before the message of the panic hook.
+    pub fn with_panic_handler<F>(mut self, handler: F) -> Scheduler
+        where F: Fn(&PanicContext, &(Any + Send)) + Send + Sync + 'static
+    {
+        self.panic_handler = Some(Box::new(handler));
         self
     }
 
-    /// Set the default stack size
-    pub fn default_stack_size(mut self, default_stack_size: usize) -> Scheduler {
-        self.default_spawn_options.stack_size(default_stack_size);
+    /// Set an observer which is notified about the scheduling events of all coroutines
+    ///
+    /// Without an observer the only overhead is a check for it's existence.
+    pub fn with_observer<O>(mut self, observer: O) -> Scheduler
+        where O: SchedulerObserver + 'static
+    {
+        self.observer = Some(Box::new(observer));
         self
     }
 
+    #[doc(hidden)]
     #[inline]
-    pub fn work_count(&self) -> usize {
-        ::global_work_count_get()
+    pub fn observer(&self) -> Option<&SchedulerObserver> {
+        self.observer.as_ref().map(|observer| &**observer)
     }
 
-    /// Run the scheduler
-    pub fn run<F, T>(&mut self, f: F) -> thread::Result<T>
-        where F: FnOnce() -> T + Send + 'static,
-              T: Send + 'static
+    /// Set the `Parker` which blocks idle Processors, `CondvarParker` by default
+    ///
+    /// See the `park` module.
+    pub fn with_parker<P>(mut self, parker: P) -> Scheduler
+        where P: Parker + 'static
     {
-        trace!("setting custom panic hook");
+        self.parker = Box::new(parker);
+        self
+    }
 
-        let default_handler = panic::take_hook();
-        panic::set_hook(Box::new(move |panic_info| {
-            if let Some(mut p) = Processor::current() {
-                if let Some(coro) = p.current() {
-                    let mut stderr = io::stderr();
-                    let name = match coro.name() {
-                        Some(name) => name,
-                        None => "<unnamed>",
-                    };
-                    let _ = write!(stderr, "Coroutine `{}` running in ", name);
-                }
-            }
+    /// Set a handler which is called by a Processor right before it parks for lack of work
+    ///
+    /// Use it for maintenance work like flushing metrics. It's called on the Processor's thread,
+    /// which can't run any coroutines in the meantime, so it must be cheap and must never block.
+    /// Coroutines it readies with `Processor::ready()` are resumed right away instead of parking.
+    /// Since a Processor parks whenever it runs out of work, the handler might be called
+    /// very frequently under bursty load: Rate limit expensive work yourself.
+    pub fn with_idle_handler<F>(mut self, handler: F) -> Scheduler
+        where F: Fn(&mut Processor) + Send + Sync + 'static
+    {
+        self.idle_handler = Some(Box::new(handler));
+        self
+    }
 
-            default_handler(panic_info);
-        }));
+    #[doc(hidden)]
+    #[inline]
+    pub fn idle_handler(&self) -> Option<&IdleHandler> {
+        self.idle_handler.as_ref().map(|handler| &**handler)
+    }
 
-        if self.expected_worker_count > 1 {
-            warn!("It is unsafe to run Scheduler in multithread mode, see \
-                   https://github.com/zonyitoo/coio-rs/issues/56 for details");
+    /// Warn about coroutines running for longer than `threshold` without yielding
+    ///
+    /// A coroutine which never yields blocks it's Processor and starves all coroutines in it's
+    /// queue. With a watchdog a monitor thread checks the Processors periodically and logs a
+    /// warning with the ID and name of each coroutine exceeding the threshold.
+    /// The coroutine won't be preempted though. Coroutines inside of `allow_blocking()` aren't
+    /// reported.
+    pub fn with_watchdog(self, threshold: Duration) -> Scheduler {
+        self.with_watchdog_handler(threshold, log_blocking)
+    }
+
+    /// Run a watchdog with a threshold of 100ms in debug builds only
+    ///
+    /// Meant for tests to catch accidental blocking, without paying for the monitor thread
+    /// in release builds. Does nothing if a watchdog was set already.
+    pub fn with_debug_watchdog(self) -> Scheduler {
+        if cfg!(debug_assertions) && self.watchdog.is_none() {
+            self.with_watchdog(Duration::from_millis(DEBUG_WATCHDOG_THRESHOLD_MS))
+        } else {
+            self
         }
+    }
 
-        // Timer has to be setup before any kind of operations on it
-        self.timer.lock().setup();
+    /// Call `handler` for coroutines running for longer than `threshold` without yielding
+    ///
+    /// The same as `with_watchdog()`, but instead of logging a warning the handler is
+    /// called on the monitor thread with the ID of the Processor, the ID and name
+    /// of the coroutine and how long it has been running.
+    pub fn with_watchdog_handler<F>(mut self, threshold: Duration, handler: F) -> Scheduler
+        where F: Fn(usize, u64, Option<&str>, Duration) + Send + Sync + 'static
+    {
+        assert!(threshold >= Duration::from_millis(4),
+                "Watchdog threshold must be at least 4ms");
+        self.watchdog = Some((threshold, Box::new(handler)));
+        self
+    }
 
-        trace!("creating EventLoop");
+    #[doc(hidden)]
+    #[inline]
+    pub fn has_watchdog(&self) -> bool {
+        self.watchdog.is_some()
+    }
 
-        let mut event_loop_config = EventLoopConfig::new();
-        event_loop_config.notify_capacity(4_096);
-        event_loop_config.messages_per_tick(4_096);
-        event_loop_config.timer_tick_ms(100);
-        event_loop_config.timer_wheel_size(1_024);
-        event_loop_config.timer_capacity(65_536);
+    #[doc(hidden)]
+    pub fn watchdog_notify(&self,
+                           processor_id: usize,
+                           id: u64,
+                           name: Option<&str>,
+                           elapsed: Duration) {
+        if let Some((_, ref handler)) = self.watchdog {
+            handler(processor_id, id, name, elapsed);
+        }
+    }
 
-        let mut event_loop = EventLoop::configured(event_loop_config).unwrap();
-        self.event_loop_sender = Some(event_loop.channel());
+    /// Keep the Scheduler running if one of it's Processors panics
+    ///
+    /// Coroutines are isolated from each other, but a panic in the Processor itself,
+    /// e.g. in an observer, unwinds the coroutine being scheduled at that moment.
+    /// The Processor is always restarted on the same thread and the coroutines in it's queues
+    /// are kept, but by default the Scheduler is shut down afterwards
+    /// and `run()` returns the panic's payload. If `restart` is true the panic is only logged.
+    pub fn with_processor_restart(mut self, restart: bool) -> Scheduler {
+        self.restart_processors = restart;
+        self
+    }
 
-        let mut result = None;
+    #[doc(hidden)]
+    pub fn processor_panicked(&self, processor_id: usize, err: Box<Any + Send>) {
+        if self.restart_processors {
+            return;
+        }
+
+        error!("Processor#{} panicked => shutting down", processor_id);
 
-        let cloned_event_loop_sender = event_loop.channel();
         {
-            let result = unsafe { &mut *(&mut result as *mut _) };
-            let wrapper = move || {
-                let ret = panic::catch_unwind(panic::AssertUnwindSafe(f));
+            let mut panic = self.processor_panic.lock().unwrap();
 
-                *result = Some(ret);
+            if panic.is_none() {
+                *panic = Some(err);
+            }
+        }
 
-                trace!("Coroutine(<main>) finished => sending Shutdown");
-                let _ = cloned_event_loop_sender.send(Message::Shutdown);
-            };
+        self.send_shutdown();
+    }
 
-            let mut opt = self.default_spawn_options.clone();
-            opt.name("<main>".to_owned());
-            let main_coro = Coroutine::spawn_opts(Box::new(wrapper), opt);
+    // Called by a Processor which stopped scheduling, see ProcessorError
+    #[doc(hidden)]
+    pub fn processor_failed(&self, processor_id: usize, err: ProcessorError) {
+        error!("Processor#{} stopped: {} => shutting down", processor_id, err);
+
+        // The coroutines can't be run reliably without it, e.g. the ones pinned to it
+        self.send_shutdown();
+    }
+
+    /// Set the number of threads running the closures passed to `spawn_blocking()`
+    ///
+    /// The default is 4 threads.
+    pub fn with_blocking_threads(mut self, threads: usize) -> Scheduler {
+        assert!(threads >= 1, "Must have at least one blocking thread");
+        self.blocking_thread_count = threads;
+        self
+    }
+
+    /// Set the number of workers
+    ///
+    /// Each worker is a `Processor` running on it's own OS thread. The main coroutine passed to
+    /// `run()` always starts on the first worker (`Processor#0`) and may be stolen by the others
+    /// from there on. Defaults to `1`.
+    pub fn with_workers(mut self, workers: usize) -> Scheduler {
+        assert!(workers >= 1, "Must have at least one worker");
+        assert!(workers == 1 || !self.single_threaded,
+                "A single threaded Scheduler must have exactly one worker");
+        self.expected_worker_count = workers;
+        self
+    }
+
+    /// Set the number of workers `add_processor()` may grow the Scheduler to
+    ///
+    /// The space for all of them is reserved upfront, so that the Processors can keep on
+    /// accessing each other without any locks. Defaults to the number of workers.
+    pub fn with_max_workers(mut self, max: usize) -> Scheduler {
+        assert!(max <= 1 || !self.single_threaded,
+                "A single threaded Scheduler must have exactly one worker");
+        self.max_worker_count = max;
+        self
+    }
+
+    /// Set the resolution of the timer driving `sleep()` and I/O timeouts
+    ///
+    /// All timeouts are rounded up to the next multiple of the resolution. Defaults to 100ms.
+    pub fn with_timer_resolution(mut self, resolution: Duration) -> Scheduler {
+        let tick_ms = ::duration_to_ms(resolution);
+        assert!(tick_ms >= 1, "Timer resolution must be at least 1ms");
+        self.timer = Spinlock::new(Timer::new(tick_ms, 1_024, 65_536));
+        self
+    }
+
+    /// Returns the resolution of the timer driving `sleep()` and I/O timeouts
+    pub fn timer_resolution(&self) -> Duration {
+        Duration::from_millis(self.timer.lock().tick_ms())
+    }
+
+    /// Set the maximum size of the stacks cached by each Processor for reuse
+    ///
+    /// Stacks of finished coroutines are kept, bucketed by size, and handed out to newly
+    /// spawned coroutines. Once a pool exceeds `limit` bytes, the least recently used
+    /// stacks are freed until it shrinks to half of it. A limit of 0 disables the pooling.
+    /// Defaults to 2GB.
+    pub fn with_stack_pool_limit(mut self, limit: usize) -> Scheduler {
+        self.maximum_stack_memory_limit = limit;
+        self
+    }
+
+    /// Set the strategy used by idle Processors to pick the Processor they steal from
+    ///
+    /// Defaults to `StealStrategy::Random`.
+    pub fn with_steal_strategy(mut self, strategy: StealStrategy) -> Scheduler {
+        self.steal_strategy = strategy;
+        self
+    }
+
+    #[doc(hidden)]
+    #[inline]
+    pub fn steal_strategy(&self) -> StealStrategy {
+        self.steal_strategy
+    }
+
+    /// Set a policy picking the Processors idle Processors steal from, see `StealPolicy`
+    ///
+    /// Without a policy the built-in `StealStrategy` is used, which is called directly.
+    pub fn with_steal_policy<P>(mut self, policy: P) -> Scheduler
+        where P: StealPolicy + 'static
+    {
+        self.steal_policy = Some(Box::new(policy));
+        self
+    }
+
+    #[doc(hidden)]
+    #[inline]
+    pub fn steal_policy(&self) -> Option<&StealPolicy> {
+        self.steal_policy.as_ref().map(|policy| &**policy)
+    }
+
+    /// Set how coroutines readied outside of the Processors are distributed
+    ///
+    /// Defaults to `ExternalDispatch::Global`.
+    pub fn with_external_dispatch(mut self, dispatch: ExternalDispatch) -> Scheduler {
+        self.external_dispatch = dispatch;
+        self
+    }
+
+    /// Set how many times an idle Processor tries to steal from the other Processors
+    /// before it starts backing off (see `with_park_backoff()`)
+    ///
+    /// Each attempt sweeps over all other Processors and the global queue and the thread
+    /// is yielded between two attempts. With many Processors and bursty arrivals of new
+    /// coroutines more attempts find work more often before parking, which lowers
+    /// the latency until it is resumed, at the cost of CPU time burnt by idle Processors
+    /// and more contention on the queues of busy ones. Defaults to 1.
+    pub fn with_steal_retries(mut self, retries: usize) -> Scheduler {
+        assert!(retries >= 1, "Must try to steal at least once");
+        self.steal_retries = retries;
+        self
+    }
+
+    #[doc(hidden)]
+    #[inline]
+    pub fn steal_retries(&self) -> usize {
+        self.steal_retries
+    }
+
+    /// Seed the random number generators which determine the order Processors steal in
+    ///
+    /// Each Processor's generator is seeded with `seed` plus it's ID. Together with
+    /// `single_threaded()` this makes the scheduling reproducible. By default they are seeded
+    /// randomly.
+    pub fn with_seed(mut self, seed: u64) -> Scheduler {
+        self.seed = Some(seed);
+        self
+    }
+
+    #[doc(hidden)]
+    #[inline]
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    /// Set how long an idle Processor keeps looking for work before it is parked
+    ///
+    /// A Processor which runs out of coroutines first spins `spins` times, with exponentially
+    /// growing pauses, then yields it's thread `yields` times and finally waits up to `timeout`
+    /// for a wakeup, looking for new work in between each step. Only then it is parked
+    /// until it's explicitly woken up. This reduces the park/unpark churn under bursty load
+    /// at the cost of some CPU time. A zero `timeout` skips the timed wait.
+    /// Defaults to 6 spins, 2 yields and a timeout of 1ms.
+    pub fn with_park_backoff(mut self, spins: u32, yields: u32, timeout: Duration) -> Scheduler {
+        assert!(spins <= 16, "Must spin at most 16 times");
+        self.park_backoff = (spins, yields, timeout);
+        self
+    }
+
+    #[doc(hidden)]
+    #[inline]
+    pub fn park_backoff(&self) -> (u32, u32, Duration) {
+        self.park_backoff
+    }
+
+    /// Adapt the spinning of idle Processors to how often they find work, see `AdaptiveParking`
+    ///
+    /// Processors which mostly fail to steal coroutines spin less before parking, which saves
+    /// CPU time on mostly idle systems, while busy ones keep spinning to keep the latency low.
+    /// Disabled by default.
+    pub fn with_adaptive_parking(mut self, params: AdaptiveParking) -> Scheduler {
+        assert!(params.smoothing < 10, "smoothing must be less than 10");
+        assert!(params.hot_threshold <= STEAL_RATE_ONE,
+                "hot_threshold must be at most STEAL_RATE_ONE");
+        self.adaptive_parking = Some(params);
+        self
+    }
+
+    #[doc(hidden)]
+    #[inline]
+    pub fn adaptive_parking(&self) -> Option<AdaptiveParking> {
+        self.adaptive_parking
+    }
+
+    /// Limit how deeply coroutines may spawn each other, see `current_depth()`
+    ///
+    /// Spawning a coroutine deeper than `depth` fails and it's `JoinHandle` immediately returns
+    /// an error instead, which catches accidentally unbounded recursive spawning before it
+    /// exhausts the memory. Coroutines spawned from outside of a coroutine are never limited.
+    /// Unlimited by default.
+    pub fn with_max_spawn_depth(mut self, depth: usize) -> Scheduler {
+        self.max_spawn_depth = Some(depth);
+        self
+    }
+
+    /// The limit set by `with_max_spawn_depth()`
+    #[inline]
+    pub fn max_spawn_depth(&self) -> Option<usize> {
+        self.max_spawn_depth
+    }
+
+    // Returns true if a coroutine spawned by the current one would exceed max_spawn_depth
+    fn spawn_depth_exceeded(&self) -> bool {
+        match self.max_spawn_depth {
+            Some(max) => Scheduler::current_depth().map_or(false, |depth| depth >= max),
+            None => false,
+        }
+    }
+
+    /// Set how many operations a coroutine may complete without blocking before it's suspended
+    ///
+    /// I/O operations which succeed right away count against the budget of the current
+    /// coroutine, see `consume_budget()`. Once it's used up the coroutine is suspended like with
+    /// `sched()`, which keeps a loop never running into `WouldBlock` from starving the other
+    /// coroutines on it's Processor. Every time a coroutine is resumed it's budget is refilled.
+    /// Larger budgets trade fairness for throughput. Defaults to 1, which suspends the coroutine
+    /// after every operation.
+    pub fn with_yield_budget(mut self, budget: usize) -> Scheduler {
+        assert!(budget >= 1, "the yield budget must be at least 1");
+        self.yield_budget = budget;
+        self
+    }
+
+    #[doc(hidden)]
+    #[inline]
+    pub fn yield_budget(&self) -> usize {
+        self.yield_budget
+    }
+
+    /// Set the number of queued coroutines per Processor at which the queues count as full
+    ///
+    /// See `load()`. Defaults to 128.
+    pub fn with_load_high_water(mut self, depth: usize) -> Scheduler {
+        assert!(depth > 0, "the high-water mark must not be zero");
+        self.load_high_water = depth;
+        self
+    }
+
+    /// Set the time window over which `load()` is smoothed, 100ms by default
+    ///
+    /// A zero `window` disables the smoothing.
+    pub fn with_load_window(mut self, window: Duration) -> Scheduler {
+        self.load_window = window;
+        self
+    }
+
+    /// Set the default stack size
+    pub fn default_stack_size(mut self, default_stack_size: usize) -> Scheduler {
+        self.default_spawn_options.stack_size(default_stack_size);
+        self
+    }
+
+    #[inline]
+    pub fn work_count(&self) -> usize {
+        ::global_work_count_get()
+    }
+
+    /// Run the scheduler
+    ///
+    /// `f` is run as the main coroutine and `run()` returns as soon as it finished, passing on
+    /// it's return value. If it panics, the panic is caught and it's payload is returned as
+    /// the `Err` instead. Panics of other coroutines never end up here: they are passed on
+    /// to their `JoinHandle` and the panic handler (see `with_panic_handler()`).
+    /// `Err` is also returned if one of the Processors panicked (see `with_processor_restart()`),
+    /// or if the main coroutine was dropped before it ever ran.
+    pub fn run<F, T>(&mut self, f: F) -> thread::Result<T>
+        where F: FnOnce() -> T + Send + 'static,
+              T: Send + 'static
+    {
+        trace!("setting custom panic hook");
+
+        // The previous hook is chained and restored once the Scheduler exits,
+        // which keeps hooks set up by the application working.
+        // With a panic handler the coroutine is reported through it instead, see report_panic().
+        let prev_hook = Arc::new(panic::take_hook());
+        {
+            let prev_hook = prev_hook.clone();
+            let has_panic_handler = self.panic_handler.is_some();
+
+            panic::set_hook(Box::new(move |panic_info| {
+                // The panic may have been raised while a ProcessorHandle is alive
+                if let (false, Some(mut p)) = (has_panic_handler, Processor::try_current()) {
+                    let processor_id = p.id();
+
+                    if let Some(coro) = p.current() {
+                        let _ = writeln!(io::stderr(),
+                                         "{} on Processor#{} panicked:",
+                                         coro.debug_name(),
+                                         processor_id);
+                    }
+                }
+
+                prev_hook(panic_info);
+            }));
+        }
+
+        if self.expected_worker_count > 1 {
+            warn!("It is unsafe to run Scheduler in multithread mode, see \
+                   https://github.com/zonyitoo/coio-rs/issues/56 for details");
+        }
+
+        // Timer has to be setup before any kind of operations on it
+        self.timer.lock().setup();
+
+        trace!("creating EventLoop");
+
+        let mut event_loop_config = EventLoopConfig::new();
+        event_loop_config.notify_capacity(4_096);
+        event_loop_config.messages_per_tick(4_096);
+        event_loop_config.timer_tick_ms(self.timer.lock().tick_ms());
+        event_loop_config.timer_wheel_size(1_024);
+        event_loop_config.timer_capacity(65_536);
+
+        let mut event_loop = EventLoop::configured(event_loop_config).unwrap();
+        self.event_loop_sender = Some(event_loop.channel());
+
+        let mut result = None;
+
+        let cloned_event_loop_sender = event_loop.channel();
+        let mut main_coro = {
+            let result = unsafe { &mut *(&mut result as *mut _) };
+            let wrapper = move || {
+                let ret = panic::catch_unwind(panic::AssertUnwindSafe(f));
+
+                *result = Some(ret);
 
-            self.push_global_queue(main_coro);
+                let scheduler = Scheduler::instance().unwrap();
+
+                if scheduler.is_draining() || scheduler.run_until_idle {
+                    trace!("Coroutine(<main>) finished => waiting for the other coroutines");
+                    scheduler.finish_coroutine();
+                } else {
+                    trace!("Coroutine(<main>) finished => sending Shutdown");
+                    let _ = cloned_event_loop_sender.send(Message::Shutdown);
+                }
+            };
+
+            self.running_coroutine_count.fetch_add(1, Ordering::Relaxed);
+
+            let mut opt = self.default_spawn_options.clone();
+            opt.name("<main>".to_owned());
+            Some(Coroutine::spawn_opts(Box::new(wrapper), opt))
         };
 
+        self.blocking_pool = Some(BlockingPool::new(self.blocking_thread_count));
+
+        // A previous run() might have been shut down by shutdown_graceful()
+        self.draining.store(false, Ordering::SeqCst);
+        *self.shutdown_deadline.lock().unwrap() = None;
+        self.force_killing.store(false, Ordering::SeqCst);
+        self.force_killed.lock().unwrap().clear();
+        self.dropped_coroutine_count.store(0, Ordering::SeqCst);
+
         let mut machines = unsafe { &mut *self.machines.get() };
-        machines.reserve(self.expected_worker_count);
+        machines.reserve_exact(cmp::max(self.expected_worker_count, self.max_worker_count));
 
         trace!("spawning Machines");
         {
@@ -298,19 +1426,52 @@ impl Scheduler {
             let mem = self.maximum_stack_memory_limit;
 
             for tid in 0..self.expected_worker_count {
-                machines.push(Processor::spawn(self, tid, barrier.clone(), mem));
+                // The main coroutine is handed to the first Processor
+                let initial = main_coro.take();
+                machines.push(Processor::spawn(self, tid, barrier.clone(), mem, initial));
             }
 
+            self.machine_count.store(machines.len(), Ordering::Release);
+            *self.machines_closed.lock().unwrap() = false;
+
             // After this Barrier unblocks we know that all Processors a fully spawned and
             // ready to call Processor::schedule(). This knowledge plus the fact that machines
             // is a static array after this point allows us to access that array without locks.
             barrier.wait();
         }
 
+        *self.handle_shared.scheduler.write().unwrap() = self as *const Scheduler as usize;
+
+        let watchdog = match self.watchdog {
+            Some((threshold, _)) => Some(Watchdog::spawn(self, threshold)),
+            None => None,
+        };
+
         trace!("running EventLoop");
 
         while event_loop.is_running() {
+            let shutdown_timeout = match *self.shutdown_deadline.lock().unwrap() {
+                Some(deadline) => {
+                    let now = Instant::now();
+
+                    if now >= deadline {
+                        warn!("Scheduler: shutdown timeout passed => force killing coroutines");
+                        self.force_killing.store(true, Ordering::SeqCst);
+                        break;
+                    }
+
+                    // Rounded up, since waking up too early only polls once more
+                    let dur = deadline - now;
+                    Some(dur.as_secs() * 1_000 + (dur.subsec_nanos() as u64 + 999_999) / 1_000_000)
+                }
+                None => None,
+            };
+
             let next_tick = self.timer.lock().next_tick_in_ms();
+            let next_tick = match (next_tick, shutdown_timeout) {
+                (Some(a), Some(b)) => Some(cmp::min(a, b)),
+                (a, b) => a.or(b),
+            };
             let next_tick = next_tick.map(|ms| {
                 if ms > usize::max_value() as u64 {
                     usize::max_value()
@@ -337,6 +1498,11 @@ impl Scheduler {
                                 self.io_handler_queue.push_back(hdl);
                             }
                         }
+                        Some(TimerWaitType::Select(select)) => {
+                            if let Some(hdl) = select.notify() {
+                                self.io_handler_queue.push_back(hdl);
+                            }
+                        }
                         None => break,
                     }
                 }
@@ -345,36 +1511,141 @@ impl Scheduler {
             self.append_io_handler_to_global_queue();
         }
 
+        // Coroutines can't be spawned from the outside anymore while the Processors shut down
+        *self.handle_shared.scheduler.write().unwrap() = 0;
+
+        // Coroutines which are still waiting for a timeout are handed to the Processors,
+        // which will force unwind them on their own threads during the shutdown.
+        {
+            let mut timer = self.timer.lock();
+
+            for wait in timer.drain() {
+                match wait {
+                    TimerWaitType::Handle(hdl) => self.io_handler_queue.push_back(hdl),
+                    TimerWaitType::Waiter(waiter_ptr) => {
+                        let waiter = unsafe { &**waiter_ptr };
+                        if let Some(hdl) = waiter.notify(WaiterState::Timeout) {
+                            self.io_handler_queue.push_back(hdl);
+                        }
+                    }
+                    TimerWaitType::Select(select) => {
+                        if let Some(hdl) = select.notify() {
+                            self.io_handler_queue.push_back(hdl);
+                        }
+                    }
+                }
+            }
+        }
+
+        self.append_io_handler_to_global_queue();
+
+        drop(watchdog);
+
         trace!("EventLoop finished => sending Shutdown");
         {
-            let barrier = Arc::new(Barrier::new(self.expected_worker_count + 1));
+            // Processors added by add_processor() aren't part of the vector's length yet
+            *self.machines_closed.lock().unwrap() = true;
+            unsafe { machines.set_len(self.machine_count.load(Ordering::Acquire)) };
+
+            let barrier = Arc::new(ShutdownBarrier::new(machines.len() + 1));
 
             for m in machines.iter() {
-                m.processor_handle.send(ProcMessage::Shutdown(barrier.clone())).unwrap();
+                let msg = ProcMessage::Shutdown(barrier.clone());
+
+                if let Err(SendError(msg)) = m.processor_handle.send(msg) {
+                    // The receiving end is gone and the Processor with it. Someone has to
+                    // take part in the shutdown in it's place, or all other Processors would hang.
+                    error!("{:?} is gone, skipping it's shutdown", m.processor);
+
+                    if let ProcMessage::Shutdown(barrier) = msg {
+                        thread::spawn(move || {
+                            barrier.quiesce();
+                            barrier.drain(|| 0);
+                        });
+                    }
+                }
             }
 
-            *self.idle_processor_mutex.lock().unwrap() = true;
-            self.idle_processor_condvar.notify_all();
+            // Parked Processors must wake up to take part in the shutdown and keep running
+            // until they're joined below.
+            self.parker.close();
 
-            barrier.wait();
+            // The coroutines are dropped by the Processors, see ShutdownBarrier
+            barrier.quiesce();
+            barrier.drain(|| 0);
+
+            // Added to the ones dropped by drop_queued_coroutines() in the meantime
+            let dropped = barrier.dropped_count();
+            self.dropped_coroutine_count.fetch_add(dropped, Ordering::SeqCst);
+            debug!("Scheduler: dropped {} coroutines during the shutdown", dropped);
         }
 
         trace!("awaiting completion of Machines");
         {
-            *self.idle_processor_mutex.lock().unwrap() = true;
-            self.idle_processor_condvar.notify_all();
             // NOTE: It's critical that all threads are joined since Processor
             // maintains a reference to this Scheduler using raw pointers.
+            self.machine_count.store(0, Ordering::Release);
+
             for m in machines.drain(..) {
                 let _ = m.thread_handle.join();
             }
         }
 
+        trace!("awaiting completion of blocking threads");
+        {
+            // Coroutines whose blocking closure finished only after the Processors shut down
+            // are left in the global queue and unwound here.
+            // Once the shutdown timed out, the closures which haven't started yet are dropped.
+            if self.force_killing.load(Ordering::SeqCst) {
+                if let Some(ref pool) = self.blocking_pool {
+                    pool.discard_pending();
+                }
+            }
+
+            drop(self.blocking_pool.take());
+            let global_queue = mem::replace(&mut *self.get_global_queue(), HandleList::new());
+            self.dropped_coroutine_count.fetch_add(global_queue.len(), Ordering::SeqCst);
+            drop(global_queue);
+        }
+
         // Restore panic handler
-        trace!("restoring default panic hook");
-        panic::take_hook();
+        trace!("restoring previous panic hook");
+        drop(panic::take_hook());
+        match Arc::try_unwrap(prev_hook) {
+            Ok(prev_hook) => panic::set_hook(prev_hook),
+            Err(prev_hook) => panic::set_hook(Box::new(move |panic_info| prev_hook(panic_info))),
+        }
+
+        if self.force_killing.load(Ordering::SeqCst) {
+            error!("Scheduler: force killed coroutines {:?}",
+                   *self.force_killed.lock().unwrap());
+        }
+
+        if let Some(err) = self.processor_panic.lock().unwrap().take() {
+            return Err(err);
+        }
+
+        match result {
+            Some(result) => result,
+            None => Err(Box::new("Main coroutine was dropped before it ran")),
+        }
+    }
 
-        result.unwrap()
+    /// Run the scheduler until all coroutines have finished
+    ///
+    /// Unlike `run()` this doesn't return as soon as the main coroutine `f` finished,
+    /// but only once all spawned coroutines have finished as well and no `SchedulerHandle`
+    /// exists anymore, through which new ones could be spawned. Coroutines which are parked
+    /// forever, e.g. waiting for connections in an accept loop, thus keep it running.
+    /// The return value is the same as the one of `run()`.
+    pub fn run_until_idle<F, T>(&mut self, f: F) -> thread::Result<T>
+        where F: FnOnce() -> T + Send + 'static,
+              T: Send + 'static
+    {
+        self.run_until_idle = true;
+        let ret = self.run(f);
+        self.run_until_idle = false;
+        ret
     }
 
     /// Get the global Scheduler
@@ -387,6 +1658,12 @@ impl Scheduler {
         Self::instance().ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Scheduler missing"))
     }
 
+    #[doc(hidden)]
+    #[inline]
+    pub fn default_spawn_options(&self) -> Options {
+        self.default_spawn_options.clone()
+    }
+
     /// Spawn a new coroutine with default options
     pub fn spawn<F, T>(f: F) -> JoinHandle<T>
         where F: FnOnce() -> T + Send + 'static,
@@ -396,46 +1673,775 @@ impl Scheduler {
         Scheduler::spawn_opts(f, opt)
     }
 
+    /// Spawn a coroutine running a clone of `f` on each of the Processors
+    ///
+    /// Each coroutine is pinned to it's Processor (see `Options::pinned_to()`) and handed to
+    /// it through it's message channel, so `f` runs exactly once on every Processor.
+    /// Processors removed by `remove_processor()` are skipped. The returned `JoinHandle`s are
+    /// ordered by the ID of the Processor. Together with `TcpListener::bind_reuseport()`
+    /// this allows running one accept loop per worker, or to set up per-Processor caches.
+    ///
+    /// Outside of the Scheduler, e.g. on another thread, use
+    /// `SchedulerHandle::spawn_on_each_processor()` instead.
+    pub fn spawn_on_each_processor<F, T>(f: F) -> Vec<JoinHandle<T>>
+        where F: Fn() -> T + Clone + Send + 'static,
+              T: Send + 'static
+    {
+        let scheduler = Processor::current_required().scheduler();
+        scheduler.spawn_on_each_processor_imp(f)
+    }
+
+    // Queues a pinned coroutine on every Processor which wasn't removed
+    fn spawn_on_each_processor_imp<F, T>(&self, f: F) -> Vec<JoinHandle<T>>
+        where F: Fn() -> T + Clone + Send + 'static,
+              T: Send + 'static
+    {
+        let draining = self.is_draining();
+        let mut handles = Vec::new();
+
+        for (id, machine) in self.machines().iter().enumerate() {
+            if machine.processor.is_retired() {
+                continue;
+            }
+
+            let (tx, rx) = join_handle::handle_pair();
+
+            if draining {
+                tx.push(Err(Box::new("Scheduler is shutting down")));
+            } else {
+                let mut opts = self.default_spawn_options.clone();
+                opts.pinned_to(id);
+
+                self.running_coroutine_count.fetch_add(1, Ordering::Relaxed);
+                let coro = Coroutine::spawn_opts(Scheduler::wrap_coroutine(f.clone(), tx), opts);
+                self.ready_pinned(id, coro);
+            }
+
+            handles.push(JoinHandle { result: rx });
+        }
+
+        handles
+    }
+
     /// Spawn a new coroutine with options
     pub fn spawn_opts<F, T>(f: F, opts: Options) -> JoinHandle<T>
         where F: FnOnce() -> T + Send + 'static,
               T: Send + 'static
     {
         let (tx, rx) = join_handle::handle_pair();
-        let wrapper = move || {
-            let ret = panic::catch_unwind(panic::AssertUnwindSafe(f));
+        let scheduler = Processor::current_required().scheduler();
 
-            // No matter whether it is panicked or not, the result will be sent to the channel
-            let _ = tx.push(ret);
-        };
-        let mut processor = Processor::current_required();
-        processor.spawn_opts(wrapper, opts);
+        if scheduler.is_draining() {
+            tx.push(Err(Box::new("Scheduler is shutting down")));
+            return JoinHandle { result: rx };
+        }
+
+        if scheduler.spawn_depth_exceeded() {
+            tx.push(Err(Box::new("Maximum spawn depth exceeded")));
+            return JoinHandle { result: rx };
+        }
+
+        scheduler.running_coroutine_count.fetch_add(1, Ordering::Relaxed);
+        Processor::current_required().spawn_opts_imp(Scheduler::wrap_coroutine(f, tx), opts);
 
         JoinHandle { result: rx }
     }
 
-    /// Suspend the current coroutine or thread
-    pub fn sched() {
-        trace!("Scheduler::sched()");
+    /// Spawn a coroutine for each closure in `iter`, all of them with the same options
+    ///
+    /// The coroutines are queued all at once, which is cheaper than spawning them one by one
+    /// for large fan-outs. They are resumed in the order of `iter` after the current coroutine
+    /// yields. The `JoinHandle`s are returned in the same order.
+    pub fn spawn_batch<I, F, T>(iter: I, opts: Options) -> Vec<JoinHandle<T>>
+        where I: IntoIterator<Item = F>,
+              F: FnOnce() -> T + Send + 'static,
+              T: Send + 'static
+    {
+        let scheduler = Processor::current_required().scheduler();
+        let draining = scheduler.is_draining();
+        let too_deep = scheduler.spawn_depth_exceeded();
+        let mut handles = Vec::new();
+        let mut wrappers = Vec::new();
+
+        for f in iter {
+            let (tx, rx) = join_handle::handle_pair();
+
+            if draining {
+                tx.push(Err(Box::new("Scheduler is shutting down")));
+            } else if too_deep {
+                tx.push(Err(Box::new("Maximum spawn depth exceeded")));
+            } else {
+                wrappers.push(Scheduler::wrap_coroutine(f, tx));
+            }
 
-        match Processor::current() {
-            Some(p) => p.sched(),
-            None => thread::yield_now(),
+            handles.push(JoinHandle { result: rx });
         }
+
+        scheduler.running_coroutine_count.fetch_add(wrappers.len(), Ordering::Relaxed);
+        Processor::current_required().spawn_batch_imp(wrappers, opts);
+
+        handles
     }
 
-    /// Block the current coroutine
-    pub fn park_with<'scope, F>(f: F)
-        where F: FnOnce(&mut Processor, Handle) + 'scope
+    // Wraps the closure of a spawned coroutine, passing it's result on to the JoinHandle
+    fn wrap_coroutine<F, T>(f: F, tx: JoinHandleSender<T>) -> Box<FnBox()>
+        where F: FnOnce() -> T + Send + 'static,
+              T: Send + 'static
     {
-        Processor::current().map(|x| x.park_with(f)).unwrap()
-    }
+        Box::new(move || {
+            // Taken upfront, since coroutines unwound during the shutdown
+            // might be dropped outside of a Processor, e.g. by run() itself
+            let scheduler = Scheduler::instance();
+            let id = Scheduler::current_id();
 
-    /// A coroutine is ready for schedule
+            let ret = panic::catch_unwind(panic::AssertUnwindSafe(f));
+
+            if let Err(ref err) = ret {
+                if let (Some(scheduler), Some(id)) = (scheduler, id) {
+                    scheduler.record_force_kill(id, &**err);
+                }
+
+                if let Some(scheduler) = Scheduler::instance() {
+                    scheduler.report_panic(&**err);
+                }
+            }
+
+            // No matter whether it is panicked or not, the result will be sent to the channel
+            let _ = tx.push(ret);
+
+            if let Some(scheduler) = Scheduler::instance() {
+                scheduler.finish_coroutine();
+            }
+        })
+    }
+
+    /// Spawn a coroutine which is restarted according to `policy` whenever it panics
+    ///
+    /// `make_fn` is called to build a fresh closure for each attempt, which is run in a coroutine
+    /// of it's own. The returned `JoinHandle` belongs to a supervising coroutine, which yields
+    /// the result of the first attempt which didn't panic. Once all restarts are exhausted it
+    /// panics with the last attempt's payload instead, which is thus passed on to the
+    /// `JoinHandle` as well as the panic handler (see `with_panic_handler()`), which also
+    /// receives the panics of all of the attempts. A shutdown of the Scheduler ends the restarts.
+    pub fn spawn_supervised<M, F, T>(policy: RestartPolicy, make_fn: M) -> JoinHandle<T>
+        where M: Fn() -> F + Send + 'static,
+              F: FnOnce() -> T + Send + 'static,
+              T: Send + 'static
+    {
+        Scheduler::spawn(move || supervisor::supervise(policy, make_fn))
+    }
+
+    /// Shutdown the Scheduler after all coroutines have finished
+    ///
+    /// No new coroutines can be spawned after calling this method: their `JoinHandle` will
+    /// immediately return an error. Coroutines which are already running, queued or parked
+    /// (e.g. waiting for I/O) are allowed to run to completion. `run()` returns as soon as the
+    /// last one has finished, even if the main coroutine returned earlier.
+    pub fn shutdown_graceful(&self) {
+        trace!("Scheduler: draining");
+        self.draining.store(true, Ordering::SeqCst);
+
+        if self.running_coroutine_count.load(Ordering::SeqCst) == 0 {
+            self.send_shutdown();
+        }
+    }
+
+    /// Shutdown the Scheduler like `shutdown_graceful()`, but give up on waiting after `timeout`
+    ///
+    /// Once the timeout passed, all remaining coroutines are force unwound as if the main
+    /// coroutine had returned. This includes the ones waiting for a timer, while closures passed
+    /// to `spawn_blocking()` which haven't started running yet are dropped along with their
+    /// coroutines. The IDs of the unwound coroutines which had already started running are
+    /// logged and returned by `force_killed_coroutines()` after `run()` returned.
+    ///
+    /// Coroutines are only unwound when their Processor gets to it: One which never yields
+    /// keeps it's Processor and thus `run()` from ever returning. The same goes for a blocking
+    /// closure which never returns. Repeated calls can only shorten the timeout.
+    pub fn shutdown_with_timeout(&self, timeout: Duration) {
+        {
+            let mut deadline = self.shutdown_deadline.lock().unwrap();
+            let new_deadline = Instant::now() + timeout;
+
+            if deadline.map_or(true, |deadline| new_deadline < deadline) {
+                *deadline = Some(new_deadline);
+            }
+        }
+
+        self.shutdown_graceful();
+
+        // Makes the event loop pick up the deadline
+        if let Some(channel) = self.event_loop_sender.as_ref() {
+            let _ = channel.send(Message::Unfreeze);
+        }
+    }
+
+    /// Returns the IDs of the coroutines force killed by `shutdown_with_timeout()`
+    ///
+    /// The list is reset once the Scheduler is run again.
+    pub fn force_killed_coroutines(&self) -> Vec<u64> {
+        self.force_killed.lock().unwrap().clone()
+    }
+
+    /// Returns the number of coroutines which were dropped during the last shutdown
+    ///
+    /// These are the coroutines which were still queued or sent between the Processors once
+    /// the main coroutine finished, including those readied while unwinding the others.
+    /// Coroutines which are waiting for an event aren't queued and are dropped along with
+    /// whatever they are waiting on instead. The count is reset once the Scheduler is run again.
+    pub fn dropped_coroutine_count(&self) -> usize {
+        self.dropped_coroutine_count.load(Ordering::SeqCst)
+    }
+
+    /// Returns true if `shutdown_graceful()` was called
+    #[inline]
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Spawns another Processor while the Scheduler is running, e.g. to absorb a load spike
+    ///
+    /// The new Processor steals from the others and they steal from it as soon as it's added.
+    /// Returns it's ID or None if the Scheduler isn't running or `with_max_workers()` Processors
+    /// were spawned already. The IDs of removed Processors aren't reused, so they count towards
+    /// that limit as well.
+    pub fn add_processor(&self) -> Option<usize> {
+        let closed = self.machines_closed.lock().unwrap();
+
+        // See the NOTE on `machines`
+        let machines = unsafe { &mut *self.machines.get() };
+        let id = self.machine_count.load(Ordering::Relaxed);
+
+        if *closed || id >= machines.capacity() {
+            return None;
+        }
+
+        let barrier = Arc::new(Barrier::new(2));
+        let sched = self as *const Scheduler as *mut Scheduler;
+        let mem = self.maximum_stack_memory_limit;
+        let machine = Processor::spawn(sched, id, barrier.clone(), mem, None);
+
+        // The capacity is reserved, so this never moves the Machines the Processors are using
+        unsafe { ptr::write(machines.as_mut_ptr().offset(id as isize), machine) };
+        self.machine_count.store(id + 1, Ordering::Release);
+
+        // The new Processor must not schedule before it can find itself in `machines`
+        barrier.wait();
+
+        drop(closed);
+        Some(id)
+    }
+
+    /// Removes the Processor `id` while the Scheduler is running
+    ///
+    /// The Processor hands the coroutines queued on it over to the other Processors and stops
+    /// scheduling. Coroutines pinned to it are unpinned. Since other threads might still be
+    /// about to send coroutines to it, it's thread keeps on forwarding those until the Scheduler
+    /// shuts down. Returns false if there is no such Processor, if it was removed already or if
+    /// it's the last one.
+    pub fn remove_processor(&self, id: usize) -> bool {
+        let closed = self.machines_closed.lock().unwrap();
+        let machines = self.machines();
+
+        if *closed || id >= machines.len() || machines[id].processor.is_retired() {
+            return false;
+        }
+
+        if machines.iter().filter(|m| !m.processor.is_retired()).count() == 1 {
+            return false;
+        }
+
+        machines[id].processor.set_retired();
+        self.send_pinned(id, ProcMessage::Retire);
+
+        drop(closed);
+        true
+    }
+
+    /// Take a snapshot of the runtime metrics.
+    ///
+    /// The values are read from atomic counters without stopping any Processor
+    /// and are thus not necessarily consistent with each other.
+    pub fn metrics(&self) -> Metrics {
+        let machines = self.machines();
+
+        let processors = machines.iter()
+                                 .map(|m| {
+                                     ProcessorMetrics {
+                                         id: m.processor.id(),
+                                         queue_len: m.processor.queue_len(),
+                                         steal_count: m.processor.steal_count(),
+                                         steal_rate: m.processor.steal_rate() as f64 /
+                                                     STEAL_RATE_ONE as f64,
+                                     }
+                                 })
+                                 .collect();
+
+        Metrics {
+            coroutine_count: self.running_coroutine_count.load(Ordering::Relaxed),
+            parked_processor_count: self.idle_processor_count.load(Ordering::Relaxed),
+            global_queue_len: self.global_queue_size(),
+            processors: processors,
+        }
+    }
+
+    /// Returns how saturated the runtime is, from 0.0 (idle) to 1.0 (overloaded)
+    ///
+    /// The load is the average of the fraction of Processors which aren't parked and the
+    /// number of queued coroutines relative to the high-water mark (see `with_load_high_water()`),
+    /// the latter capped at 1.0. Thus all Processors being busy, but keeping up, results in 0.5.
+    /// Servers can use this to reject new connections before the latency degrades.
+    ///
+    /// To avoid jitter the value is an exponential moving average with a time constant of
+    /// `with_load_window()`: A sudden change shows up to about 63% after one window.
+    /// The average is only updated by calls to this method, which only reads atomic counters.
+    pub fn load(&self) -> f32 {
+        let machines = self.machines();
+
+        if machines.is_empty() {
+            return 0.0;
+        }
+
+        let busy = machines.iter().filter(|m| !m.processor.is_parked()).count();
+        let depth = machines.iter().fold(self.global_queue_size(), |n, m| n + m.processor.load());
+
+        let busy = busy as f32 / machines.len() as f32;
+        let depth = depth as f32 / (self.load_high_water * machines.len()) as f32;
+        let current = (busy + depth.min(1.0)) / 2.0;
+
+        let now = duration_to_us(self.load_epoch.elapsed());
+        let last = self.load_updated.swap(now, Ordering::Relaxed);
+        let window = duration_to_us(self.load_window);
+
+        // The first sample and a disabled smoothing replace the average altogether
+        let alpha = if last == 0 || window == 0 {
+            1.0
+        } else {
+            let dt = now.wrapping_sub(last) as f32;
+            1.0 - (-dt / window as f32).exp()
+        };
+
+        let prev = self.load_smoothed.load(Ordering::Relaxed) as f32 / LOAD_ONE as f32;
+        let load = prev + alpha * (current - prev);
+        self.load_smoothed.store((load * LOAD_ONE as f32) as usize, Ordering::Relaxed);
+
+        load
+    }
+
+    // Called by every coroutine spawned by the Scheduler which panicked or was unwound
+    fn record_force_kill(&self, id: u64, err: &(Any + Send)) {
+        if err.is::<ForceUnwind>() && self.force_killing.load(Ordering::SeqCst) {
+            self.force_killed.lock().unwrap().push(id);
+        }
+    }
+
+    // Called by every coroutine spawned by the Scheduler which panicked
+    fn report_panic(&self, err: &(Any + Send)) {
+        // Coroutines being dropped are unwound using ForceUnwind, which is no actual panic
+        if err.is::<ForceUnwind>() {
+            return;
+        }
+
+        let handler = match self.panic_handler {
+            Some(ref handler) => handler,
+            None => return,
+        };
+
+        let (id, name, processor_id) = {
+            let mut p = Processor::current_required();
+            let processor_id = p.id();
+
+            match p.current() {
+                Some(coro) => (coro.id(), coro.name().map(|name| name.to_owned()), processor_id),
+                None => return,
+            }
+        };
+
+        let context = PanicContext {
+            id: id,
+            name: name.as_ref().map(String::as_str),
+            processor_id: processor_id,
+        };
+
+        handler(&context, err);
+    }
+
+    // Called by every coroutine spawned by the Scheduler right before it finishes
+    fn finish_coroutine(&self) {
+        let prev = self.running_coroutine_count.fetch_sub(1, Ordering::SeqCst);
+
+        if prev != 1 {
+            return;
+        }
+
+        if self.is_draining() {
+            trace!("Scheduler: drained => sending Shutdown");
+            self.send_shutdown();
+        } else {
+            self.shutdown_if_idle();
+        }
+    }
+
+    // Called whenever the last coroutine finished or the last SchedulerHandle was dropped
+    //
+    // Both counters are decremented before the other one is checked, so
+    // at least one of the two callers racing each other will see both at zero.
+    fn shutdown_if_idle(&self) {
+        if self.run_until_idle && self.running_coroutine_count.load(Ordering::SeqCst) == 0 &&
+           self.handle_shared.count.load(Ordering::SeqCst) == 0 {
+            trace!("Scheduler: idle => sending Shutdown");
+            self.send_shutdown();
+        }
+    }
+
+    fn send_shutdown(&self) {
+        if let Some(channel) = self.event_loop_sender.as_ref() {
+            let mut msg = Message::Shutdown;
+
+            while let Err(NotifyError::Full(m)) = channel.send(msg) {
+                msg = m;
+            }
+        }
+    }
+
+    /// Run `f`, which is known to block the current thread, e.g. by calling a blocking std API
+    ///
+    /// This is only an annotation: A coroutine can neither be preempted nor moved to another
+    /// thread while it's blocked, so `f` still blocks the Processor and the coroutines queued
+    /// on it until it returns. What changes is that the watchdog (see `with_watchdog()` and
+    /// `with_debug_watchdog()`) doesn't report the coroutine while it's inside of `f`.
+    /// This tells the accidental blocking caught that way apart from the intended one.
+    /// Use `spawn_blocking()` to move the blocking work off of the Processors instead.
+    pub fn allow_blocking<F, T>(f: F) -> T
+        where F: FnOnce() -> T
+    {
+        // Unmarks the coroutine even if `f` panics
+        struct Guard(*const Coroutine);
+
+        impl Drop for Guard {
+            fn drop(&mut self) {
+                unsafe { (*self.0).leave_blocking() };
+            }
+        }
+
+        let _guard = {
+            let mut p = match Processor::current() {
+                Some(p) => p,
+                None => return f(),
+            };
+
+            match p.current() {
+                Some(coro) => {
+                    coro.enter_blocking();
+                    Guard(&**coro)
+                }
+                None => return f(),
+            }
+        };
+
+        f()
+    }
+
+    /// Suspend the current coroutine or thread
+    pub fn sched() {
+        trace!("Scheduler::sched()");
+
+        match Processor::current() {
+            Some(p) => p.sched(),
+            None => thread::yield_now(),
+        }
+    }
+
+    /// Count an operation against the yield budget of the current coroutine
+    ///
+    /// Suspends the coroutine like `sched()` once it's budget is used up, see
+    /// `with_yield_budget()`. Long running loops can call this to give way to the other
+    /// coroutines now and then. Outside of a coroutine the current thread yields.
+    pub fn consume_budget() {
+        let mut p = match Processor::current() {
+            Some(p) => p,
+            None => return thread::yield_now(),
+        };
+
+        let exhausted = p.current().map_or(false, |coro| coro.consume_budget());
+
+        if exhausted {
+            trace!("Scheduler::consume_budget(): budget used up");
+            p.sched();
+        }
+    }
+
+    /// Suspend the current coroutine until all currently ready coroutines had their turn
+    ///
+    /// `sched()` puts the coroutine back into the queue of it's Processor, which is
+    /// only refilled from the global queue once it runs empty or every couple of iterations.
+    /// A couple of coroutines calling `sched()` in a loop can thus delay the coroutines in
+    /// the global queue, like the ones woken up by I/O events. This method puts the coroutine
+    /// at the back of the global queue instead. Pinned coroutines are put at the back of the
+    /// pinned queue of their Processor. Outside of a coroutine the current thread yields.
+    pub fn yield_fair() {
+        trace!("Scheduler::yield_fair()");
+
+        match Processor::current() {
+            Some(p) => {
+                p.park_with(|p, coro| p.scheduler().push_global_queue_iter(Some(coro)));
+            }
+            None => thread::yield_now(),
+        }
+    }
+
+    /// Returns the ID of the current coroutine
+    ///
+    /// IDs are unique within the process and never change, even if the coroutine is
+    /// stolen by another Processor. They are unrelated to the IDs of the Processors.
+    /// Returns None outside of a coroutine.
+    pub fn current_id() -> Option<u64> {
+        let mut p = match Processor::current() {
+            Some(p) => p,
+            None => return None,
+        };
+
+        let id = p.current().map(|coro| coro.id());
+        id
+    }
+
+    /// Returns the name of the current coroutine, as set by `Options::name()`
+    pub fn current_name() -> Option<String> {
+        let mut p = match Processor::current() {
+            Some(p) => p,
+            None => return None,
+        };
+
+        let name = p.current().and_then(|coro| coro.name().map(|name| name.to_owned()));
+        name
+    }
+
+    /// Returns the number of bytes of the stack used by the current coroutine
+    ///
+    /// For coroutines spawned with `Options::track_stack_usage()` this is the peak usage since
+    /// the coroutine was spawned, which helps finding the right `Options::stack_size()`.
+    /// Otherwise it's only the current usage. Returns None outside of a coroutine.
+    pub fn current_stack_usage() -> Option<usize> {
+        let mut p = match Processor::current() {
+            Some(p) => p,
+            None => return None,
+        };
+
+        let usage = p.current().map(|coro| coro.stack_usage());
+        usage
+    }
+
+    /// Returns how deeply the current coroutine is nested in it's spawn tree
+    ///
+    /// Coroutines which weren't spawned by another coroutine, like the main one, have a depth
+    /// of 0 and each spawn adds 1 to the depth of the spawning coroutine.
+    /// Returns None outside of a coroutine.
+    pub fn current_depth() -> Option<usize> {
+        let mut p = match Processor::current() {
+            Some(p) => p,
+            None => return None,
+        };
+
+        if !p.is_running_coroutine() {
+            return None;
+        }
+
+        let depth = p.current().map(|coro| coro.depth());
+        depth
+    }
+
+    /// Returns true if the `CancellationToken` of the current coroutine was cancelled
+    ///
+    /// Always returns false outside of a coroutine or if the coroutine has no token.
+    pub fn is_cancelled() -> bool {
+        let mut p = match Processor::current() {
+            Some(p) => p,
+            None => return false,
+        };
+
+        let cancelled = p.current()
+                         .and_then(|coro| coro.cancel_token())
+                         .map_or(false, |token| token.is_cancelled());
+        cancelled
+    }
+
+    /// Block the current coroutine
+    pub fn park_with<'scope, F>(f: F)
+        where F: FnOnce(&mut Processor, Handle) + 'scope
+    {
+        Processor::current().map(|x| x.park_with(f)).unwrap()
+    }
+
+    /// Create a scope for spawning coroutines which may borrow from the current stack
+    ///
+    /// See `scope::scope()` for details.
+    #[inline]
+    pub fn scope<'a, F, R>(f: F) -> R
+        where F: FnOnce(&Scope<'a>) -> R
+    {
+        scope::scope(f)
+    }
+
+    /// Run the blocking closure `f` on a thread pool and park the current coroutine until it's done
+    ///
+    /// Use this for CPU heavy work or FFI calls which would otherwise stall the
+    /// Processor with all of it's other coroutines. A panic in `f` is propagated to the caller.
+    /// Outside of a coroutine `f` is simply called on the current thread.
+    pub fn spawn_blocking<F, T>(f: F) -> T
+        where F: FnOnce() -> T + Send + 'static,
+              T: Send + 'static
+    {
+        let p = match Processor::current() {
+            Some(p) => p,
+            None => return f(),
+        };
+
+        let mut result: Option<thread::Result<T>> = None;
+
+        {
+            // Raw pointers aren't Send. Both outlive the job, since the coroutine
+            // waits for it's completion and the Scheduler joins the blocking threads.
+            let result_ptr = &mut result as *mut Option<thread::Result<T>> as usize;
+            let scheduler_ptr = p.scheduler() as *const Scheduler as usize;
+
+            p.park_with(move |p, coro| {
+                let job = move || {
+                    let ret = panic::catch_unwind(panic::AssertUnwindSafe(f));
+
+                    unsafe {
+                        *(result_ptr as *mut Option<thread::Result<T>>) = Some(ret);
+                        (&*(scheduler_ptr as *const Scheduler)).dispatch_external(coro);
+                    }
+                };
+
+                p.scheduler().blocking_pool.as_ref().unwrap().execute(Box::new(job));
+            });
+        }
+
+        match result.expect("blocking closure didn't finish") {
+            Ok(ret) => ret,
+            Err(err) => panic::resume_unwind(err),
+        }
+    }
+
+    /// Run `f` on the current coroutine and give up on it once `dur` has elapsed.
+    ///
+    /// While `f` runs, every blocking I/O operation and sleep of the current coroutine
+    /// is woken up by the timer at the latest when the deadline is reached and fails
+    /// with `io::ErrorKind::TimedOut`. So does `recv()` on the `mpsc` and `mpmc` channels,
+    /// which fails with `RecvError`. Nested calls can only shorten the deadline.
+    ///
+    /// Returns `Err(TimeoutError)` if any of these operations timed out because the deadline
+    /// passed, even if `f` handled the error. Finishing late without ever waiting on the
+    /// deadline, e.g. because of busy work, still returns `Ok`.
+    pub fn with_timeout<F, T>(dur: Duration, f: F) -> Result<T, TimeoutError>
+        where F: FnOnce() -> T
+    {
+        Scheduler::with_deadline(Instant::now() + dur, f)
+    }
+
+    /// Like `with_timeout()`, but gives up on `f` once `deadline` is reached.
+    ///
+    /// This allows to pass a single deadline through nested calls. If it already passed,
+    /// `f` is still run, but every blocking operation in it fails immediately.
+    pub fn with_deadline<F, T>(mut deadline: Instant, f: F) -> Result<T, TimeoutError>
+        where F: FnOnce() -> T
+    {
+        if let Some(prev) = Scheduler::current_deadline() {
+            if prev < deadline {
+                deadline = prev;
+            }
+        }
+
+        let (ret, expired) = {
+            let guard = DeadlineGuard::new(deadline);
+            let ret = f();
+            (ret, guard.expired())
+        };
+
+        if expired {
+            Err(TimeoutError)
+        } else {
+            Ok(ret)
+        }
+    }
+
+    // Marks the current coroutine if a wait which just timed out did so because of its deadline
+    #[doc(hidden)]
+    pub fn note_timeout() {
+        let mut p = match Processor::current() {
+            Some(p) => p,
+            None => return,
+        };
+
+        if let Some(coro) = p.current() {
+            match coro.deadline() {
+                Some(deadline) if Instant::now() >= deadline => coro.set_deadline_expired(true),
+                _ => {}
+            }
+        }
+    }
+
+    /// Returns the deadline of the innermost `with_deadline()` or `with_timeout()` call
+    /// the current coroutine is in
+    ///
+    /// Library code can use it to shorten it's own waits or to give up early on work which
+    /// couldn't possibly finish in time. The deadline is cleared once that call returns.
+    /// Returns None if there is none or outside of a coroutine.
+    pub fn current_deadline() -> Option<Instant> {
+        let mut p = match Processor::current() {
+            Some(p) => p,
+            None => return None,
+        };
+
+        let deadline = p.current().and_then(|coro| coro.deadline());
+        deadline
+    }
+
+    /// Run `f` with the arena of the current Processor, see `coio::arena`
+    ///
+    /// Everything allocated from the arena is valid until `f` returns, even if the coroutine
+    /// parks and is resumed on another Processor in between. The arena is reset once the local
+    /// queue of it's Processor ran empty and no coroutine is inside of `with_arena()`.
+    /// Outside of a Processor `f` gets a fresh arena, which is dropped right after it returns.
+    pub fn with_arena<F, T>(f: F) -> T
+        where F: FnOnce(&Arena) -> T
+    {
+        let arena = match Processor::current() {
+            // The Processor, and thus it's arena, outlives all of it's coroutines
+            Some(p) => unsafe { &*(p.arena() as *const Arena) },
+            None => return f(&Arena::new()),
+        };
+
+        let _borrow = arena.borrow();
+        f(arena)
+    }
+
+    /// Drops the coroutines queued on the current Processor, if it's shutting down
+    ///
+    /// Returns the number of coroutines dropped, see `ProcessorHandle::drop_queued_coroutines()`.
+    #[doc(hidden)]
+    pub fn drop_queued_coroutines() -> usize {
+        let p = match Processor::current() {
+            Some(p) => p,
+            None => return 0,
+        };
+
+        let scheduler = p.scheduler();
+        let dropped = p.drop_queued_coroutines();
+        scheduler.dropped_coroutine_count.fetch_add(dropped, Ordering::SeqCst);
+        dropped
+    }
+
+    /// A coroutine is ready for schedule
     #[doc(hidden)]
-    pub fn ready(mut coro: Handle) {
+    pub fn ready(coro: Handle) {
         trace!("{:?}: readying", coro);
 
+        let mut coro = match coro.wake() {
+            Some(coro) => coro,
+            None => return,
+        };
+
         if let Some(mut current) = Processor::current() {
             trace!("{:?}: pushing into local queue", coro);
             current.ready(coro);
@@ -447,8 +2453,19 @@ impl Scheduler {
         coro.resume(0);
     }
 
-    /// Block the current coroutine and wait for I/O event
-    #[doc(hidden)]
+    /// Register a custom I/O source with the event loop
+    ///
+    /// This allows to integrate `Evented` sources other than the ones in `coio::net`,
+    /// e.g. an eventfd, timerfd or inotify instance. `interest` specifies which events are
+    /// of interest. The returned `ReadyStates` are used to park the current coroutine until
+    /// the source becomes ready, see it's docs for the edge triggered semantics.
+    /// The source should be non-blocking, since operations on it are run on the Processor.
+    ///
+    /// The event loop only holds on to the `Token` and never the source itself. The source must
+    /// however stay open until it's deregistered with `deregister()` using the returned `Token`,
+    /// which must happen before the Scheduler shuts down. Dropping the source without doing so
+    /// leaks the `Token` and might cause spurious wakeups once the file descriptor is reused.
+    /// `net::GenericEvented` does all of this automatically for most Read/Write sources.
     pub fn register<E>(&self, fd: &E, interest: EventSet) -> io::Result<(Token, ReadyStates)>
         where E: Evented + Debug
     {
@@ -489,7 +2506,10 @@ impl Scheduler {
         ret
     }
 
-    #[doc(hidden)]
+    /// Deregister a source registered with `register()`
+    ///
+    /// Coroutines waiting for readiness of the source are woken up by this,
+    /// see `ReadyStates::wait_timeout_opt()`.
     pub fn deregister<E>(&self, fd: &E, token: Token) -> io::Result<()>
         where E: Evented + Debug
     {
@@ -523,6 +2543,11 @@ impl Scheduler {
     /// Block the current coroutine until the specific time
     #[doc(hidden)]
     pub fn sleep_ms(&self, delay: u64) {
+        let delay = match cap_to_deadline(Some(Duration::from_millis(delay))) {
+            Some(dur) => ::duration_to_ms(dur),
+            None => delay,
+        };
+
         trace!("Scheduler: requesting sleep for {}ms", delay);
 
         Scheduler::park_with(|_, coro| {
@@ -531,6 +2556,8 @@ impl Scheduler {
             let channel = self.event_loop_sender.as_ref().unwrap();
             let _ = channel.send(Message::Unfreeze);
         });
+
+        Scheduler::note_timeout();
     }
 
     /// Block the current coroutine until the specific time
@@ -539,6 +2566,20 @@ impl Scheduler {
         self.sleep_ms(::duration_to_ms(delay))
     }
 
+    /// Block the current coroutine until `deadline`
+    ///
+    /// Returns immediately if the deadline already passed.
+    #[doc(hidden)]
+    pub fn sleep_until(&self, deadline: Instant) {
+        let now = Instant::now();
+
+        if deadline > now {
+            // Round up, since waking up early would require another sleep
+            let delay = deadline.duration_since(now) + Duration::new(0, 999_999);
+            self.sleep(delay)
+        }
+    }
+
     /// IO timeouts
     #[doc(hidden)]
     pub fn timeout(&self, delay: u64, waiter: &mut Waiter) -> Timeout {
@@ -555,6 +2596,22 @@ impl Scheduler {
         ret
     }
 
+    /// Timeouts of `select::timeout()`
+    #[doc(hidden)]
+    pub fn select_timeout(&self, delay: u64, select: Select) -> Timeout {
+        trace!("Scheduler: requesting select timeout for {}ms", delay);
+
+        let ret = {
+            let mut timer = self.timer.lock();
+            timer.timeout_ms(TimerWaitType::Select(select), delay)
+        };
+
+        let channel = self.event_loop_sender.as_ref().unwrap();
+        let _ = channel.send(Message::Unfreeze);
+
+        ret
+    }
+
     /// IO cancel
     pub fn cancel_timeout(&self, timeout: Timeout) -> bool {
         trace!("Scheduler: requesting to cancel timeout");
@@ -565,7 +2622,20 @@ impl Scheduler {
 
     #[doc(hidden)]
     pub fn get_machines(&'static self) -> &mut [Machine] {
-        unsafe { &mut *self.machines.get() }
+        // See the NOTE on `machines`
+        unsafe {
+            let machines = &mut *self.machines.get();
+            slice::from_raw_parts_mut(machines.as_mut_ptr(),
+                                      self.machine_count.load(Ordering::Acquire))
+        }
+    }
+
+    fn machines(&self) -> &[Machine] {
+        // See the NOTE on `machines`
+        unsafe {
+            let machines = &*self.machines.get();
+            slice::from_raw_parts(machines.as_ptr(), self.machine_count.load(Ordering::Acquire))
+        }
     }
 
     #[doc(hidden)]
@@ -573,6 +2643,24 @@ impl Scheduler {
         self.global_queue.lock().unwrap()
     }
 
+    /// Move a coroutine which was queued with a low priority and got a higher one since then
+    ///
+    /// Otherwise it would stay in the low priority queue of it's Processor and might only be
+    /// resumed once all coroutines with a higher priority are done. It's put into the global
+    /// queue instead, from where it's queued according to it's new priority once resumed.
+    /// Does nothing if the coroutine isn't in any of the low priority queues.
+    #[doc(hidden)]
+    pub fn requeue_boosted(&self, coro: *const Coroutine) {
+        let machines = self.machines();
+
+        for m in machines.iter() {
+            if let Some(hdl) = m.processor.take_low_priority(coro) {
+                trace!("{:?}: moving boosted coroutine to global queue", hdl);
+                return self.push_global_queue(hdl);
+            }
+        }
+    }
+
     #[doc(hidden)]
     pub fn push_global_queue(&self, hdl: Handle) {
         let size = {
@@ -590,30 +2678,129 @@ impl Scheduler {
     pub fn push_global_queue_iter<T>(&self, iter: T)
         where T: IntoIterator<Item = Handle>
     {
+        let mut pinned = Vec::new();
+
         let size = {
             let mut queue = self.get_global_queue();
-            queue.extend(iter);
+
+            for hdl in iter {
+                match hdl.pinned_to() {
+                    Some(id) => pinned.push((id, hdl)),
+                    None => queue.push_back(hdl),
+                }
+            }
+
             let size = queue.len();
             self.set_global_queue_size(size);
             size
         };
 
+        // Pinned coroutines are dispatched outside of the global queue lock,
+        // since a parked Processor might acquire it while holding the lock of it's Parker.
+        for (id, hdl) in pinned {
+            self.ready_pinned(id, hdl);
+        }
+
         self.unpark_processors_with_queue_size(size);
     }
 
     #[doc(hidden)]
     pub fn append_io_handler_to_global_queue(&mut self) {
         if !self.io_handler_queue.is_empty() {
-            let size = {
-                let mut queue = self.global_queue.lock().unwrap();
-                queue.append(&mut self.io_handler_queue);
-                let size = queue.len();
-                self.set_global_queue_size(size);
-                size
-            };
-
-            self.unpark_processors_with_queue_size(size);
+            let handles = mem::replace(&mut self.io_handler_queue, HandleList::new());
+            let mut global = HandleList::new();
+
+            // See Options::wake_affinity()
+            for hdl in handles.into_iter().filter_map(Handle::wake) {
+                match hdl.wake_processor() {
+                    Some(id) => self.ready_pinned(id, hdl),
+                    None => global.push_back(hdl),
+                }
+            }
+
+            self.push_global_queue_iter(global);
+        }
+    }
+
+    /// Hand a coroutine readied outside of the Processors over to one of them
+    ///
+    /// See `ExternalDispatch`. Once the Processors are shutting down it's put into the global
+    /// queue, which is drained during the shutdown.
+    #[doc(hidden)]
+    pub fn dispatch_external(&self, hdl: Handle) {
+        let hdl = match hdl.wake() {
+            Some(hdl) => hdl,
+            None => return,
+        };
+
+        // The lock prevents the Processors from shutting down during the dispatch
+        let running = self.handle_shared.scheduler.read().unwrap();
+
+        if *running == 0 {
+            self.push_global_queue(hdl);
+        } else {
+            self.dispatch(hdl);
+        }
+    }
+
+    // Must only be called while the Processors are running
+    fn dispatch(&self, hdl: Handle) {
+        if let Some(id) = hdl.pinned_to() {
+            return self.ready_pinned(id, hdl);
+        }
+
+        let machines = self.machines();
+        let count = machines.len();
+
+        let id = match self.external_dispatch {
+            ExternalDispatch::Global => return self.push_global_queue_iter(Some(hdl)),
+            ExternalDispatch::RoundRobin => {
+                let start = self.next_dispatch.fetch_add(1, Ordering::Relaxed);
+
+                // The first parked Processor or the one at `start`, skipping removed ones
+                (0..count)
+                    .map(|i| (start + i) % count)
+                    .min_by_key(|&id| {
+                        let p = &machines[id].processor;
+                        (p.is_retired(), !p.is_parked())
+                    })
+                    .unwrap()
+            }
+            ExternalDispatch::LeastLoaded => {
+                let key = |id: &usize| {
+                    let p = &machines[*id].processor;
+                    (p.is_retired(), !p.is_parked(), p.load())
+                };
+
+                (0..count).min_by_key(key).unwrap()
+            }
+        };
+
+        self.ready_pinned(id, hdl);
+    }
+
+    /// Hand a coroutine over to the Processor it is pinned to or prefers
+    #[doc(hidden)]
+    pub fn ready_pinned(&self, processor_id: usize, hdl: Handle) {
+        trace!("{:?}: sending to Processor#{}", hdl, processor_id);
+        self.send_pinned(processor_id, ProcMessage::Ready(hdl));
+    }
+
+    /// Hand multiple coroutines over to the Processor they are pinned to
+    #[doc(hidden)]
+    pub fn ready_pinned_batch(&self, processor_id: usize, hdls: Vec<Handle>) {
+        trace!("sending {} coroutines to Processor#{}", hdls.len(), processor_id);
+        self.send_pinned(processor_id, ProcMessage::ReadyBatch(hdls));
+    }
+
+    fn send_pinned(&self, processor_id: usize, msg: ProcMessage) {
+        {
+            let machines = self.machines();
+            let _ = machines[processor_id].processor_handle.send(msg);
         }
+
+        // The Processor might be parked and only unpark_all() is guaranteed to wake it up
+        self.parker.unpark_all();
     }
 
     #[doc(hidden)]
@@ -640,17 +2827,15 @@ impl Scheduler {
         self.spinning_processor_count.fetch_sub(1, Ordering::Relaxed);
     }
 
+    /// Parks the calling Processor until it's woken up or the optional `timeout` has passed
     #[doc(hidden)]
-    pub fn park_processor<F: FnOnce() -> bool>(&self, before_wait: F) {
+    pub fn park_processor<F>(&self, timeout: Option<Duration>, before_wait: F)
+        where F: FnOnce() -> bool
+    {
         self.idle_processor_count.fetch_add(1, Ordering::Relaxed);
 
-        {
-            let idle_processor_mutex = self.idle_processor_mutex.lock().unwrap();
-
-            if !*idle_processor_mutex && before_wait() {
-                let _ = self.idle_processor_condvar.wait(idle_processor_mutex);
-            }
-        }
+        let mut before_wait = Some(before_wait);
+        self.parker.park(timeout, &mut || before_wait.take().map_or(false, |f| f()));
 
         self.idle_processor_count.fetch_sub(1, Ordering::Relaxed);
     }
@@ -672,10 +2857,7 @@ impl Scheduler {
                 max
             };
 
-            let _guard = self.idle_processor_mutex.lock().unwrap();
-            for _ in 0..cnt {
-                self.idle_processor_condvar.notify_one();
-            }
+            self.parker.unpark(cnt);
         }
     }
 }
@@ -689,8 +2871,11 @@ impl Handler for Scheduler {
     fn ready(&mut self, _event_loop: &mut EventLoop<Self>, token: Token, events: EventSet) {
         trace!("Handler: got {:?} for {:?}", events, token);
 
-        let ready_states = self.slab.get(token.as_usize()).expect("Token must be registered");
-        ready_states.notify(events, &mut self.io_handler_queue)
+        // The source might have been deregistered after the events were polled
+        match self.slab.get(token.as_usize()) {
+            Some(ready_states) => ready_states.notify(events, &mut self.io_handler_queue),
+            None => trace!("Handler: dropping {:?} for deregistered {:?}", events, token),
+        }
     }
 
     fn timeout(&mut self, _event_loop: &mut EventLoop<Self>, token: Token) {
@@ -728,7 +2913,11 @@ impl Handler for Scheduler {
             Message::Deregister(msg) => {
                 trace!("Handler: deregistering for {:?}", msg.coro);
 
-                let _ = self.slab.remove(unsafe { mem::transmute(msg.token) });
+                let token = unsafe { mem::transmute(msg.token) };
+
+                if let Some(ready_states) = self.slab.remove(token) {
+                    ready_states.close(&mut self.io_handler_queue);
+                }
 
                 (msg.cb)(event_loop);
 
@@ -745,6 +2934,17 @@ impl Handler for Scheduler {
 
 #[cfg(test)]
 mod test {
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::panic;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use coroutine::Handle;
+    use net::TcpListener;
+    use options::Options;
+    use runtime::processor::Processor;
+    use sync::{mpmc, mpsc, WaitGroup};
     use super::*;
 
     #[test]
@@ -757,4 +2957,1307 @@ mod test {
             })
             .unwrap();
     }
+
+    #[test]
+    fn test_join_panicked() {
+        Scheduler::new()
+            .run(|| {
+                let guard = Scheduler::spawn(|| {
+                    panic!("Panicked inside");
+                });
+
+                let err = guard.join().unwrap_err();
+                assert_eq!(err.downcast_ref::<&str>(), Some(&"Panicked inside"));
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_join_across_processors() {
+        Scheduler::new()
+            .with_workers(4)
+            .run(|| {
+                let handles: Vec<_> = (0..100)
+                                          .map(|i| {
+                                              Scheduler::spawn(move || {
+                                                  for _ in 0..10 {
+                                                      Scheduler::sched();
+                                                  }
+
+                                                  i
+                                              })
+                                          })
+                                          .collect();
+
+                let sum = handles.into_iter().fold(0, |acc, h| acc + h.join().unwrap());
+                assert_eq!(sum, 4950);
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_shutdown_graceful() {
+        let counter = Arc::new(AtomicUsize::new(0));
+
+        {
+            let counter = counter.clone();
+
+            Scheduler::new()
+                .with_workers(2)
+                .run(move || {
+                    for _ in 0..10 {
+                        let counter = counter.clone();
+
+                        Scheduler::spawn(move || {
+                            ::sleep_ms(100);
+                            counter.fetch_add(1, Ordering::SeqCst);
+                        });
+                    }
+
+                    Scheduler::instance().unwrap().shutdown_graceful();
+
+                    // Spawning is rejected while draining
+                    assert!(Scheduler::spawn(|| {}).join().is_err());
+                })
+                .unwrap();
+        }
+
+        assert_eq!(counter.load(Ordering::SeqCst), 10);
+    }
+
+    #[test]
+    fn test_shutdown_graceful_rerun() {
+        let mut scheduler = Scheduler::new();
+
+        scheduler.run(|| Scheduler::instance().unwrap().shutdown_graceful()).unwrap();
+
+        // The next run() accepts new coroutines again
+        let ret = scheduler.run(|| Scheduler::spawn(|| 1).join().unwrap()).unwrap();
+        assert_eq!(ret, 1);
+    }
+
+    #[test]
+    fn test_yield_budget() {
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        {
+            let order = order.clone();
+
+            Scheduler::single_threaded()
+                .with_yield_budget(3)
+                .run(move || {
+                    let h = {
+                        let order = order.clone();
+                        Scheduler::spawn(move || order.lock().unwrap().push(100))
+                    };
+
+                    for i in 0..4 {
+                        order.lock().unwrap().push(i);
+                        Scheduler::consume_budget();
+                    }
+
+                    h.join().unwrap();
+                })
+                .unwrap();
+        }
+
+        // The spawned coroutine only gets to run once the budget of 3 was used up
+        assert_eq!(*order.lock().unwrap(), vec![0, 1, 2, 100, 3]);
+    }
+
+    #[test]
+    fn test_shutdown_with_timeout() {
+        let (tx, rx) = ::std::sync::mpsc::channel();
+        let start = Instant::now();
+
+        let mut scheduler = Scheduler::new().with_workers(2);
+        scheduler.run(move || {
+                     let finished = Scheduler::spawn(|| ::sleep_ms(10));
+
+                     Scheduler::spawn(move || {
+                         tx.send(Scheduler::current_id().unwrap()).unwrap();
+                         ::sleep_ms(60_000);
+                     });
+
+                     let timeout = Duration::from_millis(100);
+                     Scheduler::instance().unwrap().shutdown_with_timeout(timeout);
+                     finished.join().unwrap();
+                 })
+                 .unwrap();
+
+        assert!(start.elapsed() < Duration::from_secs(30));
+
+        let stuck = rx.recv().unwrap();
+        assert_eq!(scheduler.force_killed_coroutines(), vec![stuck]);
+    }
+
+    // Every coroutine must be dropped during the shutdown, even though they are busy being
+    // stolen and sent between Processors, and even though unwinding them readies others.
+    #[test]
+    fn test_shutdown_accounts_all_coroutines() {
+        struct Guard(Arc<AtomicUsize>);
+
+        impl Drop for Guard {
+            fn drop(&mut self) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        fn spawn_tree(spawned: Arc<AtomicUsize>, dropped: Arc<AtomicUsize>, depth: usize) {
+            spawned.fetch_add(1, Ordering::SeqCst);
+            let guard = Guard(dropped.clone());
+
+            let f = move || {
+                let _guard = guard;
+
+                if depth == 0 {
+                    loop {
+                        Scheduler::sched();
+                    }
+                }
+
+                // Dropping the children drops the Senders,
+                // which readies their parent while the queues are being drained
+                let (tx, rx) = mpsc::channel::<()>();
+
+                for _ in 0..3 {
+                    let tx = tx.clone();
+                    let (spawned, dropped) = (spawned.clone(), dropped.clone());
+
+                    Scheduler::spawn(move || {
+                        let _tx = tx;
+                        spawn_tree(spawned, dropped, depth - 1);
+
+                        loop {
+                            Scheduler::sched();
+                        }
+                    });
+                }
+
+                drop(tx);
+                let _ = rx.recv();
+            };
+
+            // Pinned coroutines are sent to their Processor when readied elsewhere
+            if depth % 2 == 1 {
+                ::Builder::new().pinned_to(depth % 4).spawn(f);
+            } else {
+                Scheduler::spawn(f);
+            }
+        }
+
+        for _ in 0..10 {
+            let spawned = Arc::new(AtomicUsize::new(0));
+            let dropped = Arc::new(AtomicUsize::new(0));
+
+            let mut scheduler = Scheduler::new().with_workers(4);
+            {
+                let (spawned, dropped) = (spawned.clone(), dropped.clone());
+
+                scheduler.run(move || {
+                             spawn_tree(spawned, dropped, 4);
+                             ::sleep_ms(20);
+                         })
+                         .unwrap();
+            }
+
+            assert_eq!(dropped.load(Ordering::SeqCst), spawned.load(Ordering::SeqCst));
+            assert!(scheduler.dropped_coroutine_count() > 0);
+        }
+    }
+
+    #[test]
+    fn test_with_timeout() {
+        Scheduler::new()
+            .run(|| {
+                let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+
+                let start = Instant::now();
+                let ret = Scheduler::with_timeout(Duration::from_millis(100),
+                                                  || listener.accept().is_err());
+                assert_eq!(ret, Err(TimeoutError));
+                assert!(start.elapsed() >= Duration::from_millis(100));
+
+                let ret = Scheduler::with_timeout(Duration::from_secs(10), || 1);
+                assert_eq!(ret, Ok(1));
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_with_timeout_nested_sleep() {
+        Scheduler::new()
+            .run(|| {
+                let start = Instant::now();
+                let ret = Scheduler::with_timeout(Duration::from_millis(100), || {
+                    Scheduler::with_timeout(Duration::from_secs(10), || ::sleep_ms(10_000))
+                });
+                assert_eq!(ret, Err(TimeoutError));
+                assert!(start.elapsed() < Duration::from_secs(5));
+            })
+            .unwrap();
+    }
+
+    // Only waits which timed out because of the deadline make with_timeout() fail
+    #[test]
+    fn test_with_timeout_late_without_waiting() {
+        Scheduler::new()
+            .run(|| {
+                let ret = Scheduler::with_timeout(Duration::from_millis(10), || {
+                    let start = Instant::now();
+                    while start.elapsed() < Duration::from_millis(50) {}
+                    1
+                });
+                assert_eq!(ret, Ok(1));
+
+                // The error is reported even if `f` recovered from it
+                let ret = Scheduler::with_timeout(Duration::from_millis(10), || {
+                    ::sleep_ms(10_000);
+                    1
+                });
+                assert_eq!(ret, Err(TimeoutError));
+
+                // An own timeout shorter than the deadline doesn't count
+                let ret = Scheduler::with_timeout(Duration::from_secs(10), || {
+                    let (_tx, rx) = mpmc::channel::<usize>(1);
+                    rx.recv_timeout(Duration::from_millis(10)).is_err()
+                });
+                assert_eq!(ret, Ok(true));
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_metrics() {
+        Scheduler::new()
+            .with_workers(2)
+            .run(|| {
+                let scheduler = Scheduler::instance().unwrap();
+
+                let metrics = scheduler.metrics();
+                assert_eq!(metrics.coroutine_count, 1);
+                assert_eq!(metrics.processors.len(), 2);
+                assert_eq!(metrics.processors[1].id, 1);
+
+                let handles: Vec<_> = (0..10).map(|_| Scheduler::spawn(|| ::sleep_ms(100))).collect();
+                assert_eq!(scheduler.metrics().coroutine_count, 11);
+
+                for h in handles {
+                    h.join().unwrap();
+                }
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_load() {
+        Scheduler::new()
+            .with_load_high_water(10)
+            .with_load_window(Duration::from_millis(0))
+            .run(|| {
+                let scheduler = Scheduler::instance().unwrap();
+
+                // The main coroutine keeps the only Processor busy
+                assert_eq!(scheduler.load(), 0.5);
+
+                // ...and the spawned ones can't run before it yields
+                let handles: Vec<_> = (0..100).map(|_| Scheduler::spawn(|| {})).collect();
+                assert!(scheduler.load() > 0.99);
+
+                for h in handles {
+                    h.join().unwrap();
+                }
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_add_remove_processor() {
+        Scheduler::new()
+            .with_max_workers(3)
+            .run(|| {
+                let scheduler = Scheduler::instance().unwrap();
+
+                assert_eq!(scheduler.add_processor(), Some(1));
+
+                let mut opts = Options::new();
+                opts.pinned_to(1);
+                let id = Scheduler::spawn_opts(|| Processor::current_required().id(), opts.clone());
+                assert_eq!(id.join().unwrap(), 1);
+
+                assert!(!scheduler.remove_processor(2));
+                assert!(scheduler.remove_processor(1));
+                assert!(!scheduler.remove_processor(1));
+
+                // The only Processor left can't be removed
+                assert!(!scheduler.remove_processor(0));
+
+                // Coroutines pinned to a removed Processor are unpinned
+                let id = Scheduler::spawn_opts(|| Processor::current_required().id(), opts);
+                assert_eq!(id.join().unwrap(), 0);
+
+                assert_eq!(scheduler.add_processor(), Some(2));
+                assert_eq!(scheduler.add_processor(), None);
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_max_spawn_depth() {
+        fn recurse() -> usize {
+            match Scheduler::spawn(recurse).join() {
+                Ok(depth) => depth,
+                Err(..) => Scheduler::current_depth().unwrap(),
+            }
+        }
+
+        assert_eq!(Scheduler::current_depth(), None);
+
+        Scheduler::new()
+            .with_max_spawn_depth(5)
+            .run(|| {
+                assert_eq!(Scheduler::current_depth(), Some(0));
+
+                let depth = Scheduler::spawn(|| Scheduler::current_depth()).join().unwrap();
+                assert_eq!(depth, Some(1));
+
+                // The coroutine at the maximum depth fails to spawn another one
+                assert_eq!(recurse(), 5);
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_with_arena() {
+        Scheduler::new()
+            .with_workers(2)
+            .run(|| {
+                let mut handles = Vec::new();
+
+                for i in 0..10 {
+                    handles.push(Scheduler::spawn(move || {
+                        Scheduler::with_arena(|arena| {
+                            let s = arena.alloc_str("request");
+                            let n = arena.alloc(i);
+
+                            // Allocations stay valid while parked
+                            ::sleep_ms(10);
+                            assert_eq!(s, "request");
+                            *n
+                        })
+                    }));
+                }
+
+                for (i, h) in handles.into_iter().enumerate() {
+                    assert_eq!(h.join().unwrap(), i);
+                }
+            })
+            .unwrap();
+
+        assert_eq!(Scheduler::with_arena(|arena| *arena.alloc(1)), 1);
+    }
+
+    #[test]
+    fn test_spawn_blocking() {
+        Scheduler::new()
+            .run(|| {
+                let counter = Arc::new(AtomicUsize::new(0));
+
+                let c = counter.clone();
+                let h = Scheduler::spawn(move || {
+                    for _ in 0..10 {
+                        c.fetch_add(1, Ordering::SeqCst);
+                        ::sleep_ms(1);
+                    }
+                });
+
+                // The only Processor must keep running other coroutines in the meantime
+                let ret = Scheduler::spawn_blocking(|| {
+                    thread::sleep(Duration::from_millis(500));
+                    42
+                });
+                assert_eq!(ret, 42);
+                assert!(counter.load(Ordering::SeqCst) > 0);
+
+                h.join().unwrap();
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_spawn_blocking_panic() {
+        Scheduler::new()
+            .with_blocking_threads(1)
+            .run(|| {
+                let ret = panic::catch_unwind(|| Scheduler::spawn_blocking(|| panic!("blocking")));
+                assert!(ret.is_err());
+
+                assert_eq!(Scheduler::spawn_blocking(|| 1), 1);
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_panic_handler() {
+        let panics = Arc::new(AtomicUsize::new(0));
+        let cloned = panics.clone();
+
+        Scheduler::new()
+            .with_panic_handler(move |context, err| {
+                assert_eq!(context.name(), Some("bad"));
+                assert_eq!(context.processor_id(), 0);
+                assert_eq!(context.to_string(),
+                           format!("Coroutine#{}(bad) on Processor#0", context.id()));
+                assert_eq!(err.downcast_ref::<&str>(), Some(&"Panicked inside"));
+                cloned.fetch_add(1, Ordering::SeqCst);
+            })
+            .run(|| {
+                let work_count = Scheduler::instance().unwrap().work_count();
+
+                for _ in 0..10 {
+                    let mut opts = Options::new();
+                    opts.name("bad".to_owned());
+
+                    let h = Scheduler::spawn_opts(|| panic!("Panicked inside"), opts);
+                    assert!(h.join().is_err());
+
+                    // The Processor must keep on scheduling other coroutines
+                    assert_eq!(Scheduler::spawn(|| 1).join().unwrap(), 1);
+                }
+
+                // The panicked coroutines and their stacks must have been freed
+                Scheduler::sched();
+                assert_eq!(Scheduler::instance().unwrap().work_count(), work_count);
+            })
+            .unwrap();
+
+        assert_eq!(panics.load(Ordering::SeqCst), 10);
+    }
+
+    #[test]
+    fn test_current_id_and_name() {
+        assert_eq!(Scheduler::current_id(), None);
+
+        Scheduler::new()
+            .with_workers(4)
+            .run(|| {
+                let main_id = Scheduler::current_id().unwrap();
+                assert_eq!(Scheduler::current_name(), None);
+
+                let mut opts = Options::new();
+                opts.name("named".to_owned());
+
+                let handles: Vec<_> = (0..4)
+                                          .map(|_| {
+                                              Scheduler::spawn_opts(|| {
+                                                                        let id = Scheduler::current_id()
+                                                                                     .unwrap();
+
+                                                                        // Might be stolen by other Processors in between
+                                                                        for _ in 0..100 {
+                                                                            Scheduler::sched();
+                                                                            assert_eq!(Scheduler::current_id(),
+                                                                                       Some(id));
+                                                                        }
+
+                                                                        assert_eq!(Scheduler::current_name(),
+                                                                                   Some("named".to_owned()));
+                                                                        id
+                                                                    },
+                                                                    opts.clone())
+                                          })
+                                          .collect();
+
+                let mut ids: Vec<u64> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+                ids.push(main_id);
+                ids.sort();
+                ids.dedup();
+
+                assert_eq!(ids.len(), 5);
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_observer() {
+        struct Recorder(Arc<Mutex<Vec<(u64, SchedulerEvent)>>>);
+
+        impl SchedulerObserver for Recorder {
+            fn on_event(&self, coroutine_id: u64, _processor_id: usize, event: SchedulerEvent) {
+                self.0.lock().unwrap().push((coroutine_id, event));
+            }
+        }
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+
+        let id = Scheduler::new()
+                     .with_observer(Recorder(events.clone()))
+                     .run(|| {
+                         let hdl = Scheduler::spawn(|| {
+                             Scheduler::sched();
+                             Scheduler::current_id().unwrap()
+                         });
+
+                         hdl.join().unwrap()
+                     })
+                     .unwrap();
+
+        let events: Vec<SchedulerEvent> = events.lock()
+                                                .unwrap()
+                                                .iter()
+                                                .filter(|&&(coro, _)| coro == id)
+                                                .map(|&(_, event)| event)
+                                                .collect();
+
+        assert_eq!(events,
+                   vec![SchedulerEvent::Spawn,
+                        SchedulerEvent::Resume,
+                        SchedulerEvent::Yield,
+                        SchedulerEvent::Resume,
+                        SchedulerEvent::Finish]);
+    }
+
+    #[test]
+    fn test_wake_coalescing() {
+        struct Resumes(Arc<Mutex<Vec<u64>>>);
+
+        impl SchedulerObserver for Resumes {
+            fn on_event(&self, coroutine_id: u64, _processor_id: usize, event: SchedulerEvent) {
+                if event == SchedulerEvent::Resume {
+                    self.0.lock().unwrap().push(coroutine_id);
+                }
+            }
+        }
+
+        let resumes = Arc::new(Mutex::new(Vec::new()));
+
+        let id = Scheduler::new()
+                     .with_workers(2)
+                     .with_observer(Resumes(resumes.clone()))
+                     .run(|| {
+                         let (tx, rx) = mpmc::channel(1);
+
+                         let parked = Scheduler::spawn(move || {
+                             Processor::current().unwrap().park_with(|_, coro| {
+                                 tx.send(coro.into_wake_handle()).unwrap();
+                             });
+
+                             Scheduler::current_id().unwrap()
+                         });
+
+                         // Both wakers race for the parked coroutine, like a timer and an I/O
+                         // event. They're released at once and only the first one may queue it.
+                         let coro = rx.recv().unwrap();
+                         let barrier = Arc::new(WaitGroup::new());
+                         barrier.add(1);
+
+                         let wakers: Vec<_> = (0..2)
+                                                  .map(|_| {
+                                                      let coro = coro.clone();
+                                                      let barrier = barrier.clone();
+
+                                                      Scheduler::spawn(move || {
+                                                          barrier.wait();
+                                                          coro.wake().map(Scheduler::ready).is_some()
+                                                      })
+                                                  })
+                                                  .collect();
+
+                         barrier.done();
+
+                         let woken = wakers.into_iter().map(|h| h.join().unwrap());
+                         assert_eq!(woken.filter(|&woken| woken).count(), 1);
+
+                         // Wakers coming late don't touch the coroutine anymore
+                         let id = parked.join().unwrap();
+                         assert!(coro.wake().is_none());
+                         id
+                     })
+                     .unwrap();
+
+        // Once before parking and once after being woken up
+        let count = resumes.lock().unwrap().iter().filter(|&&coro| coro == id).count();
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn test_park_backoff() {
+        for &(spins, yields, timeout) in &[(0, 0, Duration::from_millis(0)),
+                                           (2, 1, Duration::from_millis(50))] {
+            let counter = Arc::new(AtomicUsize::new(0));
+            let c = counter.clone();
+
+            Scheduler::new()
+                .with_workers(4)
+                .with_park_backoff(spins, yields, timeout)
+                .run(move || {
+                    // Bursts of work separated by pauses long enough for the Processors to park
+                    for _ in 0..5 {
+                        let handles: Vec<_> = (0..100)
+                                                  .map(|_| {
+                                                      let c = c.clone();
+                                                      Scheduler::spawn(move || {
+                                                          c.fetch_add(1, Ordering::SeqCst);
+                                                      })
+                                                  })
+                                                  .collect();
+
+                        for h in handles {
+                            h.join().unwrap();
+                        }
+
+                        ::sleep_ms(20);
+                    }
+                })
+                .unwrap();
+
+            assert_eq!(counter.load(Ordering::SeqCst), 500);
+        }
+    }
+
+    #[test]
+    fn test_debug_watchdog() {
+        // Nothing but the builder starts a watchdog
+        assert!(!Scheduler::new().has_watchdog());
+        assert_eq!(Scheduler::new().with_debug_watchdog().has_watchdog(),
+                   cfg!(debug_assertions));
+    }
+
+    #[test]
+    fn test_watchdog() {
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let cloned = reports.clone();
+
+        let id = Scheduler::new()
+                     .with_watchdog_handler(Duration::from_millis(20), move |_, id, name, elapsed| {
+                         assert!(elapsed >= Duration::from_millis(20));
+                         cloned.lock().unwrap().push((id, name.map(str::to_owned)));
+                     })
+                     .run(|| {
+                         let mut opts = Options::new();
+                         opts.name("blocking".to_owned());
+
+                         let f = || {
+                             // Blocks the Processor without ever yielding
+                             thread::sleep(Duration::from_millis(200));
+                             Scheduler::current_id().unwrap()
+                         };
+                         let id = Scheduler::spawn_opts(f, opts).join().unwrap();
+
+                         // Yielding often enough must never be reported
+                         for _ in 0..20 {
+                             thread::sleep(Duration::from_millis(5));
+                             Scheduler::sched();
+                         }
+
+                         id
+                     })
+                     .unwrap();
+
+        // A single resume is reported only once
+        assert_eq!(*reports.lock().unwrap(), vec![(id, Some("blocking".to_owned()))]);
+    }
+
+    #[test]
+    fn test_allow_blocking() {
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let cloned = reports.clone();
+
+        // Also works outside of a coroutine
+        assert_eq!(::allow_blocking(|| 1), 1);
+
+        Scheduler::new()
+            .with_watchdog_handler(Duration::from_millis(20), move |_, id, _, _| {
+                cloned.lock().unwrap().push(id);
+            })
+            .run(|| {
+                let ret = ::allow_blocking(|| {
+                    thread::sleep(Duration::from_millis(100));
+                    2
+                });
+                assert_eq!(ret, 2);
+            })
+            .unwrap();
+
+        assert!(reports.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_spawn_on_each_processor() {
+        Scheduler::new()
+            .with_workers(4)
+            .run(|| {
+                let handles = Scheduler::spawn_on_each_processor(|| {
+                    // Yielding must not move the coroutine to a different Processor
+                    let name = thread::current().name().unwrap().to_owned();
+                    Scheduler::sched();
+                    assert_eq!(thread::current().name().unwrap(), name);
+                    name
+                });
+
+                let names: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+                assert_eq!(names,
+                           vec!["Processor#0", "Processor#1", "Processor#2", "Processor#3"]);
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_spawn_on_each_processor_removed() {
+        Scheduler::new()
+            .with_workers(3)
+            .run(|| {
+                assert!(Scheduler::instance().unwrap().remove_processor(1));
+
+                let handles = Scheduler::spawn_on_each_processor(|| {
+                    thread::current().name().unwrap().to_owned()
+                });
+
+                let names: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+                assert_eq!(names, vec!["Processor#0", "Processor#2"]);
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_spawn_on_each_processor_handle() {
+        let mut scheduler = Scheduler::new().with_workers(2);
+        let handle = scheduler.handle();
+
+        assert!(handle.spawn_on_each_processor(|| 1).is_err());
+
+        let (tx, rx) = ::std::sync::mpsc::channel();
+        let cloned = handle.clone();
+
+        let thread = thread::spawn(move || {
+            while !cloned.is_running() {
+                thread::sleep(Duration::from_millis(1));
+            }
+
+            // Queued from a thread outside of the Scheduler
+            let handles = cloned.spawn_on_each_processor(|| {
+                                    thread::current().name().unwrap().to_owned()
+                                })
+                                .ok()
+                                .unwrap();
+
+            let names: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+            tx.send(()).unwrap();
+            names
+        });
+
+        scheduler.run(move || {
+                     while rx.try_recv().is_err() {
+                         ::sleep_ms(1);
+                     }
+                 })
+                 .unwrap();
+
+        assert_eq!(thread.join().unwrap(), vec!["Processor#0", "Processor#1"]);
+        drop(handle);
+    }
+
+    #[test]
+    fn test_yield_fair() {
+        const SPINNERS: usize = 10;
+        const ROUNDS: usize = 100;
+
+        // No spinner may ever get more than one round ahead of the others
+        fn check_progress(progress: &[AtomicUsize]) {
+            let counts: Vec<_> = progress.iter().map(|c| c.load(Ordering::SeqCst)).collect();
+            let min = *counts.iter().min().unwrap();
+            let max = *counts.iter().max().unwrap();
+            assert!(max - min <= 1, "{:?}", counts);
+        }
+
+        Scheduler::new()
+            .run(|| {
+                let progress: Arc<Vec<AtomicUsize>> =
+                    Arc::new((0..SPINNERS).map(|_| AtomicUsize::new(0)).collect());
+
+                let handles: Vec<_> = (0..SPINNERS)
+                                          .map(|i| {
+                                              let progress = progress.clone();
+
+                                              Scheduler::spawn(move || {
+                                                  for _ in 0..ROUNDS {
+                                                      progress[i].fetch_add(1, Ordering::SeqCst);
+                                                      check_progress(&progress);
+                                                      Scheduler::yield_fair();
+                                                  }
+                                              })
+                                          })
+                                          .collect();
+
+                for h in handles {
+                    h.join().unwrap();
+                }
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_current_stack_usage() {
+        fn recurse(depth: usize) -> usize {
+            let buf = [depth as u8; 1024];
+
+            let usage = if depth == 0 {
+                Scheduler::current_stack_usage().unwrap()
+            } else {
+                recurse(depth - 1)
+            };
+
+            // Keeps the buffer from being optimized out
+            assert_eq!(buf[1023], depth as u8);
+            usage
+        }
+
+        Scheduler::new()
+            .run(|| {
+                assert_eq!(Scheduler::current_stack_usage().is_some(), true);
+
+                let mut opts = Options::new();
+                opts.stack_size(256 * 1024);
+                opts.track_stack_usage(true);
+
+                let f = || {
+                    let deep = recurse(64);
+                    (deep, Scheduler::current_stack_usage().unwrap())
+                };
+                let (deep, peak) = Scheduler::spawn_opts(f, opts).join().unwrap();
+
+                // The current usage at a depth of 64 frames with 1KB each
+                assert!(deep >= 64 * 1024, "{}", deep);
+                assert!(deep < 256 * 1024, "{}", deep);
+
+                // The peak is remembered after returning from the recursion
+                assert!(peak >= deep, "{} < {}", peak, deep);
+            })
+            .unwrap();
+
+        assert_eq!(Scheduler::current_stack_usage(), None);
+    }
+
+    #[test]
+    fn test_processor_panic() {
+        struct PanicOnYield(Arc<AtomicUsize>);
+
+        impl SchedulerObserver for PanicOnYield {
+            fn on_event(&self, coroutine_id: u64, _processor_id: usize, event: SchedulerEvent) {
+                if event == SchedulerEvent::Yield &&
+                   coroutine_id as usize == self.0.load(Ordering::SeqCst) {
+                    self.0.store(0, Ordering::SeqCst);
+                    panic!("Panicked in observer");
+                }
+            }
+        }
+
+        for &restart in &[false, true] {
+            let target = Arc::new(AtomicUsize::new(0));
+            let cloned = target.clone();
+
+            let ret = Scheduler::new()
+                          .with_observer(PanicOnYield(target))
+                          .with_processor_restart(restart)
+                          .run(move || {
+                              let h = Scheduler::spawn(move || {
+                                  let id = Scheduler::current_id().unwrap() as usize;
+                                  cloned.store(id, Ordering::SeqCst);
+                                  Scheduler::sched();
+                              });
+
+                              // The coroutine being scheduled during the panic is unwound
+                              assert!(h.join().is_err());
+
+                              // The restarted Processor keeps on scheduling other coroutines
+                              Scheduler::spawn(|| 1).join().unwrap()
+                          });
+
+            if restart {
+                assert_eq!(ret.unwrap(), 1);
+            } else {
+                let err = ret.unwrap_err();
+                assert_eq!(err.downcast_ref::<&str>(), Some(&"Panicked in observer"));
+            }
+        }
+    }
+
+    #[test]
+    fn test_spawn_batch() {
+        Scheduler::new()
+            .with_workers(2)
+            .run(|| {
+                let order = Arc::new(Mutex::new(Vec::new()));
+
+                let fs: Vec<_> = (0..100)
+                                     .map(|i| {
+                                         let order = order.clone();
+                                         move || {
+                                             assert_eq!(thread::current().name(),
+                                                        Some("Processor#1"));
+                                             order.lock().unwrap().push(i);
+                                             i * 2
+                                         }
+                                     })
+                                     .collect();
+
+                let mut opts = Options::new();
+                opts.pinned_to(1);
+
+                let handles = Scheduler::spawn_batch(fs, opts);
+                let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+                // All of them ran on the Processor they are pinned to, in the order spawned
+                assert_eq!(results, (0..100).map(|i| i * 2).collect::<Vec<_>>());
+                assert_eq!(*order.lock().unwrap(), (0..100).collect::<Vec<_>>());
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_run_result() {
+        assert_eq!(Scheduler::new().run(|| 42).unwrap(), 42);
+
+        // Panics of other coroutines only reach the panic handler
+        let panics = Arc::new(AtomicUsize::new(0));
+        let cloned = panics.clone();
+
+        let ret = Scheduler::new()
+                      .with_panic_handler(move |_, _| {
+                          cloned.fetch_add(1, Ordering::SeqCst);
+                      })
+                      .run(|| {
+                          assert!(Scheduler::spawn(|| panic!("child")).join().is_err());
+                          "main"
+                      });
+
+        assert_eq!(ret.unwrap(), "main");
+        assert_eq!(panics.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_run_main_panic() {
+        let panics = Arc::new(AtomicUsize::new(0));
+        let cloned = panics.clone();
+
+        let ret = Scheduler::new()
+                      .with_workers(2)
+                      .with_panic_handler(move |_, _| {
+                          cloned.fetch_add(1, Ordering::SeqCst);
+                      })
+                      .run(|| -> usize {
+                          // Coroutines still running while the main one panics are unwound
+                          Scheduler::spawn(|| ::sleep(Duration::from_secs(3600)));
+                          panic!("main");
+                      });
+
+        let err = ret.unwrap_err();
+        assert_eq!(err.downcast_ref::<&str>(), Some(&"main"));
+        assert_eq!(panics.load(Ordering::SeqCst), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_register_custom_source() {
+        use std::io::{self, Read, Write};
+        use mio::unix;
+
+        Scheduler::new()
+            .run(|| {
+                let (mut reader, mut writer) = unix::pipe().unwrap();
+                let scheduler = Scheduler::instance().unwrap();
+                let (token, ready_states) = scheduler.register(&reader, EventSet::readable())
+                                                     .unwrap();
+
+                let h = Scheduler::spawn(move || {
+                    ::sleep_ms(10);
+                    writer.write_all(b"x").unwrap();
+                });
+
+                let mut buf = [0u8; 1];
+                loop {
+                    match reader.read(&mut buf) {
+                        Ok(n) => {
+                            assert_eq!(n, 1);
+                            break;
+                        }
+                        Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                            ready_states.wait(ReadyType::Readable);
+                        }
+                        Err(err) => panic!("{}", err),
+                    }
+                }
+
+                assert_eq!(&buf, b"x");
+                h.join().unwrap();
+                scheduler.deregister(&reader, token).unwrap();
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_idle_handler() {
+        let parked: Arc<Mutex<Option<Handle>>> = Arc::new(Mutex::new(None));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let cloned_parked = parked.clone();
+        let cloned_calls = calls.clone();
+
+        Scheduler::new()
+            .with_park_backoff(0, 0, Duration::from_millis(0))
+            .with_idle_handler(move |p| {
+                cloned_calls.fetch_add(1, Ordering::SeqCst);
+
+                if let Some(coro) = cloned_parked.lock().unwrap().take() {
+                    p.ready(coro);
+                }
+            })
+            .run(move || {
+                // Nothing but the idle handler is going to wake this coroutine up again
+                Scheduler::park_with(|_, coro| {
+                    *parked.lock().unwrap() = Some(coro);
+                });
+            })
+            .unwrap();
+
+        assert!(calls.load(Ordering::SeqCst) >= 1);
+    }
+
+    #[test]
+    fn test_scheduler_handle() {
+        let mut scheduler = Scheduler::new().with_workers(2);
+        let handle = scheduler.handle();
+
+        // Spawning fails before the Scheduler runs
+        assert!(handle.spawn(|| 1).is_err());
+
+        let (tx, rx) = ::std::sync::mpsc::channel();
+        let cloned = handle.clone();
+
+        let thread = thread::spawn(move || {
+            while !cloned.is_running() {
+                thread::sleep(Duration::from_millis(1));
+            }
+
+            // Joined from a thread outside of the Scheduler
+            let ret = cloned.spawn(|| Scheduler::current_id().is_some()).ok().unwrap().join();
+            tx.send(()).unwrap();
+            ret.unwrap()
+        });
+
+        let cloned = handle.clone();
+        scheduler.run(move || {
+                     assert!(cloned.is_running());
+
+                     while rx.try_recv().is_err() {
+                         ::sleep_ms(1);
+                     }
+                 })
+                 .unwrap();
+
+        assert!(thread.join().unwrap());
+        assert!(!handle.is_running());
+        assert!(handle.spawn(|| 1).is_err());
+    }
+
+    #[test]
+    fn test_external_dispatch() {
+        for &dispatch in &[ExternalDispatch::Global,
+                           ExternalDispatch::RoundRobin,
+                           ExternalDispatch::LeastLoaded] {
+            let mut scheduler = Scheduler::new().with_workers(4).with_external_dispatch(dispatch);
+            let handle = scheduler.handle();
+            let (tx, rx) = ::std::sync::mpsc::channel();
+
+            let thread = thread::spawn(move || {
+                while !handle.is_running() {
+                    thread::sleep(Duration::from_millis(1));
+                }
+
+                // Each coroutine is readied externally twice: once when spawned through the
+                // handle and once when its blocking closure completes
+                let handles: Vec<_> = (0..16)
+                                          .map(|i| {
+                                              let f = move || Scheduler::spawn_blocking(move || i);
+                                              handle.spawn(f).ok().unwrap()
+                                          })
+                                          .collect();
+
+                let sum = handles.into_iter().map(|h| h.join().unwrap()).fold(0, |a, b| a + b);
+                tx.send(()).unwrap();
+                sum
+            });
+
+            scheduler.run(move || {
+                         while rx.try_recv().is_err() {
+                             ::sleep_ms(1);
+                         }
+                     })
+                     .unwrap();
+
+            assert_eq!(thread.join().unwrap(), (0..16).fold(0, |a, b| a + b));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_deregister_wakes_waiters() {
+        use std::io;
+        use mio::unix;
+
+        Scheduler::new()
+            .with_workers(2)
+            .run(|| {
+                let (reader, _writer) = unix::pipe().unwrap();
+                let scheduler = Scheduler::instance().unwrap();
+                let (token, ready_states) = scheduler.register(&reader, EventSet::readable())
+                                                     .unwrap();
+
+                let waiter = {
+                    let ready_states = ready_states.clone();
+                    let f = move || ready_states.wait_timeout_opt(ReadyType::Readable, None);
+                    Scheduler::spawn(f)
+                };
+
+                // Make sure that the coroutine is parked before deregistering
+                ::sleep_ms(10);
+                scheduler.deregister(&reader, token).unwrap();
+
+                let err = waiter.join().unwrap().unwrap_err();
+                assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+                assert!(ready_states.is_deregistered());
+
+                // Waiting on a deregistered source doesn't park
+                let err = ready_states.wait_timeout_opt(ReadyType::Writable, None).unwrap_err();
+                assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_run_until_idle() {
+        let finished = Arc::new(AtomicUsize::new(0));
+
+        {
+            let finished = finished.clone();
+
+            let ret = Scheduler::new()
+                          .with_workers(2)
+                          .run_until_idle(move || {
+                              for _ in 0..10 {
+                                  let finished = finished.clone();
+                                  Scheduler::spawn(move || {
+                                      ::sleep_ms(20);
+                                      finished.fetch_add(1, Ordering::SeqCst);
+                                  });
+                              }
+
+                              // Returns long before the other coroutines
+                              1
+                          });
+
+            assert_eq!(ret.unwrap(), 1);
+        }
+
+        assert_eq!(finished.load(Ordering::SeqCst), 10);
+
+        // Coroutines can still be spawned through a handle after the main coroutine finished
+        let mut scheduler = Scheduler::new();
+        let handle = scheduler.handle();
+        let (tx, rx) = ::std::sync::mpsc::channel();
+
+        let thread = thread::spawn(move || {
+            rx.recv().unwrap();
+            let ret = handle.spawn(|| 2).ok().unwrap().join().unwrap();
+
+            // Dropping the last handle lets run_until_idle() return
+            drop(handle);
+            ret
+        });
+
+        scheduler.run_until_idle(move || tx.send(()).unwrap()).unwrap();
+        assert_eq!(thread.join().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_with_deadline() {
+        Scheduler::new()
+            .run(|| {
+                let scheduler = Scheduler::instance().unwrap();
+
+                // A single deadline is shared by all nested calls
+                let deadline = Instant::now() + Duration::from_millis(50);
+                let ret = Scheduler::with_deadline(deadline, || {
+                    let ret = Scheduler::with_deadline(deadline + Duration::from_secs(10),
+                                                       || ::sleep_ms(10_000));
+                    assert_eq!(ret, Err(TimeoutError));
+                });
+                assert_eq!(ret, Err(TimeoutError));
+                assert!(Instant::now() >= deadline);
+
+                // A deadline in the past resumes immediately
+                let start = Instant::now();
+                let ret = Scheduler::with_deadline(start, || ::sleep_ms(10_000));
+                assert_eq!(ret, Err(TimeoutError));
+                scheduler.sleep_until(start - Duration::from_millis(1));
+                assert!(start.elapsed() < Duration::from_secs(5));
+
+                let deadline = Instant::now() + Duration::from_millis(20);
+                scheduler.sleep_until(deadline);
+                assert!(Instant::now() >= deadline);
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_current_deadline() {
+        assert_eq!(Scheduler::current_deadline(), None);
+
+        Scheduler::new()
+            .run(|| {
+                assert_eq!(Scheduler::current_deadline(), None);
+
+                let deadline = Instant::now() + Duration::from_millis(20);
+                let ret = Scheduler::with_deadline(deadline, || {
+                    assert_eq!(Scheduler::current_deadline(), Some(deadline));
+
+                    // Gives up on the channel, whose sender is still alive
+                    let (_tx, rx) = mpsc::channel::<usize>();
+                    assert!(rx.recv().is_err());
+                });
+                assert_eq!(ret, Err(TimeoutError));
+                assert!(Instant::now() >= deadline);
+
+                // Cleared once with_deadline() returned
+                assert_eq!(Scheduler::current_deadline(), None);
+
+                let (tx, rx) = mpmc::channel::<usize>(1);
+                tx.send(1).unwrap();
+                let ret = Scheduler::with_timeout(Duration::from_secs(10), || rx.recv());
+                assert_eq!(ret, Ok(Ok(1)));
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_adaptive_parking() {
+        Scheduler::new()
+            .with_workers(2)
+            .with_adaptive_parking(AdaptiveParking::default())
+            .run(|| {
+                let handles: Vec<_> = (0..100)
+                                          .map(|_| Scheduler::spawn(|| ::sleep_ms(1)))
+                                          .collect();
+
+                for h in handles {
+                    h.join().unwrap();
+                }
+
+                let metrics = Scheduler::instance().unwrap().metrics();
+
+                for p in metrics.processors {
+                    assert!(p.steal_rate >= 0.0 && p.steal_rate <= 1.0);
+                }
+            })
+            .unwrap();
+    }
 }
@@ -0,0 +1,275 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Scoped coroutines which may borrow from the stack of their parent
+
+use std::boxed::FnBox;
+use std::marker::PhantomData;
+use std::mem;
+use std::panic;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use coroutine::ForceUnwind;
+use scheduler::{JoinHandle, Scheduler};
+use sync::{Spinlock, WaitGroup};
+
+struct ScopeState {
+    wait_group: WaitGroup,
+    panicked: AtomicBool,
+}
+
+/// A scope for spawning coroutines which borrow data living at least as long as `'a`.
+///
+/// Created by `Scheduler::scope()`.
+pub struct Scope<'a> {
+    state: Arc<ScopeState>,
+
+    // Invariant in 'a, so that the borrow cannot be shortened
+    marker: PhantomData<::std::cell::Cell<&'a mut ()>>,
+}
+
+impl<'a> Scope<'a> {
+    /// Spawn a new coroutine which may borrow from the enclosing scope.
+    ///
+    /// The coroutine is guaranteed to finish before `Scheduler::scope()` returns.
+    pub fn spawn<F, T>(&self, f: F) -> ScopedJoinHandle<'a, T>
+        where F: FnOnce() -> T + Send + 'a,
+              T: Send + 'a
+    {
+        let packet = Arc::new(Spinlock::new(None));
+        let guard = DoneGuard(self.state.clone());
+        let their_packet = packet.clone();
+
+        self.state.wait_group.add(1);
+
+        let closure: Box<FnBox() + Send + 'a> = Box::new(move || {
+            // Dropped last, even while unwinding: Nothing borrowed from 'a may be touched afterwards
+            let _guard = guard;
+            let packet = their_packet;
+
+            *packet.lock() = Some(f());
+        });
+
+        // The DoneGuard makes sure that the scope waits for the closure
+        // to be completely consumed, before the borrowed data goes away.
+        let closure: Box<FnBox() + Send + 'static> = unsafe { mem::transmute(closure) };
+
+        ScopedJoinHandle {
+            inner: Scheduler::spawn(move || closure()),
+            packet: packet,
+            marker: PhantomData,
+        }
+    }
+}
+
+struct DoneGuard(Arc<ScopeState>);
+
+impl Drop for DoneGuard {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.0.panicked.store(true, Ordering::SeqCst);
+        }
+
+        self.0.wait_group.done();
+    }
+}
+
+/// A handle to a coroutine spawned by `Scope::spawn()`
+pub struct ScopedJoinHandle<'a, T: 'a> {
+    inner: JoinHandle<()>,
+    packet: Arc<Spinlock<Option<T>>>,
+    marker: PhantomData<&'a ()>,
+}
+
+unsafe impl<'a, T: Send + 'a> Send for ScopedJoinHandle<'a, T> {}
+
+impl<'a, T: 'a> ScopedJoinHandle<'a, T> {
+    /// Await completion of the coroutine and return it's result.
+    pub fn join(self) -> thread::Result<T> {
+        try!(self.inner.join());
+        Ok(self.packet.lock().take().expect("scoped coroutine finished without a result"))
+    }
+}
+
+/// Create a scope for spawning coroutines which may borrow from the current stack.
+///
+/// All coroutines spawned through the `Scope` are joined before this function returns.
+/// If any of them panicked this function panics as well, even if the panic was
+/// already observed through `ScopedJoinHandle::join()`.
+pub fn scope<'a, F, R>(f: F) -> R
+    where F: FnOnce(&Scope<'a>) -> R
+{
+    let scope = Scope {
+        state: Arc::new(ScopeState {
+            wait_group: WaitGroup::new(),
+            panicked: AtomicBool::new(false),
+        }),
+        marker: PhantomData,
+    };
+
+    let ret = match panic::catch_unwind(panic::AssertUnwindSafe(|| f(&scope))) {
+        Err(err) => {
+            // The coroutine is being dropped and may not be parked anymore. The children
+            // have to be gone before the unwind continues nevertheless. Those queued on this
+            // Processor are unwound right here, the ones on others are waited for.
+            if err.is::<ForceUnwind>() {
+                while scope.state.wait_group.count() > 0 {
+                    if Scheduler::drop_queued_coroutines() == 0 {
+                        thread::yield_now();
+                    }
+                }
+
+                panic::resume_unwind(err);
+            }
+
+            Err(err)
+        }
+        Ok(ret) => Ok(ret),
+    };
+
+    // The children might still borrow from our stack, even if `f` panicked
+    scope.state.wait_group.wait();
+
+    match ret {
+        Err(err) => panic::resume_unwind(err),
+        Ok(ret) => {
+            if scope.state.panicked.load(Ordering::SeqCst) {
+                panic!("a scoped coroutine panicked");
+            }
+
+            ret
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::panic;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use scheduler::Scheduler;
+
+    #[test]
+    fn scope_borrows_stack() {
+        Scheduler::new()
+            .with_workers(4)
+            .run(|| {
+                let mut values = vec![1usize, 2, 3, 4];
+                let sum = AtomicUsize::new(0);
+
+                scope(|s| {
+                    for value in values.iter_mut() {
+                        let sum = &sum;
+
+                        s.spawn(move || {
+                            Scheduler::sched();
+                            *value *= 2;
+                            sum.fetch_add(*value, Ordering::SeqCst);
+                        });
+                    }
+                });
+
+                assert_eq!(values, vec![2, 4, 6, 8]);
+                assert_eq!(sum.load(Ordering::SeqCst), 20);
+
+                let len = scope(|s| s.spawn(|| values.len()).join().unwrap());
+                assert_eq!(len, 4);
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn scope_propagates_panic() {
+        Scheduler::new()
+            .run(|| {
+                let finished = AtomicUsize::new(0);
+
+                let ret = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+                    scope(|s| {
+                        s.spawn(|| panic!("child panicked"));
+
+                        s.spawn(|| {
+                            Scheduler::sched();
+                            finished.fetch_add(1, Ordering::SeqCst);
+                        });
+                    })
+                }));
+
+                assert!(ret.is_err());
+                assert_eq!(finished.load(Ordering::SeqCst), 1);
+            })
+            .unwrap();
+    }
+
+    // The parent is dropped during the shutdown while it's children are queued as well
+    #[test]
+    fn scope_unwinds_children_on_shutdown() {
+        struct Child<'a>(&'a AtomicUsize);
+
+        impl<'a> Drop for Child<'a> {
+            fn drop(&mut self) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        struct DropCheck<'a>(&'a AtomicUsize, Arc<AtomicUsize>);
+
+        impl<'a> Drop for DropCheck<'a> {
+            fn drop(&mut self) {
+                self.1.store(self.0.load(Ordering::SeqCst), Ordering::SeqCst);
+            }
+        }
+
+        let unwound_first = Arc::new(AtomicUsize::new(0));
+
+        {
+            let unwound_first = unwound_first.clone();
+
+            Scheduler::new()
+                .run(move || {
+                    Scheduler::spawn(move || {
+                        let unwound = AtomicUsize::new(0);
+
+                        {
+                            // Dropped while the stack of the parent is unwound
+                            let _check = DropCheck(&unwound, unwound_first);
+
+                            scope(|s| {
+                                for _ in 0..4 {
+                                    let child = Child(&unwound);
+
+                                    s.spawn(move || {
+                                        let _child = child;
+
+                                        loop {
+                                            Scheduler::sched();
+                                        }
+                                    });
+                                }
+
+                                loop {
+                                    Scheduler::sched();
+                                }
+                            });
+                        }
+                    });
+
+                    Scheduler::sched();
+                })
+                .unwrap();
+        }
+
+        // All of the children were unwound before the parent's stack
+        assert_eq!(unwound_first.load(Ordering::SeqCst), 4);
+    }
+}
@@ -0,0 +1,345 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Waiting for the first of multiple operations to complete
+//!
+//! ```ignore
+//! select! {
+//!     msg = rx.select_recv() => println!("received {:?}", msg),
+//!     len = stream.select_read(&mut buf) => println!("read {:?}", len),
+//!     _ = select::timeout(Duration::from_secs(1)) => println!("timed out"),
+//! }
+//! ```
+//!
+//! Each arm consists of an operation which can be attempted without blocking.
+//! `select!` attempts all of them in order and parks the current coroutine until one of their
+//! sources is notified if none is able to complete, after which all of them are attempted again.
+//! Only the body of the single operation which actually completed is evaluated,
+//! with the operation's result bound to the pattern. The others have no effect,
+//! e.g. no item is removed from their channel.
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use coroutine::Handle;
+use runtime::Processor;
+use runtime::timer::Timeout;
+use scheduler::Scheduler;
+use sync::spinlock::Spinlock;
+
+struct SelectState {
+    fired: bool,
+    handle: Option<Handle>,
+}
+
+/// Used by `select!` to wait for the first of multiple operations to complete
+///
+/// Sources of `SelectOp`s keep a list of all Selects registered with them and
+/// notify them whenever one of the operations might be able to complete.
+#[derive(Clone)]
+pub struct Select {
+    inner: Arc<Spinlock<SelectState>>,
+}
+
+unsafe impl Send for Select {}
+unsafe impl Sync for Select {}
+
+impl Select {
+    pub fn new() -> Select {
+        Select {
+            inner: Arc::new(Spinlock::new(SelectState {
+                fired: false,
+                handle: None,
+            })),
+        }
+    }
+
+    /// Attempts the operations until the first one completes and returns it's index.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a coroutine.
+    pub fn wait(&self, ops: &mut [&mut SelectOp]) -> usize {
+        assert!(Processor::current().is_some(),
+                "select! only works inside of coroutines");
+
+        loop {
+            self.inner.lock().fired = false;
+
+            // Registering first ensures that no notification between attempt and park is lost
+            for op in ops.iter_mut() {
+                op.register(self);
+            }
+
+            let completed = ops.iter_mut().position(|op| op.try_complete());
+
+            if completed.is_none() {
+                Scheduler::park_with(|p, coro| {
+                    let mut state = self.inner.lock();
+
+                    if state.fired {
+                        drop(state);
+                        p.ready(coro);
+                    } else {
+                        state.handle = Some(coro);
+                    }
+                });
+            }
+
+            for op in ops.iter_mut() {
+                op.unregister(self);
+            }
+
+            if let Some(idx) = completed {
+                return idx;
+            }
+        }
+    }
+
+    /// Notify the Select about a source, returning the parked coroutine if any
+    ///
+    /// The returned Handle must be readied by the caller.
+    #[doc(hidden)]
+    pub fn notify(&self) -> Option<Handle> {
+        let mut state = self.inner.lock();
+        state.fired = true;
+        state.handle.take()
+    }
+
+    /// Returns true if both refer to the same Select
+    #[doc(hidden)]
+    pub fn is(&self, other: &Select) -> bool {
+        &*self.inner as *const _ == &*other.inner as *const _
+    }
+}
+
+impl fmt::Debug for Select {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Select({:p})", &*self.inner)
+    }
+}
+
+impl Default for Select {
+    fn default() -> Select {
+        Select::new()
+    }
+}
+
+/// An operation which can be used in `select!`
+///
+/// Besides this trait every operation has an inherent `take()` method which
+/// returns the result, once the operation completed.
+pub trait SelectOp {
+    /// Attempts to complete the operation without blocking and returns true if it did.
+    fn try_complete(&mut self) -> bool;
+
+    /// Returns true if `try_complete()` succeeded.
+    fn is_completed(&self) -> bool;
+
+    /// Notifies the Select whenever the operation might be able to complete.
+    fn register(&mut self, select: &Select);
+
+    /// Undoes `register()`. After this call the Select must not be notified anymore.
+    fn unregister(&mut self, select: &Select);
+}
+
+// Helpers for the lists of registered Selects kept by the sources
+
+#[doc(hidden)]
+pub fn notify_all<F>(selects: &[Select], mut ready: F)
+    where F: FnMut(Handle)
+{
+    for select in selects {
+        if let Some(hdl) = select.notify() {
+            ready(hdl);
+        }
+    }
+}
+
+#[doc(hidden)]
+pub fn remove(selects: &mut Vec<Select>, select: &Select) {
+    selects.retain(|s| !s.is(select));
+}
+
+/// A `select!` operation which completes after `dur` has passed
+pub fn timeout(dur: Duration) -> TimeoutOp {
+    timeout_at(Instant::now() + dur)
+}
+
+/// A `select!` operation which completes once `deadline` is reached
+pub fn timeout_at(deadline: Instant) -> TimeoutOp {
+    TimeoutOp {
+        deadline: deadline,
+        timeout: None,
+        completed: false,
+    }
+}
+
+/// Created by `select::timeout()` and `select::timeout_at()`
+pub struct TimeoutOp {
+    deadline: Instant,
+    timeout: Option<Timeout>,
+    completed: bool,
+}
+
+impl TimeoutOp {
+    pub fn take(&mut self) {
+        assert!(self.completed, "TimeoutOp didn't complete yet");
+        Scheduler::note_timeout();
+    }
+}
+
+impl SelectOp for TimeoutOp {
+    fn try_complete(&mut self) -> bool {
+        self.completed = Instant::now() >= self.deadline;
+        self.completed
+    }
+
+    fn is_completed(&self) -> bool {
+        self.completed
+    }
+
+    fn register(&mut self, select: &Select) {
+        let now = Instant::now();
+        let remaining = if now < self.deadline {
+            ::duration_to_ms(self.deadline.duration_since(now))
+        } else {
+            0
+        };
+
+        let p = Processor::current_required();
+        self.timeout = Some(p.scheduler().select_timeout(remaining, select.clone()));
+    }
+
+    fn unregister(&mut self, _select: &Select) {
+        if let Some(timeout) = self.timeout.take() {
+            Processor::current_required().scheduler().cancel_timeout(timeout);
+        }
+    }
+}
+
+/// Waits for the first of multiple operations to complete, see the `select` module
+#[macro_export]
+macro_rules! select {
+    (@bind [$(($name:pat, $var:ident, $body:expr))*]) => {{
+        $crate::select::Select::new().wait(&mut [$(&mut $var as &mut $crate::select::SelectOp),*]);
+
+        $(
+            if $crate::select::SelectOp::is_completed(&$var) {
+                let $name = $var.take();
+                $body
+            } else
+        )* {
+            unreachable!()
+        }
+    }};
+    (@bind [$($bound:tt)*] $name:pat = $op:expr => $body:expr, $($rest:tt)*) => {{
+        let mut __select_op = $op;
+        select!(@bind [$($bound)* ($name, __select_op, $body)] $($rest)*)
+    }};
+    (@bind [$($bound:tt)*] $name:pat = $op:expr => $body:expr) => {
+        select!(@bind [$($bound)*] $name = $op => $body,)
+    };
+    ($($arms:tt)+) => {
+        select!(@bind [] $($arms)+)
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Write;
+    use std::time::Duration;
+
+    use net::{TcpListener, TcpStream};
+    use scheduler::Scheduler;
+    use sync::{mpmc, mpsc};
+
+    #[test]
+    fn select_channels() {
+        Scheduler::new()
+            .run(|| {
+                let (tx1, rx1) = mpsc::channel::<usize>();
+                let (tx2, rx2) = mpmc::channel::<usize>(4);
+
+                Scheduler::spawn(move || {
+                    Scheduler::sched();
+                    tx2.send(2).unwrap();
+                });
+
+                let fired = select! {
+                    v = rx1.select_recv() => v.unwrap(),
+                    v = rx2.select_recv() => v.unwrap(),
+                };
+                assert_eq!(fired, 2);
+
+                // The losing operation didn't consume anything
+                tx1.send(1).unwrap();
+                assert_eq!(rx1.recv().unwrap(), 1);
+
+                // The disconnect of a channel completes it's operation as well
+                let disconnected = select! {
+                    v = rx2.select_recv() => v.is_err(),
+                    _ = timeout(Duration::from_secs(10)) => false,
+                };
+                assert!(disconnected);
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn select_timeout() {
+        Scheduler::new()
+            .with_timer_resolution(Duration::from_millis(10))
+            .run(|| {
+                let (tx, rx) = mpsc::channel::<usize>();
+
+                for _ in 0..10 {
+                    let timed_out = select! {
+                        _ = rx.select_recv() => false,
+                        _ = timeout(Duration::from_millis(10)) => true,
+                    };
+                    assert!(timed_out);
+                }
+
+                tx.send(1).unwrap();
+                assert_eq!(rx.recv().unwrap(), 1);
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn select_read() {
+        Scheduler::new()
+            .run(|| {
+                let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+                let addr = listener.local_addr().unwrap();
+
+                let hdl = Scheduler::spawn(move || {
+                    let (mut stream, _) = listener.accept().unwrap();
+                    Scheduler::sched();
+                    stream.write_all(b"hello").unwrap();
+                });
+
+                let stream = TcpStream::connect(addr).unwrap();
+                let (_tx, rx) = mpsc::channel::<usize>();
+                let mut buf = [0u8; 16];
+
+                let len = select! {
+                    _ = rx.select_recv() => unreachable!(),
+                    len = stream.select_read(&mut buf) => len.unwrap(),
+                };
+                assert_eq!(&buf[..len], b"hello");
+
+                hdl.join().unwrap();
+            })
+            .unwrap();
+    }
+}
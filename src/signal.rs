@@ -0,0 +1,228 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Delivery of process signals to coroutines
+//!
+//! Uses the self-pipe trick: Each subscription owns a pipe, which the signal handler writes
+//! the signal's number to. The read end is registered with the event loop, which is why
+//! `Signals::recv()` simply parks the coroutine until a signal arrived.
+
+use std::fmt;
+use std::io::{self, Read};
+use std::mem;
+use std::os::unix::io::AsRawFd;
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+
+use libc;
+
+use net::unix::{self, PipeReader, PipeWriter};
+
+// Maximum number of Signals alive at the same time
+const MAX_SUBSCRIBERS: usize = 64;
+
+type Subscribers = [AtomicUsize; MAX_SUBSCRIBERS];
+
+// Points to the leaked Subscribers once the first Signals was created.
+// Each slot is either 0 or contains the write end of a pipe shifted by 8 bits,
+// combined with the number of the signal it subscribed to.
+static SUBSCRIBERS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+// Bitmask of the signals the handler was installed for
+static INSTALLED: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Signals which can be subscribed to with `notify()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    /// `SIGHUP`
+    Hangup,
+    /// `SIGINT`, e.g. sent by pressing Ctrl+C
+    Interrupt,
+    /// `SIGQUIT`
+    Quit,
+    /// `SIGTERM`, the default signal sent by `kill`
+    Terminate,
+    /// `SIGUSR1`
+    User1,
+    /// `SIGUSR2`
+    User2,
+}
+
+impl Signal {
+    /// Returns the platform specific number of the signal
+    pub fn as_raw(&self) -> libc::c_int {
+        match *self {
+            Signal::Hangup => libc::SIGHUP,
+            Signal::Interrupt => libc::SIGINT,
+            Signal::Quit => libc::SIGQUIT,
+            Signal::Terminate => libc::SIGTERM,
+            Signal::User1 => libc::SIGUSR1,
+            Signal::User2 => libc::SIGUSR2,
+        }
+    }
+}
+
+/// A subscription to a signal, see `notify()`
+pub struct Signals {
+    signal: Signal,
+    slot: usize,
+    reader: PipeReader,
+    _writer: PipeWriter,
+}
+
+/// Subscribe to `signal`
+///
+/// The first subscription to a signal replaces it's handler for the whole process,
+/// which means that e.g. `SIGINT` won't terminate the process anymore from then on.
+/// Any number of coroutines may subscribe to the same signal and each one of them is notified.
+/// Signals arriving while the subscriber isn't waiting in `recv()` are queued,
+/// only limited by the pipe's capacity. At most 64 subscriptions can exist at the same
+/// time, after which this fails with `ErrorKind::Other`.
+pub fn notify(signal: Signal) -> io::Result<Signals> {
+    let (reader, writer) = try!(unix::pipe());
+    let entry = ((writer.as_raw_fd() as usize) << 8) | signal.as_raw() as usize;
+
+    let claim = |s: &AtomicUsize| s.compare_and_swap(0, entry, Ordering::SeqCst) == 0;
+
+    let slot = match subscribers().iter().position(claim) {
+        Some(slot) => slot,
+        None => {
+            return Err(io::Error::new(io::ErrorKind::Other, "too many signal subscriptions"));
+        }
+    };
+
+    let signals = Signals {
+        signal: signal,
+        slot: slot,
+        reader: reader,
+        _writer: writer,
+    };
+
+    try!(install_handler(signal.as_raw()));
+    Ok(signals)
+}
+
+impl Signals {
+    /// Returns the signal this is subscribed to
+    pub fn signal(&self) -> Signal {
+        self.signal
+    }
+
+    /// Parks the coroutine until the signal arrived
+    pub fn recv(&self) -> io::Result<Signal> {
+        let mut buf = [0u8; 1];
+        try!((&self.reader).read_exact(&mut buf));
+        Ok(self.signal)
+    }
+}
+
+impl Drop for Signals {
+    fn drop(&mut self) {
+        // The pipe is closed only afterwards, when the fields are dropped
+        subscribers()[self.slot].store(0, Ordering::SeqCst);
+    }
+}
+
+impl fmt::Debug for Signals {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Signals({:?})", self.signal)
+    }
+}
+
+fn subscribers() -> &'static Subscribers {
+    let mut ptr = SUBSCRIBERS.load(Ordering::SeqCst) as *const Subscribers;
+
+    if ptr.is_null() {
+        // All zeros is a valid and empty list of AtomicUsize
+        let new = Box::into_raw(Box::new(unsafe { mem::zeroed::<Subscribers>() }));
+        let prev = SUBSCRIBERS.compare_and_swap(0, new as usize, Ordering::SeqCst);
+
+        if prev == 0 {
+            ptr = new;
+        } else {
+            unsafe { drop(Box::from_raw(new)) };
+            ptr = prev as *const Subscribers;
+        }
+    }
+
+    unsafe { &*ptr }
+}
+
+fn install_handler(signum: libc::c_int) -> io::Result<()> {
+    let bit = 1 << signum;
+
+    if INSTALLED.fetch_or(bit, Ordering::SeqCst) & bit != 0 {
+        return Ok(());
+    }
+
+    unsafe {
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = handler as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+
+        if libc::sigaction(signum, &action, ptr::null_mut()) != 0 {
+            INSTALLED.fetch_and(!bit, Ordering::SeqCst);
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+// Only uses async-signal-safe operations: atomics and write(2)
+extern "C" fn handler(signum: libc::c_int) {
+    let ptr = SUBSCRIBERS.load(Ordering::SeqCst) as *const Subscribers;
+
+    if ptr.is_null() {
+        return;
+    }
+
+    let byte = signum as u8;
+
+    for slot in unsafe { (*ptr).iter() } {
+        let entry = slot.load(Ordering::SeqCst);
+
+        if entry != 0 && (entry & 0xff) as libc::c_int == signum {
+            // A full pipe already contains enough pending notifications
+            unsafe {
+                libc::write((entry >> 8) as libc::c_int,
+                            &byte as *const u8 as *const libc::c_void,
+                            1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use libc;
+
+    use scheduler::Scheduler;
+    use super::{notify, Signal};
+
+    #[test]
+    fn test_signal_notify() {
+        Scheduler::new()
+            .run(|| {
+                let first = notify(Signal::User1).unwrap();
+                let second = notify(Signal::User1).unwrap();
+                let other = notify(Signal::User2).unwrap();
+
+                let h = Scheduler::spawn(move || first.recv().unwrap());
+
+                unsafe { libc::raise(libc::SIGUSR1) };
+
+                // Every subscriber is notified
+                assert_eq!(h.join().unwrap(), Signal::User1);
+                assert_eq!(second.recv().unwrap(), Signal::User1);
+                assert_eq!(other.signal(), Signal::User2);
+            })
+            .unwrap();
+    }
+}
@@ -0,0 +1,194 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Spawner limiting the number of concurrently running coroutines
+
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use options::Options;
+use scheduler::{JoinHandle, Scheduler};
+use sync::semaphore::Semaphore;
+
+struct SpawnerState {
+    semaphore: Semaphore,
+    running: AtomicUsize,
+    limit: usize,
+}
+
+// Hands the permit back to the spawner as soon as the coroutine finishes,
+// even if it panicked or was force unwound.
+struct Permit(Arc<SpawnerState>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.0.running.fetch_sub(1, Ordering::SeqCst);
+        self.0.semaphore.release();
+    }
+}
+
+/// Spawns coroutines, but never more than a fixed number of them at the same time
+///
+/// Each coroutine takes a permit from an internal `Semaphore` when it is spawned and returns it
+/// when it finishes. `spawn()` parks the calling coroutine while all permits are taken,
+/// which throttles a producer spawning coroutines faster than they finish.
+/// Servers can use `try_spawn()` to reject work instead, e.g. by closing a connection.
+/// Clones share the same limit.
+#[derive(Clone)]
+pub struct BoundedSpawner {
+    state: Arc<SpawnerState>,
+}
+
+impl BoundedSpawner {
+    /// Creates a spawner allowing at most `limit` coroutines to run at the same time
+    pub fn new(limit: usize) -> BoundedSpawner {
+        assert!(limit >= 1, "Must allow at least one coroutine");
+
+        BoundedSpawner {
+            state: Arc::new(SpawnerState {
+                semaphore: Semaphore::new(limit),
+                running: AtomicUsize::new(0),
+                limit: limit,
+            }),
+        }
+    }
+
+    /// Spawns a coroutine with default options, waiting until one of the running ones finished
+    /// if the limit is reached
+    pub fn spawn<F, T>(&self, f: F) -> JoinHandle<T>
+        where F: FnOnce() -> T + Send + 'static,
+              T: Send + 'static
+    {
+        let opts = Scheduler::instance().unwrap().default_spawn_options();
+        self.spawn_opts(f, opts)
+    }
+
+    /// Spawns a coroutine with options, waiting until one of the running ones finished
+    /// if the limit is reached
+    pub fn spawn_opts<F, T>(&self, f: F, opts: Options) -> JoinHandle<T>
+        where F: FnOnce() -> T + Send + 'static,
+              T: Send + 'static
+    {
+        self.state.semaphore.acquire();
+        self.spawn_with_permit(f, opts)
+    }
+
+    /// Spawns a coroutine with default options, unless the limit is reached
+    ///
+    /// Returns the closure if all permits are taken.
+    pub fn try_spawn<F, T>(&self, f: F) -> Result<JoinHandle<T>, F>
+        where F: FnOnce() -> T + Send + 'static,
+              T: Send + 'static
+    {
+        if !self.state.semaphore.try_acquire() {
+            return Err(f);
+        }
+
+        let opts = Scheduler::instance().unwrap().default_spawn_options();
+        Ok(self.spawn_with_permit(f, opts))
+    }
+
+    /// Returns the number of coroutines spawned by this spawner which haven't finished yet
+    pub fn running(&self) -> usize {
+        self.state.running.load(Ordering::SeqCst)
+    }
+
+    /// Returns the maximum number of coroutines running at the same time
+    pub fn limit(&self) -> usize {
+        self.state.limit
+    }
+
+    fn spawn_with_permit<F, T>(&self, f: F, opts: Options) -> JoinHandle<T>
+        where F: FnOnce() -> T + Send + 'static,
+              T: Send + 'static
+    {
+        self.state.running.fetch_add(1, Ordering::SeqCst);
+        let permit = Permit(self.state.clone());
+
+        Scheduler::spawn_opts(move || {
+                                  let _permit = permit;
+                                  f()
+                              },
+                              opts)
+    }
+}
+
+impl fmt::Debug for BoundedSpawner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "BoundedSpawner {{ running: {}, limit: {} }}",
+               self.running(),
+               self.state.limit)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cmp;
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use scheduler::Scheduler;
+    use super::*;
+
+    #[test]
+    fn bounded_spawner_limit() {
+        Scheduler::new()
+            .with_workers(2)
+            .run(|| {
+                let spawner = BoundedSpawner::new(4);
+                let running = Arc::new(AtomicUsize::new(0));
+                let peak = Arc::new(Mutex::new(0));
+
+                let handles: Vec<_> = (0..100)
+                                          .map(|_| {
+                                              let running = running.clone();
+                                              let peak = peak.clone();
+
+                                              spawner.spawn(move || {
+                                                  let n = running.fetch_add(1, Ordering::SeqCst);
+                                                  let mut peak = peak.lock().unwrap();
+                                                  *peak = cmp::max(*peak, n + 1);
+                                                  drop(peak);
+
+                                                  Scheduler::sched();
+                                                  running.fetch_sub(1, Ordering::SeqCst);
+                                              })
+                                          })
+                                          .collect();
+
+                assert!(spawner.running() <= 4);
+
+                for h in handles {
+                    h.join().unwrap();
+                }
+
+                assert!(*peak.lock().unwrap() <= 4);
+                assert_eq!(spawner.running(), 0);
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn bounded_spawner_try_spawn() {
+        Scheduler::new()
+            .run(|| {
+                let spawner = BoundedSpawner::new(1);
+
+                let first = spawner.try_spawn(|| Scheduler::sched()).ok().unwrap();
+                assert!(spawner.try_spawn(|| ()).is_err());
+
+                // A panicking coroutine returns it's permit as well
+                first.join().unwrap();
+                assert!(spawner.try_spawn(|| panic!("boom")).ok().unwrap().join().is_err());
+                assert!(spawner.try_spawn(|| ()).is_ok());
+            })
+            .unwrap();
+    }
+}
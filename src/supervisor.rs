@@ -0,0 +1,142 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Coroutines which are restarted when they panic
+
+use std::cmp;
+use std::panic;
+use std::time::Duration;
+
+use coroutine::ForceUnwind;
+use scheduler::{JoinHandle, Scheduler};
+
+/// Determines how often and how fast `Scheduler::spawn_supervised()` restarts a coroutine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestartPolicy {
+    max_restarts: usize,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl RestartPolicy {
+    /// Restart a panicked coroutine up to `max_restarts` times
+    ///
+    /// By default the first restart is delayed by 10ms, which is doubled for every further one,
+    /// up to a maximum of 1s.
+    pub fn new(max_restarts: usize) -> RestartPolicy {
+        RestartPolicy {
+            max_restarts: max_restarts,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(1),
+        }
+    }
+
+    /// Set the delay before the first restart and the maximum it is doubled up to
+    ///
+    /// A zero `initial` delay restarts coroutines immediately.
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> RestartPolicy {
+        assert!(initial <= max, "The initial backoff must not exceed the maximum");
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Returns the maximum number of restarts
+    pub fn max_restarts(&self) -> usize {
+        self.max_restarts
+    }
+}
+
+// Runs inside of the supervising coroutine spawned by Scheduler::spawn_supervised()
+#[doc(hidden)]
+pub fn supervise<M, F, T>(policy: RestartPolicy, make_fn: M) -> T
+    where M: Fn() -> F + Send + 'static,
+          F: FnOnce() -> T + Send + 'static,
+          T: Send + 'static
+{
+    let mut restarts = 0;
+    let mut backoff = policy.initial_backoff;
+
+    loop {
+        let err = match Scheduler::spawn(make_fn()).join() {
+            Ok(ret) => return ret,
+            Err(err) => err,
+        };
+
+        // Attempts unwound because of a shutdown aren't restarted
+        if restarts >= policy.max_restarts || err.is::<ForceUnwind>() {
+            panic::resume_unwind(err);
+        }
+
+        restarts += 1;
+        warn!("Supervised coroutine panicked => restarting ({}/{})",
+              restarts,
+              policy.max_restarts);
+
+        if backoff > Duration::new(0, 0) {
+            ::sleep(backoff);
+            backoff = cmp::min(backoff * 2, policy.max_backoff);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use scheduler::Scheduler;
+    use super::RestartPolicy;
+
+    #[test]
+    fn test_spawn_supervised() {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let failures = Arc::new(AtomicUsize::new(0));
+        let cloned_attempts = attempts.clone();
+        let cloned_failures = failures.clone();
+
+        Scheduler::new()
+            .with_panic_handler(move |_, err| {
+                // Receives the panic of every attempt plus the supervisor's final one
+                if err.downcast_ref::<&str>() == Some(&"always") {
+                    cloned_failures.fetch_add(1, Ordering::SeqCst);
+                }
+            })
+            .run(move || {
+                let policy = RestartPolicy::new(3)
+                                 .with_backoff(Duration::from_millis(1), Duration::from_millis(4));
+
+                // Succeeds on the third attempt
+                let h = Scheduler::spawn_supervised(policy, move || {
+                    let attempts = cloned_attempts.clone();
+
+                    move || {
+                        if attempts.fetch_add(1, Ordering::SeqCst) < 2 {
+                            panic!("flaky");
+                        }
+
+                        7
+                    }
+                });
+                assert_eq!(h.join().unwrap(), 7);
+
+                // Gives up after the initial attempt and two restarts
+                let policy = RestartPolicy::new(2).with_backoff(Duration::new(0, 0),
+                                                                Duration::new(0, 0));
+                let h = Scheduler::spawn_supervised(policy, || || -> () { panic!("always") });
+                let err = h.join().unwrap_err();
+                assert_eq!(err.downcast_ref::<&str>(), Some(&"always"));
+            })
+            .unwrap();
+
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        // Three attempts plus the supervisor itself
+        assert_eq!(failures.load(Ordering::SeqCst), 4);
+    }
+}
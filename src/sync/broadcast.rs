@@ -0,0 +1,416 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Multi-producer, multi-consumer broadcast queue communication primitives.
+//!
+//! Every `Receiver` sees every value sent after it subscribed, e.g. config updates published to
+//! many coroutines. The values are kept in a bounded ring buffer and sending never parks:
+//! Once the buffer is full the oldest value is overwritten, even if some receivers haven't seen
+//! it yet. Those receivers skip the values they missed and are told how many with `Lagged`.
+
+pub use std::sync::mpsc::SendError;
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::mem;
+use std::sync::Arc;
+use std::thread;
+
+use coroutine::HandleList;
+use runtime::Processor;
+use scheduler::Scheduler;
+
+use super::spinlock::Spinlock;
+
+/// Returned by `Receiver::recv()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvError {
+    /// The receiver fell behind and the given number of values were overwritten before it
+    /// received them. The next call continues with the oldest value still buffered.
+    Lagged(u64),
+    /// All senders were dropped and the receiver has seen all values
+    Disconnected,
+}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RecvError::Lagged(n) => write!(f, "receiver lagged behind by {} values", n),
+            RecvError::Disconnected => "receiving on a closed channel".fmt(f),
+        }
+    }
+}
+
+impl Error for RecvError {
+    fn description(&self) -> &str {
+        match *self {
+            RecvError::Lagged(..) => "receiver lagged behind",
+            RecvError::Disconnected => "receiving on a closed channel",
+        }
+    }
+}
+
+/// Returned by `Receiver::try_recv()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    /// No new value was sent since the last one received
+    Empty,
+    /// See `RecvError::Lagged`
+    Lagged(u64),
+    /// See `RecvError::Disconnected`
+    Disconnected,
+}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TryRecvError::Empty => "receiving on an empty channel".fmt(f),
+            TryRecvError::Lagged(n) => write!(f, "receiver lagged behind by {} values", n),
+            TryRecvError::Disconnected => "receiving on a closed channel".fmt(f),
+        }
+    }
+}
+
+impl Error for TryRecvError {
+    fn description(&self) -> &str {
+        match *self {
+            TryRecvError::Empty => "receiving on an empty channel",
+            TryRecvError::Lagged(..) => "receiver lagged behind",
+            TryRecvError::Disconnected => "receiving on a closed channel",
+        }
+    }
+}
+
+struct Inner<T> {
+    buffer: VecDeque<T>,
+    capacity: usize,
+
+    // Position of the first value in the buffer, counted from the first value ever sent
+    head: u64,
+
+    sender_count: usize,
+    receiver_count: usize,
+
+    // Receivers waiting for the next value
+    recv_wait_list: HandleList,
+}
+
+impl<T> Inner<T> {
+    // Position of the next value to be sent
+    fn tail(&self) -> u64 {
+        self.head + self.buffer.len() as u64
+    }
+
+    // Takes the waiting receivers, which are readied with notify() once the lock is released
+    fn take_receivers(&mut self) -> HandleList {
+        mem::replace(&mut self.recv_wait_list, HandleList::new())
+    }
+}
+
+// Outside of a Processor Scheduler::ready() resumes the receivers right here,
+// which lock the channel again in recv()
+fn notify(receivers: HandleList) {
+    for coro in receivers {
+        trace!("{:?} is waken up in broadcast::Sender recv_wait_list", coro);
+        Scheduler::ready(coro);
+    }
+}
+
+type Shared<T> = Arc<Spinlock<Inner<T>>>;
+
+pub struct Sender<T> {
+    inner: Shared<T>,
+}
+
+unsafe impl<T: Send> Send for Sender<T> {}
+unsafe impl<T: Send> Sync for Sender<T> {}
+
+impl<T> Sender<T> {
+    /// Send a value to all receivers, overwriting the oldest value if the buffer is full
+    ///
+    /// Never parks. Returns the number of receivers or `SendError` if there are none,
+    /// in which case the value isn't buffered.
+    pub fn send(&self, t: T) -> Result<usize, SendError<T>> {
+        let (receivers, count) = {
+            let mut inner = self.inner.lock();
+
+            if inner.receiver_count == 0 {
+                return Err(SendError(t));
+            }
+
+            if inner.buffer.len() >= inner.capacity {
+                inner.buffer.pop_front();
+                inner.head += 1;
+            }
+
+            inner.buffer.push_back(t);
+            (inner.take_receivers(), inner.receiver_count)
+        };
+
+        notify(receivers);
+        Ok(count)
+    }
+
+    /// Creates a new receiver, which sees all values sent from now on
+    pub fn subscribe(&self) -> Receiver<T> {
+        let mut inner = self.inner.lock();
+        inner.receiver_count += 1;
+
+        Receiver {
+            inner: self.inner.clone(),
+            next: inner.tail(),
+        }
+    }
+
+    /// Returns the number of receivers
+    pub fn receiver_count(&self) -> usize {
+        self.inner.lock().receiver_count
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        self.inner.lock().sender_count += 1;
+        Sender { inner: self.inner.clone() }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let receivers = {
+            let mut inner = self.inner.lock();
+            inner.sender_count -= 1;
+
+            // The receivers won't get any more values if this is the last Sender
+            if inner.sender_count == 0 {
+                inner.take_receivers()
+            } else {
+                HandleList::new()
+            }
+        };
+
+        notify(receivers);
+    }
+}
+
+pub struct Receiver<T> {
+    inner: Shared<T>,
+    // Position of the next value to receive
+    next: u64,
+}
+
+unsafe impl<T: Send> Send for Receiver<T> {}
+unsafe impl<T: Send> Sync for Receiver<T> {}
+
+impl<T: Clone> Receiver<T> {
+    /// Receive the next value if one was sent already
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let inner = self.inner.lock();
+        try_recv_at(&mut self.next, &inner)
+    }
+
+    /// Receive the next value, parking the current coroutine until one was sent.
+    pub fn recv(&mut self) -> Result<T, RecvError> {
+        loop {
+            let mut inner = self.inner.lock();
+
+            match try_recv_at(&mut self.next, &inner) {
+                Ok(t) => return Ok(t),
+                Err(TryRecvError::Lagged(n)) => return Err(RecvError::Lagged(n)),
+                Err(TryRecvError::Disconnected) => return Err(RecvError::Disconnected),
+                Err(TryRecvError::Empty) => {}
+            }
+
+            match Processor::current() {
+                Some(p) => {
+                    p.park_with(|_, coro| {
+                        inner.recv_wait_list.push_back(coro);
+                        drop(inner); // We _must_ to hold the lock until here
+                    });
+                }
+                None => {
+                    // Path for normal thread environment
+                    drop(inner);
+                    thread::yield_now();
+                }
+            }
+        }
+    }
+}
+
+// Receives the value at `next`, which is advanced past it
+fn try_recv_at<T: Clone>(next: &mut u64, inner: &Inner<T>) -> Result<T, TryRecvError> {
+    if *next < inner.head {
+        let lagged = inner.head - *next;
+        *next = inner.head;
+        return Err(TryRecvError::Lagged(lagged));
+    }
+
+    if *next < inner.tail() {
+        let t = inner.buffer[(*next - inner.head) as usize].clone();
+        *next += 1;
+        return Ok(t);
+    }
+
+    if inner.sender_count == 0 {
+        Err(TryRecvError::Disconnected)
+    } else {
+        Err(TryRecvError::Empty)
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    /// The clone continues at the same position as the original
+    fn clone(&self) -> Receiver<T> {
+        self.inner.lock().receiver_count += 1;
+
+        Receiver {
+            inner: self.inner.clone(),
+            next: self.next,
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.inner.lock().receiver_count -= 1;
+    }
+}
+
+/// Create a channel pair buffering the last `capacity` values
+///
+/// More receivers are created with `Sender::subscribe()`.
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "broadcast::channel requires a capacity of at least 1");
+
+    let inner = Arc::new(Spinlock::new(Inner {
+        buffer: VecDeque::with_capacity(capacity),
+        capacity: capacity,
+
+        head: 0,
+
+        sender_count: 1,
+        receiver_count: 1,
+
+        recv_wait_list: HandleList::new(),
+    }));
+
+    (Sender { inner: inner.clone() },
+     Receiver {
+        inner: inner,
+        next: 0,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::thread;
+    use std::time::Duration;
+
+    use scheduler::Scheduler;
+
+    #[test]
+    fn test_broadcast_basic() {
+        let (tx, mut rx1) = channel(4);
+
+        assert_eq!(tx.send(1), Ok(1));
+
+        // Only sees the values sent after it subscribed
+        let mut rx2 = tx.subscribe();
+        assert_eq!(tx.send(2), Ok(2));
+
+        assert_eq!(rx1.try_recv(), Ok(1));
+        assert_eq!(rx1.try_recv(), Ok(2));
+        assert_eq!(rx1.try_recv(), Err(TryRecvError::Empty));
+        assert_eq!(rx2.try_recv(), Ok(2));
+
+        drop(tx);
+        assert_eq!(rx2.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn test_broadcast_lagged() {
+        let (tx, mut rx) = channel(2);
+
+        for i in 0..5 {
+            tx.send(i).unwrap();
+        }
+
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Lagged(3)));
+        assert_eq!(rx.try_recv(), Ok(3));
+        assert_eq!(rx.try_recv(), Ok(4));
+
+        drop(rx);
+        assert_eq!(tx.send(5), Err(SendError(5)));
+    }
+
+    #[test]
+    fn test_broadcast_multi_processors() {
+        Scheduler::new()
+            .with_workers(4)
+            .run(|| {
+                let (tx, rx) = channel(16);
+
+                let handles: Vec<_> = (0..8)
+                                          .map(|_| {
+                                              let mut rx = rx.clone();
+
+                                              Scheduler::spawn(move || {
+                                                  let mut sum = 0;
+
+                                                  while let Ok(i) = rx.recv() {
+                                                      sum += i;
+                                                  }
+
+                                                  sum
+                                              })
+                                          })
+                                          .collect();
+
+                drop(rx);
+
+                // Lets the receivers park in between
+                for i in 0..10 {
+                    tx.send(i).unwrap();
+                    ::sleep_ms(1);
+                }
+
+                drop(tx);
+
+                for h in handles {
+                    assert_eq!(h.join().unwrap(), 45);
+                }
+            })
+            .unwrap();
+    }
+
+    // The Sender sends and is dropped on a thread outside of the Scheduler,
+    // while the receiving coroutine is parked
+    #[test]
+    fn test_broadcast_foreign_sender() {
+        Scheduler::new()
+            .run(|| {
+                let (tx, mut rx) = channel(4);
+
+                let t = thread::spawn(move || {
+                    thread::sleep(Duration::from_millis(10));
+                    tx.send(1).unwrap();
+                    thread::sleep(Duration::from_millis(10));
+                    drop(tx);
+                });
+
+                assert_eq!(rx.recv(), Ok(1));
+                assert_eq!(rx.recv(), Err(RecvError::Disconnected));
+                t.join().unwrap();
+            })
+            .unwrap();
+    }
+}
@@ -4,9 +4,11 @@ use std::mem;
 use std::ptr::Shared;
 use std::time::Duration;
 
+use cancel::CancellationToken;
 use coroutine::{Handle, HandleList};
 use runtime::processor::Processor;
 use runtime::timer::Timeout;
+use scheduler::Scheduler;
 use sync::spinlock::Spinlock;
 
 #[repr(u8)]
@@ -15,6 +17,7 @@ pub enum WaiterState {
     Empty,
     Succeeded,
     Timeout,
+    Cancelled,
     Error,
 }
 
@@ -35,6 +38,7 @@ pub struct Waiter {
     // The following fields are owned by Condvar and only accessed when it's lock is acquired:
     prev: Option<Shared<Waiter>>,
     next: Option<Shared<Waiter>>,
+    linked: bool,
 
     // The following fields are shared between  Condvar, Scheduler and Waiter:
     shared: Spinlock<SharedWaiter>,
@@ -45,6 +49,7 @@ impl Waiter {
         Waiter {
             prev: None,
             next: None,
+            linked: false,
 
             shared: Spinlock::new(SharedWaiter {
                 handle: None,
@@ -77,7 +82,7 @@ impl Waiter {
     pub fn try_wait(&self, coro: Handle) -> Option<Handle> {
         let mut shared = self.shared.lock();
 
-        match mem::replace(&mut shared.state, WaiterState::Empty) {
+        match shared.state {
             WaiterState::Empty => {
                 shared.handle = Some(coro);
                 None
@@ -102,6 +107,8 @@ struct WaiterList {
 
 impl WaiterList {
     fn push_back(&mut self, waiter: &mut Waiter) {
+        waiter.linked = true;
+
         match self.tail {
             None => {
                 // Since head is None the list must be empty => set head and tail to hdl
@@ -124,6 +131,8 @@ impl WaiterList {
         match self.head.take() {
             None => None,
             Some(head) => {
+                unsafe { &mut **head }.linked = false;
+
                 match unsafe { &mut **head }.next.take() {
                     None => self.tail = None,
                     Some(next) => {
@@ -138,6 +147,13 @@ impl WaiterList {
     }
 
     fn remove(&mut self, waiter: &mut Waiter) {
+        // The waiter might have been popped by a notification already
+        if !waiter.linked {
+            return;
+        }
+
+        waiter.linked = false;
+
         let prev = waiter.prev.take();
         let next = waiter.next.take();
 
@@ -167,72 +183,87 @@ impl Default for WaiterList {
 }
 
 /// A Condition variable
+///
+/// It isn't tied to a lock and is what the I/O sources wait on. Use `sync::MutexCondvar`
+/// to wait for a condition protected by a `sync::Mutex`.
 pub struct Condvar {
     waiter_list: UnsafeCell<WaiterList>,
-    lock: Spinlock<()>,
+    // Protects waiter_list and contains true if the Condvar was closed
+    lock: Spinlock<bool>,
 }
 
 impl Condvar {
     pub fn new() -> Condvar {
         Condvar {
             waiter_list: Default::default(),
-            lock: Spinlock::new(()),
+            lock: Spinlock::new(false),
         }
     }
 
     pub fn wait(&self) {
-        let guard = self.lock.lock();
-        let p = Processor::current_required();
-        let mut waiter = Waiter::new();
-
-        self.get_waiter_list().push_back(&mut waiter);
-
-        p.park_with(|p, coro| {
-            if let Some(coro) = waiter.try_wait(coro) {
-                p.ready(coro);
-            }
-
-            drop(guard);
-        });
+        self.wait_timeout_opt(None);
     }
 
     pub fn wait_timeout(&self, dur: Duration) -> Result<(), WaitTimeoutResult> {
+        match self.wait_timeout_opt(Some(dur)) {
+            WaiterState::Timeout => Err(WaitTimeoutResult(true)),
+            _ => Ok(()),
+        }
+    }
+
+    // Waits for a notification, the timeout if `dur` is Some or the
+    // cancellation of the current coroutine's CancellationToken.
+    // Returns the reason for waking up.
+    pub fn wait_timeout_opt(&self, dur: Option<Duration>) -> WaiterState {
         let guard = self.lock.lock();
-        let p = Processor::current_required();
+
+        // A closed Condvar never gets notified again
+        if *guard {
+            return WaiterState::Succeeded;
+        }
+
+        let mut p = Processor::current_required();
+        let scheduler = p.scheduler();
         let mut waiter = Waiter::new();
 
+        let token = p.current().and_then(|coro| coro.cancel_token().cloned());
+
         self.get_waiter_list().push_back(&mut waiter);
 
-        let timeout = p.scheduler().timeout(::duration_to_ms(dur), &mut waiter);
-        waiter.set_timeout(timeout);
+        if let Some(dur) = dur {
+            let timeout = scheduler.timeout(::duration_to_ms(dur), &mut waiter);
+            waiter.set_timeout(timeout);
+        }
+
+        if let Some(ref token) = token {
+            token.register(&waiter, scheduler);
+        }
+
+        // Nothing may refer to the waiter anymore once it's gone, even if the coroutine is
+        // force unwound while parked, e.g. by a shutdown
+        let wait_guard = WaitGuard {
+            condvar: self,
+            waiter: &mut waiter,
+            token: token.as_ref(),
+            scheduler: scheduler,
+        };
 
         p.park_with(|p, coro| {
-            if let Some(coro) = waiter.try_wait(coro) {
+            if let Some(coro) = wait_guard.waiter().try_wait(coro) {
                 p.ready(coro);
             }
 
             drop(guard);
         });
 
-        {
-            let _guard = self.lock.lock();
-            self.get_waiter_list().remove(&mut waiter);
-        }
-
-        let p = Processor::current_required();
-
-        match waiter.state() {
-            WaiterState::Empty => panic!("WaiterState is Empty"),
-            WaiterState::Succeeded => {
-                if let Some(timeout) = waiter.take_timeout() {
-                    p.scheduler().cancel_timeout(timeout);
-                }
+        let state = wait_guard.waiter().state();
+        drop(wait_guard);
 
-                Ok(())
-            }
-            WaiterState::Timeout => Err(WaitTimeoutResult(true)),
-            WaiterState::Error => unimplemented!(),
+        if state == WaiterState::Empty {
+            panic!("WaiterState is Empty");
         }
+
+        state
     }
 
     pub fn notify_one(&self, hdl_list: &mut HandleList) {
@@ -251,8 +282,22 @@ impl Condvar {
     }
 
     pub fn notify_all(&self, hdl_list: &mut HandleList) {
+        self.notify_all_imp(hdl_list, false);
+    }
+
+    /// Notifies all waiters and makes all future waits return immediately
+    pub fn close(&self, hdl_list: &mut HandleList) {
+        self.notify_all_imp(hdl_list, true);
+    }
+
+    pub fn is_closed(&self) -> bool {
+        *self.lock.lock()
+    }
+
+    fn notify_all_imp(&self, hdl_list: &mut HandleList, close: bool) {
         let mut lst: WaiterList = {
-            let _guard = self.lock.lock();
+            let mut guard = self.lock.lock();
+            *guard = *guard || close;
             mem::replace(self.get_waiter_list(), Default::default())
         };
 
@@ -294,6 +339,42 @@ impl fmt::Debug for Condvar {
     }
 }
 
+// Removes a Waiter from the Condvar, the CancellationToken and the timer once the wait is over
+struct WaitGuard<'a> {
+    condvar: &'a Condvar,
+    waiter: *mut Waiter,
+    token: Option<&'a CancellationToken>,
+    scheduler: &'a Scheduler,
+}
+
+impl<'a> WaitGuard<'a> {
+    fn waiter(&self) -> &Waiter {
+        unsafe { &*self.waiter }
+    }
+}
+
+impl<'a> Drop for WaitGuard<'a> {
+    fn drop(&mut self) {
+        let waiter = unsafe { &mut *self.waiter };
+
+        if let Some(token) = self.token {
+            token.unregister(waiter);
+        }
+
+        {
+            let _guard = self.condvar.lock.lock();
+            self.condvar.get_waiter_list().remove(waiter);
+        }
+
+        // A timeout which fired already is gone
+        if waiter.state() != WaiterState::Timeout {
+            if let Some(timeout) = waiter.take_timeout() {
+                self.scheduler.cancel_timeout(timeout);
+            }
+        }
+    }
+}
+
 pub struct WaitTimeoutResult(bool);
 
 impl WaitTimeoutResult {
@@ -8,16 +8,25 @@
 
 //! Coroutine synchronization
 
+pub mod broadcast;
 pub mod condvar;
 pub mod mono_barrier;
+pub mod mpmc;
 pub mod mpsc;
 pub mod mutex;
+pub mod oneshot;
+pub mod rwlock;
 pub mod semaphore;
 pub mod spinlock;
+pub mod spsc;
+pub mod wait_group;
 
 pub use self::condvar::Condvar;
 pub use self::spinlock::{Spinlock, TicketSpinlock};
-pub use self::mutex::Mutex;
+pub use self::mutex::{Mutex, MutexCondvar};
+pub use self::rwlock::RwLock;
+pub use self::semaphore::Semaphore;
+pub use self::wait_group::WaitGroup;
 
 use std::sync;
 
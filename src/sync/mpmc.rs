@@ -0,0 +1,448 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Multi-producer, multi-consumer bounded FIFO queue communication primitives.
+
+pub use std::sync::mpsc::{TrySendError, SendError, TryRecvError, RecvError, RecvTimeoutError};
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use coroutine::HandleList;
+use runtime::Processor;
+use scheduler::Scheduler;
+use select::{self, Select, SelectOp};
+
+use super::spinlock::Spinlock;
+
+struct Inner<T> {
+    buffer: VecDeque<T>,
+    capacity: usize,
+
+    sender_count: usize,
+    receiver_count: usize,
+
+    // Coroutines waiting for the buffer to have room or items, oldest first
+    send_wait_list: HandleList,
+    recv_wait_list: HandleList,
+
+    // Selects waiting for items
+    recv_selects: Vec<Select>,
+}
+
+impl<T> Inner<T> {
+    // Called after an item was pushed into the buffer
+    fn notify_receiver(&mut self) {
+        if let Some(coro) = self.recv_wait_list.pop_front() {
+            trace!("{:?} is waken up in mpmc::Sender recv_wait_list", coro);
+            Scheduler::ready(coro);
+        }
+
+        select::notify_all(&self.recv_selects, Scheduler::ready);
+    }
+}
+
+type Shared<T> = Arc<Spinlock<Inner<T>>>;
+
+pub struct Sender<T> {
+    inner: Shared<T>,
+}
+
+unsafe impl<T: Send> Send for Sender<T> {}
+unsafe impl<T: Send> Sync for Sender<T> {}
+
+impl<T> Sender<T> {
+    pub fn try_send(&self, t: T) -> Result<(), TrySendError<T>> {
+        let mut inner = self.inner.lock();
+
+        if inner.receiver_count == 0 {
+            return Err(TrySendError::Disconnected(t));
+        }
+
+        if inner.buffer.len() >= inner.capacity {
+            return Err(TrySendError::Full(t));
+        }
+
+        inner.buffer.push_back(t);
+
+        inner.notify_receiver();
+
+        Ok(())
+    }
+
+    /// Send a value, parking the current coroutine while the buffer is full.
+    pub fn send(&self, mut t: T) -> Result<(), SendError<T>> {
+        loop {
+            let mut inner = self.inner.lock();
+
+            if inner.receiver_count == 0 {
+                return Err(SendError(t));
+            }
+
+            if inner.buffer.len() < inner.capacity {
+                inner.buffer.push_back(t);
+
+                inner.notify_receiver();
+
+                return Ok(());
+            }
+
+            match Processor::current() {
+                Some(p) => {
+                    p.park_with(|_, coro| {
+                        inner.send_wait_list.push_back(coro);
+                        drop(inner); // We _must_ to hold the lock until here
+                    });
+                }
+                None => {
+                    // Path for normal thread environment
+                    drop(inner);
+                    thread::yield_now();
+                }
+            }
+        }
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        self.inner.lock().sender_count += 1;
+        Sender { inner: self.inner.clone() }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut inner = self.inner.lock();
+        inner.sender_count -= 1;
+
+        // The receivers won't get any more items if this is the last Sender
+        if inner.sender_count == 0 {
+            while let Some(hdl) = inner.recv_wait_list.pop_front() {
+                trace!("{:?} is awaken by dropping mpmc::Sender in recv_wait_list",
+                       hdl);
+                Scheduler::ready(hdl);
+            }
+
+            select::notify_all(&inner.recv_selects, Scheduler::ready);
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    inner: Shared<T>,
+}
+
+unsafe impl<T: Send> Send for Receiver<T> {}
+unsafe impl<T: Send> Sync for Receiver<T> {}
+
+impl<T> Receiver<T> {
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut inner = self.inner.lock();
+
+        match inner.buffer.pop_front() {
+            Some(t) => {
+                if let Some(coro) = inner.send_wait_list.pop_front() {
+                    trace!("{:?} is waken up in mpmc::Receiver send_wait_list", coro);
+                    Scheduler::ready(coro);
+                }
+
+                Ok(t)
+            }
+            None if inner.sender_count == 0 => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    /// Returns a `select!` operation which completes as soon as an item was received
+    ///
+    /// It's result is the same as the one of `recv()`.
+    pub fn select_recv(&self) -> RecvOp<T> {
+        RecvOp {
+            receiver: self,
+            result: None,
+        }
+    }
+
+    /// Receive a value, parking the current coroutine for at most `timeout` while the
+    /// buffer is empty.
+    ///
+    /// The parked coroutine is readied again by the timer. An item arriving at the same time
+    /// is preferred over the timeout. Under `Scheduler::with_deadline()` the timeout is capped
+    /// to the deadline. Outside of a coroutine the thread yields until either happens.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let mut deadline = Instant::now() + timeout;
+
+        if let Some(current) = Scheduler::current_deadline() {
+            if current < deadline {
+                deadline = current;
+            }
+        }
+
+        if Processor::current().is_none() {
+            loop {
+                match self.try_recv() {
+                    Ok(t) => return Ok(t),
+                    Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                    Err(TryRecvError::Empty) => {}
+                }
+
+                if Instant::now() >= deadline {
+                    return Err(RecvTimeoutError::Timeout);
+                }
+
+                thread::yield_now();
+            }
+        }
+
+        // The item is attempted first, which is what makes it win against the timeout
+        select! {
+            r = self.select_recv() => r.map_err(|_| RecvTimeoutError::Disconnected),
+            _ = select::timeout_at(deadline) => Err(RecvTimeoutError::Timeout),
+        }
+    }
+
+    /// Receive a value, parking the current coroutine while the buffer is empty.
+    ///
+    /// Under `Scheduler::with_deadline()` it fails with `RecvError` once the deadline passed.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        if let Some(deadline) = Scheduler::current_deadline() {
+            return select! {
+                r = self.select_recv() => r,
+                _ = select::timeout_at(deadline) => Err(RecvError),
+            };
+        }
+
+        loop {
+            let mut inner = self.inner.lock();
+
+            if let Some(t) = inner.buffer.pop_front() {
+                if let Some(coro) = inner.send_wait_list.pop_front() {
+                    trace!("{:?} is waken up in mpmc::Receiver send_wait_list", coro);
+                    Scheduler::ready(coro);
+                }
+
+                return Ok(t);
+            }
+
+            if inner.sender_count == 0 {
+                return Err(RecvError);
+            }
+
+            match Processor::current() {
+                Some(p) => {
+                    p.park_with(|_, coro| {
+                        inner.recv_wait_list.push_back(coro);
+                        drop(inner); // We _must_ to hold the lock until here
+                    });
+                }
+                None => {
+                    // Path for normal thread environment
+                    drop(inner);
+                    thread::yield_now();
+                }
+            }
+        }
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Receiver<T> {
+        self.inner.lock().receiver_count += 1;
+        Receiver { inner: self.inner.clone() }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut inner = self.inner.lock();
+        inner.receiver_count -= 1;
+
+        // Nobody will make room in the buffer anymore if this is the last Receiver
+        if inner.receiver_count == 0 {
+            while let Some(hdl) = inner.send_wait_list.pop_front() {
+                trace!("{:?} is awaken by dropping mpmc::Receiver in send_wait_list",
+                       hdl);
+                Scheduler::ready(hdl);
+            }
+        }
+    }
+}
+
+/// Created by `Receiver::select_recv()`
+pub struct RecvOp<'a, T: 'a> {
+    receiver: &'a Receiver<T>,
+    result: Option<Result<T, RecvError>>,
+}
+
+impl<'a, T: 'a> RecvOp<'a, T> {
+    pub fn take(&mut self) -> Result<T, RecvError> {
+        self.result.take().expect("RecvOp didn't complete yet")
+    }
+}
+
+impl<'a, T: 'a> SelectOp for RecvOp<'a, T> {
+    fn try_complete(&mut self) -> bool {
+        self.result = match self.receiver.try_recv() {
+            Ok(t) => Some(Ok(t)),
+            Err(TryRecvError::Disconnected) => Some(Err(RecvError)),
+            Err(TryRecvError::Empty) => None,
+        };
+
+        self.result.is_some()
+    }
+
+    fn is_completed(&self) -> bool {
+        self.result.is_some()
+    }
+
+    fn register(&mut self, select: &Select) {
+        self.receiver.inner.lock().recv_selects.push(select.clone());
+    }
+
+    fn unregister(&mut self, select: &Select) {
+        select::remove(&mut self.receiver.inner.lock().recv_selects, select);
+    }
+}
+
+/// Create a channel pair with a buffer for `capacity` items
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "mpmc::channel requires a capacity of at least 1");
+
+    let inner = Arc::new(Spinlock::new(Inner {
+        buffer: VecDeque::with_capacity(capacity),
+        capacity: capacity,
+
+        sender_count: 1,
+        receiver_count: 1,
+
+        send_wait_list: HandleList::new(),
+        recv_wait_list: HandleList::new(),
+
+        recv_selects: Vec::new(),
+    }));
+
+    (Sender { inner: inner.clone() }, Receiver { inner: inner })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::time::Duration;
+
+    use scheduler::Scheduler;
+
+    #[test]
+    fn test_mpmc_basic() {
+        Scheduler::new()
+            .run(|| {
+                let (tx, rx) = channel(1);
+
+                let h = Scheduler::spawn(move || {
+                    for i in 0..10 {
+                        tx.send(i).unwrap();
+                    }
+                });
+
+                for i in 0..10 {
+                    assert_eq!(rx.recv(), Ok(i));
+                }
+
+                h.join().unwrap();
+                assert_eq!(rx.recv(), Err(RecvError));
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_mpmc_recv_timeout() {
+        Scheduler::new()
+            .with_timer_resolution(Duration::from_millis(10))
+            .run(|| {
+                let (tx, rx) = channel(1);
+
+                assert_eq!(rx.recv_timeout(Duration::from_millis(20)),
+                           Err(RecvTimeoutError::Timeout));
+
+                let h = Scheduler::spawn(move || {
+                    ::sleep_ms(10);
+                    tx.send(1).unwrap();
+                });
+
+                assert_eq!(rx.recv_timeout(Duration::from_secs(10)), Ok(1));
+                h.join().unwrap();
+                assert_eq!(rx.recv_timeout(Duration::from_secs(10)),
+                           Err(RecvTimeoutError::Disconnected));
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_mpmc_try_send_full() {
+        Scheduler::new()
+            .run(|| {
+                let (tx, rx) = channel(1);
+
+                assert_eq!(tx.try_send(1), Ok(()));
+                assert_eq!(tx.try_send(2), Err(TrySendError::Full(2)));
+                assert_eq!(rx.try_recv(), Ok(1));
+                assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+
+                drop(rx);
+                assert_eq!(tx.try_send(3), Err(TrySendError::Disconnected(3)));
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_mpmc_multi_processors() {
+        Scheduler::new()
+            .with_workers(4)
+            .run(|| {
+                let (tx, rx) = channel(4);
+
+                let senders: Vec<_> = (0..4)
+                                          .map(|_| {
+                                              let tx = tx.clone();
+                                              Scheduler::spawn(move || {
+                                                  for i in 0..1000 {
+                                                      tx.send(i).unwrap();
+                                                  }
+                                              })
+                                          })
+                                          .collect();
+
+                let receivers: Vec<_> = (0..4)
+                                            .map(|_| {
+                                                let rx = rx.clone();
+                                                Scheduler::spawn(move || {
+                                                    let mut sum = 0;
+                                                    while let Ok(i) = rx.recv() {
+                                                        sum += i;
+                                                    }
+                                                    sum
+                                                })
+                                            })
+                                            .collect();
+
+                drop(tx);
+                drop(rx);
+
+                for h in senders {
+                    h.join().unwrap();
+                }
+
+                let sum = receivers.into_iter().fold(0, |acc, h| acc + h.join().unwrap());
+                assert_eq!(sum, 4 * (0..1000).fold(0, |acc, i| acc + i));
+            })
+            .unwrap();
+    }
+}
@@ -16,12 +16,14 @@ use std::sync::{Arc, Mutex};
 use coroutine::HandleList;
 use runtime::Processor;
 use scheduler::Scheduler;
+use select::{self, Select, SelectOp};
 
 #[derive(Clone)]
 pub struct Sender<T> {
     inner: Option<mpsc::Sender<T>>,
 
     wait_list: Arc<Mutex<HandleList>>,
+    select_list: Arc<Mutex<Vec<Select>>>,
 }
 
 unsafe impl<T: Send> Send for Sender<T> {}
@@ -30,10 +32,14 @@ impl<T> Sender<T> {
     pub fn send(&self, t: T) -> Result<(), SendError<T>> {
         match self.inner.as_ref().unwrap().send(t) {
             Ok(..) => {
-                let mut wait_list = self.wait_list.lock().unwrap();
-                if let Some(coro) = wait_list.pop_front() {
-                    Scheduler::ready(coro);
+                {
+                    let mut wait_list = self.wait_list.lock().unwrap();
+                    if let Some(coro) = wait_list.pop_front() {
+                        Scheduler::ready(coro);
+                    }
                 }
+
+                select::notify_all(&self.select_list.lock().unwrap(), Scheduler::ready);
                 Ok(())
             }
             Err(err) => Err(err),
@@ -56,6 +62,8 @@ impl<T> Drop for Sender<T> {
                 trace!("{:?} is awaken by dropping Sender in wait_list", hdl);
                 Scheduler::ready(hdl);
             }
+
+            select::notify_all(&self.select_list.lock().unwrap(), Scheduler::ready);
         }
     }
 }
@@ -64,6 +72,7 @@ pub struct Receiver<T> {
     inner: mpsc::Receiver<T>,
 
     wait_list: Arc<Mutex<HandleList>>,
+    select_list: Arc<Mutex<Vec<Select>>>,
 }
 
 unsafe impl<T: Send> Send for Receiver<T> {}
@@ -73,7 +82,27 @@ impl<T> Receiver<T> {
         self.inner.try_recv()
     }
 
+    /// Returns a `select!` operation which completes as soon as an item was received
+    ///
+    /// It's result is the same as the one of `recv()`.
+    pub fn select_recv(&self) -> RecvOp<T> {
+        RecvOp {
+            receiver: self,
+            result: None,
+        }
+    }
+
+    /// Receives an item, parking the current coroutine until one is available
+    ///
+    /// Under `Scheduler::with_deadline()` it fails with `RecvError` once the deadline passed.
     pub fn recv(&self) -> Result<T, RecvError> {
+        if let Some(deadline) = Scheduler::current_deadline() {
+            return select! {
+                r = self.select_recv() => r,
+                _ = select::timeout_at(deadline) => Err(RecvError),
+            };
+        }
+
         while let Some(processor) = Processor::current() {
             // 1. Try to receive first
             let mut r = self.try_recv();
@@ -117,19 +146,58 @@ impl<T> Receiver<T> {
     }
 }
 
+/// Created by `Receiver::select_recv()`
+pub struct RecvOp<'a, T: 'a> {
+    receiver: &'a Receiver<T>,
+    result: Option<Result<T, RecvError>>,
+}
+
+impl<'a, T: 'a> RecvOp<'a, T> {
+    pub fn take(&mut self) -> Result<T, RecvError> {
+        self.result.take().expect("RecvOp didn't complete yet")
+    }
+}
+
+impl<'a, T: 'a> SelectOp for RecvOp<'a, T> {
+    fn try_complete(&mut self) -> bool {
+        self.result = match self.receiver.try_recv() {
+            Ok(t) => Some(Ok(t)),
+            Err(TryRecvError::Disconnected) => Some(Err(RecvError)),
+            Err(TryRecvError::Empty) => None,
+        };
+
+        self.result.is_some()
+    }
+
+    fn is_completed(&self) -> bool {
+        self.result.is_some()
+    }
+
+    fn register(&mut self, select: &Select) {
+        self.receiver.select_list.lock().unwrap().push(select.clone());
+    }
+
+    fn unregister(&mut self, select: &Select) {
+        select::remove(&mut self.receiver.select_list.lock().unwrap(), select);
+    }
+}
+
 /// Create a channel pair
 pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
     let (tx, rx) = mpsc::channel();
     let wait_list = Arc::new(Mutex::new(HandleList::new()));
+    let select_list = Arc::new(Mutex::new(Vec::new()));
 
     let sender = Sender {
         inner: Some(tx),
         wait_list: wait_list.clone(),
+        select_list: select_list.clone(),
     };
 
     let receiver = Receiver {
         inner: rx,
         wait_list: wait_list,
+        select_list: select_list,
     };
 
     (sender, receiver)
@@ -164,7 +232,8 @@ impl<T> SyncSender<T> {
     }
 
     pub fn send(&self, mut t: T) -> Result<(), SendError<T>> {
-        while let Some(p) = Processor::current() {
+        // try_send() may wake up a receiver, so the ProcessorHandle is acquired afterwards
+        while Processor::current().is_some() {
             let mut r = self.try_send(t);
 
             match r {
@@ -178,7 +247,7 @@ impl<T> SyncSender<T> {
             r = Ok(());
             {
                 let r_ptr = &mut r;
-                p.park_with(move |p, coro| {
+                Processor::current_required().park_with(move |p, coro| {
                     let mut send_wait_list = self.send_wait_list.lock().unwrap();
                     let r = self.try_send(t);
 
@@ -267,7 +336,8 @@ impl<T> SyncReceiver<T> {
     }
 
     pub fn recv(&self) -> Result<T, RecvError> {
-        while let Some(processor) = Processor::current() {
+        // try_recv() may wake up a sender, so the ProcessorHandle is acquired afterwards
+        while Processor::current().is_some() {
             let mut r = self.try_recv();
 
             match r {
@@ -276,7 +346,7 @@ impl<T> SyncReceiver<T> {
                 Err(TryRecvError::Disconnected) => return Err(RecvError),
             }
 
-            processor.park_with(|p, coro| {
+            Processor::current_required().park_with(|p, coro| {
                 let mut recv_wait_list = self.recv_wait_list.lock().unwrap();
 
                 r = self.try_recv();
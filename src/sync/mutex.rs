@@ -9,17 +9,37 @@
 use std::cell::UnsafeCell;
 use std::fmt;
 use std::error::Error;
-use std::marker::Reflect;
+use std::marker::{PhantomData, Reflect};
+use std::mem;
 use std::ops::{Deref, DerefMut};
 
+use coroutine::{Coroutine, HandleList};
+use options::Priority;
+use runtime::Processor;
+use scheduler::Scheduler;
 use sync::semaphore::Semaphore;
+use sync::spinlock::Spinlock;
 
 pub type LockResult<G> = Result<G, PoisonError<G>>;
-pub type TryLockResult<G> = Result<G, PoisonError<G>>;
+pub type TryLockResult<G> = Result<G, TryLockError<G>>;
+
+// The coroutine holding a Mutex
+struct Owner {
+    coro: *const Coroutine,
+}
 
 /// A mutual exclusion primitive useful for protecting shared data
+///
+/// # Priority inheritance
+///
+/// A coroutine which has to wait for the lock raises the priority (see `Options::priority()`)
+/// of the coroutine holding it to it's own, if that one's is lower. Otherwise a low priority
+/// holder could be kept from ever releasing the lock by coroutines with a medium priority,
+/// which would block the high priority waiter as well. The holder gets back the priority it
+/// had before acquiring the lock once it released all of the Mutexes it holds.
 pub struct Mutex<T: ?Sized> {
     sema: Semaphore,
+    owner: Spinlock<Option<Owner>>,
     data: UnsafeCell<T>,
 }
 
@@ -28,6 +48,7 @@ impl<T> Mutex<T> {
     pub fn new(data: T) -> Mutex<T> {
         Mutex {
             sema: Semaphore::new(1),
+            owner: Spinlock::new(None),
             data: UnsafeCell::new(data),
         }
     }
@@ -36,20 +57,84 @@ impl<T> Mutex<T> {
 impl<T: ?Sized> Mutex<T> {
     /// Acquires a mutex, blocking the current thread until it is able to do so.
     pub fn lock(&self) -> LockResult<Guard<T>> {
-        self.sema.acquire();
+        if !self.sema.try_acquire() {
+            self.boost_owner();
+            self.sema.acquire();
+        }
+
+        self.set_owner();
         Ok(Guard::new(unsafe { &mut *self.data.get() }, self))
     }
 
-    /// Try to acquire a mutex, will return immediately
+    /// Attempts to acquire a mutex without parking the current coroutine
+    ///
+    /// Fails with `TryLockError::WouldBlock` if the mutex is currently locked. Unlike `lock()`
+    /// this doesn't raise the priority of the owner, since the caller doesn't wait for it.
     pub fn try_lock(&self) -> TryLockResult<Guard<T>> {
         if self.sema.try_acquire() {
-            Err(PoisonError::new(Guard::new(unsafe { &mut *self.data.get() }, self)))
-        } else {
+            self.set_owner();
             Ok(Guard::new(unsafe { &mut *self.data.get() }, self))
+        } else {
+            Err(TryLockError::WouldBlock)
+        }
+    }
+
+    fn set_owner(&self) {
+        *self.owner.lock() = current_coroutine().map(|coro| {
+            unsafe { (*coro).lock_acquired() };
+            Owner { coro: coro }
+        });
+    }
+
+    // Raises the priority of the owner to the one of the current coroutine, see `Mutex`
+    fn boost_owner(&self) {
+        let priority = match current_coroutine() {
+            Some(coro) => unsafe { (*coro).priority() },
+            None => return,
+        };
+
+        // The owner can't release the lock while we hold this, so it's still alive
+        let owner = self.owner.lock();
+
+        if let Some(ref owner) = *owner {
+            let holder = unsafe { &*owner.coro };
+            let prev = holder.priority();
+
+            // NOTE: Priority::High is the smallest one
+            if priority < prev {
+                trace!("Mutex: raising priority of the owner to {:?}", priority);
+                holder.set_priority(priority);
+
+                if prev == Priority::Low {
+                    if let Some(scheduler) = Scheduler::instance() {
+                        scheduler.requeue_boosted(owner.coro);
+                    }
+                }
+            }
+        }
+    }
+
+    // Restores the priority of the owner, see Coroutine::lock_released()
+    //
+    // The Guard isn't Send, so the owner is still alive, even if it's dropped elsewhere
+    // than in the owner itself, like in MutexCondvar::wait().
+    fn clear_owner(&self) {
+        if let Some(owner) = self.owner.lock().take() {
+            unsafe { (*owner.coro).lock_released() };
         }
     }
 }
 
+fn current_coroutine() -> Option<*const Coroutine> {
+    let mut p = match Processor::current() {
+        Some(p) => p,
+        None => return None,
+    };
+
+    let coro = p.current().map(|coro| &**coro as *const Coroutine);
+    coro
+}
+
 unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
 unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
 
@@ -59,19 +144,25 @@ unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
 pub struct Guard<'a, T: ?Sized + 'a> {
     data: &'a mut T,
     mutex: &'a Mutex<T>,
+    // Not Send, since the priority of the owner is restored when the guard is dropped
+    _marker: PhantomData<*const ()>,
 }
 
+unsafe impl<'a, T: ?Sized + Sync + 'a> Sync for Guard<'a, T> {}
+
 impl<'a, T: ?Sized + 'a> Guard<'a, T> {
     fn new(data: &'a mut T, mutex: &'a Mutex<T>) -> Guard<'a, T> {
         Guard {
             data: data,
             mutex: mutex,
+            _marker: PhantomData,
         }
     }
 }
 
 impl<'a, T: ?Sized + 'a> Drop for Guard<'a, T> {
     fn drop(&mut self) {
+        self.mutex.clear_owner();
         self.mutex.sema.release();
     }
 }
@@ -92,6 +183,75 @@ impl<'a, T: 'a> DerefMut for Guard<'a, T> {
     }
 }
 
+/// A Condition Variable which blocks coroutines waiting for an event, used together with `Mutex`
+///
+/// Just like with `std::sync::Condvar` the condition should be checked in a loop around `wait()`.
+/// Unlike `sync::condvar::Condvar`, which is a bare notifier without a lock used by the I/O
+/// sources, it releases the `Mutex` of the guard while parked.
+pub struct MutexCondvar {
+    wait_list: Spinlock<HandleList>,
+}
+
+impl MutexCondvar {
+    /// Creates a new condition variable
+    pub fn new() -> MutexCondvar {
+        MutexCondvar { wait_list: Spinlock::new(HandleList::new()) }
+    }
+
+    /// Blocks the current coroutine until this condition variable receives a notification.
+    ///
+    /// The mutex of the `guard` is released while the coroutine is parked and
+    /// re-acquired before this method returns.
+    pub fn wait<'a, T: ?Sized>(&self, guard: Guard<'a, T>) -> LockResult<Guard<'a, T>> {
+        let mutex = guard.mutex;
+
+        // Nobody may raise our priority anymore once we're parked
+        mutex.clear_owner();
+
+        {
+            let mut wait_list = self.wait_list.lock();
+
+            Processor::current_required().park_with(|_, coro| {
+                wait_list.push_back(coro);
+
+                // The mutex is released while still holding the lock of the wait list.
+                // A notifier thus either sees us in the list or didn't acquire the mutex yet.
+                drop(guard);
+                drop(wait_list); // We _must_ to hold the lock until here
+            });
+        }
+
+        mutex.lock()
+    }
+
+    /// Wakes up one coroutine blocked on this condition variable, the one which waited the longest.
+    pub fn notify_one(&self) {
+        let hdl = self.wait_list.lock().pop_front();
+
+        if let Some(hdl) = hdl {
+            Scheduler::ready(hdl);
+        }
+    }
+
+    /// Wakes up all coroutines blocked on this condition variable.
+    pub fn notify_all(&self) {
+        let wait_list = mem::replace(&mut *self.wait_list.lock(), HandleList::new());
+
+        for hdl in wait_list {
+            Scheduler::ready(hdl);
+        }
+    }
+}
+
+unsafe impl Send for MutexCondvar {}
+unsafe impl Sync for MutexCondvar {}
+
+impl fmt::Debug for MutexCondvar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MutexCondvar {{ .. }}")
+    }
+}
+
 /// A type of error which can be returned whenever a lock is acquired.
 ///
 /// Currently this error does not act just like the
@@ -139,10 +299,63 @@ impl<T: Send + Reflect> Error for PoisonError<T> {
     }
 }
 
+/// An enumeration of possible errors which can occur while calling the `try_lock()` method
+/// of `Mutex` and the `try_read()` and `try_write()` methods of `RwLock`
+pub enum TryLockError<T> {
+    /// The lock could not be acquired because another coroutine failed while holding it
+    Poisoned(PoisonError<T>),
+    /// The lock could not be acquired at this time, because it would have to park otherwise
+    WouldBlock,
+}
+
+impl<T> From<PoisonError<T>> for TryLockError<T> {
+    fn from(err: PoisonError<T>) -> TryLockError<T> {
+        TryLockError::Poisoned(err)
+    }
+}
+
+impl<T> fmt::Debug for TryLockError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TryLockError::Poisoned(..) => "Poisoned(..)".fmt(f),
+            TryLockError::WouldBlock => "WouldBlock".fmt(f),
+        }
+    }
+}
+
+impl<T> fmt::Display for TryLockError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TryLockError::Poisoned(ref err) => err.fmt(f),
+            TryLockError::WouldBlock => "try_lock failed because the operation would block".fmt(f),
+        }
+    }
+}
+
+impl<T: Send + Reflect> Error for TryLockError<T> {
+    fn description(&self) -> &str {
+        match *self {
+            TryLockError::Poisoned(ref err) => err.description(),
+            TryLockError::WouldBlock => "try_lock failed because the operation would block",
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            TryLockError::Poisoned(ref err) => Some(err),
+            TryLockError::WouldBlock => None,
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use std::collections::VecDeque;
     use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
+    use options::{Options, Priority};
+    use runtime::Processor;
     use scheduler::Scheduler;
 
     use super::*;
@@ -177,4 +390,248 @@ mod test {
 
         assert_eq!(*num.lock().unwrap(), 1000);
     }
+
+    #[test]
+    fn test_mutex_try_lock() {
+        Scheduler::new()
+            .run(|| {
+                let mutex = Arc::new(Mutex::new(0));
+
+                {
+                    let mut guard = mutex.try_lock().unwrap();
+                    *guard += 1;
+
+                    let mutex = mutex.clone();
+                    Scheduler::spawn(move || {
+                        match mutex.try_lock() {
+                            Err(TryLockError::WouldBlock) => {}
+                            ret => panic!("unexpected result {:?}", ret.map(|_| ())),
+                        }
+                    })
+                        .join()
+                        .unwrap();
+                }
+
+                assert_eq!(*mutex.try_lock().unwrap(), 1);
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_condvar_basic() {
+        Scheduler::new()
+            .with_workers(2)
+            .run(|| {
+                let pair = Arc::new((Mutex::new(false), MutexCondvar::new()));
+
+                let cloned = pair.clone();
+                Scheduler::spawn(move || {
+                    let mut guard = cloned.0.lock().unwrap();
+                    *guard = true;
+                    cloned.1.notify_one();
+                });
+
+                let mut guard = pair.0.lock().unwrap();
+                while !*guard {
+                    guard = pair.1.wait(guard).unwrap();
+                }
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_condvar_protected_queue() {
+        Scheduler::new()
+            .with_workers(4)
+            .run(|| {
+                let pair = Arc::new((Mutex::new(VecDeque::new()), MutexCondvar::new()));
+
+                let mut consumers = Vec::with_capacity(10);
+
+                for _ in 0..10 {
+                    let pair = pair.clone();
+                    let consumer = Scheduler::spawn(move || {
+                        let mut queue = pair.0.lock().unwrap();
+                        while queue.is_empty() {
+                            queue = pair.1.wait(queue).unwrap();
+                        }
+
+                        queue.pop_front().unwrap()
+                    });
+                    consumers.push(consumer);
+                }
+
+                let cloned_pair = pair.clone();
+                let producer = Scheduler::spawn(move || {
+                    for i in 0..10 {
+                        let mut queue = cloned_pair.0.lock().unwrap();
+                        queue.push_back(i);
+                        cloned_pair.1.notify_one();
+                        drop(queue);
+                        Scheduler::sched();
+                    }
+                });
+
+                producer.join().unwrap();
+
+                let sum = consumers.into_iter().fold(0, |acc, h| acc + h.join().unwrap());
+                assert_eq!(sum, 45);
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_condvar_notify_all() {
+        Scheduler::new()
+            .with_workers(4)
+            .run(|| {
+                let pair = Arc::new((Mutex::new(false), MutexCondvar::new()));
+
+                let waiters: Vec<_> = (0..10)
+                                          .map(|_| {
+                                              let pair = pair.clone();
+                                              Scheduler::spawn(move || {
+                                                  let mut guard = pair.0.lock().unwrap();
+                                                  while !*guard {
+                                                      guard = pair.1.wait(guard).unwrap();
+                                                  }
+                                              })
+                                          })
+                                          .collect();
+
+                Scheduler::sched();
+
+                *pair.0.lock().unwrap() = true;
+                pair.1.notify_all();
+
+                for h in waiters {
+                    h.join().unwrap();
+                }
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_mutex_priority_inheritance() {
+        const MEDIUM_COUNT: usize = 4;
+        const MEDIUM_YIELDS: usize = 1000;
+
+        Scheduler::single_threaded()
+            .run(|| {
+                let mutex = Arc::new(Mutex::new(()));
+                let locked = Arc::new(AtomicBool::new(false));
+                let progress = Arc::new(AtomicUsize::new(0));
+
+                // A low priority coroutine takes the lock and yields a couple of times
+                let low = {
+                    let mutex = mutex.clone();
+                    let locked = locked.clone();
+                    let mut opts = Options::new();
+                    opts.priority(Priority::Low);
+
+                    Scheduler::spawn_opts(move || {
+                                              let _guard = mutex.lock().unwrap();
+                                              locked.store(true, Ordering::SeqCst);
+
+                                              for _ in 0..10 {
+                                                  Scheduler::sched();
+                                              }
+                                          },
+                                          opts)
+                };
+
+                while !locked.load(Ordering::SeqCst) {
+                    Scheduler::sched();
+                }
+
+                // Medium priority coroutines would starve it until they are done...
+                let mediums: Vec<_> = (0..MEDIUM_COUNT)
+                                          .map(|_| {
+                                              let progress = progress.clone();
+
+                                              Scheduler::spawn(move || {
+                                                  for _ in 0..MEDIUM_YIELDS {
+                                                      progress.fetch_add(1, Ordering::SeqCst);
+                                                      Scheduler::sched();
+                                                  }
+                                              })
+                                          })
+                                          .collect();
+
+                // ...if the high priority coroutine waiting for the lock wouldn't boost it
+                let high = {
+                    let mutex = mutex.clone();
+                    let progress = progress.clone();
+                    let mut opts = Options::new();
+                    opts.priority(Priority::High);
+
+                    Scheduler::spawn_opts(move || {
+                                              let _guard = mutex.lock().unwrap();
+                                              progress.load(Ordering::SeqCst)
+                                          },
+                                          opts)
+                };
+
+                assert!(high.join().unwrap() < MEDIUM_COUNT * MEDIUM_YIELDS);
+                low.join().unwrap();
+
+                for h in mediums {
+                    h.join().unwrap();
+                }
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_mutex_nested_priority_inheritance() {
+        fn current_priority() -> Priority {
+            let mut p = Processor::current_required();
+            let priority = p.current().unwrap().priority();
+            priority
+        }
+
+        Scheduler::single_threaded()
+            .run(|| {
+                let outer = Arc::new(Mutex::new(()));
+                let locked = Arc::new(AtomicBool::new(false));
+
+                let low = {
+                    let outer = outer.clone();
+                    let locked = locked.clone();
+                    let mut opts = Options::new();
+                    opts.priority(Priority::Low);
+
+                    Scheduler::spawn_opts(move || {
+                                              let inner = Mutex::new(());
+                                              let outer_guard = outer.lock().unwrap();
+                                              let inner_guard = inner.lock().unwrap();
+                                              locked.store(true, Ordering::SeqCst);
+
+                                              while current_priority() != Priority::High {
+                                                  Scheduler::sched();
+                                              }
+
+                                              // Still holding the lock the waiter waits for
+                                              drop(inner_guard);
+                                              assert_eq!(current_priority(), Priority::High);
+
+                                              drop(outer_guard);
+                                              assert_eq!(current_priority(), Priority::Low);
+                                          },
+                                          opts)
+                };
+
+                while !locked.load(Ordering::SeqCst) {
+                    Scheduler::sched();
+                }
+
+                let mut opts = Options::new();
+                opts.priority(Priority::High);
+                let high = Scheduler::spawn_opts(move || drop(outer.lock().unwrap()), opts);
+
+                high.join().unwrap();
+                low.join().unwrap();
+            })
+            .unwrap();
+    }
 }
@@ -0,0 +1,317 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Single value handoff communication primitives.
+//!
+//! The `Sender` completes the channel exactly once, waking up the `Receiver` waiting in
+//! `recv()`, e.g. to reply to a request handed to another coroutine. If the `Sender` is dropped
+//! without sending a value `recv()` returns `Canceled` instead. Either side may be used
+//! on any Processor as well as on threads outside of the Scheduler.
+
+use std::error::Error;
+use std::fmt;
+use std::mem;
+use std::sync::{Arc, Condvar, Mutex};
+
+use coroutine::Handle;
+use runtime::Processor;
+use scheduler::Scheduler;
+
+/// Returned by `Receiver::recv()` if the `Sender` was dropped without sending a value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Canceled;
+
+impl fmt::Display for Canceled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.description().fmt(f)
+    }
+}
+
+impl Error for Canceled {
+    fn description(&self) -> &str {
+        "oneshot canceled"
+    }
+}
+
+/// Returned by `Receiver::try_recv()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    /// The `Sender` hasn't sent a value yet
+    Empty,
+    /// See `Canceled`
+    Canceled,
+}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.description().fmt(f)
+    }
+}
+
+impl Error for TryRecvError {
+    fn description(&self) -> &str {
+        match *self {
+            TryRecvError::Empty => "receiving on an empty oneshot",
+            TryRecvError::Canceled => "oneshot canceled",
+        }
+    }
+}
+
+enum Waiter {
+    Nobody,
+    Thread,
+    Coroutine(Handle),
+}
+
+struct State<T> {
+    value: Option<T>,
+    // Set once the Sender sent a value or was dropped
+    complete: bool,
+    receiver_alive: bool,
+    waiter: Waiter,
+}
+
+struct Inner<T> {
+    state: Mutex<State<T>>,
+    cond: Condvar,
+}
+
+impl<T> Inner<T> {
+    fn complete(&self, value: Option<T>) -> Result<(), T> {
+        let waiter = {
+            let mut state = self.state.lock().unwrap();
+
+            // Nothing left to do when dropping the Sender after send()
+            if state.complete {
+                return Ok(());
+            }
+
+            state.complete = true;
+
+            if !state.receiver_alive {
+                return value.map_or(Ok(()), Err);
+            }
+
+            state.value = value;
+
+            match mem::replace(&mut state.waiter, Waiter::Nobody) {
+                Waiter::Thread => {
+                    self.cond.notify_one();
+                    None
+                }
+                Waiter::Coroutine(coro) => Some(coro),
+                Waiter::Nobody => None,
+            }
+        };
+
+        // Outside of a Processor Scheduler::ready() resumes the receiver right here,
+        // which locks the state again in recv()
+        if let Some(coro) = waiter {
+            trace!("{:?} is waken up by oneshot::Sender", coro);
+            Scheduler::ready(coro);
+        }
+
+        Ok(())
+    }
+}
+
+pub struct Sender<T> {
+    inner: Arc<Inner<T>>,
+}
+
+unsafe impl<T: Send> Send for Sender<T> {}
+
+impl<T> Sender<T> {
+    /// Complete the channel with `t`, waking up the receiver
+    ///
+    /// Never parks. Returns `Err(t)` if the `Receiver` was dropped already.
+    pub fn send(self, t: T) -> Result<(), T> {
+        self.inner.complete(Some(t))
+    }
+
+    /// Returns true if the `Receiver` was dropped, i.e. a value sent would be returned
+    pub fn is_canceled(&self) -> bool {
+        !self.inner.state.lock().unwrap().receiver_alive
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let _ = self.inner.complete(None);
+    }
+}
+
+pub struct Receiver<T> {
+    inner: Arc<Inner<T>>,
+}
+
+unsafe impl<T: Send> Send for Receiver<T> {}
+
+impl<T> Receiver<T> {
+    /// Receive the value if the `Sender` completed the channel already
+    ///
+    /// Once the value was received all further calls return `TryRecvError::Canceled`.
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let mut state = self.inner.state.lock().unwrap();
+
+        if !state.complete {
+            return Err(TryRecvError::Empty);
+        }
+
+        state.value.take().ok_or(TryRecvError::Canceled)
+    }
+
+    /// Receive the value, parking the current coroutine until the `Sender` completed the channel
+    pub fn recv(self) -> Result<T, Canceled> {
+        let mut state = self.inner.state.lock().unwrap();
+
+        while !state.complete {
+            match Processor::current() {
+                Some(p) => {
+                    p.park_with(|_, coro| {
+                        state.waiter = Waiter::Coroutine(coro);
+                        drop(state); // We _must_ to hold the lock until here
+                    });
+
+                    state = self.inner.state.lock().unwrap();
+                }
+                None => {
+                    // Path for normal thread environment
+                    state.waiter = Waiter::Thread;
+                    state = self.inner.cond.wait(state).unwrap();
+                }
+            }
+        }
+
+        state.value.take().ok_or(Canceled)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let value = {
+            let mut state = self.inner.state.lock().unwrap();
+            state.receiver_alive = false;
+            state.value.take()
+        };
+
+        // The value is dropped without holding the lock
+        drop(value);
+    }
+}
+
+/// Create a channel pair for handing over a single value
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let inner = Arc::new(Inner {
+        state: Mutex::new(State {
+            value: None,
+            complete: false,
+            receiver_alive: true,
+            waiter: Waiter::Nobody,
+        }),
+        cond: Condvar::new(),
+    });
+
+    (Sender { inner: inner.clone() }, Receiver { inner: inner })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::thread;
+    use std::time::Duration;
+
+    use scheduler::Scheduler;
+
+    #[test]
+    fn test_oneshot_basic() {
+        let (tx, mut rx) = channel();
+
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        assert_eq!(tx.send(1), Ok(()));
+        assert_eq!(rx.try_recv(), Ok(1));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Canceled));
+
+        let (tx, rx) = channel::<i32>();
+        assert!(!tx.is_canceled());
+        drop(rx);
+        assert!(tx.is_canceled());
+        assert_eq!(tx.send(2), Err(2));
+    }
+
+    #[test]
+    fn test_oneshot_canceled() {
+        Scheduler::new()
+            .run(|| {
+                let (tx, rx) = channel::<i32>();
+
+                Scheduler::spawn(move || {
+                    ::sleep_ms(1);
+                    drop(tx);
+                });
+
+                assert_eq!(rx.recv(), Err(Canceled));
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_oneshot_multi_processors() {
+        Scheduler::new()
+            .with_workers(4)
+            .run(|| {
+                let handles: Vec<_> = (0..16)
+                                          .map(|i| {
+                                              let (tx, rx) = channel();
+
+                                              Scheduler::spawn(move || {
+                                                  tx.send(i).unwrap();
+                                              });
+
+                                              Scheduler::spawn(move || rx.recv().unwrap())
+                                          })
+                                          .collect();
+
+                for (i, h) in handles.into_iter().enumerate() {
+                    assert_eq!(h.join().unwrap(), i);
+                }
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_oneshot_thread() {
+        let (tx, rx) = channel();
+
+        let t = thread::spawn(move || rx.recv());
+        thread::sleep(Duration::from_millis(10));
+        tx.send(1).unwrap();
+
+        assert_eq!(t.join().unwrap(), Ok(1));
+    }
+
+    // The Sender completes the channel on a thread outside of the Scheduler,
+    // while the receiving coroutine is parked
+    #[test]
+    fn test_oneshot_foreign_sender() {
+        Scheduler::new()
+            .run(|| {
+                let (tx, rx) = channel();
+
+                let t = thread::spawn(move || {
+                    thread::sleep(Duration::from_millis(10));
+                    tx.send(1).unwrap();
+                });
+
+                assert_eq!(rx.recv(), Ok(1));
+                t.join().unwrap();
+            })
+            .unwrap();
+    }
+}
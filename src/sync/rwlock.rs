@@ -0,0 +1,329 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Reader-writer lock for Coroutines
+
+use std::cell::UnsafeCell;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+
+use coroutine::HandleList;
+use runtime::Processor;
+use scheduler::Scheduler;
+use sync::mutex::{LockResult, TryLockError, TryLockResult};
+
+use super::spinlock::Spinlock;
+
+struct State {
+    reader_count: usize,
+    writer: bool,
+
+    read_wait_list: HandleList,
+    write_wait_list: HandleList,
+}
+
+/// A reader-writer lock parking the coroutines waiting for it
+///
+/// The lock is phase fair: New readers queue up behind waiting writers,
+/// and a writer releasing the lock hands it over to all queued readers first.
+/// Thus neither readers nor writers can be starved.
+///
+/// Woken up coroutines are handed the lock directly, instead of competing for it again.
+pub struct RwLock<T: ?Sized> {
+    state: Spinlock<State>,
+    data: UnsafeCell<T>,
+}
+
+impl<T> RwLock<T> {
+    /// Creates a new RwLock in an unlocked state ready for use.
+    pub fn new(data: T) -> RwLock<T> {
+        RwLock {
+            state: Spinlock::new(State {
+                reader_count: 0,
+                writer: false,
+
+                read_wait_list: HandleList::new(),
+                write_wait_list: HandleList::new(),
+            }),
+            data: UnsafeCell::new(data),
+        }
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Locks this RwLock with shared read access, blocking the current coroutine until it can be acquired.
+    pub fn read(&self) -> LockResult<ReadGuard<T>> {
+        let mut state = self.state.lock();
+
+        if !state.writer && state.write_wait_list.is_empty() {
+            state.reader_count += 1;
+        } else {
+            // The reader_count is incremented by the releasing writer
+            Processor::current_required().park_with(|_, coro| {
+                state.read_wait_list.push_back(coro);
+                drop(state); // We _must_ to hold the lock until here
+            });
+        }
+
+        Ok(ReadGuard { lock: self })
+    }
+
+    /// Locks this RwLock with exclusive write access, blocking the current coroutine until it can be acquired.
+    pub fn write(&self) -> LockResult<WriteGuard<T>> {
+        let mut state = self.state.lock();
+
+        if !state.writer && state.reader_count == 0 {
+            state.writer = true;
+        } else {
+            // The writer flag is kept set by the releasing reader or writer
+            Processor::current_required().park_with(|_, coro| {
+                state.write_wait_list.push_back(coro);
+                drop(state); // We _must_ to hold the lock until here
+            });
+        }
+
+        Ok(WriteGuard { lock: self })
+    }
+
+    /// Attempts to acquire this RwLock with shared read access without parking
+    ///
+    /// Fails with `TryLockError::WouldBlock` if `read()` would have to wait, which includes
+    /// the case where a writer is queued up.
+    pub fn try_read(&self) -> TryLockResult<ReadGuard<T>> {
+        let mut state = self.state.lock();
+
+        if !state.writer && state.write_wait_list.is_empty() {
+            state.reader_count += 1;
+            Ok(ReadGuard { lock: self })
+        } else {
+            Err(TryLockError::WouldBlock)
+        }
+    }
+
+    /// Attempts to acquire this RwLock with exclusive write access without parking
+    ///
+    /// Fails with `TryLockError::WouldBlock` if the lock is currently held by anyone.
+    pub fn try_write(&self) -> TryLockResult<WriteGuard<T>> {
+        let mut state = self.state.lock();
+
+        if !state.writer && state.reader_count == 0 {
+            state.writer = true;
+            Ok(WriteGuard { lock: self })
+        } else {
+            Err(TryLockError::WouldBlock)
+        }
+    }
+
+    fn read_unlock(&self) {
+        let hdl = {
+            let mut state = self.state.lock();
+            state.reader_count -= 1;
+
+            if state.reader_count > 0 {
+                return;
+            }
+
+            let hdl = state.write_wait_list.pop_front();
+
+            if hdl.is_some() {
+                state.writer = true;
+            }
+
+            hdl
+        };
+
+        if let Some(hdl) = hdl {
+            Scheduler::ready(hdl);
+        }
+    }
+
+    fn write_unlock(&self) {
+        let readers = {
+            let mut state = self.state.lock();
+
+            if !state.read_wait_list.is_empty() {
+                state.writer = false;
+                state.reader_count = state.read_wait_list.len();
+                mem::replace(&mut state.read_wait_list, HandleList::new())
+            } else {
+                match state.write_wait_list.pop_front() {
+                    Some(hdl) => {
+                        drop(state);
+                        Scheduler::ready(hdl);
+                        return;
+                    }
+                    None => {
+                        state.writer = false;
+                        return;
+                    }
+                }
+            }
+        };
+
+        for hdl in readers {
+            Scheduler::ready(hdl);
+        }
+    }
+}
+
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+/// RAII structure used to release the shared read access of a lock when dropped.
+#[must_use]
+pub struct ReadGuard<'a, T: ?Sized + 'a> {
+    lock: &'a RwLock<T>,
+}
+
+impl<'a, T: ?Sized + 'a> Drop for ReadGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.read_unlock();
+    }
+}
+
+impl<'a, T: ?Sized + 'a> Deref for ReadGuard<'a, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+/// RAII structure used to release the exclusive write access of a lock when dropped.
+#[must_use]
+pub struct WriteGuard<'a, T: ?Sized + 'a> {
+    lock: &'a RwLock<T>,
+}
+
+impl<'a, T: ?Sized + 'a> Drop for WriteGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.write_unlock();
+    }
+}
+
+impl<'a, T: ?Sized + 'a> Deref for WriteGuard<'a, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized + 'a> DerefMut for WriteGuard<'a, T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use scheduler::Scheduler;
+
+    use super::*;
+
+    #[test]
+    fn test_rwlock() {
+        Scheduler::new()
+            .with_workers(4)
+            .run(|| {
+                let lock = Arc::new(RwLock::new(0));
+                let readers = Arc::new(AtomicUsize::new(0));
+                let mut handles = Vec::new();
+
+                for i in 0..100 {
+                    let lock = lock.clone();
+                    let readers = readers.clone();
+
+                    let h = Scheduler::spawn(move || {
+                        for _ in 0..10 {
+                            if i % 4 == 0 {
+                                let mut guard = lock.write().unwrap();
+                                assert_eq!(readers.load(Ordering::SeqCst), 0);
+                                *guard += 1;
+                                Scheduler::sched();
+                            } else {
+                                let _guard = lock.read().unwrap();
+                                readers.fetch_add(1, Ordering::SeqCst);
+                                Scheduler::sched();
+                                readers.fetch_sub(1, Ordering::SeqCst);
+                            }
+                        }
+                    });
+                    handles.push(h);
+                }
+
+                for h in handles {
+                    h.join().unwrap();
+                }
+
+                assert_eq!(*lock.read().unwrap(), 250);
+            })
+            .unwrap();
+    }
+
+    // A writer releasing the lock must wake up all queued readers at once
+    #[test]
+    fn test_rwlock_wakes_all_readers() {
+        Scheduler::new()
+            .run(|| {
+                let lock = Arc::new(RwLock::new(()));
+                let readers = Arc::new(AtomicUsize::new(0));
+
+                let guard = lock.write().unwrap();
+
+                let handles: Vec<_> = (0..10)
+                                          .map(|_| {
+                                              let lock = lock.clone();
+                                              let readers = readers.clone();
+
+                                              Scheduler::spawn(move || {
+                                                  let _guard = lock.read().unwrap();
+                                                  readers.fetch_add(1, Ordering::SeqCst);
+                                                  Scheduler::sched();
+                                                  readers.load(Ordering::SeqCst)
+                                              })
+                                          })
+                                          .collect();
+
+                Scheduler::sched();
+                drop(guard);
+
+                for h in handles {
+                    assert_eq!(h.join().unwrap(), 10);
+                }
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_rwlock_try() {
+        let lock = RwLock::new(0);
+
+        {
+            let r1 = lock.try_read().unwrap();
+            let r2 = lock.try_read().unwrap();
+            assert_eq!(*r1 + *r2, 0);
+            assert!(lock.try_write().is_err());
+        }
+
+        {
+            let mut w = lock.try_write().unwrap();
+            *w += 1;
+            assert!(lock.try_read().is_err());
+            assert!(lock.try_write().is_err());
+        }
+
+        assert_eq!(*lock.try_read().unwrap(), 1);
+    }
+}
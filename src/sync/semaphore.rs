@@ -8,32 +8,55 @@
 
 //! Semaphore for Coroutines
 
-use coroutine::HandleList;
+use std::collections::VecDeque;
+
+use coroutine::Handle;
 use scheduler::Scheduler;
 use runtime::Processor;
 
 use super::spinlock::Spinlock;
 
+struct SemaphoreState {
+    count: usize,
+    // Parked coroutines and the number of permits they are waiting for, in FIFO order
+    waiters: VecDeque<(usize, Handle)>,
+}
+
 /// Semaphore
-pub struct Semaphore(Spinlock<(usize, HandleList)>);
+///
+/// A counter of permits. Coroutines acquiring more permits than available are parked
+/// and woken up in the order they arrived in, as soon as enough permits were released.
+/// A waiter needing many permits thus blocks all waiters behind it, even if they need
+/// less permits than available, so that it can't be starved.
+pub struct Semaphore(Spinlock<SemaphoreState>);
 
 impl Semaphore {
     /// Create a semaphore by providing an initial count.
     pub fn new(count: usize) -> Semaphore {
-        Semaphore(Spinlock::new((count, HandleList::new())))
+        Semaphore(Spinlock::new(SemaphoreState {
+            count: count,
+            waiters: VecDeque::new(),
+        }))
     }
 
     /// Semaphore acquire (or down, P). If the counter is 0, block the current coroutine.
+    #[inline]
     pub fn acquire(&self) {
+        self.acquire_n(1)
+    }
+
+    /// Acquire `n` permits at once, blocking the current coroutine until they are available.
+    pub fn acquire_n(&self, n: usize) {
         let mut inner = self.0.lock();
 
-        if inner.0 > 0 {
-            inner.0 -= 1;
+        if inner.waiters.is_empty() && inner.count >= n {
+            inner.count -= n;
         } else {
             match Processor::current() {
                 Some(p) => {
+                    // The permits are handed over by release_n() before waking us up
                     p.park_with(|_, coro| {
-                        inner.1.push_back(coro);
+                        inner.waiters.push_back((n, coro));
                         drop(inner); // We _must_ to hold the lock until here
                     });
                 }
@@ -47,14 +70,18 @@ impl Semaphore {
     }
 
     /// Semaphore acquire (or down, P). Return immediately no matter success or not.
+    #[inline]
     pub fn try_acquire(&self) -> bool {
-        let mut inner = match self.0.try_lock() {
-            Some(inner) => inner,
-            None => return false,
-        };
+        self.try_acquire_n(1)
+    }
+
+    /// Acquire `n` permits at once if they are available and nobody is waiting for any.
+    /// Return immediately no matter success or not.
+    pub fn try_acquire_n(&self, n: usize) -> bool {
+        let mut inner = self.0.lock();
 
-        if inner.0 > 0 {
-            inner.0 -= 1;
+        if inner.waiters.is_empty() && inner.count >= n {
+            inner.count -= n;
             true
         } else {
             false
@@ -62,14 +89,43 @@ impl Semaphore {
     }
 
     /// Semaphore release (or up, V).
+    #[inline]
     pub fn release(&self) {
-        let mut inner = self.0.lock();
+        self.release_n(1)
+    }
 
-        if let Some(h) = inner.1.pop_front() {
-            Scheduler::ready(h);
-        } else {
-            inner.0 += 1;
+    /// Release `n` permits at once and wake up the waiters which can be satisfied by them.
+    pub fn release_n(&self, n: usize) {
+        let mut ready = Vec::new();
+
+        {
+            let mut inner = self.0.lock();
+            inner.count += n;
+
+            loop {
+                let needed = match inner.waiters.front() {
+                    Some(&(needed, _)) => needed,
+                    None => break,
+                };
+
+                if needed > inner.count {
+                    break;
+                }
+
+                inner.count -= needed;
+                ready.push(inner.waiters.pop_front().unwrap().1);
+            }
         }
+
+        // Pinned coroutines are sent back to their own Processor by Scheduler::ready()
+        for hdl in ready {
+            Scheduler::ready(hdl);
+        }
+    }
+
+    /// Returns the number of currently available permits
+    pub fn available(&self) -> usize {
+        self.0.lock().count
     }
 }
 
@@ -80,7 +136,7 @@ unsafe impl Sync for Semaphore {}
 mod test {
     use super::*;
 
-    use std::sync::Arc;
+    use std::sync::{Arc, Mutex};
 
     use scheduler::Scheduler;
 
@@ -137,4 +193,51 @@ mod test {
             })
             .unwrap();
     }
+
+    #[test]
+    fn semaphore_acquire_n_fifo() {
+        Scheduler::new()
+            .run(|| {
+                let sema = Arc::new(Semaphore::new(3));
+                let order = Arc::new(Mutex::new(Vec::new()));
+
+                sema.acquire_n(3);
+                assert_eq!(sema.available(), 0);
+                assert!(!sema.try_acquire());
+
+                // The waiter for 3 permits blocks the ones for a single permit behind it
+                let hlist: Vec<_> = [3, 1, 1]
+                                        .iter()
+                                        .enumerate()
+                                        .map(|(id, &n)| {
+                                            let sema = sema.clone();
+                                            let order = order.clone();
+
+                                            let h = Scheduler::spawn(move || {
+                                                sema.acquire_n(n);
+                                                order.lock().unwrap().push(id);
+                                                sema.release_n(n);
+                                            });
+                                            Scheduler::sched();
+                                            h
+                                        })
+                                        .collect();
+
+                sema.release_n(1);
+                Scheduler::sched();
+                assert!(order.lock().unwrap().is_empty());
+                assert!(!sema.try_acquire_n(1));
+
+                sema.release_n(2);
+
+                for h in hlist {
+                    h.join().unwrap();
+                }
+
+                assert_eq!(*order.lock().unwrap(), vec![0, 1, 2]);
+                assert_eq!(sema.available(), 3);
+                assert!(sema.try_acquire_n(3));
+            })
+            .unwrap();
+    }
 }
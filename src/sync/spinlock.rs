@@ -15,8 +15,9 @@ use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 use sync::Lock;
 
+#[doc(hidden)]
 #[inline(always)]
-fn cpu_relax() {
+pub fn cpu_relax() {
     if cfg!(any(target_arch = "x86", target_arch = "x86_64")) {
         unsafe {
             // "Modern" processors exiting a tight loop (like this one)
@@ -0,0 +1,456 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Single-producer, single-consumer bounded FIFO queue communication primitives.
+//!
+//! Unlike `mpmc` the items are passed through a lock-free ring buffer. Since there is only one
+//! coroutine on each side, the sender and the receiver only have to coordinate with each other
+//! when one of them parks, which makes it well suited for pipelines between two coroutines.
+
+pub use std::sync::mpsc::{TrySendError, SendError, TryRecvError, RecvError, RecvTimeoutError};
+
+use std::cell::UnsafeCell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use coroutine::Handle;
+use runtime::Processor;
+use runtime::processor::ProcessorHandle;
+use scheduler::Scheduler;
+use select::{self, Select, SelectOp};
+
+use super::spinlock::Spinlock;
+
+enum Waiter {
+    Coroutine(Handle),
+    Select(Select),
+}
+
+// The coroutine of one side parked on the channel or the Select it's waiting in
+struct ParkSlot {
+    parked: AtomicBool,
+    waiter: Spinlock<Option<Waiter>>,
+}
+
+impl ParkSlot {
+    fn new() -> ParkSlot {
+        ParkSlot {
+            parked: AtomicBool::new(false),
+            waiter: Spinlock::new(None),
+        }
+    }
+
+    // Parks the current coroutine, unless `ready()` returns true once it's registered
+    fn park<F>(&self, p: ProcessorHandle, ready: F)
+        where F: Fn() -> bool
+    {
+        p.park_with(|p, coro| {
+            *self.waiter.lock() = Some(Waiter::Coroutine(coro));
+            self.parked.store(true, Ordering::SeqCst);
+
+            // The other side might have missed the registration
+            if ready() {
+                if let Some(Waiter::Coroutine(coro)) = self.take() {
+                    p.ready(coro);
+                }
+            }
+        });
+    }
+
+    // Unlike a parked coroutine the Select stays registered until unregister() is called
+    fn register(&self, select: &Select) {
+        *self.waiter.lock() = Some(Waiter::Select(select.clone()));
+        self.parked.store(true, Ordering::SeqCst);
+    }
+
+    fn unregister(&self) {
+        drop(self.take());
+    }
+
+    fn take(&self) -> Option<Waiter> {
+        if !self.parked.load(Ordering::SeqCst) || !self.parked.swap(false, Ordering::SeqCst) {
+            return None;
+        }
+
+        self.waiter.lock().take()
+    }
+
+    fn wake(&self) {
+        match self.take() {
+            Some(Waiter::Coroutine(coro)) => Scheduler::ready(coro),
+            Some(Waiter::Select(select)) => {
+                if let Some(coro) = select.notify() {
+                    Scheduler::ready(coro);
+                }
+            }
+            None => {}
+        }
+    }
+}
+
+struct Inner<T> {
+    buffer: Vec<UnsafeCell<Option<T>>>,
+
+    // Index of the next item to receive, only advanced by the Receiver
+    head: AtomicUsize,
+    // Index of the next item to send, only advanced by the Sender
+    tail: AtomicUsize,
+
+    sender_alive: AtomicBool,
+    receiver_alive: AtomicBool,
+
+    sender: ParkSlot,
+    receiver: ParkSlot,
+}
+
+impl<T> Inner<T> {
+    fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
+    fn slot(&self, idx: usize) -> *mut Option<T> {
+        self.buffer[idx % self.capacity()].get()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.head.load(Ordering::SeqCst) == self.tail.load(Ordering::SeqCst)
+    }
+
+    fn is_full(&self) -> bool {
+        let head = self.head.load(Ordering::SeqCst);
+        self.tail.load(Ordering::SeqCst).wrapping_sub(head) >= self.capacity()
+    }
+}
+
+pub struct Sender<T> {
+    inner: Arc<Inner<T>>,
+}
+
+// NOTE: Sender is deliberately not Sync, since that would allow multiple producers.
+unsafe impl<T: Send> Send for Sender<T> {}
+
+impl<T> Sender<T> {
+    pub fn try_send(&self, t: T) -> Result<(), TrySendError<T>> {
+        let inner = &*self.inner;
+
+        if !inner.receiver_alive.load(Ordering::SeqCst) {
+            return Err(TrySendError::Disconnected(t));
+        }
+
+        if inner.is_full() {
+            return Err(TrySendError::Full(t));
+        }
+
+        let tail = inner.tail.load(Ordering::Relaxed);
+        unsafe { *inner.slot(tail) = Some(t) };
+        inner.tail.store(tail.wrapping_add(1), Ordering::SeqCst);
+
+        inner.receiver.wake();
+        Ok(())
+    }
+
+    /// Send a value, parking the current coroutine while the buffer is full.
+    pub fn send(&self, mut t: T) -> Result<(), SendError<T>> {
+        loop {
+            match self.try_send(t) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Disconnected(t)) => return Err(SendError(t)),
+                Err(TrySendError::Full(t_)) => t = t_,
+            }
+
+            let inner = &*self.inner;
+
+            match Processor::current() {
+                Some(p) => {
+                    inner.sender.park(p, || {
+                        !inner.is_full() || !inner.receiver_alive.load(Ordering::SeqCst)
+                    });
+                }
+                None => thread::yield_now(),
+            }
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.inner.sender_alive.store(false, Ordering::SeqCst);
+        self.inner.receiver.wake();
+    }
+}
+
+pub struct Receiver<T> {
+    inner: Arc<Inner<T>>,
+}
+
+// NOTE: Receiver is deliberately not Sync, since that would allow multiple consumers.
+unsafe impl<T: Send> Send for Receiver<T> {}
+
+impl<T> Receiver<T> {
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let inner = &*self.inner;
+        let head = inner.head.load(Ordering::Relaxed);
+
+        if head == inner.tail.load(Ordering::SeqCst) {
+            if inner.sender_alive.load(Ordering::SeqCst) {
+                return Err(TryRecvError::Empty);
+            }
+
+            // The Sender might have sent an item right before it was dropped
+            if head == inner.tail.load(Ordering::SeqCst) {
+                return Err(TryRecvError::Disconnected);
+            }
+        }
+
+        let t = unsafe { (*inner.slot(head)).take().unwrap() };
+        inner.head.store(head.wrapping_add(1), Ordering::SeqCst);
+
+        inner.sender.wake();
+        Ok(t)
+    }
+
+    /// Returns a `select!` operation which completes as soon as an item was received
+    ///
+    /// It's result is the same as the one of `recv()`.
+    pub fn select_recv(&self) -> RecvOp<T> {
+        RecvOp {
+            receiver: self,
+            result: None,
+        }
+    }
+
+    /// Receive a value, parking the current coroutine for at most `timeout` while the
+    /// buffer is empty.
+    ///
+    /// The parked coroutine is readied again by the timer. An item arriving at the same time
+    /// is preferred over the timeout. Under `Scheduler::with_deadline()` the timeout is capped
+    /// to the deadline. Outside of a coroutine the thread yields until either happens.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let mut deadline = Instant::now() + timeout;
+
+        if let Some(current) = Scheduler::current_deadline() {
+            if current < deadline {
+                deadline = current;
+            }
+        }
+
+        if Processor::current().is_none() {
+            loop {
+                match self.try_recv() {
+                    Ok(t) => return Ok(t),
+                    Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                    Err(TryRecvError::Empty) => {}
+                }
+
+                if Instant::now() >= deadline {
+                    return Err(RecvTimeoutError::Timeout);
+                }
+
+                thread::yield_now();
+            }
+        }
+
+        // The item is attempted first, which is what makes it win against the timeout
+        select! {
+            r = self.select_recv() => r.map_err(|_| RecvTimeoutError::Disconnected),
+            _ = select::timeout_at(deadline) => Err(RecvTimeoutError::Timeout),
+        }
+    }
+
+    /// Receive a value, parking the current coroutine while the buffer is empty.
+    ///
+    /// Under `Scheduler::with_deadline()` it fails with `RecvError` once the deadline passed.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        if let Some(deadline) = Scheduler::current_deadline() {
+            return select! {
+                r = self.select_recv() => r,
+                _ = select::timeout_at(deadline) => Err(RecvError),
+            };
+        }
+
+        loop {
+            match self.try_recv() {
+                Ok(t) => return Ok(t),
+                Err(TryRecvError::Disconnected) => return Err(RecvError),
+                Err(TryRecvError::Empty) => {}
+            }
+
+            let inner = &*self.inner;
+
+            match Processor::current() {
+                Some(p) => {
+                    inner.receiver.park(p, || {
+                        !inner.is_empty() || !inner.sender_alive.load(Ordering::SeqCst)
+                    });
+                }
+                None => thread::yield_now(),
+            }
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.inner.receiver_alive.store(false, Ordering::SeqCst);
+        self.inner.sender.wake();
+    }
+}
+
+/// Created by `Receiver::select_recv()`
+pub struct RecvOp<'a, T: 'a> {
+    receiver: &'a Receiver<T>,
+    result: Option<Result<T, RecvError>>,
+}
+
+impl<'a, T: 'a> RecvOp<'a, T> {
+    pub fn take(&mut self) -> Result<T, RecvError> {
+        self.result.take().expect("RecvOp didn't complete yet")
+    }
+}
+
+impl<'a, T: 'a> SelectOp for RecvOp<'a, T> {
+    fn try_complete(&mut self) -> bool {
+        self.result = match self.receiver.try_recv() {
+            Ok(t) => Some(Ok(t)),
+            Err(TryRecvError::Disconnected) => Some(Err(RecvError)),
+            Err(TryRecvError::Empty) => None,
+        };
+
+        self.result.is_some()
+    }
+
+    fn is_completed(&self) -> bool {
+        self.result.is_some()
+    }
+
+    fn register(&mut self, select: &Select) {
+        self.receiver.inner.receiver.register(select);
+    }
+
+    fn unregister(&mut self, _select: &Select) {
+        self.receiver.inner.receiver.unregister();
+    }
+}
+
+/// Create a channel pair with a buffer for `capacity` items
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "spsc::channel requires a capacity of at least 1");
+
+    let inner = Arc::new(Inner {
+        buffer: (0..capacity).map(|_| UnsafeCell::new(None)).collect(),
+
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+
+        sender_alive: AtomicBool::new(true),
+        receiver_alive: AtomicBool::new(true),
+
+        sender: ParkSlot::new(),
+        receiver: ParkSlot::new(),
+    });
+
+    (Sender { inner: inner.clone() }, Receiver { inner: inner })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::time::Duration;
+
+    use scheduler::Scheduler;
+
+    #[test]
+    fn spsc_basic() {
+        let (tx, rx) = channel(2);
+
+        tx.try_send(1).unwrap();
+        tx.try_send(2).unwrap();
+        assert_eq!(tx.try_send(3), Err(TrySendError::Full(3)));
+
+        assert_eq!(rx.try_recv(), Ok(1));
+        tx.try_send(3).unwrap();
+        assert_eq!(rx.try_recv(), Ok(2));
+        assert_eq!(rx.try_recv(), Ok(3));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+
+        tx.try_send(4).unwrap();
+        drop(tx);
+        assert_eq!(rx.try_recv(), Ok(4));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn spsc_pipeline() {
+        Scheduler::new()
+            .with_workers(2)
+            .run(|| {
+                let (tx, rx) = channel(4);
+
+                let producer = Scheduler::spawn(move || {
+                    for i in 0..10_000 {
+                        tx.send(i).unwrap();
+                    }
+                });
+
+                for i in 0..10_000 {
+                    assert_eq!(rx.recv(), Ok(i));
+                }
+                assert_eq!(rx.recv(), Err(RecvError));
+
+                producer.join().unwrap();
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn spsc_recv_timeout() {
+        Scheduler::new()
+            .with_timer_resolution(Duration::from_millis(10))
+            .run(|| {
+                let (tx, rx) = channel(1);
+
+                assert_eq!(rx.recv_timeout(Duration::from_millis(20)),
+                           Err(RecvTimeoutError::Timeout));
+
+                let h = Scheduler::spawn(move || {
+                    ::sleep_ms(10);
+                    tx.send(1).unwrap();
+                });
+
+                assert_eq!(rx.recv_timeout(Duration::from_secs(10)), Ok(1));
+                h.join().unwrap();
+                assert_eq!(rx.recv_timeout(Duration::from_secs(10)),
+                           Err(RecvTimeoutError::Disconnected));
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn spsc_receiver_dropped() {
+        Scheduler::new()
+            .run(|| {
+                let (tx, rx) = channel(1);
+
+                let producer = Scheduler::spawn(move || {
+                    tx.send(1).unwrap();
+
+                    // Parks on the full buffer until the receiver is dropped
+                    tx.send(2)
+                });
+
+                Scheduler::sched();
+                drop(rx);
+
+                assert_eq!(producer.join().unwrap(), Err(SendError(2)));
+            })
+            .unwrap();
+    }
+}
@@ -0,0 +1,144 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! WaitGroup for Coroutines
+
+use std::mem;
+
+use coroutine::HandleList;
+use scheduler::Scheduler;
+use runtime::Processor;
+
+use super::spinlock::Spinlock;
+
+/// Waits for a collection of coroutines to finish, like Go's `sync.WaitGroup`
+pub struct WaitGroup(Spinlock<(usize, HandleList)>);
+
+impl WaitGroup {
+    /// Create a WaitGroup with a counter of 0.
+    pub fn new() -> WaitGroup {
+        WaitGroup(Spinlock::new((0, HandleList::new())))
+    }
+
+    /// Add `n` to the counter.
+    pub fn add(&self, n: usize) {
+        self.0.lock().0 += n;
+    }
+
+    /// Decrement the counter. Wakes up all waiting coroutines if it reaches 0.
+    pub fn done(&self) {
+        let waiters = {
+            let mut inner = self.0.lock();
+
+            assert!(inner.0 > 0, "WaitGroup::done() called more often than add()");
+            inner.0 -= 1;
+
+            if inner.0 > 0 {
+                return;
+            }
+
+            mem::replace(&mut inner.1, HandleList::new())
+        };
+
+        for hdl in waiters {
+            Scheduler::ready(hdl);
+        }
+    }
+
+    /// Block the current coroutine until the counter reaches 0.
+    pub fn wait(&self) {
+        let mut inner = self.0.lock();
+
+        if inner.0 == 0 {
+            return;
+        }
+
+        match Processor::current() {
+            Some(p) => {
+                p.park_with(|_, coro| {
+                    inner.1.push_back(coro);
+                    drop(inner); // We _must_ to hold the lock until here
+                });
+            }
+            None => {
+                panic!("WaitGroup will not work in thread environment");
+            }
+        }
+    }
+
+    /// Returns the current value of the counter.
+    pub fn count(&self) -> usize {
+        self.0.lock().0
+    }
+}
+
+unsafe impl Send for WaitGroup {}
+unsafe impl Sync for WaitGroup {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use scheduler::Scheduler;
+
+    #[test]
+    fn test_wait_group() {
+        Scheduler::new()
+            .with_workers(4)
+            .run(|| {
+                let wg = Arc::new(WaitGroup::new());
+                let finished = Arc::new(AtomicUsize::new(0));
+
+                wg.add(10);
+
+                for _ in 0..10 {
+                    let wg = wg.clone();
+                    let finished = finished.clone();
+
+                    Scheduler::spawn(move || {
+                        Scheduler::sched();
+                        finished.fetch_add(1, Ordering::SeqCst);
+                        wg.done();
+                    });
+                }
+
+                let waiters: Vec<_> = (0..4)
+                                          .map(|_| {
+                                              let wg = wg.clone();
+                                              let finished = finished.clone();
+
+                                              Scheduler::spawn(move || {
+                                                  wg.wait();
+                                                  assert_eq!(finished.load(Ordering::SeqCst), 10);
+                                              })
+                                          })
+                                          .collect();
+
+                wg.wait();
+                assert_eq!(wg.count(), 0);
+
+                for h in waiters {
+                    h.join().unwrap();
+                }
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_wait_group_empty() {
+        Scheduler::new()
+            .run(|| {
+                let wg = WaitGroup::new();
+                wg.wait();
+            })
+            .unwrap();
+    }
+}
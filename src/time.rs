@@ -0,0 +1,173 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Timers for coroutines
+
+use std::time::{Duration, Instant};
+
+/// What an `Interval` does if ticks were missed because the coroutine didn't wait in time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissedTicks {
+    /// Fire all missed ticks immediately, one after another, to catch up with the schedule
+    Burst,
+    /// Fire a single tick immediately and drop the other missed ones, keeping the schedule
+    Skip,
+    /// Fire a single tick immediately and restart the schedule from then on
+    Delay,
+}
+
+/// Yields a tick every `period`, see `Interval::new()`
+///
+/// `next_tick()` parks the current coroutine until the next tick is due, using the timer wheel
+/// of the Scheduler just like `coio::sleep()`. Outside of a coroutine it blocks the thread.
+/// An `Interval` is also an infinite `Iterator` over the ticks:
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use coio::time::Interval;
+///
+/// for _ in Interval::new(Duration::from_secs(1)) {
+///     // Runs once a second
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Interval {
+    period: Duration,
+    next: Instant,
+    missed_ticks: MissedTicks,
+}
+
+impl Interval {
+    /// Creates an `Interval` whose first tick is due after one `period`
+    ///
+    /// Missed ticks are handled according to `MissedTicks::Burst`.
+    pub fn new(period: Duration) -> Interval {
+        Interval::starting_at(Instant::now() + period, period)
+    }
+
+    /// Creates an `Interval` whose first tick is due at `start`
+    pub fn starting_at(start: Instant, period: Duration) -> Interval {
+        assert!(period != Duration::new(0, 0), "Interval period must not be zero");
+
+        Interval {
+            period: period,
+            next: start,
+            missed_ticks: MissedTicks::Burst,
+        }
+    }
+
+    /// Sets how missed ticks are handled
+    pub fn with_missed_ticks(mut self, missed_ticks: MissedTicks) -> Interval {
+        self.missed_ticks = missed_ticks;
+        self
+    }
+
+    #[inline]
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Parks the current coroutine until the next tick is due and returns when it was due
+    ///
+    /// If the tick is already overdue, it returns immediately.
+    pub fn next_tick(&mut self) -> Instant {
+        let tick = self.next;
+        let mut now = Instant::now();
+
+        if now < tick {
+            ::sleep_until(tick);
+            now = Instant::now();
+        }
+
+        self.next = match self.missed_ticks {
+            MissedTicks::Burst => tick + self.period,
+            MissedTicks::Skip => {
+                let mut next = tick + self.period;
+
+                while next <= now {
+                    next = next + self.period;
+                }
+
+                next
+            }
+            MissedTicks::Delay => now + self.period,
+        };
+
+        tick
+    }
+
+    /// Returns when the next tick is due
+    #[inline]
+    pub fn deadline(&self) -> Instant {
+        self.next
+    }
+}
+
+impl Iterator for Interval {
+    type Item = Instant;
+
+    fn next(&mut self) -> Option<Instant> {
+        Some(self.next_tick())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use scheduler::Scheduler;
+    use super::{Interval, MissedTicks};
+
+    fn run_missed(missed_ticks: MissedTicks) -> (Instant, Vec<Instant>) {
+        Scheduler::new()
+            .run(move || {
+                let period = Duration::from_millis(20);
+                let mut interval = Interval::new(period).with_missed_ticks(missed_ticks);
+
+                let first = interval.next_tick();
+                assert!(Instant::now() >= first);
+
+                // Miss the next two ticks
+                ::sleep(Duration::from_millis(55));
+
+                let ticks = (0..3).map(|_| interval.next_tick()).collect();
+                (first, ticks)
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_interval_burst() {
+        let period = Duration::from_millis(20);
+        let (first, ticks) = run_missed(MissedTicks::Burst);
+
+        assert_eq!(ticks, vec![first + period, first + period * 2, first + period * 3]);
+    }
+
+    #[test]
+    fn test_interval_skip() {
+        let period = Duration::from_millis(20);
+        let (first, ticks) = run_missed(MissedTicks::Skip);
+
+        assert_eq!(ticks[0], first + period);
+
+        // The ticks stay on the schedule, but at least one was skipped
+        assert!(ticks[1] >= first + period * 3);
+        assert_eq!(ticks[2], ticks[1] + period);
+    }
+
+    #[test]
+    fn test_interval_delay() {
+        let period = Duration::from_millis(20);
+        let (first, ticks) = run_missed(MissedTicks::Delay);
+
+        assert_eq!(ticks[0], first + period);
+        assert!(ticks[1] >= first + Duration::from_millis(55) + period);
+        assert!(ticks[2] >= ticks[1] + period);
+    }
+}
@@ -9,9 +9,12 @@
 extern crate coio;
 
 use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::time::Duration;
 
-use coio::Scheduler;
-use coio::net::{TcpListener, TcpStream, UdpSocket};
+use coio::{CancellationToken, Options, Scheduler};
+use coio::net::{self, TcpListener, TcpStream, UdpSocket};
+use coio::net::pool::ConnectionPool;
 
 #[test]
 fn test_tcp_echo() {
@@ -125,3 +128,386 @@ fn test_unix_socket_echo() {
         })
         .unwrap();
 }
+
+#[cfg(unix)]
+#[test]
+fn test_tcp_vectored() {
+    const LEN: usize = 4 * 1024 * 1024;
+
+    Scheduler::new()
+        .run(move || {
+            let acceptor = TcpListener::bind("127.0.0.1:6793").unwrap();
+
+            let listen_fut = Scheduler::spawn(move || {
+                let (stream, _) = acceptor.accept().unwrap();
+
+                let mut header = [0u8; 4];
+                let mut body = vec![0u8; LEN];
+                let mut total = 0;
+
+                // Reads the header and the first part of the body with a single call
+                total += stream.read_vectored(&mut [&mut header[..], &mut body[..]]).unwrap();
+                assert!(total >= 4);
+                assert_eq!(&header, b"head");
+
+                let mut body_len = total - 4;
+                while body_len < LEN {
+                    body_len += stream.read_vectored(&mut [&mut body[body_len..]]).unwrap();
+                }
+
+                assert!(body.iter().enumerate().all(|(i, &b)| b == i as u8));
+            });
+
+            // The body is large enough to cause short writes
+            let body: Vec<u8> = (0..LEN).map(|i| i as u8).collect();
+            let (first, second) = body.split_at(LEN / 3);
+
+            let stream = TcpStream::connect("127.0.0.1:6793").unwrap();
+            stream.write_all_vectored(&[&b"head"[..], first, second]).unwrap();
+
+            listen_fut.join().unwrap();
+        })
+        .unwrap();
+}
+
+// More buffers than a single writev() call accepts
+#[cfg(unix)]
+#[test]
+fn test_tcp_vectored_many_buffers() {
+    const COUNT: usize = 4096;
+
+    Scheduler::new()
+        .run(move || {
+            let acceptor = TcpListener::bind("127.0.0.1:6798").unwrap();
+
+            let listen_fut = Scheduler::spawn(move || {
+                let (mut stream, _) = acceptor.accept().unwrap();
+
+                let mut buf = vec![0u8; COUNT];
+                stream.read_exact(&mut buf).unwrap();
+                assert!(buf.iter().enumerate().all(|(i, &b)| b == i as u8));
+            });
+
+            let data: Vec<u8> = (0..COUNT).map(|i| i as u8).collect();
+            let bufs: Vec<&[u8]> = data.chunks(1).collect();
+
+            let stream = TcpStream::connect("127.0.0.1:6798").unwrap();
+            stream.write_all_vectored(&bufs).unwrap();
+
+            listen_fut.join().unwrap();
+        })
+        .unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_unix_socket_send_fd() {
+    use coio::net::{UnixStream, UnixListener};
+    use std::fs::{self, File};
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+
+    Scheduler::new()
+        .run(move || {
+            const FILE_PATH_STR: &'static str = "/tmp/coio-unix-socket-fd-test.sock";
+            const DATA_PATH_STR: &'static str = "/tmp/coio-unix-socket-fd-test.txt";
+
+            let _ = fs::remove_file(&FILE_PATH_STR);
+            let acceptor = UnixListener::bind(&FILE_PATH_STR).unwrap();
+
+            File::create(&DATA_PATH_STR).unwrap().write_all(b"passed along").unwrap();
+
+            let listen_fut = Scheduler::spawn(move || {
+                let stream = acceptor.accept().unwrap();
+
+                let mut buf = [0u8; 16];
+                let (len, fd) = stream.recv_fd(&mut buf).unwrap();
+                assert_eq!(&buf[..len], b"fd");
+
+                let mut file = unsafe { File::from_raw_fd(fd.unwrap()) };
+                let mut content = String::new();
+                file.read_to_string(&mut content).unwrap();
+                assert_eq!(content, "passed along");
+            });
+
+            let file = File::open(&DATA_PATH_STR).unwrap();
+            let stream = UnixStream::connect(&FILE_PATH_STR).unwrap();
+            assert_eq!(stream.send_fd(b"fd", file.as_raw_fd()).unwrap(), 2);
+
+            listen_fut.join().unwrap();
+            let _ = fs::remove_file(&DATA_PATH_STR);
+        })
+        .unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_udp_connected() {
+    Scheduler::new()
+        .run(move || {
+            let acceptor = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let acceptor_addr = acceptor.local_addr().unwrap();
+
+            let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let sender_addr = sender.local_addr().unwrap();
+
+            sender.connect(acceptor_addr).unwrap();
+            acceptor.connect(sender_addr).unwrap();
+
+            let listen_fut = Scheduler::spawn(move || {
+                // The datagram is larger than the buffer and gets truncated
+                let mut buf = [0u8; 4];
+                let len = acceptor.recv(&mut buf).unwrap();
+                assert_eq!(len, 4);
+                assert_eq!(&buf, b"abcd");
+
+                acceptor.send(&buf[..len]).unwrap();
+            });
+
+            sender.send(b"abcdefg").unwrap();
+
+            let mut buf = [0u8; 1024];
+            let len = sender.recv(&mut buf).unwrap();
+            assert_eq!(&buf[..len], b"abcd");
+
+            listen_fut.join().unwrap();
+        })
+        .unwrap();
+}
+
+#[test]
+fn test_resolve() {
+    Scheduler::new()
+        .run(|| {
+            let addrs = net::resolve("127.0.0.1", 80).unwrap();
+            assert_eq!(addrs, vec!["127.0.0.1:80".parse::<SocketAddr>().unwrap()]);
+
+            let addrs = net::resolve("localhost", 6791).unwrap();
+            assert!(!addrs.is_empty());
+            assert!(addrs.iter().all(|addr| addr.port() == 6791));
+
+            // Hostnames are resolved without blocking the Processor
+            let acceptor = TcpListener::bind("localhost:6791").unwrap();
+            let hdl = Scheduler::spawn(move || {
+                acceptor.accept().unwrap();
+            });
+
+            TcpStream::connect(&addrs[..]).unwrap();
+            hdl.join().unwrap();
+        })
+        .unwrap();
+}
+
+#[test]
+fn test_tcp_incoming() {
+    Scheduler::new()
+        .run(|| {
+            let acceptor = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = acceptor.local_addr().unwrap();
+
+            let token = CancellationToken::new();
+            let mut opts = Options::new();
+            opts.cancel_token(token.clone());
+
+            let server = Scheduler::spawn_opts(move || {
+                                                   let mut count = 0;
+
+                                                   for stream in acceptor.incoming() {
+                                                       let mut stream = stream.unwrap();
+                                                       stream.write_all(b"hi").unwrap();
+                                                       count += 1;
+                                                   }
+
+                                                   count
+                                               },
+                                               opts);
+
+            for _ in 0..3 {
+                let mut stream = TcpStream::connect(addr).unwrap();
+                let mut buf = [0u8; 2];
+                stream.read_exact(&mut buf).unwrap();
+                assert_eq!(&buf, b"hi");
+            }
+
+            // Cancelling the token ends the iteration instead of waiting forever
+            token.cancel();
+            assert_eq!(server.join().unwrap(), 3);
+        })
+        .unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_tcp_bind_reuseport() {
+    Scheduler::new()
+        .with_workers(2)
+        .run(|| {
+            let addr = TcpListener::bind_reuseport("127.0.0.1:0").unwrap().local_addr().unwrap();
+
+            // One accept loop per worker, all of them sharing the same address
+            let handles = Scheduler::spawn_on_each_processor(move || {
+                let acceptor = TcpListener::bind_reuseport(addr).unwrap();
+                assert_eq!(acceptor.local_addr().unwrap(), addr);
+            });
+            assert_eq!(handles.len(), 2);
+
+            for h in handles {
+                h.join().unwrap();
+            }
+
+            // A listener bound without it still blocks the address
+            let _acceptor = TcpListener::bind(addr).unwrap();
+            assert!(TcpListener::bind_reuseport(addr).is_err());
+        })
+        .unwrap();
+}
+
+#[test]
+fn test_connection_pool() {
+    Scheduler::new()
+        .run(|| {
+            let acceptor = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = acceptor.local_addr().unwrap();
+
+            let server = Scheduler::spawn(move || {
+                let mut count = 0;
+
+                while let Ok((stream, _)) = acceptor.accept() {
+                    count += 1;
+
+                    // Tells the client how many connections were opened so far
+                    Scheduler::spawn(move || {
+                        let mut stream = stream;
+                        let mut buf = [0u8; 1];
+                        while let Ok(1) = stream.read(&mut buf) {
+                            stream.write_all(&[count]).unwrap();
+                        }
+                    });
+
+                    if count == 3 {
+                        break;
+                    }
+                }
+            });
+
+            let pool = ConnectionPool::new(addr, 2)
+                .unwrap()
+                .with_max_wait(Duration::from_millis(50))
+                .with_idle_timeout(Duration::from_millis(200));
+
+            let ping = |conn: &mut TcpStream| {
+                let mut buf = [0u8; 1];
+                conn.write_all(b"x").unwrap();
+                conn.read_exact(&mut buf).unwrap();
+                buf[0]
+            };
+
+            // Returned connections are reused
+            {
+                let mut conn = pool.get().unwrap();
+                assert_eq!(ping(&mut *conn), 1);
+            }
+            {
+                let mut conn = pool.get().unwrap();
+                assert_eq!(ping(&mut *conn), 1);
+            }
+
+            // Getting more than `size` connections times out
+            {
+                let mut a = pool.get().unwrap();
+                let mut b = pool.get().unwrap();
+                assert_eq!(ping(&mut *a), 1);
+                assert_eq!(ping(&mut *b), 2);
+                assert_eq!(pool.get().unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+            }
+
+            // Expired idle connections are replaced by new ones
+            coio::sleep(Duration::from_millis(300));
+            {
+                let mut conn = pool.get().unwrap();
+                assert_eq!(ping(&mut *conn), 3);
+            }
+
+            server.join().unwrap();
+        })
+        .unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_tcp_socket_options() {
+    Scheduler::new()
+        .run(move || {
+            let acceptor = TcpListener::bind("127.0.0.1:6795").unwrap();
+
+            let listen_fut = Scheduler::spawn(move || {
+                // The options can be set on accepted streams as well
+                let (stream, _) = acceptor.accept().unwrap();
+
+                stream.set_nodelay(true).unwrap();
+                assert!(stream.nodelay().unwrap());
+
+                stream.set_keepalive(Some(Duration::from_millis(1500))).unwrap();
+                stream.set_keepalive(None).unwrap();
+            });
+
+            let stream = TcpStream::connect("127.0.0.1:6795").unwrap();
+
+            assert!(!stream.nodelay().unwrap());
+            stream.set_nodelay(true).unwrap();
+            assert!(stream.nodelay().unwrap());
+            stream.set_nodelay(false).unwrap();
+            assert!(!stream.nodelay().unwrap());
+
+            stream.set_keepalive(Some(Duration::from_secs(30))).unwrap();
+
+            assert_eq!(stream.linger().unwrap(), None);
+            stream.set_linger(Some(Duration::from_secs(1))).unwrap();
+            assert_eq!(stream.linger().unwrap(), Some(Duration::from_secs(1)));
+
+            // Rounded up, since a linger of 0 would reset the connection on close
+            stream.set_linger(Some(Duration::from_millis(500))).unwrap();
+            assert_eq!(stream.linger().unwrap(), Some(Duration::from_secs(1)));
+            stream.set_linger(None).unwrap();
+            assert_eq!(stream.linger().unwrap(), None);
+
+            listen_fut.join().unwrap();
+        })
+        .unwrap();
+}
+
+#[test]
+fn test_tcp_send_file() {
+    use std::env;
+    use std::fs::{self, File};
+
+    let path = env::temp_dir().join("coio-test-send-file");
+    let data: Vec<u8> = (0..1024 * 1024).map(|i| i as u8).collect();
+    File::create(&path).unwrap().write_all(&data).unwrap();
+
+    let expected = data.clone();
+    let file_path = path.clone();
+
+    Scheduler::new()
+        .run(move || {
+            let acceptor = TcpListener::bind("127.0.0.1:6797").unwrap();
+
+            let listen_fut = Scheduler::spawn(move || {
+                let (stream, _) = acceptor.accept().unwrap();
+                let file = File::open(&file_path).unwrap();
+
+                // Large enough to fill the socket buffers and park the coroutine
+                let sent = stream.send_file(&file, 3, data.len()).unwrap();
+                assert_eq!(sent, data.len() - 3);
+            });
+
+            let mut stream = TcpStream::connect("127.0.0.1:6797").unwrap();
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received).unwrap();
+            assert_eq!(&received[..], &expected[3..]);
+
+            listen_fut.join().unwrap();
+        })
+        .unwrap();
+
+    fs::remove_file(&path).unwrap();
+}
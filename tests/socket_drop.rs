@@ -0,0 +1,79 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate coio;
+
+use std::io::Read;
+use std::sync::Arc;
+use std::time::Duration;
+
+use coio::Scheduler;
+use coio::net::{TcpListener, TcpStream};
+
+const ROUNDS: usize = 200;
+const CONCURRENCY: usize = 16;
+
+// Rapidly creates sockets, parks coroutines on them and drops them, mostly on
+// a different Processor than the one they were created and registered on.
+// Events which are still in flight for an already deregistered socket must be ignored.
+#[test]
+fn test_socket_drop_while_parked() {
+    Scheduler::new()
+        .with_workers(4)
+        .run(|| {
+            let acceptor = Arc::new(TcpListener::bind("127.0.0.1:0").unwrap());
+            let addr = acceptor.local_addr().unwrap();
+
+            let handles: Vec<_> = (0..CONCURRENCY)
+                                      .map(|_| {
+                                          let acceptor = acceptor.clone();
+
+                                          Scheduler::spawn(move || {
+                                              for i in 0..ROUNDS {
+                                                  let stream = TcpStream::connect(addr).unwrap();
+                                                  let (peer, _) = acceptor.accept().unwrap();
+                                                  churn(stream, peer, i);
+                                              }
+                                          })
+                                      })
+                                      .collect();
+
+            for h in handles {
+                h.join().unwrap();
+            }
+        })
+        .unwrap();
+}
+
+fn churn(stream: TcpStream, peer: TcpStream, i: usize) {
+    let stream = Arc::new(stream);
+
+    // Parks on a read, which is then either woken up by the peer being dropped or times out
+    let reader = {
+        let stream = stream.clone();
+
+        Scheduler::spawn(move || {
+            if i % 2 == 0 {
+                stream.set_read_timeout(Some(Duration::from_millis(1))).unwrap();
+            }
+
+            let mut buf = [0u8; 16];
+            let _ = (&*stream).read(&mut buf);
+        })
+    };
+
+    if i % 2 == 1 {
+        drop(peer);
+    } else {
+        // Drop the peer on another coroutine, which most likely runs elsewhere
+        Scheduler::spawn(move || drop(peer)).join().unwrap();
+    }
+
+    reader.join().unwrap();
+    drop(stream);
+}
@@ -0,0 +1,69 @@
+// Copyright 2015 The coio Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#![cfg(unix)]
+
+extern crate coio;
+
+use std::env;
+use std::process::Command;
+use std::os::unix::process::ExitStatusExt;
+use std::ptr;
+
+use coio::{Builder, Scheduler};
+
+const CHILD_ENV: &'static str = "COIO_TEST_STACK_OVERFLOW_CHILD";
+
+const SIGSEGV: i32 = 11;
+
+// Some platforms, like macOS, report access to the guard page as SIGBUS instead of SIGSEGV
+#[cfg(target_os = "linux")]
+const SIGBUS: i32 = 7;
+#[cfg(not(target_os = "linux"))]
+const SIGBUS: i32 = 10;
+
+fn recurse(depth: usize) -> usize {
+    let buf = [depth as u8; 1024];
+
+    if depth == 0 {
+        return 0;
+    }
+
+    // The volatile read forces buf onto the stack and prevents tail call optimization
+    recurse(depth - 1) + unsafe { ptr::read_volatile(&buf[depth % buf.len()]) } as usize
+}
+
+// A stack overflow kills the process, which is why the test runs itself in a child process.
+#[test]
+fn test_stack_overflow_hits_guard_page() {
+    if env::var_os(CHILD_ENV).is_some() {
+        Scheduler::new()
+            .run(|| {
+                Builder::new()
+                    .stack_size(32 * 1024)
+                    .guard_page(true)
+                    .spawn(|| recurse(1024 * 1024))
+                    .join()
+                    .unwrap();
+            })
+            .unwrap();
+
+        unreachable!("the stack overflow went unnoticed");
+    }
+
+    let status = Command::new(env::current_exe().unwrap())
+                     .arg("test_stack_overflow_hits_guard_page")
+                     .env(CHILD_ENV, "1")
+                     .status()
+                     .unwrap();
+
+    let signal = status.signal();
+    assert!(signal == Some(SIGSEGV) || signal == Some(SIGBUS),
+            "expected the child to fault, but it exited with {:?}",
+            status);
+}
@@ -1,7 +1,7 @@
 extern crate coio;
 extern crate env_logger;
 
-use std::io::{Read, Write};
+use std::io::{ErrorKind, Read, Write};
 use std::time::Duration;
 
 use coio::Scheduler;
@@ -62,3 +62,255 @@ fn test_tcp_echo() {
         })
         .unwrap();
 }
+
+#[test]
+fn test_tcp_connect_timeout() {
+    Scheduler::new()
+        .run(move || {
+            let acceptor = TcpListener::bind("127.0.0.1:6790").unwrap();
+
+            let listen_fut = Scheduler::spawn(move || {
+                let (mut stream, _) = acceptor.accept().unwrap();
+                stream.write_all(b"abc").unwrap();
+            });
+
+            let mut stream = TcpStream::connect_timeout("127.0.0.1:6790",
+                                                        Duration::from_secs(5))
+                                 .unwrap();
+
+            let mut buf = [0u8; 3];
+            stream.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"abc");
+
+            listen_fut.join().unwrap();
+        })
+        .unwrap();
+}
+
+#[test]
+fn test_tcp_connect_timeout_refused() {
+    Scheduler::new()
+        .run(move || {
+            // Nobody is listening on this port anymore
+            let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+
+            let err = TcpStream::connect_timeout(addr, Duration::from_secs(5)).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+        })
+        .unwrap();
+}
+
+#[test]
+fn test_tcp_read_timeout_keeps_data() {
+    Scheduler::new()
+        .run(move || {
+            let acceptor = TcpListener::bind("127.0.0.1:6791").unwrap();
+
+            let listen_fut = Scheduler::spawn(move || {
+                let (mut stream, _) = acceptor.accept().unwrap();
+                stream.write_all(b"abc").unwrap();
+
+                // Give the reader enough time to run into it's timeout
+                sleep(Duration::from_millis(500));
+                stream.write_all(b"def").unwrap();
+            });
+
+            let mut stream = TcpStream::connect("127.0.0.1:6791").unwrap();
+            stream.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+
+            let mut buf = [0u8; 6];
+            let mut len = 0;
+
+            while len < 3 {
+                len += stream.read(&mut buf[len..]).unwrap();
+            }
+            assert_eq!(&buf[..len], b"abc");
+
+            let err = stream.read(&mut buf[len..]).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::TimedOut);
+
+            // The data arriving after the timeout must not be lost
+            stream.set_read_timeout(None).unwrap();
+            while len < 6 {
+                len += stream.read(&mut buf[len..]).unwrap();
+            }
+            assert_eq!(&buf, b"abcdef");
+
+            listen_fut.join().unwrap();
+        })
+        .unwrap();
+}
+
+#[test]
+fn test_tcp_zero_timeout() {
+    Scheduler::new()
+        .run(move || {
+            let _acceptor = TcpListener::bind("127.0.0.1:6792").unwrap();
+            let stream = TcpStream::connect("127.0.0.1:6792").unwrap();
+
+            let err = stream.set_read_timeout(Some(Duration::new(0, 0))).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+            let err = stream.set_write_timeout(Some(Duration::new(0, 0))).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        })
+        .unwrap();
+}
+
+#[test]
+fn test_tcp_connect_happy_eyeballs() {
+    Scheduler::new()
+        .run(move || {
+            let acceptor = TcpListener::bind("127.0.0.1:0").unwrap();
+            let port = acceptor.local_addr().unwrap().port();
+
+            let hdl = Scheduler::spawn(move || {
+                for _ in 0..2 {
+                    acceptor.accept().unwrap();
+                }
+            });
+
+            let stream = TcpStream::connect_happy_eyeballs("127.0.0.1", port).unwrap();
+            assert_eq!(stream.peer_addr().unwrap().port(), port);
+
+            // The attempt to ::1, if any, is refused and IPv4 wins
+            let stream = TcpStream::connect_happy_eyeballs("localhost", port).unwrap();
+            assert_eq!(stream.peer_addr().unwrap().port(), port);
+
+            hdl.join().unwrap();
+        })
+        .unwrap();
+}
+
+#[test]
+fn test_tcp_connect_happy_eyeballs_refused() {
+    Scheduler::new()
+        .run(move || {
+            // Nobody is listening on this port anymore
+            let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+
+            let err = TcpStream::connect_happy_eyeballs("127.0.0.1", port).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+        })
+        .unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_tcp_peek() {
+    Scheduler::new()
+        .run(move || {
+            let acceptor = TcpListener::bind("127.0.0.1:6794").unwrap();
+
+            let listen_fut = Scheduler::spawn(move || {
+                let (mut stream, _) = acceptor.accept().unwrap();
+                stream.write_all(b"\x03abc").unwrap();
+
+                // Give the reader enough time to run into it's timeout
+                sleep(Duration::from_millis(500));
+                stream.write_all(b"d").unwrap();
+            });
+
+            let mut stream = TcpStream::connect("127.0.0.1:6794").unwrap();
+            stream.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+
+            // Peeking the length prefix doesn't consume it
+            let mut prefix = [0u8; 1];
+            assert_eq!(stream.peek(&mut prefix).unwrap(), 1);
+            assert_eq!(stream.peek(&mut prefix).unwrap(), 1);
+            assert_eq!(prefix[0], 3);
+
+            let mut buf = [0u8; 4];
+            let mut len = 0;
+
+            while len < 4 {
+                len += stream.read(&mut buf[len..]).unwrap();
+            }
+            assert_eq!(&buf, b"\x03abc");
+
+            let err = stream.peek(&mut prefix).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::TimedOut);
+
+            stream.set_read_timeout(None).unwrap();
+            assert_eq!(stream.peek(&mut prefix).unwrap(), 1);
+            assert_eq!(stream.read(&mut buf).unwrap(), 1);
+            assert_eq!(&buf[..1], b"d");
+
+            listen_fut.join().unwrap();
+        })
+        .unwrap();
+}
+
+#[test]
+fn test_tcp_try_read() {
+    Scheduler::new()
+        .run(move || {
+            let acceptor = TcpListener::bind("127.0.0.1:6796").unwrap();
+
+            let listen_fut = Scheduler::spawn(move || {
+                let (mut stream, _) = acceptor.accept().unwrap();
+                sleep(Duration::from_millis(100));
+                stream.write_all(b"abc").unwrap();
+            });
+
+            let mut stream = TcpStream::connect("127.0.0.1:6796").unwrap();
+            let mut buf = [0u8; 3];
+
+            // Nothing was sent yet, so this must return right away
+            let err = stream.try_read(&mut buf).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::WouldBlock);
+
+            let mut len = 0;
+            while len < 3 {
+                len += stream.read(&mut buf[len..]).unwrap();
+            }
+            assert_eq!(&buf, b"abc");
+
+            listen_fut.join().unwrap();
+        })
+        .unwrap();
+}
+
+// The total deadline runs out while the attempts to several addresses are still pending
+#[cfg(target_os = "linux")]
+#[test]
+fn test_tcp_connect_timeout_across_addresses() {
+    use std::net::{self, SocketAddr};
+    use std::time::Instant;
+
+    Scheduler::new()
+        .run(move || {
+            // A listener which never accepts drops further SYNs once it's backlog is full,
+            // which leaves the connection attempts pending
+            let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+
+            let mut backlog = Vec::new();
+            loop {
+                match TcpStream::connect_timeout(addr, Duration::from_millis(50)) {
+                    Ok(stream) => backlog.push(stream),
+                    Err(err) => {
+                        assert_eq!(err.kind(), ErrorKind::TimedOut);
+                        break;
+                    }
+                }
+
+                assert!(backlog.len() < 4096, "the backlog never filled up");
+            }
+
+            let addrs: Vec<SocketAddr> = vec![addr, addr, addr];
+            let timeout = Duration::from_millis(300);
+
+            let start = Instant::now();
+            let err = TcpStream::connect_timeout(&addrs[..], timeout).unwrap_err();
+            let elapsed = start.elapsed();
+
+            // The first attempt used up all of the time, the others weren't even started
+            assert_eq!(err.kind(), ErrorKind::TimedOut);
+            assert!(elapsed >= timeout);
+            assert!(elapsed < timeout * 2);
+
+            drop(listener);
+        })
+        .unwrap();
+}